@@ -0,0 +1,38 @@
+//! Times `ChannelFilter` on a second's worth of 20 MS/s samples, which it has to beat to keep up
+//! with the radio. Run with `cargo bench --bench channel_filter`.
+
+use std::process;
+use std::time::Instant;
+
+use rs_libhackrf::dsp::{ChannelFilter, Nco, Stage};
+
+const INPUT_RATE: f64 = 20e6;
+const BUFFER_SAMPLES: usize = 131_072;
+
+fn main() {
+    let mut buffer = [0.5f32, 0.0].repeat(BUFFER_SAMPLES);
+    let mut too_slow = false;
+
+    Nco::new(1e6, INPUT_RATE).mix_in_place(&mut buffer);
+
+    for &(bandwidth, rate) in &[(1e6, 1.25e6), (150e3, 200e3), (10e3, 12.5e3)] {
+        let mut filter = ChannelFilter::new(INPUT_RATE, 3e6, bandwidth, rate).unwrap();
+        let mut output = Vec::new();
+        let start = Instant::now();
+
+        for _ in 0..(INPUT_RATE as usize / BUFFER_SAMPLES) {
+            output.clear();
+            filter.process(&buffer, &mut output);
+        }
+
+        let elapsed = start.elapsed();
+
+        println!("{} Hz at {} S/s: {:?} per second of input", bandwidth, rate, elapsed);
+        too_slow |= elapsed.as_secs() >= 1;
+    }
+
+    if too_slow {
+        eprintln!("Channel filter can't keep up with {} S/s", INPUT_RATE);
+        process::exit(1);
+    }
+}
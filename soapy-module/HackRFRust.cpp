@@ -0,0 +1,261 @@
+// Registers rs-libhackrf with SoapySDR as the "hackrf_rs" driver. Everything is forwarded to the
+// crate's C ABI; this file only adapts it to SoapySDR::Device and the module registry.
+//
+// Devices are found by index; pass simulate=true to get the crate's simulated HackRF instead.
+
+#include <SoapySDR/Device.hpp>
+#include <SoapySDR/Formats.hpp>
+#include <SoapySDR/Registry.hpp>
+
+#include <cstdint>
+#include <stdexcept>
+#include <string>
+#include <vector>
+
+#include "hackrf_rs_soapy.h"
+
+namespace {
+
+void check(int ret)
+{
+    if (ret < 0) throw std::runtime_error(hackrf_rs_soapy_last_error());
+}
+
+// streams are identified by their direction
+SoapySDR::Stream *stream_of(int direction)
+{
+    return reinterpret_cast<SoapySDR::Stream *>(static_cast<intptr_t>(direction + 1));
+}
+
+int direction_of(SoapySDR::Stream *stream)
+{
+    return static_cast<int>(reinterpret_cast<intptr_t>(stream)) - 1;
+}
+
+class HackRFRust : public SoapySDR::Device
+{
+public:
+    explicit HackRFRust(const SoapySDR::Kwargs &args)
+    {
+        const bool simulate = args.count("simulate") && args.at("simulate") == "true";
+        const int index = args.count("index") ? std::stoi(args.at("index")) : 0;
+
+        device = hackrf_rs_soapy_open(index, simulate);
+        if (device == nullptr) throw std::runtime_error(hackrf_rs_soapy_last_error());
+    }
+
+    ~HackRFRust() override { hackrf_rs_soapy_close(device); }
+
+    std::string getDriverKey() const override { return "hackrf_rs"; }
+
+    std::string getHardwareKey() const override
+    {
+        char key[64];
+        check(hackrf_rs_soapy_hardware_key(device, key, sizeof(key)));
+        return key;
+    }
+
+    size_t getNumChannels(const int) const override { return 1; }
+    bool getFullDuplex(const int, const size_t) const override { return false; }
+
+    std::vector<std::string> listAntennas(const int, const size_t) const override { return {"TX/RX"}; }
+    void setAntenna(const int, const size_t, const std::string &) override {}
+    std::string getAntenna(const int, const size_t) const override { return "TX/RX"; }
+
+    std::vector<std::string> listGains(const int direction, const size_t) const override
+    {
+        const char *names[8];
+        const size_t count = hackrf_rs_soapy_list_gains(device, direction, names, 8);
+        return std::vector<std::string>(names, names + count);
+    }
+
+    void setGain(const int direction, const size_t, const double value) override
+    {
+        check(hackrf_rs_soapy_set_gain(device, direction, value));
+    }
+
+    double getGain(const int direction, const size_t) const override
+    {
+        return hackrf_rs_soapy_get_gain(device, direction);
+    }
+
+    void setGain(const int direction, const size_t, const std::string &name, const double value) override
+    {
+        check(hackrf_rs_soapy_set_gain_element(device, direction, name.c_str(), value));
+    }
+
+    double getGain(const int direction, const size_t, const std::string &name) const override
+    {
+        return hackrf_rs_soapy_get_gain_element(device, direction, name.c_str());
+    }
+
+    SoapySDR::Range getGainRange(const int direction, const size_t) const override
+    {
+        return gainRange(direction, nullptr);
+    }
+
+    SoapySDR::Range getGainRange(const int direction, const size_t, const std::string &name) const override
+    {
+        return gainRange(direction, name.c_str());
+    }
+
+    void setFrequency(const int direction, const size_t, const double frequency, const SoapySDR::Kwargs &) override
+    {
+        check(hackrf_rs_soapy_set_frequency(device, direction, frequency));
+    }
+
+    double getFrequency(const int direction, const size_t) const override
+    {
+        return hackrf_rs_soapy_get_frequency(device, direction);
+    }
+
+    SoapySDR::RangeList getFrequencyRange(const int, const size_t) const override
+    {
+        return {SoapySDR::Range(0.0, 7.25e9)};
+    }
+
+    void setSampleRate(const int direction, const size_t, const double rate) override
+    {
+        check(hackrf_rs_soapy_set_sample_rate(device, direction, rate));
+    }
+
+    double getSampleRate(const int direction, const size_t) const override
+    {
+        return hackrf_rs_soapy_get_sample_rate(device, direction);
+    }
+
+    SoapySDR::RangeList getSampleRateRange(const int, const size_t) const override
+    {
+        // the rates hackrf_rs_soapy_set_sample_rate accepts
+        return {SoapySDR::Range(4e6, 20e6)};
+    }
+
+    void setBandwidth(const int direction, const size_t, const double bandwidth) override
+    {
+        check(hackrf_rs_soapy_set_bandwidth(device, direction, bandwidth));
+    }
+
+    double getBandwidth(const int direction, const size_t) const override
+    {
+        return hackrf_rs_soapy_get_bandwidth(device, direction);
+    }
+
+    SoapySDR::ArgInfoList getSettingInfo() const override
+    {
+        SoapySDR::ArgInfo bias;
+        bias.key = "bias_tx";
+        bias.value = "false";
+        bias.name = "Antenna Bias";
+        bias.description = "Antenna port power control";
+        bias.type = SoapySDR::ArgInfo::BOOL;
+        return {bias};
+    }
+
+    void writeSetting(const std::string &key, const std::string &value) override
+    {
+        check(hackrf_rs_soapy_write_setting(device, key.c_str(), value.c_str()));
+    }
+
+    std::string readSetting(const std::string &key) const override
+    {
+        char value[64];
+        if (hackrf_rs_soapy_read_setting(device, key.c_str(), value, sizeof(value)) < 0) return "";
+        return value;
+    }
+
+    std::vector<std::string> getStreamFormats(const int, const size_t) const override
+    {
+        return {SOAPY_SDR_CF32, SOAPY_SDR_CS16, SOAPY_SDR_CS8};
+    }
+
+    std::string getNativeStreamFormat(const int, const size_t, double &fullScale) const override
+    {
+        fullScale = 128;
+        return SOAPY_SDR_CS8;
+    }
+
+    SoapySDR::Stream *setupStream(const int direction, const std::string &format,
+                                  const std::vector<size_t> &channels, const SoapySDR::Kwargs &) override
+    {
+        if (channels.size() > 1 || (channels.size() == 1 && channels[0] != 0)) {
+            throw std::runtime_error("hackrf_rs has one channel");
+        }
+
+        check(hackrf_rs_soapy_setup_stream(device, direction, format.c_str()));
+        return stream_of(direction);
+    }
+
+    void closeStream(SoapySDR::Stream *stream) override
+    {
+        hackrf_rs_soapy_close_stream(device, direction_of(stream));
+    }
+
+    size_t getStreamMTU(SoapySDR::Stream *stream) const override
+    {
+        return hackrf_rs_soapy_stream_mtu(device, direction_of(stream));
+    }
+
+    int activateStream(SoapySDR::Stream *stream, const int, const long long, const size_t) override
+    {
+        return hackrf_rs_soapy_activate_stream(device, direction_of(stream));
+    }
+
+    int deactivateStream(SoapySDR::Stream *stream, const int, const long long) override
+    {
+        return hackrf_rs_soapy_deactivate_stream(device, direction_of(stream));
+    }
+
+    int readStream(SoapySDR::Stream *, void *const *buffs, const size_t numElems, int &flags,
+                   long long &, const long timeoutUs) override
+    {
+        flags = 0;
+        return hackrf_rs_soapy_read_stream(device, buffs[0], numElems, timeoutUs);
+    }
+
+    int writeStream(SoapySDR::Stream *, const void *const *buffs, const size_t numElems, int &flags,
+                    const long long, const long timeoutUs) override
+    {
+        flags = 0;
+        return hackrf_rs_soapy_write_stream(device, buffs[0], numElems, timeoutUs);
+    }
+
+private:
+    SoapySDR::Range gainRange(const int direction, const char *name) const
+    {
+        double minimum, maximum, step;
+        check(hackrf_rs_soapy_get_gain_range(device, direction, name, &minimum, &maximum, &step));
+        return SoapySDR::Range(minimum, maximum, step);
+    }
+
+    hackrf_rs_soapy *device;
+};
+
+SoapySDR::KwargsList find(const SoapySDR::Kwargs &args)
+{
+    SoapySDR::KwargsList results;
+
+    if (args.count("simulate") && args.at("simulate") == "true") {
+        results.push_back({{"driver", "hackrf_rs"}, {"simulate", "true"}, {"label", "HackRF (simulated)"}});
+        return results;
+    }
+
+    const int count = hackrf_rs_soapy_count();
+
+    for (int i = 0; i < count; i++) {
+        const std::string index = std::to_string(i);
+
+        if (args.count("index") && args.at("index") != index) continue;
+        results.push_back({{"driver", "hackrf_rs"}, {"index", index}, {"label", "HackRF #" + index}});
+    }
+
+    return results;
+}
+
+SoapySDR::Device *make(const SoapySDR::Kwargs &args)
+{
+    return new HackRFRust(args);
+}
+
+SoapySDR::Registry registration("hackrf_rs", &find, &make, SOAPY_SDR_ABI_VERSION);
+
+}
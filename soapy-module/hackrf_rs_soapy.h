@@ -0,0 +1,57 @@
+/*
+ * The C ABI exported by rs-libhackrf when built with the `soapy` feature; see src/soapy/ffi.rs.
+ *
+ * Directions are SOAPY_SDR_TX (0) and SOAPY_SDR_RX (1). Calls that can fail return 0 or a
+ * negative SOAPY_SDR_* code, getters return 0 and open returns NULL, and the reason is left for
+ * hackrf_rs_soapy_last_error() on the calling thread.
+ */
+#ifndef HACKRF_RS_SOAPY_H
+#define HACKRF_RS_SOAPY_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct hackrf_rs_soapy hackrf_rs_soapy;
+
+const char *hackrf_rs_soapy_last_error(void);
+int hackrf_rs_soapy_count(void);
+hackrf_rs_soapy *hackrf_rs_soapy_open(int index, int simulate);
+void hackrf_rs_soapy_close(hackrf_rs_soapy *device);
+int hackrf_rs_soapy_hardware_key(const hackrf_rs_soapy *device, char *out, size_t len);
+
+int hackrf_rs_soapy_set_frequency(const hackrf_rs_soapy *device, int direction, double freq_hz);
+double hackrf_rs_soapy_get_frequency(const hackrf_rs_soapy *device, int direction);
+int hackrf_rs_soapy_set_sample_rate(const hackrf_rs_soapy *device, int direction, double rate);
+double hackrf_rs_soapy_get_sample_rate(const hackrf_rs_soapy *device, int direction);
+int hackrf_rs_soapy_set_bandwidth(const hackrf_rs_soapy *device, int direction, double bandwidth);
+double hackrf_rs_soapy_get_bandwidth(const hackrf_rs_soapy *device, int direction);
+
+size_t hackrf_rs_soapy_list_gains(const hackrf_rs_soapy *device, int direction, const char **names, size_t max);
+int hackrf_rs_soapy_set_gain(const hackrf_rs_soapy *device, int direction, double gain);
+double hackrf_rs_soapy_get_gain(const hackrf_rs_soapy *device, int direction);
+int hackrf_rs_soapy_set_gain_element(const hackrf_rs_soapy *device, int direction, const char *name, double gain);
+double hackrf_rs_soapy_get_gain_element(const hackrf_rs_soapy *device, int direction, const char *name);
+/* name may be NULL for the overall gain */
+int hackrf_rs_soapy_get_gain_range(const hackrf_rs_soapy *device, int direction, const char *name,
+                                   double *minimum, double *maximum, double *step);
+
+int hackrf_rs_soapy_write_setting(const hackrf_rs_soapy *device, const char *key, const char *value);
+int hackrf_rs_soapy_read_setting(const hackrf_rs_soapy *device, const char *key, char *out, size_t len);
+
+/* formats are "CF32", "CS16" or "CS8"; there is one stream per direction */
+int hackrf_rs_soapy_setup_stream(const hackrf_rs_soapy *device, int direction, const char *format);
+int hackrf_rs_soapy_close_stream(const hackrf_rs_soapy *device, int direction);
+int hackrf_rs_soapy_activate_stream(const hackrf_rs_soapy *device, int direction);
+int hackrf_rs_soapy_deactivate_stream(const hackrf_rs_soapy *device, int direction);
+size_t hackrf_rs_soapy_stream_mtu(const hackrf_rs_soapy *device, int direction);
+int hackrf_rs_soapy_read_stream(const hackrf_rs_soapy *device, void *buffer, size_t num_elems, long timeout_us);
+int hackrf_rs_soapy_write_stream(const hackrf_rs_soapy *device, const void *buffer, size_t num_elems, long timeout_us);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
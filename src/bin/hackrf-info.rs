@@ -0,0 +1,172 @@
+//! Reports on every HackRF attached, like hackrf_info, optionally as JSON
+
+use std::env;
+use std::process;
+
+use serde_json::{json, Value};
+
+use rs_libhackrf::control::usb_board_name;
+use rs_libhackrf::device::Device;
+use rs_libhackrf::error::Error;
+use rs_libhackrf::hackrf::HackRF;
+
+const USAGE: &str = "Usage: hackrf-info [options]
+    --json    print a JSON object instead of text
+    -h        show this help";
+
+/// What we learn from one device
+struct Report {
+    index: usize,
+    serial: String,
+    usb_board_id: u32,
+    board: Result<Board, Error>
+}
+
+/// What we learn once the device is open
+struct Board {
+    board_id: u8,
+    board_name: String,
+    firmware: String,
+    usb_api_version: u16,
+    part_id: [u32; 2],
+    serial_no: [u32; 4],
+    operacake_boards: Vec<u8>
+}
+
+impl Board {
+    fn read(device: &Device) -> Result<Board, Error> {
+        let ids = device.board_partid_serialno_read()?;
+
+        Ok(Board {
+            board_id: device.board_id_read()?,
+            board_name: device.board_id_name()?,
+            firmware: device.version_string_read()?,
+            usb_api_version: device.usb_api_version_read()?,
+            part_id: ids.part_id,
+            serial_no: ids.serial_no,
+            operacake_boards: match device.operacake_boards() {
+                // firmware older than USB API 1.02 can't list Opera Cakes; hackrf_info says nothing either
+                Err(Error::USB_API_VERSION(_)) => Vec::new(),
+                result => result?
+            }
+        })
+    }
+
+    fn usb_api(&self) -> String {
+        format!("{:x}.{:02x}", self.usb_api_version >> 8, self.usb_api_version & 0xff)
+    }
+}
+
+impl Report {
+    fn print(&self) {
+        println!("Found HackRF");
+        println!("Index: {}", self.index);
+        println!("Serial number: {}", self.serial);
+
+        match self.board {
+            Ok(ref board) => {
+                println!("Board ID Number: {} ({})", board.board_id, board.board_name);
+                println!("Firmware Version: {} (API:{})", board.firmware, board.usb_api());
+                println!("Part ID Number: 0x{:08x} 0x{:08x}", board.part_id[0], board.part_id[1]);
+
+                for address in &board.operacake_boards {
+                    println!("Opera Cake found, address: {}", address);
+                }
+            },
+            Err(ref e) => println!("Error reading {} {}: {}", usb_board_name(self.usb_board_id), self.serial, e)
+        }
+
+        println!();
+    }
+
+    fn to_json(&self) -> Value {
+        let mut report = json!({
+            "index": self.index,
+            "serial": self.serial,
+            "usb_board_id": self.usb_board_id,
+            "usb_board_name": usb_board_name(self.usb_board_id)
+        });
+
+        match self.board {
+            Ok(ref board) => {
+                let hex = |values: &[u32]| values.iter().map(|v| format!("0x{:08x}", v)).collect::<Vec<_>>();
+
+                report["board_id"] = json!(board.board_id);
+                report["board_name"] = json!(board.board_name);
+                report["firmware_version"] = json!(board.firmware);
+                report["usb_api_version"] = json!(board.usb_api());
+                report["part_id"] = json!(hex(&board.part_id));
+                report["serial_no"] = json!(hex(&board.serial_no));
+                report["operacake_boards"] = json!(board.operacake_boards);
+            },
+            Err(ref e) => report["error"] = json!(e.to_string())
+        }
+
+        report
+    }
+}
+
+fn run(json: bool) -> Result<bool, Error> {
+    let mut hackrf = HackRF::new()?;
+    let library_version = hackrf.library_version();
+    let devices :Vec<(String, u32)> = hackrf.get_device_list()?.iter()
+        .map(|info| (String::from(info.serial()), info.board_id() as u32))
+        .collect();
+    let mut reports = Vec::with_capacity(devices.len());
+
+    for (index, (serial, usb_board_id)) in devices.into_iter().enumerate() {
+        let board = hackrf.open_device(index as i32).and_then(|device| Board::read(&device));
+
+        reports.push(Report { index, serial, usb_board_id, board });
+    }
+
+    let ok = reports.iter().all(|report| report.board.is_ok());
+
+    if json {
+        let reports :Vec<Value> = reports.iter().map(Report::to_json).collect();
+
+        println!("{}", json!({ "library_version": library_version, "devices": reports }));
+    } else {
+        println!("libhackrf version: {}", library_version);
+
+        if reports.is_empty() {
+            println!("No HackRF boards found.");
+        }
+
+        reports.iter().for_each(Report::print);
+    }
+
+    Ok(ok)
+}
+
+fn main() {
+    let mut json = false;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            other => {
+                eprintln!("Unknown option {}\n\n{}", other, USAGE);
+                process::exit(1);
+            }
+        }
+    }
+
+    match run(json) {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            if json {
+                println!("{}", json!({ "error": e.to_string(), "devices": [] }));
+            } else {
+                eprintln!("hackrf-info: {}", e);
+            }
+
+            process::exit(1);
+        }
+    }
+}
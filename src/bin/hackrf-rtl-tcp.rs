@@ -0,0 +1,149 @@
+//! Serves a HackRF, or the simulator, to rtl_tcp clients
+
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+
+use log::{error, info};
+
+use rs_libhackrf::error::Error;
+use rs_libhackrf::formats::IqReader;
+use rs_libhackrf::hackrf::HackRF;
+use rs_libhackrf::rtl_tcp::{RtlTcpServer, DEFAULT_PORT};
+use rs_libhackrf::simulator::SimulatedDevice;
+use rs_libhackrf::stream::TxSource;
+
+const USAGE: &str = "Usage: hackrf-rtl-tcp [options]
+    -a <address>     listen address (default 127.0.0.1)
+    -p <port>        listen port (default 1234)
+    -f <frequency>   initial frequency in Hz (default 100000000)
+    -s <rate>        initial sample rate in Hz (default 2048000)
+    -g <gain>        initial gain in dB (default automatic)
+    -d <index>       device index (default 0)
+    -S               serve the simulator, with a tone 100 kHz above the initial frequency
+    -r <file>        serve the simulator, looping a recording made at the hardware sample rate
+    -h               show this help";
+
+struct Options {
+    address: String,
+    port: u16,
+    frequency: u64,
+    sample_rate: f64,
+    gain: Option<f64>,
+    index: i32,
+    simulate: bool,
+    recording: Option<String>
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            address: String::from("127.0.0.1"),
+            port: DEFAULT_PORT,
+            frequency: 100_000_000,
+            sample_rate: 2_048_000.0,
+            gain: None,
+            index: 0,
+            simulate: false,
+            recording: None
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+            match arg.as_str() {
+                "-a" => options.address = value("-a")?,
+                "-p" => options.port = parse_number(&value("-p")?)?,
+                "-f" => options.frequency = parse_number(&value("-f")?)?,
+                "-s" => options.sample_rate = parse_number(&value("-s")?)?,
+                "-g" => options.gain = Some(parse_number(&value("-g")?)?),
+                "-d" => options.index = parse_number(&value("-d")?)?,
+                "-S" => options.simulate = true,
+                "-r" => {
+                    options.recording = Some(value("-r")?);
+                    options.simulate = true;
+                },
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                other => return Err(format!("Unknown option {}", other))
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid number: {}", value))
+}
+
+/// Plays a recording over and over
+struct Looping(IqReader<BufReader<File>>);
+
+impl TxSource for Looping {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        let mut filled = self.0.read(buffer)?;
+
+        while filled < buffer.len() {
+            self.0.rewind()?;
+
+            let count = self.0.read(&mut buffer[filled..])?;
+
+            if count == 0 {
+                break;
+            }
+
+            filled += count;
+        }
+
+        Ok(filled)
+    }
+}
+
+fn run(options: Options) -> Result<(), Error> {
+    let mut server = RtlTcpServer::bind((options.address.as_str(), options.port))?
+        .with_frequency(options.frequency)
+        .with_sample_rate(options.sample_rate);
+
+    if let Some(gain) = options.gain {
+        server = server.with_gain(gain);
+    }
+
+    info!("Listening on {}", server.local_addr()?);
+
+    if options.simulate {
+        let mut simulator = match options.recording {
+            Some(ref path) => SimulatedDevice::new().with_source(Looping(IqReader::open(path)?)),
+            None => SimulatedDevice::new().with_tone(options.frequency + 100_000, 0.3)
+        };
+
+        return server.serve(&mut simulator);
+    }
+
+    let mut hackrf = HackRF::new()?;
+    let mut device = hackrf.open_device(options.index)?;
+
+    info!("Serving {} (firmware {})", device.board_id_name()?, device.version_string_read()?);
+
+    server.serve(&mut device)
+}
+
+fn main() {
+    simple_logger::init_with_level(log::Level::Info).unwrap();
+
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,427 @@
+//! Sweeps a HackRF across frequency ranges and prints spectra, like hackrf_sweep
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use log::info;
+
+use rs_libhackrf::dsp::Fft;
+use rs_libhackrf::error::Error;
+use rs_libhackrf::hackrf::HackRF;
+use rs_libhackrf::radio::Radio;
+use rs_libhackrf::simulator::SimulatedDevice;
+use rs_libhackrf::stream::RxSink;
+use rs_libhackrf::sweep::{self, SweepPlan, SweepProcessor, BLOCK_BYTES, SAMPLE_RATE, TUNE_STEP_HZ};
+
+const USAGE: &str = "Usage: hackrf-sweep [options]
+    -f <min:max>     range to sweep in MHz, may be given up to 10 times (default 0:6000)
+    -w <width>       FFT bin width in Hz, rounded down to a power-of-two FFT (default 1000000)
+    -1               stop after one sweep
+    -N <sweeps>      stop after this many sweeps
+    -l <gain>        RX LNA gain, 0-40 dB in 8 dB steps (default 16)
+    -g <gain>        RX VGA gain, 0-62 dB in 2 dB steps (default 20)
+    -a <0|1>         RF amplifier (default 0)
+    -p <0|1>         antenna port power (default 0)
+    -B               binary output instead of CSV
+    -I               inverse FFT output: each sweep as f32 IQ samples (one range only)
+    -r <file>        write to a file instead of stdout
+    -d <index>       device index (default 0)
+    -S               use the simulator instead of a HackRF
+    -h               show this help";
+
+/// Sample buffers that can queue up between the RX callback and the writer before some are dropped
+const QUEUE_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Binary,
+    InverseFft
+}
+
+struct Options {
+    ranges: Vec<(u16, u16)>,
+    bin_width: f64,
+    sweeps: Option<u64>,
+    lna_gain: u32,
+    vga_gain: u32,
+    amp: bool,
+    antenna: bool,
+    format: Format,
+    path: Option<String>,
+    index: i32,
+    simulate: bool
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            ranges: Vec::new(),
+            bin_width: 1e6,
+            sweeps: None,
+            lna_gain: 16,
+            vga_gain: 20,
+            amp: false,
+            antenna: false,
+            format: Format::Csv,
+            path: None,
+            index: 0,
+            simulate: false
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+            match arg.as_str() {
+                "-f" => options.ranges.push(parse_range(&value("-f")?)?),
+                "-w" => options.bin_width = parse_number(&value("-w")?)?,
+                "-1" => options.sweeps = Some(1),
+                "-N" => options.sweeps = Some(parse_number(&value("-N")?)?),
+                "-l" => options.lna_gain = parse_number(&value("-l")?)?,
+                "-g" => options.vga_gain = parse_number(&value("-g")?)?,
+                "-a" => options.amp = parse_flag(&value("-a")?)?,
+                "-p" => options.antenna = parse_flag(&value("-p")?)?,
+                "-B" => options.format = Format::Binary,
+                "-I" => options.format = Format::InverseFft,
+                "-r" => options.path = Some(value("-r")?),
+                "-d" => options.index = parse_number(&value("-d")?)?,
+                "-S" => options.simulate = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                other => return Err(format!("Unknown option {}", other))
+            }
+        }
+
+        if options.ranges.is_empty() {
+            options.ranges.push((0, 6000));
+        }
+
+        if options.sweeps == Some(0) {
+            return Err(String::from("-N must be at least 1"));
+        }
+
+        if options.format == Format::InverseFft && options.ranges.len() > 1 {
+            return Err(String::from("-I only supports a single frequency range"));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid number: {}", value))
+}
+
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        other => Err(format!("Expected 0 or 1, not {}", other))
+    }
+}
+
+fn parse_range(value: &str) -> Result<(u16, u16), String> {
+    let mut parts = value.splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some(min), Some(max)) => Ok((parse_number(min)?, parse_number(max)?)),
+        _ => Err(format!("Expected <min:max> in MHz, not {}", value))
+    }
+}
+
+/// Hands sample buffers from the RX callback to the writer, dropping them rather than stalling USB
+struct ChannelSink {
+    sender: SyncSender<Vec<f32>>,
+    dropped: Arc<AtomicU64>
+}
+
+impl RxSink for ChannelSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        match self.sender.try_send(samples.to_vec()) {
+            Ok(()) => Ok( () ),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok( () )
+            },
+            Err(TrySendError::Disconnected(_)) => Err(Error::STREAMING_STOPPED(String::from("Sweep writer has gone")))
+        }
+    }
+}
+
+/// Reassembles a sweep's time-domain signal from its segments, as `hackrf_sweep -I` does
+struct InverseFft {
+    fft: Fft,
+    bins: Vec<f32>,
+    output: Vec<f32>
+}
+
+impl InverseFft {
+    fn new(plan: &SweepPlan) -> InverseFft {
+        let (low, high) = plan.ranges()[0];
+        let steps = (high - low) as usize * 1_000_000 / TUNE_STEP_HZ as usize;
+        // our FFT is radix-2, so round up; the extra bins stay empty
+        let size = (plan.fft_size() * steps).next_power_of_two();
+
+        InverseFft { fft: Fft::new_inverse(size), bins: vec![0.0; size * 2], output: vec![0.0; size * 2] }
+    }
+
+    /// Copies the two quarters of `spectrum` `hackrf_sweep` keeps into the bins for `offset_hz`
+    /// above the start of the range
+    fn place(&mut self, spectrum: &[f32], offset_hz: u64, bin_width: f64) {
+        let size = self.fft.size();
+        let fft_size = spectrum.len() / 2;
+        let quarter = fft_size / 4;
+        let mut index = ((offset_hz as f64 / bin_width).round() as usize + size / 2) % size;
+
+        for start in &[1 + fft_size * 5 / 8, 1 + fft_size / 8] {
+            for i in 0..quarter {
+                let to = (index + i) % size;
+
+                self.bins[to * 2] = spectrum[(start + i) * 2];
+                self.bins[to * 2 + 1] = spectrum[(start + i) * 2 + 1];
+            }
+
+            index = (index + fft_size / 2) % size;
+        }
+    }
+
+    fn write(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        let scale = 1.0 / self.fft.size() as f32;
+
+        self.output.copy_from_slice(&self.bins);
+        self.fft.process(&mut self.output);
+
+        for value in &self.output {
+            out.write_all(&(value * scale).to_le_bytes())?;
+        }
+
+        Ok( () )
+    }
+}
+
+/// Turns sweep blocks into output and counts completed sweeps
+struct SweepWriter {
+    out: Box<dyn Write>,
+    format: Format,
+    processor: SweepProcessor,
+    inverse: Option<InverseFft>,
+    first_freq: u64,
+    started: bool,
+    sweeps: u64,
+    limit: Option<u64>
+}
+
+impl SweepWriter {
+    fn new(out: Box<dyn Write>, plan: &SweepPlan, format: Format, limit: Option<u64>) -> SweepWriter {
+        SweepWriter {
+            out,
+            format,
+            processor: SweepProcessor::new(plan.fft_size()),
+            inverse: if format == Format::InverseFft { Some(InverseFft::new(plan)) } else { None },
+            first_freq: plan.ranges()[0].0 as u64 * 1_000_000,
+            started: false,
+            sweeps: 0,
+            limit
+        }
+    }
+
+    /// Handles one block, returning true once the requested number of sweeps is done
+    fn block(&mut self, block: &[f32]) -> Result<bool, Error> {
+        let freq = match sweep::parse_header(block) {
+            Some(freq) => freq,
+            None => return Ok(false)
+        };
+
+        // a sweep is complete when the radio comes back round to the first frequency
+        if freq == self.first_freq {
+            if self.started {
+                if let Some(ref mut inverse) = self.inverse {
+                    inverse.write(&mut self.out)?;
+                }
+
+                self.sweeps += 1;
+
+                if self.limit.is_some_and(|limit| self.sweeps >= limit) {
+                    return Ok(true);
+                }
+            }
+
+            self.started = true;
+        }
+
+        // blocks from before the first full sweep are only partway through one
+        if !self.started {
+            return Ok(false);
+        }
+
+        let segments = match self.processor.process_block(block) {
+            Some(segments) => segments,
+            None => return Ok(false)
+        };
+
+        match self.format {
+            Format::Csv => {
+                let now = Local::now().format("%Y-%m-%d, %H:%M:%S%.6f");
+
+                for segment in &segments {
+                    write!(self.out, "{}, {}, {}, {:.2}, {}", now, segment.freq_low, segment.freq_high, segment.bin_width, self.processor.fft_size())?;
+
+                    for power in &segment.power_db {
+                        write!(self.out, ", {:.2}", power)?;
+                    }
+
+                    writeln!(self.out)?;
+                }
+            },
+            Format::Binary => {
+                for segment in &segments {
+                    let record_length = 16 + segment.power_db.len() * 4;
+
+                    self.out.write_all(&(record_length as u32).to_le_bytes())?;
+                    self.out.write_all(&segment.freq_low.to_le_bytes())?;
+                    self.out.write_all(&segment.freq_high.to_le_bytes())?;
+
+                    for power in &segment.power_db {
+                        self.out.write_all(&power.to_le_bytes())?;
+                    }
+                }
+            },
+            Format::InverseFft => {
+                let bin_width = segments[0].bin_width;
+
+                if let Some(ref mut inverse) = self.inverse {
+                    inverse.place(self.processor.spectrum(), freq - self.first_freq, bin_width);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+fn sweep<R: Radio>(radio: &mut R, options: &Options, stop: &AtomicBool) -> Result<(), Error> {
+    let plan = SweepPlan::new(&options.ranges)?.with_bin_width(options.bin_width)?;
+    let out :Box<dyn Write> = match options.path {
+        Some(ref path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout()))
+    };
+    let mut writer = SweepWriter::new(out, &plan, options.format, options.sweeps);
+
+    radio.set_lna_gain(options.lna_gain)?;
+    radio.set_vga_gain(options.vga_gain)?;
+    radio.set_amp_enable(options.amp)?;
+    radio.set_antenna_enable(options.antenna)?;
+
+    info!("Sweeping {:?} MHz with {} point FFTs ({:.0} Hz bins)", plan.ranges(), plan.fft_size(), plan.bin_width());
+
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_DEPTH);
+    let dropped = Arc::new(AtomicU64::new(0));
+
+    plan.start(radio, Box::new(ChannelSink { sender, dropped: dropped.clone() }))?;
+
+    let started = Instant::now();
+    let mut last = Instant::now();
+    let mut pending :Vec<f32> = Vec::with_capacity(BLOCK_BYTES * 2);
+    let mut done = false;
+
+    while !done && !stop.load(Ordering::SeqCst) {
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(samples) => {
+                pending.extend_from_slice(&samples);
+
+                let mut blocks = pending.chunks_exact(BLOCK_BYTES);
+
+                for block in &mut blocks {
+                    if writer.block(block)? {
+                        done = true;
+                        break;
+                    }
+                }
+
+                let used = pending.len() - blocks.remainder().len();
+
+                pending.drain(..used);
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                if !radio.is_streaming().unwrap_or(false) {
+                    break;
+                }
+            },
+            Err(RecvTimeoutError::Disconnected) => break
+        }
+
+        if last.elapsed() >= Duration::from_secs(1) {
+            eprintln!("{} total sweeps completed, {:.2} sweeps/second", writer.sweeps, writer.sweeps as f64 / started.elapsed().as_secs_f64());
+            last = Instant::now();
+        }
+    }
+
+    if stop.load(Ordering::SeqCst) {
+        eprintln!("Caught signal, stopping");
+    }
+
+    radio.stop_rx()?;
+    writer.out.flush()?;
+
+    let dropped = dropped.load(Ordering::Relaxed);
+
+    if dropped > 0 {
+        eprintln!("{} sample buffers dropped because output couldn't keep up", dropped);
+    }
+
+    eprintln!("Exiting... {} total sweeps completed in {:.2} seconds", writer.sweeps, started.elapsed().as_secs_f64());
+
+    Ok( () )
+}
+
+fn run(options: Options) -> Result<(), Error> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler = stop.clone();
+
+    ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst))
+        .map_err(|e| Error::OTHER(format!("Error setting the signal handler: {}", e)))?;
+
+    if options.simulate {
+        // something to find a few MHz into the first range
+        let tone = options.ranges[0].0 as u64 * 1_000_000 + 3_300_000;
+        let mut simulator = SimulatedDevice::new().with_tone(tone, 0.3);
+
+        return sweep(&mut simulator, &options, &stop);
+    }
+
+    let mut hackrf = HackRF::new()?;
+    let mut device = hackrf.open_device(options.index)?;
+
+    info!("Using {} (firmware {}), sampling at {} S/s", device.board_id_name()?, device.version_string_read()?, SAMPLE_RATE);
+
+    sweep(&mut device, &options, &stop)
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(1);
+        }
+    };
+
+    // the logger writes to stdout, which carries the sweep unless -r is given
+    if options.path.is_some() {
+        simple_logger::init_with_level(log::Level::Info).unwrap();
+    }
+
+    if let Err(e) = run(options) {
+        eprintln!("hackrf-sweep: {}", e);
+        process::exit(1);
+    }
+}
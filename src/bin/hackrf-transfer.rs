@@ -0,0 +1,524 @@
+//! Records from or plays back to a HackRF, like hackrf_transfer, in any of the crate's file formats
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use rs_libhackrf::error::Error;
+use rs_libhackrf::formats::{IqReader, IqWriter, SampleFormat};
+use rs_libhackrf::hackrf::HackRF;
+use rs_libhackrf::radio::Radio;
+use rs_libhackrf::sigmf::{SigmfDatatype, SigmfSource, SigmfWriter};
+use rs_libhackrf::simulator::SimulatedDevice;
+use rs_libhackrf::soapy::baseband_filter;
+use rs_libhackrf::stream::{RxSink, TxSource};
+use rs_libhackrf::wav::{WavReader, WavWriter};
+
+const USAGE: &str = "Usage: hackrf-transfer -r <file> | -t <file> [options]
+    -r <file>        receive to a file, or - for stdout
+    -t <file>        transmit from a file, or - for stdin
+    -F <format>      file format: cs8, cu8, cs16, cf32, wav or sigmf
+                     (default from the extension; cs8 for stdin and stdout)
+    -f <frequency>   frequency in Hz (default 100000000, or the recording's)
+    -s <rate>        sample rate in Hz (default 10000000, or the recording's)
+    -b <bandwidth>   baseband filter bandwidth in Hz (default 3/4 of the sample rate)
+    -l <gain>        RX LNA gain, 0-40 dB in 8 dB steps (default 16)
+    -g <gain>        RX VGA gain, 0-62 dB in 2 dB steps (default 20)
+    -x <gain>        TX VGA gain, 0-47 dB (default 0)
+    -a <0|1>         RF amplifier (default 0)
+    -p <0|1>         antenna port power (default 0)
+    -n <samples>     stop after this many samples
+    -R               repeat the TX file until interrupted
+    -H               wait for a hardware sync trigger before streaming
+    -d <index>       device index (default 0)
+    -S               use the simulator instead of a HackRF
+    -h               show this help";
+
+/// Samples in one of the HackRF's USB transfers
+const TRANSFER_SAMPLES: u64 = 131_072;
+
+/// Transfers that can be in flight before a shortfall counts as dropped
+const TRANSFERS_IN_FLIGHT: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileKind {
+    Raw(SampleFormat),
+    Wav,
+    Sigmf
+}
+
+impl FileKind {
+    fn parse(name: &str) -> Result<FileKind, String> {
+        match name {
+            "wav" => Ok(FileKind::Wav),
+            "sigmf" => Ok(FileKind::Sigmf),
+            other => SampleFormat::from_extension(other).map(FileKind::Raw).ok_or_else(|| format!("Unknown format {}", other))
+        }
+    }
+
+    fn guess(path: &str) -> Result<FileKind, String> {
+        if path == "-" {
+            return Ok(FileKind::Raw(SampleFormat::Cs8));
+        }
+
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("sigmf-meta") | Some("sigmf-data") | Some("sigmf") => Ok(FileKind::Sigmf),
+            Some(ext) => FileKind::parse(&ext.to_ascii_lowercase()).map_err(|_| format!("Can't tell the format of {}; use -F", path)),
+            None => Err(format!("Can't tell the format of {}; use -F", path))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Receive(String),
+    Transmit(String)
+}
+
+struct Options {
+    mode: Mode,
+    kind: FileKind,
+    frequency: Option<u64>,
+    sample_rate: Option<f64>,
+    bandwidth: Option<u32>,
+    lna_gain: u32,
+    vga_gain: u32,
+    txvga_gain: u32,
+    amp: bool,
+    antenna: bool,
+    limit: Option<u64>,
+    repeat: bool,
+    hw_sync: bool,
+    index: i32,
+    simulate: bool
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut mode = None;
+        let mut format = None;
+        let mut options = Options {
+            mode: Mode::Receive(String::new()),
+            kind: FileKind::Raw(SampleFormat::Cs8),
+            frequency: None,
+            sample_rate: None,
+            bandwidth: None,
+            lna_gain: 16,
+            vga_gain: 20,
+            txvga_gain: 0,
+            amp: false,
+            antenna: false,
+            limit: None,
+            repeat: false,
+            hw_sync: false,
+            index: 0,
+            simulate: false
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+            match arg.as_str() {
+                "-r" => mode = Some(Mode::Receive(value("-r")?)),
+                "-t" => mode = Some(Mode::Transmit(value("-t")?)),
+                "-F" => format = Some(FileKind::parse(&value("-F")?)?),
+                "-f" => options.frequency = Some(parse_number(&value("-f")?)?),
+                "-s" => options.sample_rate = Some(parse_number(&value("-s")?)?),
+                "-b" => options.bandwidth = Some(parse_number(&value("-b")?)?),
+                "-l" => options.lna_gain = parse_number(&value("-l")?)?,
+                "-g" => options.vga_gain = parse_number(&value("-g")?)?,
+                "-x" => options.txvga_gain = parse_number(&value("-x")?)?,
+                "-a" => options.amp = parse_flag(&value("-a")?)?,
+                "-p" => options.antenna = parse_flag(&value("-p")?)?,
+                "-n" => options.limit = Some(parse_number(&value("-n")?)?),
+                "-R" => options.repeat = true,
+                "-H" => options.hw_sync = true,
+                "-d" => options.index = parse_number(&value("-d")?)?,
+                "-S" => options.simulate = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                other => return Err(format!("Unknown option {}", other))
+            }
+        }
+
+        options.mode = mode.ok_or_else(|| String::from("One of -r or -t is needed"))?;
+
+        let path = match options.mode {
+            Mode::Receive(ref path) | Mode::Transmit(ref path) => path.clone()
+        };
+
+        options.kind = match format {
+            Some(kind) => kind,
+            None => FileKind::guess(&path)?
+        };
+
+        if path == "-" && !matches!(options.kind, FileKind::Raw(_)) {
+            return Err(String::from("Only raw formats can be streamed through stdin or stdout"));
+        }
+
+        if options.repeat && (path == "-" || matches!(options.mode, Mode::Receive(_))) {
+            return Err(String::from("-R needs a file to transmit"));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid number: {}", value))
+}
+
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        other => Err(format!("Expected 0 or 1, not {}", other))
+    }
+}
+
+/// Counts accumulated between reports
+#[derive(Default)]
+struct Stats {
+    samples: u64,
+    power: f64,
+    first: Option<Instant>
+}
+
+impl Stats {
+    fn add(&mut self, values: impl Iterator<Item = f32>) {
+        let mut count = 0;
+
+        self.first.get_or_insert_with(Instant::now);
+
+        for v in values {
+            self.power += (v * v) as f64;
+            count += 1;
+        }
+
+        self.samples += count / 2;
+    }
+}
+
+/// Counts what passes through a sink and ends the stream after the sample limit
+struct CountedSink<S> {
+    sink: S,
+    stats: Arc<Mutex<Stats>>,
+    remaining: Option<u64>
+}
+
+impl <S: RxSink> RxSink for CountedSink<S> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let samples = match self.remaining {
+            Some(remaining) => &samples[..samples.len().min(remaining as usize * 2)],
+            None => samples
+        };
+
+        self.stats.lock().unwrap().add(samples.iter().copied());
+        self.sink.write(samples)?;
+
+        if let Some(ref mut remaining) = self.remaining {
+            *remaining -= samples.len() as u64 / 2;
+
+            if *remaining == 0 {
+                self.sink.flush()?;
+                return Err(Error::STREAMING_EXIT_CALLED(String::from("Sample limit reached")));
+            }
+        }
+
+        Ok( () )
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.sink.flush()
+    }
+}
+
+/// Counts what passes through a source and ends the stream after the sample limit
+struct CountedSource<S> {
+    source: S,
+    stats: Arc<Mutex<Stats>>,
+    remaining: Option<u64>
+}
+
+impl <S: TxSource> TxSource for CountedSource<S> {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        let wanted = match self.remaining {
+            Some(remaining) => buffer.len().min(remaining as usize * 2),
+            None => buffer.len()
+        };
+        let count = self.source.read(&mut buffer[..wanted])?;
+
+        self.stats.lock().unwrap().add(buffer[..count].iter().map(|v| *v as f32 / 128.0));
+
+        if let Some(ref mut remaining) = self.remaining {
+            *remaining -= count as u64 / 2;
+        }
+
+        Ok(count)
+    }
+}
+
+/// Where received samples go
+enum Output {
+    Raw(IqWriter<Box<dyn Write + Send>>),
+    Wav(WavWriter),
+    Sigmf(SigmfWriter)
+}
+
+impl Output {
+    fn create<R: Radio>(path: &str, kind: FileKind, radio: &R) -> Result<Output, Error> {
+        Ok(match kind {
+            FileKind::Raw(format) => {
+                let inner :Box<dyn Write + Send> = if path == "-" {
+                    Box::new(io::BufWriter::new(io::stdout()))
+                } else {
+                    Box::new(io::BufWriter::new(File::create(path)?))
+                };
+
+                Output::Raw(IqWriter::new(inner, format))
+            },
+            FileKind::Wav => Output::Wav(WavWriter::with_tuning(path, SampleFormat::Cu8, radio.tuning_handle())?),
+            FileKind::Sigmf => {
+                let hw = format!("{}, firmware {}", radio.board_id_name()?, radio.version_string_read()?);
+
+                Output::Sigmf(SigmfWriter::with_tuning(path, SigmfDatatype::Ci8, Some(hw), radio.tuning_handle())?)
+            }
+        })
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        match self {
+            Output::Raw(writer) => writer.flush(),
+            Output::Wav(writer) => writer.finish(),
+            Output::Sigmf(writer) => writer.finish()
+        }
+    }
+}
+
+impl RxSink for Output {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        match self {
+            Output::Raw(writer) => writer.write(samples),
+            Output::Wav(writer) => writer.write(samples),
+            Output::Sigmf(writer) => writer.write(samples)
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Output::Raw(writer) => writer.flush(),
+            Output::Wav(writer) => writer.flush(),
+            Output::Sigmf(writer) => writer.flush()
+        }
+    }
+}
+
+/// Plays a raw recording over and over
+struct Looping(IqReader<BufReader<File>>);
+
+impl TxSource for Looping {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        let mut filled = self.0.read(buffer)?;
+
+        while filled < buffer.len() {
+            self.0.rewind()?;
+
+            let count = self.0.read(&mut buffer[filled..])?;
+
+            if count == 0 {
+                break;
+            }
+
+            filled += count;
+        }
+
+        Ok(filled)
+    }
+}
+
+/// The file to transmit, with the frequency and sample rate it was recorded at if it says
+type Input = (Box<dyn TxSource + Send>, Option<u64>, Option<f64>);
+
+fn open_input(path: &str, kind: FileKind, repeat: bool) -> Result<Input, Error> {
+    Ok(match kind {
+        FileKind::Raw(format) if path == "-" => (Box::new(IqReader::new(io::stdin(), format)), None, None),
+        FileKind::Raw(format) if repeat => (Box::new(Looping(IqReader::open_with_format(path, format)?)), None, None),
+        FileKind::Raw(format) => (Box::new(IqReader::open_with_format(path, format)?), None, None),
+        FileKind::Wav => {
+            let reader = WavReader::open(path)?.with_loop(repeat);
+            let frequency = reader.auxi().map(|auxi| auxi.center_freq).filter(|freq| *freq != 0);
+            let rate = reader.sample_rate() as f64;
+
+            (Box::new(reader), frequency, Some(rate))
+        },
+        FileKind::Sigmf => {
+            let source = SigmfSource::open(path)?.with_loop(repeat);
+            let frequency = source.frequency().map(|freq| freq.round() as u64);
+            let rate = source.metadata().sample_rate;
+
+            (Box::new(source), frequency, Some(rate))
+        }
+    })
+}
+
+fn configure<R: Radio>(radio: &R, options: &Options, frequency: Option<u64>, sample_rate: Option<f64>) -> Result<(), Error> {
+    let frequency = options.frequency.or(frequency).unwrap_or(100_000_000);
+    let sample_rate = options.sample_rate.or(sample_rate).unwrap_or(10e6);
+
+    radio.set_sample_rate(sample_rate)?;
+    radio.set_baseband_filter_bandwidth(options.bandwidth.unwrap_or_else(|| baseband_filter(sample_rate * 0.75)))?;
+    radio.set_freq(frequency)?;
+    radio.set_amp_enable(options.amp)?;
+    radio.set_antenna_enable(options.antenna)?;
+
+    match options.mode {
+        Mode::Receive(_) => {
+            radio.set_lna_gain(options.lna_gain)?;
+            radio.set_vga_gain(options.vga_gain)?;
+        },
+        Mode::Transmit(_) => radio.set_txvga_gain(options.txvga_gain)?
+    }
+
+    info!("{} Hz, {} S/s, filter {} Hz", frequency, sample_rate, radio.tuning().baseband_filter_hz.unwrap_or(0));
+
+    Ok( () )
+}
+
+/// Prints a line of hackrf_transfer-style statistics for the last interval
+fn report(stats: &Mutex<Stats>, interval: Duration, expected_rate: f64, total: &mut u64) {
+    let (samples, power, first) = {
+        let mut stats = stats.lock().unwrap();
+        let taken = (stats.samples, stats.power, stats.first);
+
+        stats.samples = 0;
+        stats.power = 0.0;
+        taken
+    };
+    let bytes = samples as f64 * 2.0;
+    let seconds = interval.as_secs_f64();
+    let power = if samples > 0 { 10.0 * (power / (samples as f64 * 2.0)).log10() } else { f64::NEG_INFINITY };
+
+    *total += samples;
+
+    // anything the radio should have produced by now beyond what's in flight has been lost
+    let expected = first.map(|first| first.elapsed().as_secs_f64() * expected_rate).unwrap_or(0.0) as u64;
+    let dropped = expected.saturating_sub(*total) / TRANSFER_SAMPLES;
+
+    eprintln!("{:4.1} MiB / {:5.3} sec = {:4.1} MiB/second, average power {:5.1} dBfs, {} transfers dropped",
+              bytes / 1048576.0, seconds, bytes / 1048576.0 / seconds, power, dropped.saturating_sub(TRANSFERS_IN_FLIGHT));
+}
+
+fn transfer<R: Radio>(radio: &mut R, options: &Options, stop: &AtomicBool) -> Result<(), Error> {
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let mut output = None;
+
+    match options.mode {
+        Mode::Receive(ref path) => {
+            configure(radio, options, None, None)?;
+
+            let shared = Arc::new(Mutex::new(Output::create(path, options.kind, radio)?));
+
+            output = Some(shared.clone());
+            radio.start_rx_sink(Box::new(CountedSink { sink: shared, stats: stats.clone(), remaining: options.limit }))?;
+        },
+        Mode::Transmit(ref path) => {
+            let (source, frequency, sample_rate) = open_input(path, options.kind, options.repeat)?;
+
+            configure(radio, options, frequency, sample_rate)?;
+            radio.start_tx_source(Box::new(CountedSource { source, stats: stats.clone(), remaining: options.limit }))?;
+        }
+    }
+
+    let rate = radio.tuning().sample_rate.unwrap_or(0.0);
+    let started = Instant::now();
+    let mut last = Instant::now();
+    let mut total = 0;
+
+    while !stop.load(Ordering::SeqCst) && radio.is_streaming().unwrap_or(false) {
+        thread::sleep(Duration::from_millis(50));
+
+        if last.elapsed() >= Duration::from_secs(1) {
+            report(&stats, last.elapsed(), rate, &mut total);
+            last = Instant::now();
+        }
+    }
+
+    if stop.load(Ordering::SeqCst) {
+        eprintln!("Caught signal, stopping");
+    }
+
+    match options.mode {
+        Mode::Receive(_) => radio.stop_rx()?,
+        Mode::Transmit(_) => radio.stop_tx()?
+    }
+
+    total += stats.lock().unwrap().samples;
+
+    if let Some(output) = output {
+        output.lock().unwrap().finish()?;
+    }
+
+    eprintln!("{} samples in {:.3} seconds", total, started.elapsed().as_secs_f64());
+
+    Ok( () )
+}
+
+fn run(options: Options) -> Result<(), Error> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler = stop.clone();
+
+    ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst))
+        .map_err(|e| Error::OTHER(format!("Error setting the signal handler: {}", e)))?;
+
+    if options.simulate {
+        if options.hw_sync {
+            warn!("The simulator has no hardware sync input; ignoring -H");
+        }
+
+        let mut simulator = SimulatedDevice::new().with_tone(options.frequency.unwrap_or(100_000_000) + 100_000, 0.3);
+
+        return transfer(&mut simulator, &options, &stop);
+    }
+
+    let mut hackrf = HackRF::new()?;
+    let mut device = hackrf.open_device(options.index)?;
+
+    info!("Using {} (firmware {})", device.board_id_name()?, device.version_string_read()?);
+
+    if options.hw_sync {
+        device.enable_hardware_sync(true)?;
+        info!("Waiting for the hardware sync trigger");
+    }
+
+    transfer(&mut device, &options, &stop)
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(1);
+        }
+    };
+
+    // the logger writes to stdout, which may be carrying samples
+    if options.mode != Mode::Receive(String::from("-")) {
+        simple_logger::init_with_level(log::Level::Info).unwrap();
+    }
+
+    if let Err(e) = run(options) {
+        eprintln!("hackrf-transfer: {}", e);
+        process::exit(1);
+    }
+}
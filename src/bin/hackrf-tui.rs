@@ -0,0 +1,722 @@
+//! A terminal spectrum analyzer: a live spectrum plot over a scrolling waterfall, usable over SSH.
+//!
+//! Spans up to 20 MHz are a single tuning of the RX stream; anything wider is swept with
+//! `init_sweep`. Without a HackRF attached it runs against the simulator.
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, BufWriter, Stdout, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use rs_libhackrf::error::Error;
+use rs_libhackrf::hackrf::HackRF;
+use rs_libhackrf::radio::Radio;
+use rs_libhackrf::simulator::SimulatedDevice;
+use rs_libhackrf::soapy::baseband_filter;
+use rs_libhackrf::spectrum::{RxSpectrum, SpectrumFrame, SweepSpectrum};
+use rs_libhackrf::stream::RxSink;
+use rs_libhackrf::sweep::{SweepPlan, MAX_FREQ_MHZ, SAMPLE_RATE};
+
+const USAGE: &str = "Usage: hackrf-tui [options]
+    -f <frequency>   center frequency in Hz (default 100000000)
+    -s <span>        span in Hz; over 20000000 sweeps (default 10000000)
+    -l <gain>        RX LNA gain, 0-40 dB in 8 dB steps (default 16)
+    -g <gain>        RX VGA gain, 0-62 dB in 2 dB steps (default 20)
+    -a <0|1>         RF amplifier (default 0)
+    -d <index>       device index (default 0)
+    -S               use the simulator even if a HackRF is attached
+    -h               show this help";
+
+const KEYS: &str = "←/→ tune  [/] span  f/s enter freq/span  l/L lna  g/G vga  a amp  r/R ref  p peak hold  m marker  ,/. move  n peak  q quit";
+
+/// The widest span one tuning covers; wider spans are swept
+const MAX_RX_SPAN: f64 = SAMPLE_RATE;
+
+/// Lowest sample rate used for narrow spans, which are cropped from it
+const MIN_SAMPLE_RATE: f64 = 4e6;
+
+const MIN_SPAN: f64 = 500e3;
+const MAX_SPAN: f64 = MAX_FREQ_MHZ as f64 * 1e6;
+
+const RX_FFT_SIZE: usize = 2048;
+const RX_AVERAGING: usize = 4;
+const FRAME_RATE: f64 = 25.0;
+
+/// Dynamic range shown between the reference level and the bottom of the plot
+const RANGE_DB: f32 = 80.0;
+
+/// Columns taken by the dB axis
+const AXIS_WIDTH: u16 = 6;
+
+const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+struct Options {
+    center: u64,
+    span: f64,
+    lna_gain: u32,
+    vga_gain: u32,
+    amp: bool,
+    index: i32,
+    simulate: bool
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            center: 100_000_000,
+            span: 10e6,
+            lna_gain: 16,
+            vga_gain: 20,
+            amp: false,
+            index: 0,
+            simulate: false
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+            match arg.as_str() {
+                "-f" => options.center = parse_number(&value("-f")?)?,
+                "-s" => options.span = parse_number(&value("-s")?)?,
+                "-l" => options.lna_gain = parse_number(&value("-l")?)?,
+                "-g" => options.vga_gain = parse_number(&value("-g")?)?,
+                "-a" => options.amp = parse_flag(&value("-a")?)?,
+                "-d" => options.index = parse_number(&value("-d")?)?,
+                "-S" => options.simulate = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                other => return Err(format!("Unknown option {}", other))
+            }
+        }
+
+        if !(MIN_SPAN..=MAX_SPAN).contains(&options.span) {
+            return Err(format!("Span must be between {} and {} Hz", MIN_SPAN, MAX_SPAN));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid number: {}", value))
+}
+
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        other => Err(format!("Expected 0 or 1, not {}", other))
+    }
+}
+
+/// How the radio is producing spectra
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Rx { sample_rate: f64 },
+    Sweep(SweepPlan)
+}
+
+/// What the user has asked to see
+struct View {
+    center: u64,
+    span: f64,
+    lna_gain: u32,
+    vga_gain: u32,
+    amp: bool,
+    reference: f32,
+    peak_hold: bool,
+    marker: Option<usize>
+}
+
+impl View {
+    /// The frequencies shown, low to high
+    fn window(&self) -> (f64, f64) {
+        (self.center as f64 - self.span / 2.0, self.center as f64 + self.span / 2.0)
+    }
+
+    fn mode(&self) -> Result<Mode, Error> {
+        if self.span <= MAX_RX_SPAN {
+            return Ok(Mode::Rx { sample_rate: self.span.max(MIN_SAMPLE_RATE) });
+        }
+
+        let (low, high) = self.window();
+        let low = (low / 1e6).floor().max(0.0) as u16;
+        let high = ((high / 1e6).ceil() as u16).min(MAX_FREQ_MHZ).max(low + 1);
+        // about a thousand bins across the span
+        let bin_width = (self.span / 1000.0).clamp(5e3, 5e6);
+
+        SweepPlan::new(&[(low, high)])?.with_bin_width(bin_width).map(Mode::Sweep)
+    }
+
+    fn tune(&mut self, center: f64) {
+        let lowest = self.span / 2.0;
+        let highest = MAX_SPAN - self.span / 2.0;
+
+        self.center = center.max(lowest).min(highest.max(lowest)).round() as u64;
+    }
+
+    fn set_span(&mut self, span: f64) {
+        self.span = span.clamp(MIN_SPAN, MAX_SPAN);
+        self.tune(self.center as f64);
+    }
+}
+
+/// Starts `mode` streaming, sending its spectra to `frames`
+fn start<R: Radio>(radio: &mut R, view: &View, mode: &Mode, frames: &Sender<SpectrumFrame>) -> Result<(), Error> {
+    let frames = frames.clone();
+    let output = move |frame| frames.send(frame)
+        .map_err(|_| Error::STREAMING_EXIT_CALLED(String::from("Display has shut down")));
+
+    match *mode {
+        Mode::Rx { sample_rate } => {
+            retune(radio, view, sample_rate)?;
+
+            let sink = RxSpectrum::new(RX_FFT_SIZE, RX_AVERAGING, radio.tuning_handle(), output)?
+                .with_frame_rate(FRAME_RATE);
+
+            radio.start_rx_sink(Box::new(sink))
+        },
+        Mode::Sweep(ref plan) => {
+            let sink :Box<dyn RxSink + Send> = Box::new(SweepSpectrum::new(plan, 1, output)?);
+
+            plan.start(radio, sink)
+        }
+    }
+}
+
+/// Moves a running RX stream, which needs no restart
+fn retune<R: Radio>(radio: &R, view: &View, sample_rate: f64) -> Result<(), Error> {
+    radio.set_sample_rate(sample_rate)?;
+    radio.set_baseband_filter_bandwidth(baseband_filter(sample_rate * 0.75))?;
+    radio.set_freq(view.center)
+}
+
+/// A whole frame reduced to one level per column of the display
+fn columns(frame: &SpectrumFrame, window: (f64, f64), width: usize) -> Vec<f32> {
+    let bin_width = frame.bin_width();
+    let bins = frame.power_db.len() as i64;
+    let column_width = (window.1 - window.0) / width as f64;
+
+    (0..width).map(|x| {
+        let low = window.0 + x as f64 * column_width;
+        let first = ((low - frame.freq_start) / bin_width).floor() as i64;
+        let last = (((low + column_width) - frame.freq_start) / bin_width).ceil() as i64;
+        let last = last.max(first + 1);
+
+        (first.max(0)..last.min(bins))
+            .map(|bin| frame.power_db[bin as usize])
+            .filter(|db| !db.is_nan())
+            .fold(f32::NAN, f32::max)
+    }).collect()
+}
+
+/// Dark blue through cyan, yellow and red to white, as a 256-colour palette entry
+fn heat(level: f32) -> Color {
+    const STOPS: [(f32, f32, f32); 6] = [(0.0, 0.0, 0.1), (0.0, 0.0, 0.8), (0.0, 0.8, 0.8), (0.9, 0.9, 0.0), (0.9, 0.0, 0.0), (1.0, 1.0, 1.0)];
+
+    if level.is_nan() {
+        return Color::AnsiValue(16);
+    }
+
+    let position = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position.floor() as usize).min(STOPS.len() - 2);
+    let t = position - index as f32;
+    let (a, b) = (STOPS[index], STOPS[index + 1]);
+    let cube = |from: f32, to: f32| ((from + (to - from) * t) * 5.0).round() as u8;
+
+    Color::AnsiValue(16 + 36 * cube(a.0, b.0) + 6 * cube(a.1, b.1) + cube(a.2, b.2))
+}
+
+fn format_freq(hz: f64) -> String {
+    if hz.abs() >= 1e9 {
+        format!("{:.4} GHz", hz / 1e9)
+    } else {
+        format!("{:.3} MHz", hz / 1e6)
+    }
+}
+
+/// A value being typed in, in MHz
+enum Prompt {
+    Frequency(String),
+    Span(String)
+}
+
+/// Everything on screen that outlives a frame
+struct Screen {
+    out: BufWriter<Stdout>,
+    width: u16,
+    height: u16,
+    current: Vec<f32>,
+    peak: Vec<f32>,
+    history: VecDeque<Vec<f32>>,
+    message: String
+}
+
+impl Screen {
+    fn new() -> Result<Screen, Error> {
+        let (width, height) = terminal::size()?;
+        let mut out = BufWriter::new(io::stdout());
+
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        Ok(Screen { out, width, height, current: Vec::new(), peak: Vec::new(), history: VecDeque::new(), message: String::new() })
+    }
+
+    fn plot_width(&self) -> usize {
+        self.width.saturating_sub(AXIS_WIDTH) as usize
+    }
+
+    /// Rows for the plot and for the waterfall, which share what the status and key lines leave
+    fn layout(&self) -> (u16, u16) {
+        let free = self.height.saturating_sub(4);
+        let plot = (free * 2 / 5).max(3).min(free);
+
+        (plot, free - plot)
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.clear();
+    }
+
+    /// Forgets what's been shown, after the window onto the spectrum moves
+    fn clear(&mut self) {
+        self.current.clear();
+        self.peak.clear();
+        self.history.clear();
+    }
+
+    fn add(&mut self, frame: &SpectrumFrame, view: &View) {
+        let levels = columns(frame, view.window(), self.plot_width());
+
+        if self.peak.len() != levels.len() {
+            self.peak = levels.clone();
+        }
+
+        for (peak, level) in self.peak.iter_mut().zip(levels.iter()) {
+            // a NaN peak is replaced, a NaN level never is
+            if peak.is_nan() || *level > *peak {
+                *peak = *level;
+            }
+        }
+
+        let (_, waterfall) = self.layout();
+
+        self.history.push_front(levels.clone());
+        self.history.truncate(waterfall as usize * 2);
+        self.current = levels;
+    }
+
+    /// Where `db` sits between the bottom of the plot and the reference level, 0 to 1
+    fn scale(view: &View, db: f32) -> f32 {
+        (db - (view.reference - RANGE_DB)) / RANGE_DB
+    }
+
+    fn draw(&mut self, view: &View, mode: &Mode, board: &str, prompt: &Option<Prompt>) -> Result<(), Error> {
+        let (plot, waterfall) = self.layout();
+        let width = self.plot_width();
+        let (low, high) = view.window();
+
+        let source = match *mode {
+            Mode::Rx { sample_rate } => format!("RX {} S/s, {}-point FFT", sample_rate, RX_FFT_SIZE),
+            Mode::Sweep(ref plan) => format!("sweep {:?} MHz, {:.0} Hz bins", plan.ranges(), plan.bin_width())
+        };
+        let status = format!(" {} | center {} span {} | {} | LNA {} dB VGA {} dB amp {} | ref {} dB",
+                             board, format_freq(view.center as f64), format_freq(view.span), source,
+                             view.lna_gain, view.vga_gain, if view.amp { "on" } else { "off" }, view.reference);
+
+        queue!(self.out, MoveTo(0, 0), SetBackgroundColor(Color::DarkBlue), SetForegroundColor(Color::White),
+               Print(fit(&status, self.width as usize)), ResetColor)?;
+
+        let second = match *prompt {
+            Some(Prompt::Frequency(ref typed)) => format!(" Center frequency (MHz): {}_", typed),
+            Some(Prompt::Span(ref typed)) => format!(" Span (MHz): {}_", typed),
+            None => match view.marker.filter(|x| *x < self.current.len()) {
+                Some(x) => {
+                    let freq = low + (x as f64 + 0.5) * (high - low) / width as f64;
+
+                    format!(" Marker {} {:.1} dB{}  {}", format_freq(freq), self.current[x],
+                            if view.peak_hold { format!(" (peak {:.1} dB)", self.peak[x]) } else { String::new() }, self.message)
+                },
+                None => format!(" {}", self.message)
+            }
+        };
+
+        queue!(self.out, MoveTo(0, 1), SetForegroundColor(Color::Yellow), Print(fit(&second, self.width as usize)), ResetColor)?;
+
+        // the plot, in eighths of a row
+        for row in 0..plot {
+            let from_bottom = (plot - 1 - row) as i32;
+            let label = if row % 3 == 0 {
+                format!("{:>5.0} ", view.reference - RANGE_DB * row as f32 / plot as f32)
+            } else {
+                String::from("      ")
+            };
+
+            queue!(self.out, MoveTo(0, 2 + row), SetForegroundColor(Color::DarkGrey), Print(label))?;
+
+            let mut color = None;
+
+            for x in 0..width {
+                let eighths = |db: f32| if db.is_nan() { 0 } else { (Screen::scale(view, db).clamp(0.0, 1.0) * plot as f32 * 8.0).round() as i32 };
+                let level = self.current.get(x).map_or(0, |db| eighths(*db));
+                let fill = (level - from_bottom * 8).clamp(0, 8) as usize;
+                let held = view.peak_hold && self.peak.get(x).is_some_and(|db| {
+                    let peak = eighths(*db);
+
+                    peak > level && (peak - 1) / 8 == from_bottom
+                });
+                let (symbol, cell) = if held {
+                    ('▔', Color::Red)
+                } else if view.marker == Some(x) {
+                    (if fill == 0 { '│' } else { BARS[fill] }, Color::Magenta)
+                } else {
+                    (BARS[fill], Color::Green)
+                };
+
+                if color != Some(cell) {
+                    queue!(self.out, SetForegroundColor(cell))?;
+                    color = Some(cell);
+                }
+
+                queue!(self.out, Print(symbol))?;
+            }
+        }
+
+        // frequency axis
+        let left = format_freq(low);
+        let middle = format_freq(view.center as f64);
+        let right = format_freq(high);
+        let mut axis = vec![' '; width];
+
+        for (text, at) in &[(left, 0), (middle.clone(), (width / 2).saturating_sub(middle.len() / 2)), (right.clone(), width.saturating_sub(right.len()))] {
+            for (i, c) in text.chars().enumerate() {
+                if let Some(slot) = axis.get_mut(at + i) {
+                    *slot = c;
+                }
+            }
+        }
+
+        queue!(self.out, MoveTo(0, 2 + plot), SetForegroundColor(Color::Grey), Print("      "), Print(axis.into_iter().collect::<String>()))?;
+
+        // the waterfall, newest at the top, two spectra to a row with half blocks
+        let history = &self.history;
+
+        for row in 0..waterfall {
+            queue!(self.out, MoveTo(0, 3 + plot + row), ResetColor, Print("      "))?;
+
+            for x in 0..width {
+                let level = |line: usize| history.get(line).and_then(|levels| levels.get(x))
+                    .map_or(f32::NAN, |db| if db.is_nan() { f32::NAN } else { Screen::scale(view, *db) });
+                let line = row as usize * 2;
+
+                queue!(self.out, SetForegroundColor(heat(level(line))), SetBackgroundColor(heat(level(line + 1))), Print('▀'))?;
+            }
+        }
+
+        queue!(self.out, MoveTo(0, self.height.saturating_sub(1)), ResetColor, SetForegroundColor(Color::DarkGrey),
+               Print(fit(KEYS, self.width as usize)), ResetColor)?;
+        self.out.flush()?;
+
+        Ok( () )
+    }
+
+    fn restore(&mut self) {
+        let _ = execute!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// `text` cut or padded to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut line :String = text.chars().take(width).collect();
+    let length = line.chars().count();
+
+    line.push_str(&" ".repeat(width - length));
+    line
+}
+
+/// What a key asks for
+enum Action {
+    Nothing,
+    Redraw,
+    /// The window moved; RX can follow by retuning, sweeps restart
+    Retune,
+    Gains,
+    Quit
+}
+
+fn handle_key(key: KeyEvent, view: &mut View, current: &[f32], prompt: &mut Option<Prompt>) -> Action {
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        return Action::Quit;
+    }
+
+    if let Some(mut typing) = prompt.take() {
+        let typed = match typing {
+            Prompt::Frequency(ref mut typed) | Prompt::Span(ref mut typed) => typed
+        };
+
+        match key.code {
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '.' => typed.push(c),
+            KeyCode::Backspace => { typed.pop(); },
+            KeyCode::Esc => return Action::Redraw,
+            KeyCode::Enter => {
+                let mhz = match typed.parse::<f64>() {
+                    Ok(mhz) if mhz >= 0.0 => mhz,
+                    _ => return Action::Redraw
+                };
+
+                match typing {
+                    Prompt::Frequency(_) => view.tune(mhz * 1e6),
+                    Prompt::Span(_) => view.set_span(mhz * 1e6)
+                }
+
+                return Action::Retune;
+            },
+            _ => {}
+        }
+
+        *prompt = Some(typing);
+        return Action::Redraw;
+    }
+
+    let step = view.span / 10.0;
+    let last_column = current.len().saturating_sub(1);
+
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+        KeyCode::Left => { view.tune(view.center as f64 - step); Action::Retune },
+        KeyCode::Right => { view.tune(view.center as f64 + step); Action::Retune },
+        KeyCode::Char('[') => { view.set_span(view.span / 2.0); Action::Retune },
+        KeyCode::Char(']') => { view.set_span(view.span * 2.0); Action::Retune },
+        KeyCode::Char('f') => { *prompt = Some(Prompt::Frequency(String::new())); Action::Redraw },
+        KeyCode::Char('s') => { *prompt = Some(Prompt::Span(String::new())); Action::Redraw },
+        KeyCode::Char('l') => { view.lna_gain = view.lna_gain.saturating_sub(8); Action::Gains },
+        KeyCode::Char('L') => { view.lna_gain = (view.lna_gain + 8).min(40); Action::Gains },
+        KeyCode::Char('g') => { view.vga_gain = view.vga_gain.saturating_sub(2); Action::Gains },
+        KeyCode::Char('G') => { view.vga_gain = (view.vga_gain + 2).min(62); Action::Gains },
+        KeyCode::Char('a') => { view.amp = !view.amp; Action::Gains },
+        KeyCode::Char('r') => { view.reference -= 5.0; Action::Redraw },
+        KeyCode::Char('R') => { view.reference += 5.0; Action::Redraw },
+        KeyCode::Char('p') => { view.peak_hold = !view.peak_hold; Action::Redraw },
+        KeyCode::Char('m') => {
+            view.marker = match view.marker {
+                Some(_) => None,
+                None => Some(current.len() / 2)
+            };
+            Action::Redraw
+        },
+        KeyCode::Char(',') => { view.marker = view.marker.map(|x| x.saturating_sub(1)); Action::Redraw },
+        KeyCode::Char('.') => { view.marker = view.marker.map(|x| (x + 1).min(last_column)); Action::Redraw },
+        KeyCode::Char('n') => { view.marker = peak_column(current); Action::Redraw },
+        _ => Action::Nothing
+    }
+}
+
+/// The column with the strongest signal, for jumping the marker to
+fn peak_column(levels: &[f32]) -> Option<usize> {
+    levels.iter().enumerate()
+        .filter(|(_, db)| !db.is_nan())
+        .fold(None, |best: Option<(usize, f32)>, (x, db)| match best {
+            Some((_, top)) if top >= *db => best,
+            _ => Some((x, *db))
+        })
+        .map(|(x, _)| x)
+}
+
+fn set_gains<R: Radio>(radio: &R, view: &View) -> Result<(), Error> {
+    radio.set_lna_gain(view.lna_gain)?;
+    radio.set_vga_gain(view.vga_gain)?;
+    radio.set_amp_enable(view.amp)
+}
+
+fn run_ui<R: Radio>(radio: &mut R, options: &Options, board: &str, stop: &AtomicBool) -> Result<(), Error> {
+    let mut view = View {
+        center: options.center,
+        span: options.span,
+        lna_gain: options.lna_gain,
+        vga_gain: options.vga_gain,
+        amp: options.amp,
+        reference: -10.0,
+        peak_hold: false,
+        marker: None
+    };
+
+    view.tune(options.center as f64);
+
+    let (sender, frames): (Sender<SpectrumFrame>, Receiver<SpectrumFrame>) = mpsc::channel();
+    let mut screen = Screen::new()?;
+    let mut mode = view.mode()?;
+    // the center and span `mode` is producing
+    let mut tuned = (view.center, view.span);
+    let mut prompt = None;
+
+    set_gains(radio, &view)?;
+    start(radio, &view, &mode, &sender)?;
+
+    while !stop.load(Ordering::SeqCst) {
+        let mut redraw = false;
+
+        while event::poll(Duration::from_millis(10))? {
+            let action = match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => handle_key(key, &mut view, &screen.current, &mut prompt),
+                Event::Resize(width, height) => {
+                    screen.resize(width, height);
+                    Action::Redraw
+                },
+                _ => Action::Nothing
+            };
+
+            // a problem with a setting goes on screen rather than ending the session
+            let result = match action {
+                Action::Nothing => Ok( () ),
+                Action::Redraw => {
+                    redraw = true;
+                    Ok( () )
+                },
+                Action::Quit => return stop_streaming(radio),
+                Action::Gains => {
+                    redraw = true;
+                    set_gains(radio, &view)
+                },
+                Action::Retune => {
+                    redraw = true;
+                    screen.clear();
+
+                    let result = view.mode().and_then(|next| {
+                        match (&mode, &next) {
+                            (Mode::Rx { .. }, Mode::Rx { sample_rate }) => retune(radio, &view, *sample_rate)?,
+                            _ => {
+                                radio.stop_rx()?;
+                                start(radio, &view, &next, &sender)?;
+                            }
+                        }
+
+                        mode = next;
+                        Ok( () )
+                    });
+
+                    match result {
+                        Ok(()) => tuned = (view.center, view.span),
+                        Err(_) => {
+                            // go back to what was showing, so the radio isn't left stopped or half
+                            // retuned under a view it isn't producing
+                            view.center = tuned.0;
+                            view.span = tuned.1;
+
+                            match mode {
+                                Mode::Rx { sample_rate } if radio.is_streaming()? => retune(radio, &view, sample_rate)?,
+                                _ => {
+                                    stop_streaming(radio)?;
+                                    start(radio, &view, &mode, &sender)?;
+                                }
+                            }
+                        }
+                    }
+
+                    result
+                }
+            };
+
+            screen.message = match result {
+                Ok(()) => String::new(),
+                Err(e) => e.to_string()
+            };
+        }
+
+        while let Ok(frame) = frames.try_recv() {
+            screen.add(&frame, &view);
+            redraw = true;
+        }
+
+        if redraw {
+            screen.draw(&view, &mode, board, &prompt)?;
+        }
+    }
+
+    stop_streaming(radio)
+}
+
+fn stop_streaming<R: Radio>(radio: &mut R) -> Result<(), Error> {
+    if radio.is_streaming().unwrap_or(false) {
+        radio.stop_rx()?;
+    }
+
+    Ok( () )
+}
+
+fn simulator(options: &Options) -> SimulatedDevice {
+    // a few things to look at around the default tuning and in the ISM bands
+    SimulatedDevice::new()
+        .with_tone(options.center + 1_200_000, 0.3)
+        .with_tone(options.center - 2_700_000, 0.05)
+        .with_tone(433_920_000, 0.2)
+        .with_tone(2_437_000_000, 0.1)
+        .with_noise(0.01)
+}
+
+fn run(options: Options) -> Result<(), Error> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler = stop.clone();
+
+    ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst))
+        .map_err(|e| Error::OTHER(format!("Error setting the signal handler: {}", e)))?;
+
+    if options.simulate {
+        return run_ui(&mut simulator(&options), &options, "Simulator", &stop);
+    }
+
+    let mut hackrf = match HackRF::new() {
+        Ok(hackrf) => hackrf,
+        Err(e) => return fall_back(&options, e, &stop)
+    };
+
+    match hackrf.open_device(options.index) {
+        Ok(mut device) => {
+            let board = device.board_id_name()?;
+
+            run_ui(&mut device, &options, &board, &stop)
+        },
+        Err(e) => fall_back(&options, e, &stop)
+    }
+}
+
+fn fall_back(options: &Options, e: Error, stop: &AtomicBool) -> Result<(), Error> {
+    eprintln!("No HackRF ({}); using the simulator", e);
+    run_ui(&mut simulator(options), options, "Simulator", stop)
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("hackrf-tui: {}", e);
+        process::exit(1);
+    }
+}
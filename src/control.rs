@@ -0,0 +1,412 @@
+//! An HTTP/JSON API for configuring a radio remotely, with a Server-Sent Events feed of changes.
+//!
+//! | Endpoint                     | Does                                                          |
+//! |------------------------------|---------------------------------------------------------------|
+//! | `GET /devices`               | the HackRFs attached to the host                              |
+//! | `GET /board`                 | board ID, board name and firmware version                     |
+//! | `GET /settings`              | every setting, as from `radio::settings_json`                 |
+//! | `PUT`/`PATCH /settings`      | applies an object of settings, as `radio::apply_settings` does |
+//! | `GET`/`PUT /settings/<name>` | one setting; the body of a `PUT` is the bare JSON value        |
+//! | `GET /streaming`             | whether the radio is streaming                                |
+//! | `GET /events`                | `text/event-stream` of `state` events whenever anything changes |
+//!
+//! With a token set, requests need `Authorization: Bearer <token>`, or `?token=<token>` for browsers'
+//! `EventSource`, which can't send headers.
+
+use std::io::{self, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+use crate::error::Error;
+use crate::http::{Request, Response};
+use crate::radio::{self, Radio};
+
+/// How often an idle event stream gets a comment, so dead clients are noticed
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// How long a client may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Work for the thread that owns the radio
+enum Job {
+    Request(Request, Sender<Response>),
+    Subscribe(Sender<String>)
+}
+
+/// Name of a board from its USB product ID, as listed by `HackRF::get_device_list`
+pub fn usb_board_name(usb_board_id: u32) -> &'static str {
+    match usb_board_id {
+        0x604b => "Jawbreaker",
+        0x6089 => "HackRF One",
+        0xcc15 => "rad1o",
+        _ => "Unknown"
+    }
+}
+
+pub struct ControlServer {
+    listener: TcpListener,
+    shutdown: Arc<AtomicBool>,
+    token: Option<String>,
+    devices: Vec<Value>
+}
+
+impl ControlServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<ControlServer, Error> {
+        let listener = TcpListener::bind(addr)?;
+
+        listener.set_nonblocking(true)?;
+
+        Ok(ControlServer {
+            listener,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            token: None,
+            devices: Vec::new()
+        })
+    }
+
+    /// Requires `token` on every request
+    pub fn with_token(mut self, token: &str) -> ControlServer {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Lists a device under `/devices`; call once per entry of `HackRF::get_device_list`
+    pub fn with_device(mut self, serial: &str, usb_board_id: u32) -> ControlServer {
+        self.devices.push(json!({
+            "index": self.devices.len(),
+            "serial": serial,
+            "usb_board_id": usb_board_id,
+            "board_name": usb_board_name(usb_board_id)
+        }));
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// A flag that makes `serve` return when set
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+
+    /// Serves requests until shut down. Connections are read on their own threads, but every call
+    /// on `radio` happens on this one.
+    pub fn serve<R: Radio>(&self, radio: &mut R) -> Result<(), Error> {
+        let (queue, jobs) = mpsc::channel();
+        let mut subscribers :Vec<Sender<String>> = Vec::new();
+        let mut last_state = None;
+
+        while !self.shutdown.load(Ordering::SeqCst) {
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, peer)) => {
+                        debug!("Control client connected from {}", peer);
+                        spawn_connection(stream, queue.clone(), self.token.clone());
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        warn!("Error accepting control client: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            match jobs.recv_timeout(Duration::from_millis(50)) {
+                Ok(Job::Request(request, reply)) => {
+                    let _ = reply.send(self.handle(radio, &request));
+                },
+                Ok(Job::Subscribe(subscriber)) => {
+                    if subscriber.send(state_event(radio)).is_ok() {
+                        subscribers.push(subscriber);
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {}
+            }
+
+            let state = (radio.tuning_handle().generation(), radio.is_streaming().unwrap_or(false));
+
+            if last_state != Some(state) {
+                if last_state.is_some() {
+                    let event = state_event(radio);
+
+                    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+                }
+
+                last_state = Some(state);
+            }
+        }
+
+        Ok( () )
+    }
+
+    fn handle<R: Radio>(&self, radio: &mut R, request: &Request) -> Response {
+        debug!("{} {}", request.method, request.path);
+
+        let segments :Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let method = request.method.as_str();
+
+        let result = match (method, segments.as_slice()) {
+            ("GET", ["devices"]) => Ok(Value::from(self.devices.clone())),
+            ("GET", ["board"]) => board_json(radio),
+            ("GET", ["settings"]) => Ok(radio::settings_json(radio)),
+            ("PUT", ["settings"]) | ("PATCH", ["settings"]) => request.json().and_then(|body| match body {
+                Value::Object(settings) => radio::apply_settings(radio, &settings).map(|_| radio::settings_json(radio)),
+                _ => Err(Error::INVALID_PARAM(String::from("Body must be a JSON object of settings")))
+            }),
+            ("GET", ["settings", name]) => setting_json(radio, name),
+            ("PUT", ["settings", name]) => request.json().and_then(|value| {
+                let mut settings = Map::new();
+
+                settings.insert(name.to_string(), value);
+                radio::apply_settings(radio, &settings)?;
+                setting_json(radio, name)
+            }),
+            ("GET", ["streaming"]) => radio.is_streaming().map(|streaming| json!({ "streaming": streaming })),
+            (_, ["devices"]) | (_, ["board"]) | (_, ["settings"]) | (_, ["settings", _]) | (_, ["streaming"]) | (_, ["events"]) => {
+                return Response::error(405, &format!("{} not allowed on {}", method, request.path));
+            },
+            _ => return Response::error(404, &format!("No such endpoint {}", request.path))
+        };
+
+        match result {
+            Ok(body) => Response::json(200, &body),
+            Err(Error::NOT_FOUND(message)) => Response::error(404, &message),
+            Err(e) => Response::from_error(&e)
+        }
+    }
+}
+
+fn board_json<R: Radio>(radio: &R) -> Result<Value, Error> {
+    Ok(json!({
+        "board_id": radio.board_id_read()?,
+        "board_name": radio.board_id_name()?,
+        "firmware": radio.version_string_read()?
+    }))
+}
+
+fn setting_json<R: Radio>(radio: &R, name: &str) -> Result<Value, Error> {
+    match radio::settings_json(radio).get(name) {
+        Some(value) => Ok(json!({ name: value })),
+        None => Err(Error::NOT_FOUND(format!("No setting named {}", name)))
+    }
+}
+
+fn state_event<R: Radio>(radio: &R) -> String {
+    let state = json!({
+        "settings": radio::settings_json(radio),
+        "streaming": radio.is_streaming().unwrap_or(false)
+    });
+
+    format!("event: state\ndata: {}\n\n", state)
+}
+
+fn authorized(request: &Request, token: &Option<String>) -> bool {
+    match token {
+        None => true,
+        Some(token) => {
+            let bearer = request.header("authorization").and_then(|value| value.strip_prefix("Bearer "));
+
+            bearer.or_else(|| request.query("token"))
+                .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+        }
+    }
+}
+
+/// Compares without stopping at the first difference, so response times don't reveal how much
+/// of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn spawn_connection(stream: TcpStream, queue: Sender<Job>, token: Option<String>) {
+    thread::spawn(move || {
+        if let Err(e) = connection(stream, queue, token) {
+            debug!("Control connection ended: {}", e);
+        }
+    });
+}
+
+fn connection(mut stream: TcpStream, queue: Sender<Job>, token: Option<String>) -> Result<(), Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let request = match Request::read(&mut BufReader::new(stream.try_clone()?)) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok( () ),
+        Err(e) => return Response::from_error(&e).write_to(&mut stream)
+    };
+
+    if !authorized(&request, &token) {
+        return Response::error(401, "Missing or wrong token")
+            .with_header("WWW-Authenticate", "Bearer")
+            .write_to(&mut stream);
+    }
+
+    if request.method == "GET" && request.path == "/events" {
+        let (subscriber, events) = mpsc::channel();
+
+        queue.send(Job::Subscribe(subscriber))
+            .map_err(|_| Error::OTHER(String::from("Control server has shut down")))?;
+
+        return event_stream(stream, events);
+    }
+
+    let (reply, response) = mpsc::channel();
+
+    queue.send(Job::Request(request, reply))
+        .map_err(|_| Error::OTHER(String::from("Control server has shut down")))?;
+
+    match response.recv() {
+        Ok(response) => response.write_to(&mut stream),
+        Err(_) => Response::error(500, "Control server has shut down").write_to(&mut stream)
+    }
+}
+
+fn event_stream(mut stream: TcpStream, events: Receiver<String>) -> Result<(), Error> {
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+
+    loop {
+        match events.recv_timeout(KEEPALIVE) {
+            Ok(event) => stream.write_all(event.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => stream.write_all(b": keepalive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok( () )
+        }
+
+        stream.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedDevice;
+    use std::io::{BufRead, Read};
+
+    /// Reads the next event from an event stream, returning its name and data
+    fn read_event<R: BufRead>(reader: &mut R) -> (String, String) {
+        let (mut name, mut data) = (String::new(), String::new());
+
+        loop {
+            let mut line = String::new();
+
+            assert!(reader.read_line(&mut line).unwrap() > 0, "event stream closed");
+
+            match line.trim_end() {
+                "" if !data.is_empty() => return (name, data),
+                text if text.starts_with("event: ") => name = text[7..].to_string(),
+                text if text.starts_with("data: ") => data = text[6..].to_string(),
+                _ => {}
+            }
+        }
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let auth = token.map(|t| format!("Authorization: Bearer {}\r\n", t)).unwrap_or_default();
+
+        write!(stream, "{} {} HTTP/1.1\r\nHost: test\r\n{}Content-Length: {}\r\n\r\n{}", method, path, auth, body.len(), body).unwrap();
+
+        let mut response = String::new();
+
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn serves_simulated_device_over_loopback() {
+        let server = ControlServer::bind("127.0.0.1:0").unwrap().with_token("s3cret").with_device("0000000000000000457863dc2b2d1c4f", 0x6089);
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let mut sim = SimulatedDevice::new();
+
+        let client = thread::spawn(move || {
+            let token = Some("s3cret");
+
+            assert_eq!(request(addr, "GET", "/settings", None, "").0, 401);
+            assert_eq!(request(addr, "GET", "/settings", Some("wrong"), "").0, 401);
+
+            let (status, devices) = request(addr, "GET", "/devices?token=s3cret", None, "");
+
+            assert_eq!(status, 200);
+            assert_eq!(devices[0]["board_name"], "HackRF One");
+
+            assert_eq!(request(addr, "PUT", "/settings/freq", token, "915000000"), (200, json!({ "freq": 915_000_000 })));
+
+            let (status, settings) = request(addr, "PATCH", "/settings", token, r#"{"lna_gain": 24, "amp_enable": true, "sample_rate": 10e6}"#);
+
+            assert_eq!(status, 200);
+            assert_eq!(settings["lna_gain"], 24);
+            assert_eq!(settings["sample_rate"], 10e6);
+
+            assert_eq!(request(addr, "PUT", "/settings/vga_gain", token, "99").0, 400);
+            assert_eq!(request(addr, "PUT", "/settings/amp_enable", token, "1").0, 400);
+            assert_eq!(request(addr, "PUT", "/settings/lna_gain", token, "4294967336").0, 400);
+            assert_eq!(request(addr, "PATCH", "/settings", token, r#"{"lna_gain": 32, "volume": 11}"#).0, 400);
+            assert_eq!(request(addr, "GET", "/settings/nothing", token, "").0, 404);
+            assert_eq!(request(addr, "DELETE", "/settings", token, "").0, 405);
+            assert_eq!(request(addr, "GET", "/nowhere", token, "").0, 404);
+            assert_eq!(request(addr, "GET", "/board", token, "").1["board_id"], 2);
+            assert_eq!(request(addr, "GET", "/streaming", token, ""), (200, json!({ "streaming": false })));
+
+            shutdown.store(true, Ordering::SeqCst);
+        });
+
+        server.serve(&mut sim).unwrap();
+        client.join().unwrap();
+
+        let state = sim.tuning();
+
+        assert_eq!(state.freq_hz, Some(915_000_000));
+        assert_eq!((state.lna_gain, state.amp_enable, state.vga_gain), (Some(24), Some(true), None));
+    }
+
+    #[test]
+    fn event_stream_reports_changes() {
+        let server = ControlServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let mut sim = SimulatedDevice::new();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+
+            stream.write_all(b"GET /events HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+
+            let mut events = BufReader::new(stream);
+            let mut status = String::new();
+
+            events.read_line(&mut status).unwrap();
+            assert!(status.starts_with("HTTP/1.1 200"));
+
+            let (name, data) = read_event(&mut events);
+            let initial :Value = serde_json::from_str(&data).unwrap();
+
+            assert_eq!(name, "state");
+            assert_eq!(initial["settings"]["freq"], Value::Null);
+
+            assert_eq!(request(addr, "PUT", "/settings/freq", None, "433920000").0, 200);
+
+            let (_, data) = read_event(&mut events);
+            let changed :Value = serde_json::from_str(&data).unwrap();
+
+            assert_eq!(changed["settings"]["freq"], 433_920_000);
+            assert_eq!(changed["streaming"], false);
+
+            shutdown.store(true, Ordering::SeqCst);
+        });
+
+        server.serve(&mut sim).unwrap();
+        client.join().unwrap();
+    }
+}
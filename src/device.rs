@@ -19,7 +19,9 @@ use crate::{
     hackrf_init_sweep,
     hackrf_is_streaming,
     hackrf_set_baseband_filter_bandwidth,
+    hackrf_board_id,
     hackrf_board_id_read,
+    hackrf_board_id_name,
     hackrf_version_string_read,
     hackrf_usb_api_version_read,
     hackrf_set_freq,
@@ -33,14 +35,32 @@ use crate::{
     hackrf_set_txvga_gain,
     hackrf_set_antenna_enable,
     hackrf_compute_baseband_filter_bw,
-    hackrf_set_hw_sync_mode
+    hackrf_set_hw_sync_mode,
+    hackrf_get_operacake_boards,
+    hackrf_max2837_read,
+    hackrf_max2837_write,
+    hackrf_si5351c_read,
+    hackrf_si5351c_write,
+    hackrf_rffc5071_read,
+    hackrf_rffc5071_write,
+    hackrf_spiflash_erase,
+    hackrf_spiflash_write,
+    hackrf_spiflash_read,
+    hackrf_spiflash_status,
+    hackrf_spiflash_clear_status
 };
 
 use crate::error::Error;
+use crate::dsp::{Pipeline, OffsetTuning};
+use crate::flash::Flash;
+use crate::registers::{Chip, RegisterChange, RegisterSnapshot};
+use crate::stream::{RxSink, TxSource};
+use crate::tuning::{TuningHandle, TuningState};
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
 use std::os::raw::c_void;
+use std::path::Path;
 use std::ptr;
 use std::slice;
 use rayon::prelude::*;
@@ -61,6 +81,11 @@ lazy_static! {
     };
 }
 
+/// The boxed RX callback whose pointer libhackrf hands back as the transfer's `rx_ctx`
+type RxCallback<'c> = Box<dyn FnMut(&[f32]) -> Error + 'c>;
+
+/// The boxed TX callback whose pointer libhackrf hands back as the transfer's `tx_ctx`
+type TxCallback<'c> = Box<dyn FnMut(&mut [i8]) -> Error + 'c>;
 
 #[derive(Debug)]
 pub enum State {
@@ -75,6 +100,7 @@ pub struct Device<'a> {
     pub(super) device_ptr: *mut hackrf_device,
     pub(super) callback_ptr: *mut c_void,
     pub(super) state: State,
+    tuning: TuningHandle,
     phantom: PhantomData<&'a hackrf_device>
 }
 
@@ -85,6 +111,7 @@ impl <'a> Device<'a> {
             device_ptr: device,
             callback_ptr: ptr::null_mut(),
             state: State::IDLE,
+            tuning: TuningHandle::new(),
             phantom: PhantomData
         }
     }
@@ -119,7 +146,7 @@ impl <'a> Device<'a> {
     where F: FnMut(&[f32]) -> Error
     {
         unsafe {
-            let ctx :Box<dyn FnMut(&[f32]) -> Error> = Box::new(callback);
+            let ctx :RxCallback<'_> = Box::new(callback);
             let ctx = Box::new(ctx);
             let ctx :*mut c_void = Box::into_raw(ctx) as _;
 
@@ -139,6 +166,28 @@ impl <'a> Device<'a> {
         Ok( () )
     }
 
+    /// Starts receiving, running every buffer through `pipeline` before handing it to `callback`
+    pub fn start_rx_pipeline<F>(&mut self, mut pipeline: Pipeline, mut callback: F) -> Result<(), Error>
+    where F: FnMut(&[f32]) -> Error + 'static
+    {
+        self.start_rx(move |buffer: &[f32]| callback(pipeline.process(buffer)))
+    }
+
+    /// Starts receiving, writing every buffer to `sink`; streaming stops if the sink returns an error
+    pub fn start_rx_sink<S>(&mut self, mut sink: S) -> Result<(), Error>
+    where S: RxSink + 'static
+    {
+        self.start_rx(move |buffer: &[f32]| {
+            match sink.write(buffer) {
+                Ok( () ) => Error::SUCCESS,
+                Err(e) => {
+                    error!("Error writing RX samples to sink: {}", e);
+                    e
+                }
+            }
+        })
+    }
+
     pub fn stop_rx(&mut self) -> Result<(), Error> {
         unsafe {
             let ret = hackrf_stop_rx(self.device_ptr);
@@ -149,7 +198,8 @@ impl <'a> Device<'a> {
 
             if !self.callback_ptr.is_null() {
                 // capture the box, so it'll drop and free the memory
-                let mut callback :Box<Box<dyn FnMut(&[u8]) -> Error>> = Box::from_raw(self.callback_ptr as _);
+                let _callback :Box<RxCallback<'_>> = Box::from_raw(self.callback_ptr as _);
+                self.callback_ptr = ptr::null_mut();
             }
         }
 
@@ -159,6 +209,52 @@ impl <'a> Device<'a> {
         Ok( () )
     }
 
+    // wrapper function for start_tx_callback
+    unsafe extern "C" fn tx_callback(transfer: *mut hackrf_transfer) -> i32 {
+        // the buffer is filled in place with interleaved signed 8-bit IQ
+        let buffer :&mut [i8] = slice::from_raw_parts_mut((*transfer).buffer as *mut i8, (*transfer).valid_length as usize);
+
+        // same ownership scheme as rx_callback; freed in stop_tx
+        let callback = (*transfer).tx_ctx as *mut *mut dyn FnMut(&mut [i8]) -> Error;
+        let callback = &mut **callback;
+
+        Into::into(callback(buffer))
+    }
+
+    /// Starts transmitting, calling `callback` to fill each buffer with interleaved signed 8-bit IQ.
+    /// Returning anything but `Error::SUCCESS` stops transmitting, and that buffer is not sent.
+    pub fn start_tx_callback<F>(&mut self, callback: F) -> Result<(), Error>
+    where F: FnMut(&mut [i8]) -> Error
+    {
+        unsafe {
+            let ctx :TxCallback<'_> = Box::new(callback);
+            let ctx = Box::new(ctx);
+            let ctx :*mut c_void = Box::into_raw(ctx) as _;
+
+            self.callback_ptr = ctx;
+
+            let ret = hackrf_start_tx(self.device_ptr, Some(Device::tx_callback), ctx);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        // set the state so we can cleanup properly
+        self.state = State::TRANSMITTING;
+
+        Ok( () )
+    }
+
+    /// Starts transmitting samples read from `source` until it runs out or returns an error
+    pub fn start_tx_source<S>(&mut self, mut source: S) -> Result<(), Error>
+    where S: TxSource + 'static
+    {
+        let mut done = false;
+
+        self.start_tx_callback(move |buffer: &mut [i8]| fill_tx_buffer(&mut source, buffer, &mut done))
+    }
+
     pub fn start_tx(&mut self, callback: hackrf_sample_block_cb_fn, tx_ctx: *mut c_void) -> Result<(), Error> {
         unsafe {
             let ret = hackrf_start_tx(self.device_ptr, callback, tx_ctx);
@@ -181,6 +277,12 @@ impl <'a> Device<'a> {
             if ret != hackrf_error_HACKRF_SUCCESS {
                 return Err(Error::from(ret));
             }
+
+            if !self.callback_ptr.is_null() {
+                // capture the box, so it'll drop and free the memory
+                let _callback :Box<TxCallback<'_>> = Box::from_raw(self.callback_ptr as _);
+                self.callback_ptr = ptr::null_mut();
+            }
         }
 
         // set the state so we don't erroneously cleanup
@@ -198,9 +300,15 @@ impl <'a> Device<'a> {
     ///   * `LINEAR` means `step_width` is added to the current frequency at each step.
     ///   * `INTERLEAVED` invokes a scheme in which each step is divided into two interleaved sub-steps, allowing the host to select the best portions of the FFT of each sub-step and discard the rest.
     pub fn init_sweep(&self, frequency_list: &[u16], num_bytes: u32, step_width: u32, offset: u32, style: sweep_style) -> Result<(), Error> {
+        // libhackrf takes the number of ranges and reads two values for each
+        if frequency_list.is_empty() || !frequency_list.chunks_exact(2).remainder().is_empty() {
+            return Err(Error::INVALID_PARAM(String::from("Frequency list must hold start/stop pairs")));
+        }
+
         unsafe {
             let frequency_list_ptr = frequency_list.as_ptr();
-            let ret = hackrf_init_sweep(self.device_ptr, frequency_list_ptr, frequency_list.len() as i32, num_bytes, step_width, offset, style);
+            let num_ranges = (frequency_list.len() / 2) as i32;
+            let ret = hackrf_init_sweep(self.device_ptr, frequency_list_ptr, num_ranges, num_bytes, step_width, offset, style);
 
             if ret != hackrf_error_HACKRF_SUCCESS {
                 return Err(Error::from(ret));
@@ -237,6 +345,8 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.baseband_filter_hz = Some(bandwidth_hz));
+
         Ok( () )
     }
 
@@ -259,6 +369,23 @@ impl <'a> Device<'a> {
         }
     }
 
+    /// Human readable name of the board, e.g. "HackRF One"
+    pub fn board_id_name(&self) -> Result<String, Error> {
+        let board_id = match self.board_id_read()? {
+            0 => hackrf_board_id::BOARD_ID_JELLYBEAN,
+            1 => hackrf_board_id::BOARD_ID_JAWBREAKER,
+            2 => hackrf_board_id::BOARD_ID_HACKRF_ONE,
+            3 => hackrf_board_id::BOARD_ID_RAD1O,
+            _ => hackrf_board_id::BOARD_ID_INVALID
+        };
+
+        unsafe {
+            let name = CStr::from_ptr(hackrf_board_id_name(board_id));
+
+            Ok(String::from(name.to_str().expect("Error converting board name")))
+        }
+    }
+
     pub fn version_string_read(&self) -> Result<String, Error> {
         unsafe {
             let buff = Vec::<u8>::with_capacity(255); // one less than max so there is space for a null
@@ -298,9 +425,23 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.freq_hz = Some(freq_hz));
+
         Ok( () )
     }
 
+    /// Tunes the hardware `tuning.offset_hz()` away from `target_hz` to keep the DC spike out of the signal.
+    /// Run the stream through `tuning.stage()` to bring `target_hz` back to 0 Hz.
+    pub fn set_freq_offset(&self, target_hz: u64, tuning: &OffsetTuning) -> Result<(), Error> {
+        tuning.validate()?;
+
+        let freq_hz = tuning.hardware_freq(target_hz)?;
+
+        debug!("Offset tuning {} Hz to {} Hz", target_hz, freq_hz);
+
+        self.set_freq(freq_hz)
+    }
+
     /// Sets the intermediate frequency (`if_freq_hz`) and local oscillator (`lo_freq_hz`) explicitly
     /// * `if_freq_hz` - must be in the range [2150000000, 2750000000]
     /// * `lo_freq_hz` - must be in the range [84375000, 5400000000]
@@ -327,6 +468,15 @@ impl <'a> Device<'a> {
             }
         }
 
+        // same mapping hackrf_transfer uses to report the RF frequency
+        let freq_hz = match path {
+            rf_path_filter::RF_PATH_FILTER_BYPASS => if_freq_hz,
+            rf_path_filter::RF_PATH_FILTER_LOW_PASS => if_freq_hz.abs_diff(lo_freq_hz),
+            rf_path_filter::RF_PATH_FILTER_HIGH_PASS => if_freq_hz + lo_freq_hz
+        };
+
+        self.tuning.update(|t| t.freq_hz = Some(freq_hz));
+
         Ok( () )
     }
 
@@ -341,6 +491,8 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.sample_rate = Some(freq_hz as f64 / divider as f64));
+
         Ok( () )
     }
 
@@ -361,6 +513,8 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.sample_rate = Some(freq_hz));
+
         Ok( () )
     }
 
@@ -374,6 +528,8 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.amp_enable = Some(value));
+
         Ok( () )
     }
 
@@ -400,6 +556,8 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.lna_gain = Some(value));
+
         Ok( () )
     }
 
@@ -413,6 +571,8 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.vga_gain = Some(value));
+
         Ok( () )
     }
 
@@ -426,6 +586,8 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.txvga_gain = Some(value));
+
         Ok( () )
     }
 
@@ -439,9 +601,37 @@ impl <'a> Device<'a> {
             }
         }
 
+        self.tuning.update(|t| t.antenna_enable = Some(value));
+
         Ok( () )
     }
 
+    /// The settings last applied through this `Device`
+    pub fn tuning(&self) -> TuningState {
+        self.tuning.get()
+    }
+
+    /// A handle that tracks this device's settings, for use from streaming callbacks
+    pub fn tuning_handle(&self) -> TuningHandle {
+        self.tuning.clone()
+    }
+
+    /// Addresses of the Opera Cake boards attached, empty if there are none
+    pub fn operacake_boards(&self) -> Result<Vec<u8>, Error> {
+        let mut boards = [0u8; MAX_OPERACAKE_BOARDS];
+
+        unsafe {
+            let ret = hackrf_get_operacake_boards(self.device_ptr, boards.as_mut_ptr());
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        // the list ends at the first unused slot, which is left 0
+        Ok(boards.iter().copied().take_while(|address| *address != 0).collect())
+    }
+
     /// Enable or disable hardware sync mode
     pub fn enable_hardware_sync(&self, enable: bool) -> Result<(), Error> {
         unsafe {
@@ -454,6 +644,223 @@ impl <'a> Device<'a> {
 
         Ok( () )
     }
+
+    /* MAX2837 transceiver: registers 0-31, 10 bits each */
+    pub fn max2837_read(&self, register: u8) -> Result<u16, Error> {
+        Chip::Max2837.check_register(register as u16)?;
+
+        let mut value = 0u16;
+
+        unsafe {
+            let ret = hackrf_max2837_read(self.device_ptr, register, &mut value);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok(value)
+    }
+
+    pub fn max2837_write(&self, register: u8, value: u16) -> Result<(), Error> {
+        Chip::Max2837.check_register(register as u16)?;
+        Chip::Max2837.check_value(value)?;
+
+        unsafe {
+            let ret = hackrf_max2837_write(self.device_ptr, register, value);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok( () )
+    }
+
+    /* Si5351C clock generator: registers 0-255, 8 bits each */
+    pub fn si5351c_read(&self, register: u16) -> Result<u16, Error> {
+        Chip::Si5351c.check_register(register)?;
+
+        let mut value = 0u16;
+
+        unsafe {
+            let ret = hackrf_si5351c_read(self.device_ptr, register, &mut value);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok(value)
+    }
+
+    pub fn si5351c_write(&self, register: u16, value: u16) -> Result<(), Error> {
+        Chip::Si5351c.check_register(register)?;
+        Chip::Si5351c.check_value(value)?;
+
+        unsafe {
+            let ret = hackrf_si5351c_write(self.device_ptr, register, value);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok( () )
+    }
+
+    /* RFFC5071 mixer: registers 0-30, 16 bits each */
+    pub fn rffc5071_read(&self, register: u8) -> Result<u16, Error> {
+        Chip::Rffc5071.check_register(register as u16)?;
+
+        let mut value = 0u16;
+
+        unsafe {
+            let ret = hackrf_rffc5071_read(self.device_ptr, register, &mut value);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok(value)
+    }
+
+    pub fn rffc5071_write(&self, register: u8, value: u16) -> Result<(), Error> {
+        Chip::Rffc5071.check_register(register as u16)?;
+
+        unsafe {
+            let ret = hackrf_rffc5071_write(self.device_ptr, register, value);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok( () )
+    }
+
+    /// Saves every MAX2837, Si5351C and RFFC5071 register to `path`, see `RegisterSnapshot`
+    pub fn snapshot_registers<P: AsRef<Path>>(&self, path: P) -> Result<RegisterSnapshot, Error> {
+        let snapshot = RegisterSnapshot::take(self)?;
+
+        snapshot.save(path)?;
+
+        Ok(snapshot)
+    }
+
+    /// Puts back the registers saved by `snapshot_registers`, checking each one took, and returns
+    /// what changed. Fails if the snapshot came from a different board ID.
+    pub fn restore_registers<P: AsRef<Path>>(&self, path: P) -> Result<Vec<RegisterChange>, Error> {
+        RegisterSnapshot::load(path)?.restore(self)
+    }
+
+    /// The SPI flash, with chunking, page alignment, progress and verification
+    pub fn flash(&self) -> Flash<'_, Device<'a>> {
+        Flash::new(self)
+    }
+
+    /* erases the whole SPI flash */
+    pub fn spiflash_erase(&self) -> Result<(), Error> {
+        unsafe {
+            let ret = hackrf_spiflash_erase(self.device_ptr);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok( () )
+    }
+
+    /* one transfer: at most u16::MAX bytes, and not past a 256-byte page boundary */
+    pub fn spiflash_write(&self, address: u32, data: &[u8]) -> Result<(), Error> {
+        if data.len() > u16::MAX as usize {
+            return Err(Error::INVALID_PARAM(format!("SPI flash writes are at most {} bytes", u16::MAX)));
+        }
+
+        unsafe {
+            // libhackrf doesn't modify the data, despite the pointer being mutable
+            let ret = hackrf_spiflash_write(self.device_ptr, address, data.len() as u16, data.as_ptr() as *mut u8);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok( () )
+    }
+
+    pub fn spiflash_read(&self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        if data.len() > u16::MAX as usize {
+            return Err(Error::INVALID_PARAM(format!("SPI flash reads are at most {} bytes", u16::MAX)));
+        }
+
+        unsafe {
+            let ret = hackrf_spiflash_read(self.device_ptr, address, data.len() as u16, data.as_mut_ptr());
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok( () )
+    }
+
+    /* the flash's status registers 1 and 2; older firmware only fills the first */
+    pub fn spiflash_status(&self) -> Result<[u8; 2], Error> {
+        let mut status = [0u8; 2];
+
+        unsafe {
+            let ret = hackrf_spiflash_status(self.device_ptr, status.as_mut_ptr());
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok(status)
+    }
+
+    pub fn spiflash_clear_status(&self) -> Result<(), Error> {
+        unsafe {
+            let ret = hackrf_spiflash_clear_status(self.device_ptr);
+
+            if ret != hackrf_error_HACKRF_SUCCESS {
+                return Err(Error::from(ret));
+            }
+        }
+
+        Ok( () )
+    }
+}
+
+/// Most Opera Cake boards `hackrf_get_operacake_boards` reports
+const MAX_OPERACAKE_BOARDS: usize = 8;
+
+/// Fills one TX buffer from `source`. A short read is padded with zeros and still sent,
+/// then the following call ends the stream.
+fn fill_tx_buffer<S: TxSource>(source: &mut S, buffer: &mut [i8], done: &mut bool) -> Error {
+    if *done {
+        return Error::STREAMING_EXIT_CALLED(String::from("TX source finished"));
+    }
+
+    match source.read(buffer) {
+        Ok(count) => {
+            if count < buffer.len() {
+                debug!("TX source finished; padding last {} bytes", buffer.len() - count);
+
+                buffer[count..].iter_mut().for_each(|v| *v = 0);
+                *done = true;
+            }
+
+            Error::SUCCESS
+        },
+        Err(e) => {
+            error!("Error reading TX samples from source: {}", e);
+            e
+        }
+    }
 }
 
 #[cfg(test)]
@@ -499,6 +906,34 @@ mod tests {
     }
 
 
+    #[test]
+    fn fill_tx_buffer_pads_and_stops() {
+        struct Counter(usize);
+
+        impl TxSource for Counter {
+            fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+                let count = self.0.min(buffer.len());
+
+                buffer[..count].iter_mut().for_each(|v| *v = 1);
+                self.0 -= count;
+
+                Ok(count)
+            }
+        }
+
+        let mut source = Counter(10);
+        let mut buffer = [7i8; 8];
+        let mut done = false;
+
+        assert!(Into::<i32>::into(fill_tx_buffer(&mut source, &mut buffer, &mut done)) == 0);
+        assert_eq!(buffer, [1; 8]);
+
+        assert!(Into::<i32>::into(fill_tx_buffer(&mut source, &mut buffer, &mut done)) == 0);
+        assert_eq!(buffer, [1, 1, 0, 0, 0, 0, 0, 0]);
+
+        assert!(Into::<i32>::into(fill_tx_buffer(&mut source, &mut buffer, &mut done)) != 0);
+    }
+
     #[test]
     fn is_streaming() {
         LOGGER_INIT.call_once(|| simple_logger::init_with_level(log::Level::Trace).unwrap());
@@ -0,0 +1,224 @@
+use crate::dsp::{Nco, FirDecimator, Stage};
+use crate::dsp::cic::CicDecimator;
+use crate::dsp::fir;
+use crate::error::Error;
+
+/// Stopband attenuation used when none is given
+pub const DEFAULT_ATTENUATION_DB: f64 = 60.0;
+
+/// Above this decimation a CIC filter does the bulk of the rate reduction
+const MAX_FIR_DECIMATION: usize = 16;
+
+/// Largest decimation left to the compensating FIR when a CIC filter is used
+const MAX_COMPENSATOR_DECIMATION: usize = 8;
+
+/// Number of integrator/comb stages in the CIC filter
+const CIC_STAGES: usize = 4;
+
+/// Selects one channel out of a wideband stream: shifts it to 0 Hz, low-pass filters it to the
+/// requested bandwidth, and decimates to the requested rate.
+///
+/// Small decimations use a single polyphase FIR; larger ones use a CIC decimator followed by a
+/// FIR that compensates the CIC droop and does the last bit of decimation.
+#[derive(Debug, Clone)]
+pub struct ChannelFilter {
+    input_rate: f64,
+    output_rate: f64,
+    bandwidth_hz: f64,
+    nco: Nco,
+    cic: Option<CicDecimator>,
+    fir: FirDecimator,
+    mixed: Vec<f32>,
+    narrowed: Vec<f32>
+}
+
+impl ChannelFilter {
+    /// * `input_rate` - sample rate of the incoming stream
+    /// * `offset_hz` - center of the channel relative to the center of the stream
+    /// * `bandwidth_hz` - width of the channel to keep
+    /// * `output_rate` - must divide `input_rate` evenly, and be more than `bandwidth_hz`
+    pub fn new(input_rate: f64, offset_hz: f64, bandwidth_hz: f64, output_rate: f64) -> Result<ChannelFilter, Error> {
+        ChannelFilter::with_attenuation(input_rate, offset_hz, bandwidth_hz, output_rate, DEFAULT_ATTENUATION_DB)
+    }
+
+    /// Same as `new` but with the stopband attenuation given in dB
+    pub fn with_attenuation(input_rate: f64, offset_hz: f64, bandwidth_hz: f64, output_rate: f64, attenuation_db: f64) -> Result<ChannelFilter, Error> {
+        let decimation = decimation_for(input_rate, output_rate)?;
+
+        if bandwidth_hz <= 0.0 || bandwidth_hz >= output_rate {
+            let err_str = format!("bandwidth_hz {} must be between 0 and the output rate {}, exclusive", bandwidth_hz, output_rate);
+            return Err(Error::INVALID_PARAM(err_str));
+        }
+
+        if offset_hz.abs() + bandwidth_hz / 2.0 > input_rate / 2.0 {
+            let err_str = format!("channel at {} Hz with bandwidth {} Hz falls outside the input band", offset_hz, bandwidth_hz);
+            return Err(Error::INVALID_PARAM(err_str));
+        }
+
+        // anything above output_rate - bandwidth/2 aliases into the channel after decimating
+        let passband = bandwidth_hz / 2.0;
+        let stopband = output_rate - bandwidth_hz / 2.0;
+
+        let (cic, fir) = if decimation <= MAX_FIR_DECIMATION {
+            let taps = fir::kaiser_lowpass(passband / input_rate, stopband / input_rate, attenuation_db)?;
+
+            (None, FirDecimator::new(taps, decimation))
+        } else {
+            let fir_decimation = (1..=MAX_COMPENSATOR_DECIMATION).rev().find(|d| decimation % d == 0).unwrap_or(1);
+            let cic_decimation = decimation / fir_decimation;
+
+            if !CicDecimator::fits(cic_decimation, CIC_STAGES) {
+                let err_str = format!("decimation {} from {} to {} S/s is too large for the CIC filter", decimation, input_rate, output_rate);
+                return Err(Error::INVALID_PARAM(err_str));
+            }
+
+            let cic_rate = input_rate / cic_decimation as f64;
+            let taps = fir::cic_compensator(passband / cic_rate, stopband / cic_rate, attenuation_db, cic_decimation, CIC_STAGES)?;
+
+            (Some(CicDecimator::new(cic_decimation, CIC_STAGES)), FirDecimator::new(taps, fir_decimation))
+        };
+
+        debug!("Channel filter: decimation {} (CIC {:?}), {} taps", decimation, cic.as_ref().map(|c| c.decimation()), fir.taps().len());
+
+        Ok(ChannelFilter {
+            input_rate,
+            output_rate,
+            bandwidth_hz,
+            nco: Nco::new(-offset_hz, input_rate),
+            cic,
+            fir,
+            mixed: Vec::new(),
+            narrowed: Vec::new()
+        })
+    }
+
+    pub fn input_rate(&self) -> f64 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> f64 {
+        self.output_rate
+    }
+
+    pub fn bandwidth(&self) -> f64 {
+        self.bandwidth_hz
+    }
+
+    pub fn offset(&self) -> f64 {
+        -self.nco.frequency()
+    }
+
+    /// Total decimation from input to output
+    pub fn decimation(&self) -> usize {
+        self.fir.decimation() * self.cic.as_ref().map(|c| c.decimation()).unwrap_or(1)
+    }
+
+    /// Moves the channel within the input band without disturbing the filter state
+    pub fn set_offset(&mut self, offset_hz: f64) {
+        self.nco.set_frequency(-offset_hz);
+    }
+}
+
+/// Works out the integer decimation between two rates
+pub(crate) fn decimation_for(input_rate: f64, output_rate: f64) -> Result<usize, Error> {
+    if output_rate <= 0.0 || output_rate > input_rate {
+        let err_str = format!("output rate {} must be between 0 and the input rate {}", output_rate, input_rate);
+        return Err(Error::INVALID_PARAM(err_str));
+    }
+
+    let decimation = (input_rate / output_rate).round();
+
+    if (input_rate / decimation - output_rate).abs() > output_rate * 1e-9 {
+        let err_str = format!("output rate {} does not evenly divide the input rate {}", output_rate, input_rate);
+        return Err(Error::INVALID_PARAM(err_str));
+    }
+
+    Ok(decimation as usize)
+}
+
+impl Stage for ChannelFilter {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.mixed.clear();
+        self.nco.process(input, &mut self.mixed);
+
+        match self.cic {
+            Some(ref mut cic) => {
+                self.narrowed.clear();
+                cic.process(&self.mixed, &mut self.narrowed);
+                self.fir.process(&self.narrowed, output);
+            },
+            None => self.fir.process(&self.mixed, output)
+        }
+    }
+
+    fn reset(&mut self) {
+        self.nco.reset();
+        self.fir.reset();
+
+        if let Some(ref mut cic) = self.cic {
+            cic.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(freq: f64, rate: f64, len: usize) -> Vec<f32> {
+        let mut samples = [0.5f32, 0.0].repeat(len);
+
+        Nco::new(freq, rate).mix_in_place(&mut samples);
+        samples
+    }
+
+    fn power(samples: &[f32]) -> f32 {
+        samples.iter().map(|v| v * v).sum::<f32>() / (samples.len() / 2) as f32
+    }
+
+    #[test]
+    fn rejects_bad_rates() {
+        assert!(ChannelFilter::new(10e6, 0.0, 100e3, 300e3).is_err());
+        assert!(ChannelFilter::new(10e6, 0.0, 300e3, 250e3).is_err());
+        assert!(ChannelFilter::new(10e6, 0.0, 1e6, 1e6).is_err());
+        assert!(ChannelFilter::new(20e6, 0.0, 400.0, 500.0).is_err());
+        assert!(ChannelFilter::new(10e6, 4.99e6, 100e3, 250e3).is_err());
+        assert!(ChannelFilter::new(10e6, 1e6, 100e3, 250e3).is_ok());
+    }
+
+    #[test]
+    fn fir_only_channel() {
+        let mut filter = ChannelFilter::new(10e6, 1e6, 400e3, 1e6).unwrap();
+        let mut wanted = Vec::new();
+        let mut unwanted = Vec::new();
+
+        assert!(filter.cic.is_none());
+        assert_eq!(filter.decimation(), 10);
+
+        filter.process(&tone(1.05e6, 10e6, 100_000), &mut wanted);
+        filter.reset();
+        filter.process(&tone(2e6, 10e6, 100_000), &mut unwanted);
+
+        assert_eq!(wanted.len(), 20_000);
+        assert!((power(&wanted[2000..]) - 0.25).abs() < 0.01);
+        assert!(power(&unwanted[2000..]) < 0.25e-6);
+    }
+
+    #[test]
+    fn cic_channel() {
+        let mut filter = ChannelFilter::new(20e6, -2e6, 20e3, 25e3).unwrap();
+        let mut wanted = Vec::new();
+        let mut unwanted = Vec::new();
+
+        assert!(filter.cic.is_some());
+        assert_eq!(filter.decimation(), 800);
+
+        filter.process(&tone(-2.005e6, 20e6, 800_000), &mut wanted);
+        filter.reset();
+        filter.process(&tone(-2.04e6, 20e6, 800_000), &mut unwanted);
+
+        assert_eq!(wanted.len(), 2_000);
+        assert!((power(&wanted[400..]) - 0.25).abs() < 0.01, "{}", power(&wanted[400..]));
+        assert!(power(&unwanted[400..]) < 0.25e-5, "{}", power(&unwanted[400..]));
+    }
+}
@@ -0,0 +1,427 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::dsp::{ChannelFilter, Stage};
+use crate::dsp::fft::Fft;
+use crate::dsp::fir;
+use crate::error::Error;
+
+/// Stopband attenuation of the filter bank's prototype filter
+const PROTOTYPE_ATTENUATION_DB: f64 = 60.0;
+
+/// Each bin is usable out to this fraction of the bin spacing either side of its center
+const USABLE_BIN_FRACTION: f64 = 0.75;
+
+/// Identifies a channel added to a `Channelizer`
+pub type ChannelId = usize;
+
+/// Receives a channel's output after every input buffer
+pub type ChannelSink = Box<dyn FnMut(&[f32]) + Send>;
+
+/// One entry in a channel plan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelSpec {
+    /// Center of the channel relative to the center of the capture
+    pub offset_hz: f64,
+    /// Width of the channel to keep
+    pub bandwidth_hz: f64,
+    /// Rate of the channel's output stream; when `None` the lowest rate at least 1.25x the bandwidth is picked
+    pub output_rate: Option<f64>
+}
+
+impl ChannelSpec {
+    pub fn new(offset_hz: f64, bandwidth_hz: f64) -> ChannelSpec {
+        ChannelSpec { offset_hz, bandwidth_hz, output_rate: None }
+    }
+
+    pub fn with_output_rate(mut self, output_rate: f64) -> ChannelSpec {
+        self.output_rate = Some(output_rate);
+        self
+    }
+}
+
+/// Where a channel gets its samples from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    /// Output of a filter bank bin
+    Bin(usize),
+    /// The full-rate input, for channels too wide for a bin
+    Wideband
+}
+
+enum Command {
+    Add(Box<Channel>),
+    Remove(ChannelId)
+}
+
+struct Channel {
+    id: ChannelId,
+    source: Source,
+    filter: ChannelFilter,
+    sink: ChannelSink,
+    output: Vec<f32>
+}
+
+/// Handle for adding and removing channels while the `Channelizer` is running on the RX thread
+#[derive(Clone)]
+pub struct ChannelizerControl {
+    input_rate: f64,
+    num_bins: usize,
+    next_id: Arc<AtomicUsize>,
+    commands: Sender<Command>
+}
+
+impl ChannelizerControl {
+    /// Adds a channel whose output is handed to `sink` after every input buffer.
+    /// The channel starts with the next buffer the `Channelizer` processes.
+    pub fn add_channel<F>(&self, spec: ChannelSpec, sink: F) -> Result<ChannelId, Error>
+    where F: FnMut(&[f32]) + Send + 'static
+    {
+        let (source, filter) = self.plan(&spec)?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        debug!("Adding channel {} at {} Hz from {:?}", id, spec.offset_hz, source);
+
+        let channel = Channel { id, source, filter, sink: Box::new(sink), output: Vec::new() };
+
+        self.commands.send(Command::Add(Box::new(channel)))
+            .map_err(|_| Error::OTHER(String::from("Channelizer has been dropped")))?;
+
+        Ok(id)
+    }
+
+    /// Removes a channel; it stops receiving samples from the next buffer processed
+    pub fn remove_channel(&self, id: ChannelId) -> Result<(), Error> {
+        self.commands.send(Command::Remove(id))
+            .map_err(|_| Error::OTHER(String::from("Channelizer has been dropped")))
+    }
+
+    /// Picks the source for a channel and designs its filter
+    fn plan(&self, spec: &ChannelSpec) -> Result<(Source, ChannelFilter), Error> {
+        if spec.offset_hz.abs() + spec.bandwidth_hz / 2.0 > self.input_rate / 2.0 {
+            let err_str = format!("channel at {} Hz with bandwidth {} Hz falls outside the input band", spec.offset_hz, spec.bandwidth_hz);
+            return Err(Error::INVALID_PARAM(err_str));
+        }
+
+        let spacing = self.input_rate / self.num_bins as f64;
+        let bin_rate = 2.0 * spacing;
+        let nearest = (spec.offset_hz / spacing).round();
+        let residual = spec.offset_hz - nearest * spacing;
+
+        let (source, rate, offset) = if residual.abs() + spec.bandwidth_hz / 2.0 <= USABLE_BIN_FRACTION * spacing {
+            let bin = (nearest as i64).rem_euclid(self.num_bins as i64) as usize;
+
+            (Source::Bin(bin), bin_rate, residual)
+        } else {
+            warn!("Channel at {} Hz with bandwidth {} Hz does not fit in a bin; filtering it from the full-rate input", spec.offset_hz, spec.bandwidth_hz);
+
+            (Source::Wideband, self.input_rate, spec.offset_hz)
+        };
+
+        let output_rate = match spec.output_rate {
+            Some(output_rate) => output_rate,
+            None => rate / (rate / (1.25 * spec.bandwidth_hz)).floor().max(1.0)
+        };
+
+        let filter = ChannelFilter::new(rate, offset, spec.bandwidth_hz, output_rate)?;
+
+        Ok( (source, filter) )
+    }
+}
+
+/// Splits one wideband stream into many narrow channels.
+///
+/// A 2x oversampled polyphase filter bank first splits the input into `num_bins` evenly spaced bins,
+/// then each channel is shifted, filtered and decimated out of the bin nearest to it by a `ChannelFilter`.
+pub struct Channelizer {
+    num_bins: usize,
+    taps_per_bin: usize,
+    prototype: Vec<f32>,
+    fft: Fft,
+    history: Vec<f32>,
+    next: usize,
+    phase: usize,
+    folded: Vec<f32>,
+    bins: Vec<Vec<f32>>,
+    channels: Vec<Channel>,
+    commands: Receiver<Command>,
+    control: ChannelizerControl
+}
+
+impl Channelizer {
+    /// * `input_rate` - sample rate of the incoming stream
+    /// * `num_bins` - number of filter bank bins; a power of two, at least 4
+    pub fn new(input_rate: f64, num_bins: usize) -> Result<Channelizer, Error> {
+        if !num_bins.is_power_of_two() || num_bins < 4 {
+            return Err(Error::INVALID_PARAM(format!("num_bins {} must be a power of two, at least 4", num_bins)));
+        }
+
+        let passband = USABLE_BIN_FRACTION / num_bins as f64;
+        let stopband = (2.0 - USABLE_BIN_FRACTION) / num_bins as f64;
+        let mut prototype = fir::kaiser_lowpass(passband, stopband, PROTOTYPE_ATTENUATION_DB)?;
+        let taps_per_bin = prototype.len().div_ceil(num_bins);
+
+        prototype.resize(taps_per_bin * num_bins, 0.0);
+
+        let (sender, receiver) = channel();
+        let keep = prototype.len() - 1;
+
+        Ok(Channelizer {
+            num_bins,
+            taps_per_bin,
+            prototype,
+            fft: Fft::new_inverse(num_bins),
+            history: vec![0.0; keep * 2],
+            next: keep,
+            phase: 0,
+            folded: vec![0.0; num_bins * 2],
+            bins: vec![Vec::new(); num_bins],
+            channels: Vec::new(),
+            commands: receiver,
+            control: ChannelizerControl {
+                input_rate,
+                num_bins,
+                next_id: Arc::new(AtomicUsize::new(0)),
+                commands: sender
+            }
+        })
+    }
+
+    /// Returns a handle for changing the channel plan from another thread
+    pub fn control(&self) -> ChannelizerControl {
+        self.control.clone()
+    }
+
+    /// Shorthand for `control().add_channel(...)`
+    pub fn add_channel<F>(&self, spec: ChannelSpec, sink: F) -> Result<ChannelId, Error>
+    where F: FnMut(&[f32]) + Send + 'static
+    {
+        self.control.add_channel(spec, sink)
+    }
+
+    /// Shorthand for `control().remove_channel(...)`
+    pub fn remove_channel(&self, id: ChannelId) -> Result<(), Error> {
+        self.control.remove_channel(id)
+    }
+
+    /// Number of channels currently being produced
+    pub fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Sample rate of each filter bank bin
+    pub fn bin_rate(&self) -> f64 {
+        2.0 * self.control.input_rate / self.num_bins as f64
+    }
+
+    /// Applies any pending channel plan changes
+    fn apply_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::Add(channel) => self.channels.push(*channel),
+                Command::Remove(id) => self.channels.retain(|c| c.id != id)
+            }
+        }
+    }
+
+    /// Runs the filter bank over `input`, filling the bins that have a channel
+    fn analyze(&mut self, input: &[f32]) {
+        let num_bins = self.num_bins;
+        let decimation = num_bins / 2;
+        let len = self.prototype.len();
+
+        let mut active = vec![false; num_bins];
+
+        for channel in self.channels.iter() {
+            if let Source::Bin(bin) = channel.source {
+                active[bin] = true;
+            }
+        }
+
+        for bin in self.bins.iter_mut() {
+            bin.clear();
+        }
+
+        self.history.extend_from_slice(input);
+
+        let num_samples = self.history.len() / 2;
+        let mut n = self.next;
+
+        while n < num_samples {
+            let window = &self.history[(n + 1 - len) * 2..(n + 1) * 2];
+
+            // fold the windowed input into one sample per bin: v[m] = sum_p h[m + pM] x[n - m - pM]
+            for m in 0..num_bins {
+                let (mut i, mut q) = (0.0f32, 0.0f32);
+
+                for p in 0..self.taps_per_bin {
+                    let l = m + p * num_bins;
+                    let w = (len - 1 - l) * 2;
+
+                    i += self.prototype[l] * window[w];
+                    q += self.prototype[l] * window[w + 1];
+                }
+
+                self.folded[m * 2] = i;
+                self.folded[m * 2 + 1] = q;
+            }
+
+            self.fft.process(&mut self.folded);
+
+            // undo the rotation from the bins' modulation: y_k = V_k * e^(-j 2 pi k n / M)
+            for (k, bin) in self.bins.iter_mut().enumerate() {
+                if !active[k] {
+                    continue;
+                }
+
+                let angle = -2.0 * std::f32::consts::PI * ((k * self.phase) % num_bins) as f32 / num_bins as f32;
+                let (s, c) = angle.sin_cos();
+                let (re, im) = (self.folded[k * 2], self.folded[k * 2 + 1]);
+
+                bin.push(re * c - im * s);
+                bin.push(re * s + im * c);
+            }
+
+            self.phase = (self.phase + decimation) % num_bins;
+            n += decimation;
+        }
+
+        let consumed = num_samples - (len - 1);
+
+        self.history.drain(..consumed * 2);
+        self.next = n - consumed;
+    }
+
+    /// Channelizes one buffer of interleaved IQ samples, handing each channel's output to its sink
+    pub fn process(&mut self, input: &[f32]) {
+        self.apply_commands();
+        self.analyze(input);
+
+        for channel in self.channels.iter_mut() {
+            channel.output.clear();
+
+            match channel.source {
+                Source::Bin(bin) => channel.filter.process(&self.bins[bin], &mut channel.output),
+                Source::Wideband => channel.filter.process(input, &mut channel.output)
+            }
+
+            (channel.sink)(&channel.output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::Nco;
+    use std::sync::Mutex;
+
+    fn tones(freqs: &[f64], rate: f64, len: usize) -> Vec<f32> {
+        let mut sum = vec![0.0f32; len * 2];
+
+        for &freq in freqs {
+            let mut tone = [0.5f32, 0.0].repeat(len);
+
+            Nco::new(freq, rate).mix_in_place(&mut tone);
+
+            for (s, t) in sum.iter_mut().zip(tone.iter()) {
+                *s += t;
+            }
+        }
+
+        sum
+    }
+
+    fn collector() -> (Arc<Mutex<Vec<f32>>>, ChannelSink) {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let sink_samples = samples.clone();
+
+        (samples, Box::new(move |b: &[f32]| sink_samples.lock().unwrap().extend_from_slice(b)))
+    }
+
+    fn power(samples: &[f32]) -> f32 {
+        samples.iter().map(|v| v * v).sum::<f32>() / (samples.len() / 2) as f32
+    }
+
+    #[test]
+    fn splits_channels() {
+        let rate = 8e6;
+        let mut channelizer = Channelizer::new(rate, 32).unwrap();
+        let (a, a_sink) = collector();
+        let (b, b_sink) = collector();
+        let (c, c_sink) = collector();
+
+        channelizer.add_channel(ChannelSpec::new(1e6, 50e3).with_output_rate(62.5e3), a_sink).unwrap();
+        channelizer.add_channel(ChannelSpec::new(-2.13e6, 25e3), b_sink).unwrap();
+        channelizer.add_channel(ChannelSpec::new(3e6, 50e3), c_sink).unwrap();
+
+        // tones inside the first two channels, nothing in the third
+        let input = tones(&[1.01e6, -2.125e6], rate, 400_000);
+
+        for chunk in input.chunks(32768) {
+            channelizer.process(chunk);
+        }
+
+        assert_eq!(channelizer.num_channels(), 3);
+
+        let a = a.lock().unwrap();
+        let b = b.lock().unwrap();
+        let c = c.lock().unwrap();
+
+        assert_eq!(a.len(), 400_000 * 2 / 128);
+        assert!((power(&a[200..]) - 0.25).abs() < 0.02, "{}", power(&a[200..]));
+        assert!((power(&b[200..]) - 0.25).abs() < 0.02, "{}", power(&b[200..]));
+        assert!(power(&c[200..]) < 1e-5, "{}", power(&c[200..]));
+    }
+
+    #[test]
+    fn wide_channel_uses_full_rate_input() {
+        let rate = 8e6;
+        let mut channelizer = Channelizer::new(rate, 64).unwrap();
+        let (wide, wide_sink) = collector();
+
+        channelizer.add_channel(ChannelSpec::new(500e3, 400e3).with_output_rate(500e3), wide_sink).unwrap();
+        channelizer.process(&tones(&[600e3], rate, 160_000));
+
+        let wide = wide.lock().unwrap();
+
+        assert_eq!(channelizer.channels[0].source, Source::Wideband);
+        assert!((power(&wide[1000..]) - 0.25).abs() < 0.02, "{}", power(&wide[1000..]));
+    }
+
+    #[test]
+    fn add_and_remove_at_runtime() {
+        let rate = 4e6;
+        let mut channelizer = Channelizer::new(rate, 16).unwrap();
+        let control = channelizer.control();
+        let input = tones(&[250e3], rate, 16_384);
+
+        channelizer.process(&input);
+        assert_eq!(channelizer.num_channels(), 0);
+
+        let (samples, sink) = collector();
+        let id = control.add_channel(ChannelSpec::new(250e3, 100e3), sink).unwrap();
+
+        channelizer.process(&input);
+        assert_eq!(channelizer.num_channels(), 1);
+
+        let before = samples.lock().unwrap().len();
+        assert!(before > 0);
+
+        control.remove_channel(id).unwrap();
+        channelizer.process(&input);
+
+        assert_eq!(channelizer.num_channels(), 0);
+        assert_eq!(samples.lock().unwrap().len(), before);
+    }
+
+    #[test]
+    fn rejects_bad_plans() {
+        let channelizer = Channelizer::new(4e6, 16).unwrap();
+
+        assert!(Channelizer::new(4e6, 12).is_err());
+        assert!(channelizer.add_channel(ChannelSpec::new(1.99e6, 100e3), |_| ()).is_err());
+        assert!(channelizer.add_channel(ChannelSpec::new(0.0, 100e3).with_output_rate(33e3), |_| ()).is_err());
+    }
+}
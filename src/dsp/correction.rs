@@ -0,0 +1,353 @@
+use std::sync::{Arc, Mutex};
+
+use crate::dsp::Stage;
+
+/// Default DC removal time constant, in samples
+pub const DEFAULT_DC_TIME_CONSTANT: f64 = 100_000.0;
+
+/// Default IQ imbalance estimation time constant, in samples
+pub const DEFAULT_IQ_TIME_CONSTANT: f64 = 1_000_000.0;
+
+/// The DC offset currently being removed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DcEstimate {
+    pub i: f32,
+    pub q: f32
+}
+
+/// The IQ imbalance currently being corrected: Q is `gain` times larger than I,
+/// and leads it by `phase` radians on top of the ideal 90 degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IqEstimate {
+    pub gain: f32,
+    pub phase: f32
+}
+
+impl Default for IqEstimate {
+    fn default() -> IqEstimate {
+        IqEstimate { gain: 1.0, phase: 0.0 }
+    }
+}
+
+/// Removes the DC offset with a single-pole running average
+#[derive(Debug, Clone)]
+pub struct DcBlocker {
+    alpha: f64,
+    mean: (f64, f64)
+}
+
+impl DcBlocker {
+    /// * `time_constant` - number of samples the running average is taken over
+    pub fn new(time_constant: f64) -> DcBlocker {
+        DcBlocker {
+            alpha: 1.0 / time_constant.max(1.0),
+            mean: (0.0, 0.0)
+        }
+    }
+
+    pub fn set_time_constant(&mut self, time_constant: f64) {
+        self.alpha = 1.0 / time_constant.max(1.0);
+    }
+
+    pub fn estimate(&self) -> DcEstimate {
+        DcEstimate { i: self.mean.0 as f32, q: self.mean.1 as f32 }
+    }
+
+    fn correct(&mut self, samples: &mut [f32]) {
+        let alpha = self.alpha;
+        let (mut mean_i, mut mean_q) = self.mean;
+
+        for iq in samples.chunks_exact_mut(2) {
+            mean_i += alpha * (iq[0] as f64 - mean_i);
+            mean_q += alpha * (iq[1] as f64 - mean_q);
+
+            iq[0] -= mean_i as f32;
+            iq[1] -= mean_q as f32;
+        }
+
+        self.mean = (mean_i, mean_q);
+    }
+}
+
+impl Stage for DcBlocker {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let start = output.len();
+
+        output.extend_from_slice(input);
+        self.correct(&mut output[start..]);
+    }
+
+    fn reset(&mut self) {
+        self.mean = (0.0, 0.0);
+    }
+}
+
+/// Blind IQ gain/phase imbalance estimation and correction.
+///
+/// Relies on I and Q of a real signal having equal power and being uncorrelated on average,
+/// so the running powers give the gain error and the running cross-correlation gives the phase error.
+#[derive(Debug, Clone)]
+pub struct IqBalancer {
+    alpha: f64,
+    power_i: f64,
+    power_q: f64,
+    cross: f64
+}
+
+impl IqBalancer {
+    /// * `time_constant` - number of samples the running statistics are taken over
+    pub fn new(time_constant: f64) -> IqBalancer {
+        IqBalancer {
+            alpha: 1.0 / time_constant.max(1.0),
+            power_i: 0.0,
+            power_q: 0.0,
+            cross: 0.0
+        }
+    }
+
+    pub fn set_time_constant(&mut self, time_constant: f64) {
+        self.alpha = 1.0 / time_constant.max(1.0);
+    }
+
+    pub fn estimate(&self) -> IqEstimate {
+        if self.power_i <= 0.0 || self.power_q <= 0.0 {
+            return IqEstimate::default();
+        }
+
+        let sin_phase = (self.cross / (self.power_i * self.power_q).sqrt()).clamp(-0.99, 0.99);
+
+        IqEstimate {
+            gain: (self.power_q / self.power_i).sqrt() as f32,
+            phase: sin_phase.asin() as f32
+        }
+    }
+
+    fn correct(&mut self, samples: &mut [f32]) {
+        // the correction is held constant over a buffer, and the statistics updated as we go
+        let estimate = self.estimate();
+        let inv_gain = 1.0 / estimate.gain;
+        let (sin_phase, cos_phase) = (estimate.phase.sin(), estimate.phase.cos());
+        let alpha = self.alpha;
+
+        for iq in samples.chunks_exact_mut(2) {
+            let (i, q) = (iq[0] as f64, iq[1] as f64);
+
+            self.power_i += alpha * (i * i - self.power_i);
+            self.power_q += alpha * (q * q - self.power_q);
+            self.cross += alpha * (i * q - self.cross);
+
+            iq[1] = (iq[1] * inv_gain - iq[0] * sin_phase) / cos_phase;
+        }
+    }
+}
+
+impl Stage for IqBalancer {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let start = output.len();
+
+        output.extend_from_slice(input);
+        self.correct(&mut output[start..]);
+    }
+
+    fn reset(&mut self) {
+        self.power_i = 0.0;
+        self.power_q = 0.0;
+        self.cross = 0.0;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ControlState {
+    dc_enabled: bool,
+    iq_enabled: bool,
+    dc_time_constant: f64,
+    iq_time_constant: f64,
+    dc: DcEstimate,
+    iq: IqEstimate
+}
+
+/// Handle for switching and tuning a running `Correction` stage, and reading back its estimates
+#[derive(Debug, Clone)]
+pub struct CorrectionControl {
+    state: Arc<Mutex<ControlState>>
+}
+
+impl CorrectionControl {
+    pub fn set_dc_enabled(&self, enabled: bool) {
+        self.state.lock().unwrap().dc_enabled = enabled;
+    }
+
+    pub fn set_iq_enabled(&self, enabled: bool) {
+        self.state.lock().unwrap().iq_enabled = enabled;
+    }
+
+    pub fn set_dc_time_constant(&self, time_constant: f64) {
+        self.state.lock().unwrap().dc_time_constant = time_constant;
+    }
+
+    pub fn set_iq_time_constant(&self, time_constant: f64) {
+        self.state.lock().unwrap().iq_time_constant = time_constant;
+    }
+
+    pub fn dc_enabled(&self) -> bool {
+        self.state.lock().unwrap().dc_enabled
+    }
+
+    pub fn iq_enabled(&self) -> bool {
+        self.state.lock().unwrap().iq_enabled
+    }
+
+    /// The DC offset as of the last processed buffer
+    pub fn dc_estimate(&self) -> DcEstimate {
+        self.state.lock().unwrap().dc
+    }
+
+    /// The IQ imbalance as of the last processed buffer
+    pub fn iq_estimate(&self) -> IqEstimate {
+        self.state.lock().unwrap().iq
+    }
+}
+
+/// DC offset removal followed by IQ imbalance correction, each of which can be switched
+/// and tuned while streaming through the `CorrectionControl` handle
+#[derive(Debug)]
+pub struct Correction {
+    dc: DcBlocker,
+    iq: IqBalancer,
+    control: CorrectionControl
+}
+
+impl Correction {
+    /// Creates the stage with both blocks enabled and the default time constants
+    pub fn new() -> Correction {
+        let state = ControlState {
+            dc_enabled: true,
+            iq_enabled: true,
+            dc_time_constant: DEFAULT_DC_TIME_CONSTANT,
+            iq_time_constant: DEFAULT_IQ_TIME_CONSTANT,
+            dc: DcEstimate::default(),
+            iq: IqEstimate::default()
+        };
+
+        Correction {
+            dc: DcBlocker::new(DEFAULT_DC_TIME_CONSTANT),
+            iq: IqBalancer::new(DEFAULT_IQ_TIME_CONSTANT),
+            control: CorrectionControl { state: Arc::new(Mutex::new(state)) }
+        }
+    }
+
+    /// Returns a handle that stays valid after the stage is moved into a `Pipeline`
+    pub fn control(&self) -> CorrectionControl {
+        self.control.clone()
+    }
+}
+
+impl Default for Correction {
+    fn default() -> Correction {
+        Correction::new()
+    }
+}
+
+impl Stage for Correction {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let settings = self.control.state.lock().unwrap().clone();
+        let start = output.len();
+
+        output.extend_from_slice(input);
+
+        if settings.dc_enabled {
+            self.dc.set_time_constant(settings.dc_time_constant);
+            self.dc.correct(&mut output[start..]);
+        }
+
+        if settings.iq_enabled {
+            self.iq.set_time_constant(settings.iq_time_constant);
+            self.iq.correct(&mut output[start..]);
+        }
+
+        let mut state = self.control.state.lock().unwrap();
+
+        state.dc = self.dc.estimate();
+        state.iq = self.iq.estimate();
+    }
+
+    fn reset(&mut self) {
+        self.dc.reset();
+        self.iq.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// A tone with a DC offset and IQ imbalance applied
+    fn impaired_tone(len: usize, dc: (f32, f32), gain: f32, phase: f32) -> Vec<f32> {
+        (0..len).flat_map(|n| {
+            let w = 2.0 * PI * 0.01 * n as f32;
+
+            vec![w.cos() + dc.0, gain * (w.sin() * phase.cos() + w.cos() * phase.sin()) + dc.1]
+        }).collect()
+    }
+
+    #[test]
+    fn dc_blocker_removes_offset() {
+        let mut dc = DcBlocker::new(10_000.0);
+        let input = impaired_tone(50_000, (0.25, -0.1), 1.0, 0.0);
+        let mut output = Vec::new();
+
+        dc.process(&input, &mut output);
+
+        let estimate = dc.estimate();
+
+        assert!((estimate.i - 0.25).abs() < 0.01, "{:?}", estimate);
+        assert!((estimate.q + 0.1).abs() < 0.01, "{:?}", estimate);
+    }
+
+    #[test]
+    fn iq_balancer_estimates_and_corrects() {
+        let mut iq = IqBalancer::new(10_000.0);
+        let input = impaired_tone(200_000, (0.0, 0.0), 1.2, 0.1);
+        let mut output = Vec::new();
+
+        for chunk in input.chunks(8192) {
+            output.clear();
+            iq.process(chunk, &mut output);
+        }
+
+        let estimate = iq.estimate();
+
+        assert!((estimate.gain - 1.2).abs() < 0.02, "{:?}", estimate);
+        assert!((estimate.phase - 0.1).abs() < 0.02, "{:?}", estimate);
+
+        // the corrected output should be back on the unit circle
+        for pair in output.chunks_exact(2) {
+            let mag = (pair[0] * pair[0] + pair[1] * pair[1]).sqrt();
+
+            assert!((mag - 1.0).abs() < 0.05, "magnitude {}", mag);
+        }
+    }
+
+    #[test]
+    fn correction_control_switches_blocks() {
+        let mut correction = Correction::new();
+        let control = correction.control();
+        let input = impaired_tone(1_000, (0.5, 0.5), 1.0, 0.0);
+        let mut output = Vec::new();
+
+        control.set_dc_enabled(false);
+        control.set_iq_enabled(false);
+        correction.process(&input, &mut output);
+
+        assert_eq!(input, output);
+        assert_eq!(control.dc_estimate(), DcEstimate::default());
+
+        control.set_dc_enabled(true);
+        control.set_dc_time_constant(10.0);
+        output.clear();
+        correction.process(&input, &mut output);
+
+        assert!(control.dc_estimate().i > 0.1);
+    }
+}
@@ -0,0 +1,128 @@
+use std::f64::consts::PI;
+
+/// Radix-2 complex FFT over interleaved IQ samples
+#[derive(Debug, Clone)]
+pub struct Fft {
+    size: usize,
+    inverse: bool,
+    twiddles: Vec<(f32, f32)>,
+    bit_reverse: Vec<usize>
+}
+
+impl Fft {
+    /// Plans a forward transform; `size` must be a power of two
+    pub fn new(size: usize) -> Fft {
+        Fft::plan(size, false)
+    }
+
+    /// Plans an inverse transform (positive exponent, no 1/N scaling); `size` must be a power of two
+    pub fn new_inverse(size: usize) -> Fft {
+        Fft::plan(size, true)
+    }
+
+    fn plan(size: usize, inverse: bool) -> Fft {
+        assert!(size.is_power_of_two(), "FFT size must be a power of two");
+
+        let sign = if inverse { 1.0 } else { -1.0 };
+        let bits = size.trailing_zeros();
+
+        let twiddles = (0..size / 2).map(|k| {
+            let angle = sign * 2.0 * PI * k as f64 / size as f64;
+
+            (angle.cos() as f32, angle.sin() as f32)
+        }).collect();
+
+        let bit_reverse = (0..size).map(|i| if bits == 0 { 0 } else { i.reverse_bits() >> (std::mem::size_of::<usize>() as u32 * 8 - bits) }).collect();
+
+        Fft { size, inverse, twiddles, bit_reverse }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_inverse(&self) -> bool {
+        self.inverse
+    }
+
+    /// Transforms `data`, which holds `size()` interleaved IQ pairs, in place
+    pub fn process(&self, data: &mut [f32]) {
+        assert_eq!(data.len(), self.size * 2, "FFT buffer has the wrong length");
+
+        for (i, &j) in self.bit_reverse.iter().enumerate() {
+            if i < j {
+                data.swap(2 * i, 2 * j);
+                data.swap(2 * i + 1, 2 * j + 1);
+            }
+        }
+
+        let mut len = 2;
+
+        while len <= self.size {
+            let half = len / 2;
+            let stride = self.size / len;
+
+            for start in (0..self.size).step_by(len) {
+                for k in 0..half {
+                    let (w_re, w_im) = self.twiddles[k * stride];
+                    let a = 2 * (start + k);
+                    let b = 2 * (start + k + half);
+
+                    let t_re = data[b] * w_re - data[b + 1] * w_im;
+                    let t_im = data[b] * w_im + data[b + 1] * w_re;
+
+                    data[b] = data[a] - t_re;
+                    data[b + 1] = data[a + 1] - t_im;
+                    data[a] += t_re;
+                    data[a + 1] += t_im;
+                }
+            }
+
+            len *= 2;
+        }
+    }
+}
+
+/// A periodic Hann window of `size` points, for tapering FFT input
+pub fn hann_window(size: usize) -> Vec<f32> {
+    (0..size).map(|n| (0.5 - 0.5 * (2.0 * PI * n as f64 / size as f64).cos()) as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_lands_in_its_bin() {
+        let size = 64;
+        let fft = Fft::new(size);
+        let mut data = [1.0f32, 0.0].repeat(size);
+
+        crate::dsp::Nco::new(5.0, size as f64).mix_in_place(&mut data);
+        fft.process(&mut data);
+
+        for k in 0..size {
+            let mag = (data[2 * k] * data[2 * k] + data[2 * k + 1] * data[2 * k + 1]).sqrt();
+
+            if k == 5 {
+                assert!((mag - size as f32).abs() < 1e-3);
+            } else {
+                assert!(mag < 1e-3, "bin {} = {}", k, mag);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_round_trip() {
+        let size = 256;
+        let input = (0..size * 2).map(|n| ((n * 7919) % 100) as f32 / 100.0).collect::<Vec<_>>();
+        let mut data = input.clone();
+
+        Fft::new(size).process(&mut data);
+        Fft::new_inverse(size).process(&mut data);
+
+        for (a, b) in input.iter().zip(data.iter()) {
+            assert!((a - b / size as f32).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::f64::consts::PI;
+
+use crate::dsp::Stage;
+use crate::error::Error;
+
+/// Designs a Hamming windowed-sinc low-pass filter with unity gain at DC.
+/// * `num_taps` - length of the filter; odd lengths give a whole-sample delay
+/// * `cutoff` - cutoff frequency as a fraction of the sample rate, in (0, 0.5)
+pub fn lowpass(num_taps: usize, cutoff: f64) -> Vec<f32> {
+    let mid = (num_taps as f64 - 1.0) / 2.0;
+
+    let taps = (0..num_taps).map(|n| {
+        let x = n as f64 - mid;
+        let sinc = if x == 0.0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * x).sin() / (PI * x) };
+        let window = if num_taps > 1 { 0.54 - 0.46 * (2.0 * PI * n as f64 / (num_taps as f64 - 1.0)).cos() } else { 1.0 };
+
+        sinc * window
+    }).collect::<Vec<_>>();
+
+    normalize(&taps)
+}
+
+/// Number of taps a Kaiser window design needs for the given transition width (as a fraction of the
+/// sample rate) and stopband attenuation; always odd. The transition width must be positive.
+pub fn kaiser_num_taps(transition: f64, attenuation_db: f64) -> Result<usize, Error> {
+    if transition.is_nan() || transition <= 0.0 {
+        return Err(Error::INVALID_PARAM(format!("Filter transition width {} must be positive", transition)));
+    }
+
+    let num_taps = ((attenuation_db - 8.0) / (2.285 * 2.0 * PI * transition)).ceil().max(1.0) as usize + 1;
+
+    Ok(num_taps | 1)
+}
+
+/// Designs a Kaiser windowed-sinc low-pass filter with unity gain at DC.
+/// * `passband` - edge of the passband as a fraction of the sample rate
+/// * `stopband` - edge of the stopband as a fraction of the sample rate; clamped to 0.5
+/// * `attenuation_db` - minimum stopband attenuation
+pub fn kaiser_lowpass(passband: f64, stopband: f64, attenuation_db: f64) -> Result<Vec<f32>, Error> {
+    kaiser_design(passband, stopband, attenuation_db, |_| 1.0)
+}
+
+/// Designs a Kaiser windowed low-pass filter that also flattens the passband droop of a CIC decimator.
+/// Band edges are fractions of the CIC *output* rate.
+/// * `cic_decimation` - decimation of the CIC filter in front of this one
+/// * `cic_stages` - number of integrator/comb stages in the CIC filter
+pub fn cic_compensator(passband: f64, stopband: f64, attenuation_db: f64, cic_decimation: usize, cic_stages: usize) -> Result<Vec<f32>, Error> {
+    kaiser_design(passband, stopband, attenuation_db, |f| {
+        // response is inverted up to the passband edge, and held flat beyond it
+        1.0 / cic_response(f.min(passband), cic_decimation, cic_stages)
+    })
+}
+
+/// Magnitude response of a CIC decimator at `freq`, a fraction of its output rate
+pub fn cic_response(freq: f64, decimation: usize, stages: usize) -> f64 {
+    if freq == 0.0 {
+        return 1.0;
+    }
+
+    let r = decimation as f64;
+    let num = (PI * freq).sin();
+    let den = r * (PI * freq / r).sin();
+
+    (num / den).abs().powi(stages as i32)
+}
+
+/// Windowed design of a low-pass filter whose passband follows `response`
+fn kaiser_design<R: Fn(f64) -> f64>(passband: f64, stopband: f64, attenuation_db: f64, response: R) -> Result<Vec<f32>, Error> {
+    let stopband = stopband.min(0.5);
+    let cutoff = (passband + stopband) / 2.0;
+    let num_taps = kaiser_num_taps(stopband - passband, attenuation_db)?;
+    let window = kaiser_window(num_taps, kaiser_beta(attenuation_db));
+    let mid = (num_taps as f64 - 1.0) / 2.0;
+
+    // the ideal low-pass is exact; only how far `response` strays from flat is numerically
+    // integrated, sampled once across the passband, so long flat filters don't alias
+    const STEPS: usize = 512;
+    let df = cutoff / STEPS as f64;
+    let desired = (0..=STEPS).map(|k| {
+        let weight = if k == 0 || k == STEPS { 0.5 } else { 1.0 };
+
+        (k as f64 * df, weight * (response(k as f64 * df) - 1.0))
+    }).filter(|(_, d)| *d != 0.0).collect::<Vec<_>>();
+
+    let taps = window.iter().enumerate().map(|(n, w)| {
+        let x = n as f64 - mid;
+        let sinc = if x == 0.0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * x).sin() / (PI * x) };
+        let deviation :f64 = desired.iter().map(|(f, d)| d * (2.0 * PI * f * x).cos()).sum();
+
+        (sinc + 2.0 * deviation * df) * w
+    }).collect::<Vec<_>>();
+
+    Ok(normalize(&taps))
+}
+
+fn kaiser_beta(attenuation_db: f64) -> f64 {
+    if attenuation_db > 50.0 {
+        0.1102 * (attenuation_db - 8.7)
+    } else if attenuation_db > 21.0 {
+        0.5842 * (attenuation_db - 21.0).powf(0.4) + 0.07886 * (attenuation_db - 21.0)
+    } else {
+        0.0
+    }
+}
+
+fn kaiser_window(num_taps: usize, beta: f64) -> Vec<f64> {
+    let denom = bessel_i0(beta);
+    let m = num_taps as f64 - 1.0;
+
+    (0..num_taps).map(|n| {
+        if num_taps == 1 {
+            return 1.0;
+        }
+
+        let r = 2.0 * n as f64 / m - 1.0;
+
+        bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt()) / denom
+    }).collect()
+}
+
+/// Zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+
+    for k in 1..50 {
+        term *= (half / k as f64) * (half / k as f64);
+        sum += term;
+
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+
+    sum
+}
+
+/// Scales `taps` so they sum to 1
+pub(crate) fn normalize(taps: &[f64]) -> Vec<f32> {
+    let sum :f64 = taps.iter().sum();
+
+    taps.iter().map(|t| (t / sum) as f32).collect()
+}
+
+/// Interleaves a reversed copy of `taps` with itself, so a window of interleaved IQ samples
+/// can be multiplied element-by-element
+pub(crate) fn interleave_reversed(taps: &[f32]) -> Vec<f32> {
+    taps.iter().rev().flat_map(|t| vec![*t, *t]).collect()
+}
+
+/// Dot product of interleaved taps (see `interleave_reversed`) with a window of interleaved IQ samples.
+/// Written with independent accumulators so the compiler can vectorize it.
+#[inline]
+pub(crate) fn dot_iq(taps: &[f32], window: &[f32]) -> (f32, f32) {
+    let mut acc = [0.0f32; 8];
+    let whole = taps.len() / 8 * 8;
+
+    for (t, w) in taps[..whole].chunks_exact(8).zip(window[..whole].chunks_exact(8)) {
+        for k in 0..8 {
+            acc[k] += t[k] * w[k];
+        }
+    }
+
+    let mut i = acc[0] + acc[2] + acc[4] + acc[6];
+    let mut q = acc[1] + acc[3] + acc[5] + acc[7];
+
+    for (t, w) in taps[whole..].chunks_exact(2).zip(window[whole..].chunks_exact(2)) {
+        i += t[0] * w[0];
+        q += t[1] * w[1];
+    }
+
+    (i, q)
+}
+
+/// FIR filter that only computes every `decimation`-th output
+#[derive(Debug, Clone)]
+pub struct FirDecimator {
+    taps: Vec<f32>,
+    interleaved: Vec<f32>,
+    decimation: usize,
+    history: Vec<f32>,
+    next: usize
+}
+
+impl FirDecimator {
+    pub fn new(taps: Vec<f32>, decimation: usize) -> FirDecimator {
+        assert!(!taps.is_empty(), "FIR filter needs at least one tap");
+        assert!(decimation > 0, "Decimation must be at least 1");
+
+        let interleaved = interleave_reversed(&taps);
+        let keep = taps.len() - 1;
+
+        FirDecimator {
+            taps,
+            interleaved,
+            decimation,
+            history: vec![0.0; keep * 2],
+            next: keep
+        }
+    }
+
+    /// A low-pass decimator with a cutoff at 80% of the output Nyquist frequency
+    pub fn lowpass(decimation: usize) -> FirDecimator {
+        FirDecimator::new(lowpass(16 * decimation + 1, 0.4 / decimation as f64), decimation)
+    }
+
+    pub fn taps(&self) -> &[f32] {
+        &self.taps
+    }
+
+    pub fn decimation(&self) -> usize {
+        self.decimation
+    }
+}
+
+impl Stage for FirDecimator {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let num_taps = self.taps.len();
+
+        self.history.extend_from_slice(input);
+
+        let num_samples = self.history.len() / 2;
+        let mut n = self.next;
+
+        output.reserve((num_samples.saturating_sub(n) / self.decimation + 1) * 2);
+
+        while n < num_samples {
+            let start = n + 1 - num_taps;
+            let (i, q) = dot_iq(&self.interleaved, &self.history[start * 2..(n + 1) * 2]);
+
+            output.push(i);
+            output.push(q);
+
+            n += self.decimation;
+        }
+
+        // only keep what the next output needs
+        let consumed = num_samples - (num_taps - 1);
+
+        self.history.drain(..consumed * 2);
+        self.next = n - consumed;
+    }
+
+    fn reset(&mut self) {
+        let keep = self.taps.len() - 1;
+
+        self.history.clear();
+        self.history.resize(keep * 2, 0.0);
+        self.next = keep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowpass_has_unity_dc_gain() {
+        let taps = lowpass(31, 0.1);
+        let sum :f32 = taps.iter().sum();
+
+        assert_eq!(taps.len(), 31);
+        assert!((sum - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn kaiser_lowpass_meets_attenuation() {
+        let taps = kaiser_lowpass(0.1, 0.15, 60.0).unwrap();
+
+        let response = |f: f64| {
+            let (re, im) = taps.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, t)| {
+                let w = 2.0 * PI * f * n as f64;
+
+                (re + *t as f64 * w.cos(), im - *t as f64 * w.sin())
+            });
+
+            20.0 * (re * re + im * im).sqrt().log10()
+        };
+
+        assert!(response(0.05).abs() < 0.1);
+        assert!(response(0.2) < -59.0, "{} dB", response(0.2));
+        assert!(response(0.4) < -59.0, "{} dB", response(0.4));
+    }
+
+    #[test]
+    fn cic_compensator_boosts_passband_edge() {
+        let taps = cic_compensator(0.2, 0.3, 60.0, 16, 4).unwrap();
+        let plain = kaiser_lowpass(0.2, 0.3, 60.0).unwrap();
+
+        assert!(kaiser_lowpass(0.2, 0.2, 60.0).is_err());
+
+        let gain_at = |taps: &[f32], f: f64| {
+            let (re, im) = taps.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, t)| {
+                let w = 2.0 * PI * f * n as f64;
+
+                (re + *t as f64 * w.cos(), im - *t as f64 * w.sin())
+            });
+
+            (re * re + im * im).sqrt()
+        };
+
+        let compensated = gain_at(&taps, 0.18) * cic_response(0.18, 16, 4);
+
+        assert!(gain_at(&taps, 0.18) > gain_at(&plain, 0.18));
+        assert!((compensated - 1.0).abs() < 0.05, "{}", compensated);
+    }
+
+    #[test]
+    fn decimates_across_buffer_boundaries() {
+        let mut whole = FirDecimator::lowpass(4);
+        let mut split = FirDecimator::lowpass(4);
+        let input = (0..1000).map(|n| (n as f32 * 0.01).sin()).collect::<Vec<_>>();
+
+        let mut expected = Vec::new();
+        whole.process(&input, &mut expected);
+
+        let mut actual = Vec::new();
+        for chunk in input.chunks(14) {
+            split.process(chunk, &mut actual);
+        }
+
+        assert_eq!(expected.len(), 500 / 4 * 2);
+        assert_eq!(expected.len(), actual.len());
+
+        for (e, a) in expected.iter().zip(actual.iter()) {
+            assert!((e - a).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_band_tone() {
+        let mut dec = FirDecimator::lowpass(8);
+        let mut nco = crate::dsp::Nco::new(3_000_000.0, 10_000_000.0);
+        let mut tone = [1.0f32, 0.0].repeat(8192);
+
+        nco.mix_in_place(&mut tone);
+
+        let mut output = Vec::new();
+        dec.process(&tone, &mut output);
+
+        let peak = output[200..].iter().fold(0.0f32, |m, v| m.max(v.abs()));
+
+        assert!(peak < 0.01, "peak {} too large", peak);
+    }
+}
@@ -0,0 +1,122 @@
+//! Processing stages for the interleaved IQ (`[I, Q, I, Q, ...]`) buffers handed out by `Device::start_rx`
+
+pub mod nco;
+pub mod fir;
+pub mod offset;
+pub mod correction;
+pub mod cic;
+pub mod channel;
+pub mod fft;
+pub mod channelizer;
+pub mod resampler;
+
+pub use self::nco::Nco;
+pub use self::fir::FirDecimator;
+pub use self::offset::{OffsetTuning, OffsetTuner};
+pub use self::correction::{Correction, CorrectionControl, DcBlocker, IqBalancer};
+pub use self::cic::CicDecimator;
+pub use self::channel::ChannelFilter;
+pub use self::fft::{Fft, hann_window};
+pub use self::channelizer::{Channelizer, ChannelizerControl, ChannelSpec, ChannelId, ChannelSink};
+pub use self::resampler::Resampler;
+
+/// A single step in an RX processing chain
+pub trait Stage {
+    /// Consume the interleaved IQ samples in `input`, appending any produced samples to `output`.
+    /// State is carried across calls so a stream can be processed one buffer at a time.
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>);
+
+    /// Clears any history so the next buffer is treated as the start of a new stream
+    fn reset(&mut self);
+}
+
+/// An ordered chain of `Stage`s
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage + Send>>,
+    buffers: [Vec<f32>; 2]
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline {
+            stages: Vec::new(),
+            buffers: [Vec::new(), Vec::new()]
+        }
+    }
+
+    /// Appends a stage to the end of the chain
+    pub fn with<S: Stage + Send + 'static>(mut self, stage: S) -> Pipeline {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Appends a stage to the end of the chain
+    pub fn push<S: Stage + Send + 'static>(&mut self, stage: S) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Runs `input` through every stage, returning the output of the last one
+    pub fn process(&mut self, input: &[f32]) -> &[f32] {
+        let mut current = 0;
+
+        self.buffers[current].clear();
+        self.buffers[current].extend_from_slice(input);
+
+        for stage in self.stages.iter_mut() {
+            let (first, second) = self.buffers.split_at_mut(1);
+            let (src, dst) = if current == 0 { (&first[0], &mut second[0]) } else { (&second[0], &mut first[0]) };
+
+            dst.clear();
+            stage.process(src, dst);
+
+            current = 1 - current;
+        }
+
+        &self.buffers[current]
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scale(f32);
+
+    impl Stage for Scale {
+        fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+            output.extend(input.iter().map(|v| v * self.0));
+        }
+
+        fn reset(&mut self) { }
+    }
+
+    #[test]
+    fn empty_pipeline_passes_through() {
+        let mut pipeline = Pipeline::new();
+
+        assert_eq!(pipeline.process(&[1.0, 2.0]), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn stages_run_in_order() {
+        let mut pipeline = Pipeline::new().with(Scale(2.0)).with(Scale(3.0)).with(Scale(0.5));
+
+        assert_eq!(pipeline.process(&[1.0, -1.0]), &[3.0, -3.0]);
+    }
+}
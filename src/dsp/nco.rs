@@ -0,0 +1,124 @@
+use std::f64::consts::PI;
+
+use crate::dsp::Stage;
+
+/// How often the rotating phasor is re-normalized to keep its magnitude at 1
+const RENORMALIZE_INTERVAL: usize = 1024;
+
+/// Numerically controlled oscillator used to shift a stream in frequency
+#[derive(Debug, Clone)]
+pub struct Nco {
+    sample_rate: f64,
+    freq_hz: f64,
+    step: (f64, f64),
+    phasor: (f64, f64),
+    count: usize
+}
+
+impl Nco {
+    /// Creates an oscillator that shifts samples by `freq_hz` (positive moves the spectrum up)
+    pub fn new(freq_hz: f64, sample_rate: f64) -> Nco {
+        let mut nco = Nco {
+            sample_rate,
+            freq_hz: 0.0,
+            step: (1.0, 0.0),
+            phasor: (1.0, 0.0),
+            count: 0
+        };
+
+        nco.set_frequency(freq_hz);
+
+        nco
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.freq_hz
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Changes the shift frequency while keeping the phase continuous
+    pub fn set_frequency(&mut self, freq_hz: f64) {
+        let delta = 2.0 * PI * freq_hz / self.sample_rate;
+
+        self.freq_hz = freq_hz;
+        self.step = (delta.cos(), delta.sin());
+    }
+
+    /// Returns the current oscillator value as `(cos, sin)` and advances the phase one sample
+    #[inline]
+    pub fn advance(&mut self) -> (f32, f32) {
+        let (re, im) = self.phasor;
+        let (step_re, step_im) = self.step;
+
+        self.phasor = (re * step_re - im * step_im, re * step_im + im * step_re);
+        self.count += 1;
+
+        if self.count == RENORMALIZE_INTERVAL {
+            let mag = (self.phasor.0 * self.phasor.0 + self.phasor.1 * self.phasor.1).sqrt();
+
+            self.phasor = (self.phasor.0 / mag, self.phasor.1 / mag);
+            self.count = 0;
+        }
+
+        (re as f32, im as f32)
+    }
+
+    /// Multiplies each IQ pair in `samples` by the oscillator
+    pub fn mix_in_place(&mut self, samples: &mut [f32]) {
+        for iq in samples.chunks_exact_mut(2) {
+            let (c, s) = self.advance();
+            let (i, q) = (iq[0], iq[1]);
+
+            iq[0] = i * c - q * s;
+            iq[1] = i * s + q * c;
+        }
+    }
+}
+
+impl Stage for Nco {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let start = output.len();
+
+        output.extend_from_slice(input);
+        self.mix_in_place(&mut output[start..]);
+    }
+
+    fn reset(&mut self) {
+        self.phasor = (1.0, 0.0);
+        self.count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_dc_to_tone() {
+        let mut nco = Nco::new(1_000.0, 8_000.0);
+        let mut samples = [1.0f32, 0.0].repeat(8);
+
+        nco.mix_in_place(&mut samples);
+
+        // quarter of the way around the circle every 2 samples
+        assert!((samples[4] - 0.0).abs() < 1e-6);
+        assert!((samples[5] - 1.0).abs() < 1e-6);
+        assert!((samples[8] + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn magnitude_stays_stable() {
+        let mut nco = Nco::new(123_456.7, 20_000_000.0);
+
+        for _ in 0..1_000_000 {
+            nco.advance();
+        }
+
+        let (c, s) = nco.advance();
+
+        assert!(((c * c + s * s) - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::dsp::{Nco, FirDecimator, Stage};
+use crate::error::Error;
+
+/// Offset tuning parameters.
+///
+/// The HackRF has a strong DC/LO-leakage spike at the center of every capture. With offset tuning the
+/// hardware is tuned to `target + offset_hz`, and the `OffsetTuner` stage mixes the stream back so the
+/// target sits at 0 Hz while the spike sits at `-offset_hz`, where the optional filter can remove it.
+#[derive(Debug, Clone, Copy)]
+pub struct OffsetTuning {
+    sample_rate: f64,
+    offset_hz: i64,
+    decimation: usize,
+    filter: bool
+}
+
+impl OffsetTuning {
+    /// * `sample_rate` - the rate passed to `Device::set_sample_rate`
+    /// * `offset_hz` - how far from the target to tune the hardware; must be within +/- half the sample rate
+    pub fn new(sample_rate: f64, offset_hz: i64) -> OffsetTuning {
+        OffsetTuning {
+            sample_rate,
+            offset_hz,
+            decimation: 1,
+            filter: false
+        }
+    }
+
+    /// Low-pass filters and keeps every `decimation`-th sample after mixing; enables the filter
+    pub fn with_decimation(mut self, decimation: usize) -> OffsetTuning {
+        self.decimation = decimation;
+        self.filter = true;
+        self
+    }
+
+    /// Enables or disables the low-pass filter after mixing
+    pub fn with_filter(mut self, filter: bool) -> OffsetTuning {
+        self.filter = filter;
+        self
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn offset_hz(&self) -> i64 {
+        self.offset_hz
+    }
+
+    pub fn decimation(&self) -> usize {
+        self.decimation
+    }
+
+    /// Rate of the samples coming out of the `OffsetTuner` stage
+    pub fn output_rate(&self) -> f64 {
+        self.sample_rate / self.decimation as f64
+    }
+
+    /// Checks the parameters are usable
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.decimation == 0 {
+            return Err(Error::INVALID_PARAM(String::from("decimation must be at least 1")));
+        }
+
+        if self.offset_hz.abs() as f64 >= self.sample_rate / 2.0 {
+            let err_str = format!("offset_hz {} must be less than half the sample rate {}", self.offset_hz, self.sample_rate);
+            return Err(Error::INVALID_PARAM(err_str));
+        }
+
+        if self.filter && self.offset_hz.abs() as f64 <= self.output_rate() * 0.4 {
+            warn!("DC spike at {} Hz falls inside the {} Hz passband", -self.offset_hz, self.output_rate() * 0.8);
+        }
+
+        Ok( () )
+    }
+
+    /// The frequency the hardware should be tuned to so `target_hz` ends up at 0 Hz
+    pub fn hardware_freq(&self, target_hz: u64) -> Result<u64, Error> {
+        let freq = target_hz as i128 + self.offset_hz as i128;
+
+        if freq < 0 || freq > u64::MAX as i128 {
+            let err_str = format!("target_hz {} + offset_hz {} is out of range", target_hz, self.offset_hz);
+            return Err(Error::INVALID_PARAM(err_str));
+        }
+
+        Ok(freq as u64)
+    }
+
+    /// Builds the stage that mixes the stream back to the target, filtering and decimating if requested
+    pub fn stage(&self) -> OffsetTuner {
+        let filter = if self.filter {
+            Some(FirDecimator::lowpass(self.decimation.max(1)))
+        } else {
+            None
+        };
+
+        OffsetTuner {
+            nco: Nco::new(self.offset_hz as f64, self.sample_rate),
+            filter,
+            mixed: Vec::new()
+        }
+    }
+}
+
+/// Mixes an offset-tuned stream back to baseband; built by `OffsetTuning::stage`
+#[derive(Debug, Clone)]
+pub struct OffsetTuner {
+    nco: Nco,
+    filter: Option<FirDecimator>,
+    mixed: Vec<f32>
+}
+
+impl Stage for OffsetTuner {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        match self.filter {
+            Some(ref mut filter) => {
+                self.mixed.clear();
+                self.nco.process(input, &mut self.mixed);
+                filter.process(&self.mixed, output);
+            },
+            None => self.nco.process(input, output)
+        }
+    }
+
+    fn reset(&mut self) {
+        self.nco.reset();
+
+        if let Some(ref mut filter) = self.filter {
+            filter.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardware_freq() {
+        let tuning = OffsetTuning::new(10_000_000.0, -250_000);
+
+        assert_eq!(tuning.hardware_freq(100_000_000).unwrap(), 99_750_000);
+        assert!(tuning.hardware_freq(100).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        assert!(OffsetTuning::new(10_000_000.0, 5_000_000).validate().is_err());
+        assert!(OffsetTuning::new(10_000_000.0, 250_000).with_decimation(0).validate().is_err());
+        assert!(OffsetTuning::new(10_000_000.0, 250_000).with_decimation(8).validate().is_ok());
+    }
+
+    #[test]
+    fn target_lands_at_dc_and_spike_is_removed() {
+        let sample_rate = 8_000_000.0;
+        let offset = 1_000_000;
+        let mut tuner = OffsetTuning::new(sample_rate, offset).with_decimation(8).stage();
+
+        // the hardware sees the target at -offset, and its own DC spike at 0 Hz
+        let mut target = [0.5f32, 0.0].repeat(16384);
+        Nco::new(-offset as f64, sample_rate).mix_in_place(&mut target);
+
+        let input = target.iter().enumerate().map(|(n, v)| if n % 2 == 0 { v + 0.5 } else { *v }).collect::<Vec<_>>();
+
+        let mut output = Vec::new();
+        tuner.process(&input, &mut output);
+
+        assert_eq!(output.len(), input.len() / 8);
+
+        // after settling only the target remains, as a constant at DC
+        for iq in output[400..].chunks_exact(2) {
+            assert!((iq[0] - 0.5).abs() < 0.01, "I = {}", iq[0]);
+            assert!(iq[1].abs() < 0.01, "Q = {}", iq[1]);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use crate::dsp::Stage;
+use crate::dsp::fir;
+use crate::error::Error;
+
+/// Stopband attenuation of the anti-aliasing/anti-imaging filter
+const ATTENUATION_DB: f64 = 60.0;
+
+/// Passband edge, as a fraction of the lower of the two rates
+const PASSBAND: f64 = 0.4;
+
+/// Ratios needing more polyphase branches than this use the fractional mode
+pub const MAX_RATIONAL_INTERPOLATION: usize = 1024;
+
+/// Number of polyphase branches, as a power of two, used by the fractional mode
+const FRACTIONAL_BITS: u32 = 8;
+
+/// Bits after the binary point of the fractional mode's position counter
+const POSITION_BITS: u32 = 32;
+
+#[derive(Debug, Clone)]
+enum Mode {
+    /// Exact L/M: output `k` sits at input position `k * M / L`
+    Rational { interpolation: usize, decimation: usize, phase: usize },
+    /// Position advances by a fixed-point `step` and neighbouring branches are interpolated
+    Fractional { step: u64, fraction: u64 }
+}
+
+/// Polyphase resampler for interleaved IQ streams.
+///
+/// In rational mode the output is exactly `interpolation / decimation` times as long as the input.
+/// Rates that don't reduce to a small ratio use the fractional mode, where the ratio is held in
+/// 32.32 fixed point; `ratio_error` gives the resulting relative error, which bounds the drift.
+/// State is carried across calls, so a stream can be fed in buffers of any size.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    input_rate: f64,
+    output_rate: f64,
+    branches: Vec<Vec<f32>>,
+    taps_per_branch: usize,
+    history: Vec<f32>,
+    next: usize,
+    mode: Mode,
+    pending: Vec<f32>,
+    scratch: Vec<f32>
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+impl Resampler {
+    /// Creates a resampler between two sample rates, using an exact ratio whenever the rates are whole
+    /// numbers of Hz that reduce to at most `MAX_RATIONAL_INTERPOLATION` branches
+    pub fn new(input_rate: f64, output_rate: f64) -> Result<Resampler, Error> {
+        if input_rate <= 0.0 || output_rate <= 0.0 {
+            return Err(Error::INVALID_PARAM(format!("rates must be positive: {} -> {}", input_rate, output_rate)));
+        }
+
+        if input_rate.fract() == 0.0 && output_rate.fract() == 0.0 {
+            let divisor = gcd(input_rate as u64, output_rate as u64);
+            let interpolation = (output_rate as u64 / divisor) as usize;
+            let decimation = (input_rate as u64 / divisor) as usize;
+
+            if interpolation <= MAX_RATIONAL_INTERPOLATION {
+                return Resampler::rational_with_rates(interpolation, decimation, input_rate);
+            }
+        }
+
+        Resampler::fractional(input_rate, output_rate)
+    }
+
+    /// Creates a resampler with an exact `interpolation / decimation` ratio
+    pub fn rational(interpolation: usize, decimation: usize) -> Result<Resampler, Error> {
+        Resampler::rational_with_rates(interpolation, decimation, decimation as f64)
+    }
+
+    fn rational_with_rates(interpolation: usize, decimation: usize, input_rate: f64) -> Result<Resampler, Error> {
+        if interpolation == 0 || decimation == 0 {
+            return Err(Error::INVALID_PARAM(String::from("interpolation and decimation must be at least 1")));
+        }
+
+        let divisor = gcd(interpolation as u64, decimation as u64) as usize;
+        let (interpolation, decimation) = (interpolation / divisor, decimation / divisor);
+        let output_rate = input_rate * interpolation as f64 / decimation as f64;
+
+        debug!("Rational resampler {}/{}", interpolation, decimation);
+
+        Resampler::build(input_rate, output_rate, interpolation, Mode::Rational { interpolation, decimation, phase: 0 })
+    }
+
+    /// Creates a resampler that always uses the fractional mode
+    pub fn fractional(input_rate: f64, output_rate: f64) -> Result<Resampler, Error> {
+        if input_rate <= 0.0 || output_rate <= 0.0 {
+            return Err(Error::INVALID_PARAM(format!("rates must be positive: {} -> {}", input_rate, output_rate)));
+        }
+
+        let step = (input_rate / output_rate * (1u64 << POSITION_BITS) as f64).round() as u64;
+
+        debug!("Fractional resampler {} -> {} (step {:#x})", input_rate, output_rate, step);
+
+        Resampler::build(input_rate, output_rate, 1 << FRACTIONAL_BITS, Mode::Fractional { step, fraction: 0 })
+    }
+
+    /// Designs the prototype filter at `branches` times the input rate and splits it into branches
+    fn build(input_rate: f64, output_rate: f64, branches: usize, mode: Mode) -> Result<Resampler, Error> {
+        let lower = input_rate.min(output_rate);
+        let upsampled = input_rate * branches as f64;
+        let prototype = fir::kaiser_lowpass(PASSBAND * lower / upsampled, (1.0 - PASSBAND) * lower / upsampled, ATTENUATION_DB)?;
+        let taps_per_branch = prototype.len() / branches + 1;
+
+        // one extra branch in fractional mode, equal to the first delayed by a sample, to interpolate towards
+        let num_branches = match mode {
+            Mode::Rational { .. } => branches,
+            Mode::Fractional { .. } => branches + 1
+        };
+
+        let branches = (0..num_branches).map(|p| {
+            let taps = (0..taps_per_branch).map(|j| {
+                // each branch sees 1/branches of the zero-stuffed input, so scale to keep unity gain
+                prototype.get(p + j * branches).map(|t| t * branches as f32).unwrap_or(0.0)
+            }).collect::<Vec<_>>();
+
+            fir::interleave_reversed(&taps)
+        }).collect();
+
+        let keep = taps_per_branch - 1;
+
+        Ok(Resampler {
+            input_rate,
+            output_rate,
+            branches,
+            taps_per_branch,
+            history: vec![0.0; keep * 2],
+            next: keep,
+            mode,
+            pending: Vec::new(),
+            scratch: Vec::new()
+        })
+    }
+
+    pub fn input_rate(&self) -> f64 {
+        self.input_rate
+    }
+
+    /// The output rate actually produced
+    pub fn output_rate(&self) -> f64 {
+        match self.mode {
+            Mode::Rational { .. } => self.output_rate,
+            Mode::Fractional { step, .. } => self.input_rate * (1u64 << POSITION_BITS) as f64 / step as f64
+        }
+    }
+
+    /// The `(interpolation, decimation)` ratio, when in rational mode
+    pub fn ratio(&self) -> Option<(usize, usize)> {
+        match self.mode {
+            Mode::Rational { interpolation, decimation, .. } => Some( (interpolation, decimation) ),
+            Mode::Fractional { .. } => None
+        }
+    }
+
+    /// Relative difference between the requested and produced output rates; 0 in rational mode.
+    /// After `n` output samples the stream has drifted by at most `n * ratio_error()` samples.
+    pub fn ratio_error(&self) -> f64 {
+        (self.output_rate() - self.output_rate).abs() / self.output_rate
+    }
+
+    /// Produces exactly `output.len()` floats if `source` can supply enough input, pulling it in as needed.
+    /// `source` appends interleaved IQ to the buffer it is given, returning false once it has run dry.
+    /// Output beyond what fits is kept for the next call, so this can fill TX buffers directly.
+    /// Returns the number of floats written.
+    pub fn fill<F>(&mut self, output: &mut [f32], mut source: F) -> usize
+    where F: FnMut(&mut Vec<f32>) -> bool
+    {
+        let mut pending = std::mem::take(&mut self.pending);
+        let mut input = std::mem::take(&mut self.scratch);
+
+        while pending.len() < output.len() {
+            input.clear();
+
+            if !source(&mut input) {
+                break;
+            }
+
+            self.process(&input, &mut pending);
+        }
+
+        let count = pending.len().min(output.len());
+
+        output[..count].copy_from_slice(&pending[..count]);
+        pending.drain(..count);
+
+        self.pending = pending;
+        self.scratch = input;
+
+        count
+    }
+}
+
+impl Stage for Resampler {
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.history.extend_from_slice(input);
+
+        let taps = self.taps_per_branch;
+        let num_samples = self.history.len() / 2;
+        let mut next = self.next;
+
+        match self.mode {
+            Mode::Rational { interpolation, decimation, ref mut phase } => {
+                while next < num_samples {
+                    let window = &self.history[(next + 1 - taps) * 2..(next + 1) * 2];
+                    let (i, q) = fir::dot_iq(&self.branches[*phase], window);
+
+                    output.push(i);
+                    output.push(q);
+
+                    *phase += decimation;
+                    next += *phase / interpolation;
+                    *phase %= interpolation;
+                }
+            },
+            Mode::Fractional { step, ref mut fraction } => {
+                let mask = (1u64 << POSITION_BITS) - 1;
+                let weight_bits = POSITION_BITS - FRACTIONAL_BITS;
+
+                while next < num_samples {
+                    let window = &self.history[(next + 1 - taps) * 2..(next + 1) * 2];
+                    let branch = (*fraction >> weight_bits) as usize;
+                    let weight = (*fraction & ((1 << weight_bits) - 1)) as f32 / (1u64 << weight_bits) as f32;
+
+                    let (i0, q0) = fir::dot_iq(&self.branches[branch], window);
+                    let (i1, q1) = fir::dot_iq(&self.branches[branch + 1], window);
+
+                    output.push(i0 + (i1 - i0) * weight);
+                    output.push(q0 + (q1 - q0) * weight);
+
+                    *fraction += step;
+                    next += (*fraction >> POSITION_BITS) as usize;
+                    *fraction &= mask;
+                }
+            }
+        }
+
+        let consumed = num_samples - (taps - 1);
+
+        self.history.drain(..consumed * 2);
+        self.next = next - consumed;
+    }
+
+    fn reset(&mut self) {
+        let keep = self.taps_per_branch - 1;
+
+        self.history.clear();
+        self.history.resize(keep * 2, 0.0);
+        self.next = keep;
+        self.pending.clear();
+
+        match self.mode {
+            Mode::Rational { ref mut phase, .. } => *phase = 0,
+            Mode::Fractional { ref mut fraction, .. } => *fraction = 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::Nco;
+
+    fn tone(freq: f64, rate: f64, len: usize) -> Vec<f32> {
+        let mut samples = [0.5f32, 0.0].repeat(len);
+
+        Nco::new(freq, rate).mix_in_place(&mut samples);
+        samples
+    }
+
+    /// Estimates the frequency of a single tone from the average phase step
+    fn measure_freq(samples: &[f32], rate: f64) -> f64 {
+        let (re, im) = samples.chunks_exact(2).zip(samples[2..].chunks_exact(2)).fold((0.0f64, 0.0f64), |(re, im), (a, b)| {
+            // b * conj(a)
+            (re + (b[0] * a[0] + b[1] * a[1]) as f64, im + (b[1] * a[0] - b[0] * a[1]) as f64)
+        });
+
+        im.atan2(re) * rate / (2.0 * std::f64::consts::PI)
+    }
+
+    #[test]
+    fn picks_rational_ratios() {
+        assert_eq!(Resampler::new(20e6, 48e3).unwrap().ratio(), Some( (3, 1250) ));
+        assert_eq!(Resampler::new(10e6, 2.048e6).unwrap().ratio(), Some( (128, 625) ));
+        assert_eq!(Resampler::new(8e6, 1.023e6).unwrap().ratio(), Some( (1023, 8000) ));
+        assert_eq!(Resampler::new(10e6, 1234567.0).unwrap().ratio(), None);
+        assert!(Resampler::rational(0, 3).is_err());
+    }
+
+    #[test]
+    fn rational_is_exact_across_buffers() {
+        let input = tone(1e3, 48e3, 48_000);
+        let mut whole = Resampler::new(48e3, 44.1e3).unwrap();
+        let mut split = whole.clone();
+
+        let mut expected = Vec::new();
+        whole.process(&input, &mut expected);
+
+        let mut actual = Vec::new();
+        for chunk in input.chunks(998) {
+            split.process(chunk, &mut actual);
+        }
+
+        assert_eq!(expected.len(), 44_100 * 2);
+        assert_eq!(expected, actual);
+        assert!((measure_freq(&expected[2000..], 44.1e3) - 1e3).abs() < 0.1);
+
+        let power = expected[2000..].iter().map(|v| v * v).sum::<f32>() / (expected.len() / 2 - 1000) as f32;
+        assert!((power - 0.25).abs() < 0.01, "{}", power);
+    }
+
+    #[test]
+    fn upsamples() {
+        let mut resampler = Resampler::rational(5, 2).unwrap();
+        let input = tone(0.05, 1.0, 4_000);
+        let mut output = Vec::new();
+
+        resampler.process(&input, &mut output);
+
+        assert_eq!(output.len(), 10_000 * 2);
+        assert!((measure_freq(&output[1000..], 2.5) - 0.05).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fractional_drift_is_bounded() {
+        let mut resampler = Resampler::new(1_000_000.0, 3_000_000.0 / 7.0).unwrap();
+        let input = tone(10e3, 1e6, 700_000);
+        let mut output = Vec::new();
+
+        assert_eq!(resampler.ratio(), None);
+        assert!(resampler.ratio_error() < 1e-9);
+
+        for chunk in input.chunks(4096) {
+            resampler.process(chunk, &mut output);
+        }
+
+        let expected = 700_000.0 * 3.0 / 7.0;
+        let produced = (output.len() / 2) as f64;
+
+        assert!((produced - expected).abs() <= 1.0 + expected * resampler.ratio_error(), "{} vs {}", produced, expected);
+        assert!((measure_freq(&output[4000..], resampler.output_rate()) - 10e3).abs() < 1.0);
+    }
+
+    #[test]
+    fn fill_produces_exact_amounts() {
+        let mut resampler = Resampler::rational(3, 2).unwrap();
+        let input = tone(0.01, 1.0, 1_000);
+        let mut remaining = 10;
+        let mut buffer = vec![0.0f32; 1_234];
+
+        let mut source = |b: &mut Vec<f32>| {
+            if remaining == 0 {
+                return false;
+            }
+
+            remaining -= 1;
+            b.extend_from_slice(&input);
+            true
+        };
+
+        let mut total = 0;
+
+        loop {
+            let count = resampler.fill(&mut buffer, &mut source);
+            total += count;
+
+            if count < buffer.len() {
+                break;
+            }
+        }
+
+        assert_eq!(total, 10 * 1_000 * 3 / 2 * 2);
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt;
 use std::ffi::CStr;
+use std::io;
 
 use crate::{
     hackrf_error_HACKRF_SUCCESS,
@@ -111,3 +112,9 @@ impl Into<i32> for Error {
         }
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::OTHER(format!("I/O error: {}", err))
+    }
+}
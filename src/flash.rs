@@ -0,0 +1,410 @@
+//! Reading, writing and erasing the HackRF's SPI flash, where its firmware lives.
+//!
+//! `Flash` splits work into transfers libhackrf accepts, keeps writes within the flash's 256-byte
+//! pages, waits for the chip to finish each program or erase, reports progress and reads writes
+//! back to check them. It runs on anything implementing `FlashAccess`: a `Device`, or a
+//! `FlashSimulator` for testing without hardware.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::device::Device;
+use crate::error::Error;
+
+/// Size of the W25Q80BV on the HackRF One
+pub const FLASH_SIZE: usize = 0x100000;
+
+/// Bytes the flash programs at once; a write running past the end of a page wraps to its start
+pub const PAGE_SIZE: usize = 256;
+
+/// Bytes per read transfer, as hackrf_spiflash uses
+pub const READ_CHUNK: usize = 256;
+
+/// Status register 1 bit set while a program or erase is in progress
+const STATUS_BUSY: u8 = 0x01;
+
+/// Status register 1 bit set while the chip accepts program and erase commands
+const STATUS_WRITE_ENABLED: u8 = 0x02;
+
+/// The raw flash operations libhackrf offers. Lengths are limited to what fits in a `u16`.
+pub trait FlashAccess {
+    fn flash_erase(&self) -> Result<(), Error>;
+    fn flash_write(&self, address: u32, data: &[u8]) -> Result<(), Error>;
+    fn flash_read(&self, address: u32, data: &mut [u8]) -> Result<(), Error>;
+    fn flash_status(&self) -> Result<FlashStatus, Error>;
+    fn flash_clear_status(&self) -> Result<(), Error>;
+}
+
+/// The flash's two status registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashStatus(pub [u8; 2]);
+
+impl FlashStatus {
+    pub fn busy(&self) -> bool {
+        self.0[0] & STATUS_BUSY != 0
+    }
+
+    pub fn write_enabled(&self) -> bool {
+        self.0[0] & STATUS_WRITE_ENABLED != 0
+    }
+}
+
+/// Checks a transfer fits libhackrf's `u16` length and stays within the flash
+fn check_transfer(address: u32, length: usize) -> Result<(), Error> {
+    if length > u16::MAX as usize {
+        return Err(Error::INVALID_PARAM(format!("Flash transfers are at most {} bytes, not {}", u16::MAX, length)));
+    }
+
+    check_range(address, length)
+}
+
+fn check_range(address: u32, length: usize) -> Result<(), Error> {
+    if address as usize + length > FLASH_SIZE {
+        return Err(Error::INVALID_PARAM(format!("0x{:x} bytes at 0x{:06x} runs past the end of the {} byte flash", length, address, FLASH_SIZE)));
+    }
+
+    Ok( () )
+}
+
+/// Splits `length` bytes from `address` into pieces of at most `limit` bytes that don't cross a
+/// multiple of `limit`, as (address, offset into the data, length)
+fn chunks(address: u32, length: usize, limit: usize) -> Vec<(u32, usize, usize)> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+
+    while offset < length {
+        let at = address as usize + offset;
+        let size = (limit - at % limit).min(length - offset);
+
+        chunks.push((at as u32, offset, size));
+        offset += size;
+    }
+
+    chunks
+}
+
+/// A handle on a radio's flash
+pub struct Flash<'d, D: FlashAccess + ?Sized> {
+    device: &'d D,
+    poll_interval: Duration,
+    timeout: Duration,
+    verify: bool
+}
+
+impl <'d, D: FlashAccess + ?Sized> Flash<'d, D> {
+    /// Waits up to 30 seconds for a program or erase to finish, and verifies writes
+    pub fn new(device: &'d D) -> Flash<'d, D> {
+        Flash { device, poll_interval: Duration::from_millis(1), timeout: Duration::from_secs(30), verify: true }
+    }
+
+    /// How long to wait for the chip before giving up
+    pub fn with_timeout(mut self, timeout: Duration) -> Flash<'d, D> {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether `write` reads back what it wrote
+    pub fn with_verify(mut self, verify: bool) -> Flash<'d, D> {
+        self.verify = verify;
+        self
+    }
+
+    pub fn status(&self) -> Result<FlashStatus, Error> {
+        self.device.flash_status()
+    }
+
+    pub fn clear_status(&self) -> Result<(), Error> {
+        self.device.flash_clear_status()
+    }
+
+    /// Polls the status until the chip is no longer busy. Firmware older than USB API 1.03 has
+    /// no status request, but only answers an erase or program once it's finished, so that counts
+    /// as ready, as it does for hackrf_spiflash.
+    pub fn wait_ready(&self) -> Result<(), Error> {
+        let start = Instant::now();
+
+        loop {
+            match self.device.flash_status() {
+                Ok(status) if status.busy() => {},
+                Ok(_) | Err(Error::USB_API_VERSION(_)) => return Ok( () ),
+                Err(e) => return Err(e)
+            }
+
+            if start.elapsed() > self.timeout {
+                return Err(Error::OTHER(format!("Flash still busy after {:?}", self.timeout)));
+            }
+
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// Erases the whole flash to 0xff and waits for it to finish
+    pub fn erase(&self) -> Result<(), Error> {
+        self.device.flash_erase()?;
+        self.wait_ready()
+    }
+
+    pub fn read(&self, address: u32, length: usize) -> Result<Vec<u8>, Error> {
+        self.read_with_progress(address, length, |_, _| {})
+    }
+
+    /// Reads `length` bytes from `address`, calling `progress` with the bytes read so far and
+    /// the total after each transfer
+    pub fn read_with_progress<F: FnMut(usize, usize)>(&self, address: u32, length: usize, mut progress: F) -> Result<Vec<u8>, Error> {
+        check_range(address, length)?;
+
+        let mut data = vec![0u8; length];
+
+        for (at, offset, size) in chunks(address, length, READ_CHUNK) {
+            self.device.flash_read(at, &mut data[offset..offset + size])?;
+            progress(offset + size, length);
+        }
+
+        Ok(data)
+    }
+
+    pub fn write(&self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.write_with_progress(address, data, |_, _| {})
+    }
+
+    /// Programs `data` at `address` a page at a time, waiting for each page to finish, then
+    /// reads it back unless verification is off. `progress` gets the bytes written so far and the
+    /// total. Flash bits can only be programmed from 1 to 0, so the area should be erased first.
+    pub fn write_with_progress<F: FnMut(usize, usize)>(&self, address: u32, data: &[u8], mut progress: F) -> Result<(), Error> {
+        check_range(address, data.len())?;
+
+        for (at, offset, size) in chunks(address, data.len(), PAGE_SIZE) {
+            self.device.flash_write(at, &data[offset..offset + size])?;
+            self.wait_ready()?;
+            progress(offset + size, data.len());
+        }
+
+        if self.verify {
+            self.verify(address, data)?;
+        }
+
+        Ok( () )
+    }
+
+    /// Checks the flash at `address` holds `data`
+    pub fn verify(&self, address: u32, data: &[u8]) -> Result<(), Error> {
+        let found = self.read(address, data.len())?;
+
+        match found.iter().zip(data.iter()).position(|(found, wanted)| found != wanted) {
+            Some(offset) => Err(Error::OTHER(format!("Flash verification failed at 0x{:06x}: read 0x{:02x}, wrote 0x{:02x}",
+                                                     address as usize + offset, found[offset], data[offset]))),
+            None => Ok( () )
+        }
+    }
+}
+
+impl <'a> FlashAccess for Device<'a> {
+    fn flash_erase(&self) -> Result<(), Error> {
+        self.spiflash_erase()
+    }
+
+    fn flash_write(&self, address: u32, data: &[u8]) -> Result<(), Error> {
+        check_transfer(address, data.len())?;
+        self.spiflash_write(address, data)
+    }
+
+    fn flash_read(&self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        check_transfer(address, data.len())?;
+        self.spiflash_read(address, data)
+    }
+
+    fn flash_status(&self) -> Result<FlashStatus, Error> {
+        self.spiflash_status().map(FlashStatus)
+    }
+
+    fn flash_clear_status(&self) -> Result<(), Error> {
+        self.spiflash_clear_status()
+    }
+}
+
+struct FlashState {
+    memory: Vec<u8>,
+    busy_polls: u32,
+    status_2: u8
+}
+
+/// An in-memory stand-in for the HackRF's flash. It behaves like NOR flash: erasing sets every
+/// bit, programming can only clear bits, a write wraps within its page, and the chip reports
+/// busy for a few status polls after each program or erase.
+pub struct FlashSimulator {
+    state: Mutex<FlashState>,
+    busy_after_write: u32,
+    busy_after_erase: u32,
+    has_status: bool
+}
+
+impl FlashSimulator {
+    /// A blank, erased flash
+    pub fn new() -> FlashSimulator {
+        FlashSimulator {
+            state: Mutex::new(FlashState { memory: vec![0xff; FLASH_SIZE], busy_polls: 0, status_2: 0 }),
+            busy_after_write: 2,
+            busy_after_erase: 20,
+            has_status: true
+        }
+    }
+
+    /// Status polls that report busy after a program and after an erase
+    pub fn with_busy_polls(mut self, after_write: u32, after_erase: u32) -> FlashSimulator {
+        self.busy_after_write = after_write;
+        self.busy_after_erase = after_erase;
+        self
+    }
+
+    /// Acts like firmware before USB API 1.03: there's no status request, and erases and
+    /// programs finish before they return
+    pub fn without_status(mut self) -> FlashSimulator {
+        self.has_status = false;
+        self
+    }
+
+    /// A copy of the whole flash
+    pub fn contents(&self) -> Vec<u8> {
+        self.state.lock().unwrap().memory.clone()
+    }
+}
+
+impl Default for FlashSimulator {
+    fn default() -> FlashSimulator {
+        FlashSimulator::new()
+    }
+}
+
+impl FlashAccess for FlashSimulator {
+    fn flash_erase(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state.memory.iter_mut().for_each(|byte| *byte = 0xff);
+        state.busy_polls = if self.has_status { self.busy_after_erase } else { 0 };
+
+        Ok( () )
+    }
+
+    fn flash_write(&self, address: u32, data: &[u8]) -> Result<(), Error> {
+        check_transfer(address, data.len())?;
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.busy_polls > 0 {
+            return Err(Error::BUSY(String::from("Simulated flash is still busy")));
+        }
+
+        let page = address as usize / PAGE_SIZE * PAGE_SIZE;
+
+        for (i, byte) in data.iter().enumerate() {
+            let at = page + (address as usize + i) % PAGE_SIZE;
+
+            state.memory[at] &= byte;
+        }
+
+        state.busy_polls = if self.has_status { self.busy_after_write } else { 0 };
+
+        Ok( () )
+    }
+
+    fn flash_read(&self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        check_transfer(address, data.len())?;
+
+        let state = self.state.lock().unwrap();
+
+        data.copy_from_slice(&state.memory[address as usize..address as usize + data.len()]);
+
+        Ok( () )
+    }
+
+    fn flash_status(&self) -> Result<FlashStatus, Error> {
+        if !self.has_status {
+            return Err(Error::USB_API_VERSION(String::from("Simulated firmware has no flash status request")));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let busy = state.busy_polls > 0;
+
+        state.busy_polls = state.busy_polls.saturating_sub(1);
+
+        Ok(FlashStatus([if busy { STATUS_BUSY | STATUS_WRITE_ENABLED } else { 0 }, state.status_2]))
+    }
+
+    fn flash_clear_status(&self) -> Result<(), Error> {
+        if !self.has_status {
+            return Err(Error::USB_API_VERSION(String::from("Simulated firmware has no flash status request")));
+        }
+
+        self.state.lock().unwrap().status_2 = 0;
+
+        Ok( () )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_stay_within_pages() {
+        assert_eq!(chunks(0x1f0, 0x220, PAGE_SIZE), vec![(0x1f0, 0, 0x10), (0x200, 0x10, 0x100), (0x300, 0x110, 0x100), (0x400, 0x210, 0x10)]);
+        assert_eq!(chunks(0x100, 0x100, PAGE_SIZE), vec![(0x100, 0, 0x100)]);
+        assert!(chunks(0, 0, PAGE_SIZE).is_empty());
+    }
+
+    #[test]
+    fn writes_reads_and_verifies() {
+        let sim = FlashSimulator::new();
+        let flash = Flash::new(&sim);
+        let data :Vec<u8> = (0..1000).map(|n| (n * 7) as u8).collect();
+        let mut reports = Vec::new();
+
+        flash.write_with_progress(0x10080, &data, |done, total| reports.push((done, total))).unwrap();
+
+        assert_eq!(reports.len(), 5);
+        assert_eq!(reports[0], (128, 1000));
+        assert_eq!(reports.last(), Some(&(1000, 1000)));
+        assert_eq!(flash.read(0x10080, 1000).unwrap(), data);
+        assert_eq!(sim.contents()[0x1007f], 0xff);
+        assert!(!flash.status().unwrap().busy());
+
+        // programming can't set bits, so rewriting without an erase fails verification
+        assert!(flash.write(0x10080, &[0xff; 16]).is_err());
+
+        flash.erase().unwrap();
+        assert!(flash.read(0, FLASH_SIZE).unwrap().iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn rejects_out_of_range() {
+        let sim = FlashSimulator::new();
+        let flash = Flash::new(&sim);
+
+        assert!(flash.read(FLASH_SIZE as u32 - 4, 8).is_err());
+        assert!(flash.write(FLASH_SIZE as u32, &[0]).is_err());
+        assert!(sim.flash_read(0, &mut vec![0; 0x10000]).is_err());
+    }
+
+    #[test]
+    fn works_without_status() {
+        let sim = FlashSimulator::new().without_status();
+        let flash = Flash::new(&sim);
+        let data = [0x5a; 600];
+
+        assert!(flash.status().is_err());
+        assert!(flash.clear_status().is_err());
+
+        flash.erase().unwrap();
+        flash.write(0x200, &data).unwrap();
+        assert_eq!(flash.read(0x200, 600).unwrap(), data.to_vec());
+    }
+
+    #[test]
+    fn times_out_when_stuck_busy() {
+        let sim = FlashSimulator::new().with_busy_polls(1, u32::MAX);
+        let flash = Flash::new(&sim).with_timeout(Duration::from_millis(20));
+
+        assert!(flash.erase().is_err());
+    }
+}
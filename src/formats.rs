@@ -0,0 +1,439 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::stream::{self, RxSink, TxSource};
+
+/// Raw interleaved IQ file formats, all little-endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Signed 8-bit, as written by `hackrf_transfer`
+    Cs8,
+    /// Unsigned 8-bit offset by 128, as written by `rtl_sdr`
+    Cu8,
+    /// Signed 16-bit
+    Cs16,
+    /// 32-bit float, GNU Radio's `gr_complex`
+    Cf32
+}
+
+impl SampleFormat {
+    /// The conventional file extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SampleFormat::Cs8 => "cs8",
+            SampleFormat::Cu8 => "cu8",
+            SampleFormat::Cs16 => "cs16",
+            SampleFormat::Cf32 => "cf32"
+        }
+    }
+
+    /// Parses a format name or extension; `.iq`/`.raw` are taken as `cs8`, `.cfile`/`.fc32` as `cf32`
+    pub fn from_extension(ext: &str) -> Option<SampleFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "cs8" | "s8" | "iq" | "raw" => Some(SampleFormat::Cs8),
+            "cu8" | "u8" => Some(SampleFormat::Cu8),
+            "cs16" | "s16" | "sc16" => Some(SampleFormat::Cs16),
+            "cf32" | "fc32" | "cfile" => Some(SampleFormat::Cf32),
+            _ => None
+        }
+    }
+
+    /// Guesses the format from a file name
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<SampleFormat> {
+        path.as_ref().extension().and_then(|ext| ext.to_str()).and_then(SampleFormat::from_extension)
+    }
+
+    /// Size in bytes of one IQ pair
+    pub fn sample_size(&self) -> usize {
+        match self {
+            SampleFormat::Cs8 | SampleFormat::Cu8 => 2,
+            SampleFormat::Cs16 => 4,
+            SampleFormat::Cf32 => 8
+        }
+    }
+
+    /// Appends interleaved `samples` to `output` encoded in this format
+    pub fn encode(&self, samples: &[f32], output: &mut Vec<u8>) {
+        match self {
+            SampleFormat::Cs8 => output.extend(samples.iter().map(|v| stream::f32_to_i8(*v) as u8)),
+            SampleFormat::Cu8 => output.extend(samples.iter().map(|v| (stream::f32_to_i8(*v) as i16 + 128) as u8)),
+            SampleFormat::Cs16 => {
+                for v in samples {
+                    output.extend_from_slice(&((v * 32768.0).round().clamp(-32768.0, 32767.0) as i16).to_le_bytes());
+                }
+            },
+            SampleFormat::Cf32 => {
+                for v in samples {
+                    output.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    /// Appends native signed 8-bit `samples` to `output` encoded in this format
+    pub fn encode_i8(&self, samples: &[i8], output: &mut Vec<u8>) {
+        match self {
+            SampleFormat::Cs8 => output.extend(samples.iter().map(|v| *v as u8)),
+            SampleFormat::Cu8 => output.extend(samples.iter().map(|v| (*v as i16 + 128) as u8)),
+            SampleFormat::Cs16 => {
+                for v in samples {
+                    output.extend_from_slice(&((*v as i16) << 8).to_le_bytes());
+                }
+            },
+            SampleFormat::Cf32 => {
+                for v in samples {
+                    output.extend_from_slice(&stream::i8_to_f32(*v).to_le_bytes());
+                }
+            }
+        }
+    }
+
+    /// Decodes `input` into the HackRF's native signed 8-bit format; `output` must hold
+    /// `input.len() / (sample_size() / 2)` values
+    pub fn decode_i8(&self, input: &[u8], output: &mut [i8]) {
+        match self {
+            SampleFormat::Cs8 => output.iter_mut().zip(input).for_each(|(o, v)| *o = *v as i8),
+            SampleFormat::Cu8 => output.iter_mut().zip(input).for_each(|(o, v)| *o = (*v as i16 - 128) as i8),
+            SampleFormat::Cs16 => {
+                for (o, v) in output.iter_mut().zip(input.chunks_exact(2)) {
+                    *o = stream::f32_to_i8(i16::from_le_bytes([v[0], v[1]]) as f32 / 32768.0);
+                }
+            },
+            SampleFormat::Cf32 => {
+                for (o, v) in output.iter_mut().zip(input.chunks_exact(4)) {
+                    *o = stream::f32_to_i8(f32::from_le_bytes([v[0], v[1], v[2], v[3]]));
+                }
+            }
+        }
+    }
+
+    /// Appends `input` to `output` as interleaved samples in [-1, 1)
+    pub fn decode_f32(&self, input: &[u8], output: &mut Vec<f32>) {
+        match self {
+            SampleFormat::Cs8 => output.extend(input.iter().map(|v| stream::i8_to_f32(*v as i8))),
+            SampleFormat::Cu8 => output.extend(input.iter().map(|v| (*v as f32 - 128.0) * (1.0 / 128.0))),
+            SampleFormat::Cs16 => output.extend(input.chunks_exact(2).map(|v| i16::from_le_bytes([v[0], v[1]]) as f32 * (1.0 / 32768.0))),
+            SampleFormat::Cf32 => output.extend(input.chunks_exact(4).map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]])))
+        }
+    }
+}
+
+/// Streams raw IQ samples out in any `SampleFormat`; use it as an RX sink
+pub struct IqWriter<W: Write> {
+    inner: W,
+    format: SampleFormat,
+    samples_written: u64,
+    scratch: Vec<u8>
+}
+
+impl IqWriter<BufWriter<File>> {
+    /// Creates `path`, taking the format from its extension
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<IqWriter<BufWriter<File>>, Error> {
+        let format = SampleFormat::from_path(&path)
+            .ok_or_else(|| Error::INVALID_PARAM(format!("Unknown IQ file extension: {:?}", path.as_ref())))?;
+
+        IqWriter::create_with_format(path, format)
+    }
+
+    pub fn create_with_format<P: AsRef<Path>>(path: P, format: SampleFormat) -> Result<IqWriter<BufWriter<File>>, Error> {
+        Ok(IqWriter::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+impl <W: Write> IqWriter<W> {
+    pub fn new(inner: W, format: SampleFormat) -> IqWriter<W> {
+        IqWriter {
+            inner,
+            format,
+            samples_written: 0,
+            scratch: Vec::new()
+        }
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// Number of IQ pairs written so far
+    pub fn samples_written(&self) -> u64 {
+        self.samples_written
+    }
+
+    /// Writes interleaved samples in the HackRF's native signed 8-bit format
+    pub fn write_i8(&mut self, samples: &[i8]) -> Result<(), Error> {
+        self.scratch.clear();
+        self.format.encode_i8(samples, &mut self.scratch);
+        self.write_scratch(samples.len())
+    }
+
+    fn write_scratch(&mut self, values: usize) -> Result<(), Error> {
+        self.inner.write_all(&self.scratch)?;
+        self.samples_written += values as u64 / 2;
+
+        Ok( () )
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn into_inner(mut self) -> Result<W, Error> {
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+impl <W: Write> RxSink for IqWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        self.scratch.clear();
+        self.format.encode(samples, &mut self.scratch);
+        self.write_scratch(samples.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()?;
+
+        Ok( () )
+    }
+}
+
+/// Streams raw IQ samples in from any `SampleFormat`; use it as a TX source
+pub struct IqReader<R: Read> {
+    inner: R,
+    format: SampleFormat,
+    samples_read: u64,
+    scratch: Vec<u8>
+}
+
+impl IqReader<BufReader<File>> {
+    /// Opens `path`, taking the format from its extension
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<IqReader<BufReader<File>>, Error> {
+        let format = SampleFormat::from_path(&path)
+            .ok_or_else(|| Error::INVALID_PARAM(format!("Unknown IQ file extension: {:?}", path.as_ref())))?;
+
+        IqReader::open_with_format(path, format)
+    }
+
+    pub fn open_with_format<P: AsRef<Path>>(path: P, format: SampleFormat) -> Result<IqReader<BufReader<File>>, Error> {
+        Ok(IqReader::new(BufReader::new(File::open(path)?), format))
+    }
+}
+
+impl <R: Read> IqReader<R> {
+    pub fn new(inner: R, format: SampleFormat) -> IqReader<R> {
+        IqReader {
+            inner,
+            format,
+            samples_read: 0,
+            scratch: Vec::new()
+        }
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// Number of IQ pairs read so far
+    pub fn samples_read(&self) -> u64 {
+        self.samples_read
+    }
+
+    /// Reads up to `max_samples` IQ pairs, appending them to `output` as interleaved samples in [-1, 1).
+    /// Returns the number of pairs read; zero means end of stream.
+    pub fn read_f32(&mut self, output: &mut Vec<f32>, max_samples: usize) -> Result<usize, Error> {
+        let count = self.fill_scratch(max_samples)?;
+
+        self.format.decode_f32(&self.scratch, output);
+
+        Ok(count)
+    }
+
+    /// Reads whole samples into the scratch buffer, returning how many were read
+    fn fill_scratch(&mut self, max_samples: usize) -> Result<usize, Error> {
+        let sample_size = self.format.sample_size();
+
+        self.scratch.resize(max_samples * sample_size, 0);
+
+        let mut filled = 0;
+
+        while filled < self.scratch.len() {
+            match self.inner.read(&mut self.scratch[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::from(e))
+            }
+        }
+
+        if filled % sample_size != 0 {
+            warn!("IQ stream ended with a partial sample; dropping {} bytes", filled % sample_size);
+        }
+
+        let count = filled / sample_size;
+
+        self.scratch.truncate(count * sample_size);
+        self.samples_read += count as u64;
+
+        Ok(count)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl <R: Read + Seek> IqReader<R> {
+    /// Goes back to the start of the stream, e.g. to repeat a transmission
+    pub fn rewind(&mut self) -> Result<(), Error> {
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.samples_read = 0;
+
+        Ok( () )
+    }
+
+    /// Number of whole IQ pairs in the stream
+    pub fn len(&mut self) -> Result<u64, Error> {
+        let position = self.inner.stream_position()?;
+        let end = self.inner.seek(SeekFrom::End(0))?;
+
+        self.inner.seek(SeekFrom::Start(position))?;
+
+        Ok(end / self.format.sample_size() as u64)
+    }
+
+    pub fn is_empty(&mut self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+}
+
+impl <R: Read> TxSource for IqReader<R> {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        let count = self.fill_scratch(buffer.len() / 2)?;
+
+        self.format.decode_i8(&self.scratch, &mut buffer[..count * 2]);
+
+        Ok(count * 2)
+    }
+}
+
+/// Reads all of `reader` into interleaved samples in [-1, 1)
+pub fn read_all<R: Read>(reader: R, format: SampleFormat) -> Result<Vec<f32>, Error> {
+    let mut reader = IqReader::new(reader, format);
+    let mut samples = Vec::new();
+
+    while reader.read_f32(&mut samples, 65536)? > 0 {}
+
+    Ok(samples)
+}
+
+/// Copies everything from `reader` into `writer`, converting between formats as needed
+pub fn convert<R: Read, W: Write>(reader: &mut IqReader<R>, writer: &mut IqWriter<W>) -> Result<u64, Error> {
+    let mut samples = Vec::new();
+    let mut total = 0;
+
+    loop {
+        samples.clear();
+
+        let count = reader.read_f32(&mut samples, 65536)?;
+
+        if count == 0 {
+            break;
+        }
+
+        writer.write(&samples)?;
+        total += count as u64;
+    }
+
+    writer.flush()?;
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const FORMATS: [SampleFormat; 4] = [SampleFormat::Cs8, SampleFormat::Cu8, SampleFormat::Cs16, SampleFormat::Cf32];
+
+    #[test]
+    fn extensions() {
+        for format in &FORMATS {
+            assert_eq!(SampleFormat::from_extension(format.extension()), Some(*format));
+        }
+
+        assert_eq!(SampleFormat::from_path("capture.CU8"), Some(SampleFormat::Cu8));
+        assert_eq!(SampleFormat::from_path("/tmp/gnuradio.cfile"), Some(SampleFormat::Cf32));
+        assert_eq!(SampleFormat::from_path("notes.txt"), None);
+        assert_eq!(SampleFormat::from_path("no_extension"), None);
+    }
+
+    #[test]
+    fn round_trip_every_format() {
+        let samples = (-128..128).map(|n| n as f32 / 128.0).collect::<Vec<_>>();
+        let native = (-128..128).map(|n| n as i8).collect::<Vec<_>>();
+
+        for format in &FORMATS {
+            let mut writer = IqWriter::new(Vec::new(), *format);
+
+            writer.write(&samples).unwrap();
+            assert_eq!(writer.samples_written(), 128);
+
+            let bytes = writer.into_inner().unwrap();
+
+            assert_eq!(bytes.len(), 128 * format.sample_size());
+            assert_eq!(read_all(Cursor::new(&bytes), *format).unwrap(), samples, "{:?}", format);
+
+            let mut reader = IqReader::new(Cursor::new(&bytes), *format);
+            let mut buffer = vec![0i8; 300];
+
+            assert_eq!(reader.len().unwrap(), 128);
+            assert_eq!(reader.read(&mut buffer).unwrap(), 256);
+            assert_eq!(&buffer[..256], &native[..], "{:?}", format);
+            assert_eq!(reader.read(&mut buffer).unwrap(), 0);
+
+            reader.rewind().unwrap();
+            assert_eq!(reader.read(&mut buffer[..10]).unwrap(), 10);
+            assert_eq!(reader.samples_read(), 5);
+
+            let mut writer = IqWriter::new(Vec::new(), *format);
+
+            writer.write_i8(&native).unwrap();
+            assert_eq!(writer.into_inner().unwrap(), bytes, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn known_encodings() {
+        let mut bytes = Vec::new();
+
+        SampleFormat::Cu8.encode(&[0.0, -1.0, 0.5, 2.0], &mut bytes);
+        assert_eq!(bytes, [128, 0, 192, 255]);
+
+        bytes.clear();
+        SampleFormat::Cs16.encode(&[0.5, -1.0], &mut bytes);
+        assert_eq!(bytes, [0x00, 0x40, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn drops_partial_sample() {
+        let mut reader = IqReader::new(Cursor::new(vec![1u8, 2, 3, 4, 5]), SampleFormat::Cs16);
+        let mut buffer = [0i8; 8];
+
+        assert_eq!(reader.read(&mut buffer).unwrap(), 2);
+        assert_eq!(reader.read(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn converts_between_formats() {
+        let mut input = Vec::new();
+
+        SampleFormat::Cs8.encode_i8(&[1, -1, 127, -128], &mut input);
+
+        let mut reader = IqReader::new(Cursor::new(input), SampleFormat::Cs8);
+        let mut writer = IqWriter::new(Vec::new(), SampleFormat::Cu8);
+
+        assert_eq!(convert(&mut reader, &mut writer).unwrap(), 2);
+        assert_eq!(writer.into_inner().unwrap(), [129, 127, 255, 0]);
+    }
+}
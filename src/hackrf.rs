@@ -14,6 +14,7 @@ use crate::{
     hackrf_error_HACKRF_TRUE,
     hackrf_init,
     hackrf_exit,
+    hackrf_library_version,
     hackrf_device_list,
     hackrf_device_list_free,
     hackrf_device_list_open,
@@ -35,6 +36,16 @@ pub struct DeviceInfo<'a> {
     board_id: hackrf_usb_board_id,
 }
 
+impl <'a> DeviceInfo<'a> {
+    pub fn serial(&self) -> &'a str {
+        self.serial
+    }
+
+    pub fn board_id(&self) -> hackrf_usb_board_id {
+        self.board_id
+    }
+}
+
 impl HackRF {
     /// Construct a new instance of the HackRF library
     pub fn new() -> Result<HackRF, Error> {
@@ -59,6 +70,30 @@ impl HackRF {
         }
     }
 
+    /// Version of the libhackrf being used, e.g. "2018.01.1"
+    pub fn library_version(&self) -> String {
+        unsafe {
+            String::from(CStr::from_ptr(hackrf_library_version()).to_str().expect("Error converting library version"))
+        }
+    }
+
+    /// Looks for attached devices again, so ones plugged in since `new` can be listed and opened.
+    /// Devices already open are unaffected.
+    pub fn refresh_device_list(&mut self) -> Result<(), Error> {
+        unsafe {
+            let device_list = hackrf_device_list();
+
+            if device_list.is_null() {
+                return Err(Error::OTHER(String::from("hackrf_device_list returned NULL")));
+            }
+
+            hackrf_device_list_free(self.device_list);
+            self.device_list = device_list;
+        }
+
+        Ok( () )
+    }
+
     /// Get the list of devices found in the system
     pub fn get_device_list(&self) -> Result<Vec<DeviceInfo>, Error> {
         let mut ret = Vec::new();
@@ -0,0 +1,246 @@
+//! Just enough HTTP/1.1 for the control and spectrum servers: one request per connection, no
+//! chunked bodies, no TLS. Put a reverse proxy in front for anything fancier.
+
+use std::io::{BufRead, Read, Write};
+
+use serde_json::{json, Value};
+
+use crate::error::Error;
+
+/// Largest request line, header line or body accepted
+const MAX_LINE: usize = 8192;
+const MAX_HEADERS: usize = 64;
+const MAX_BODY: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub method: String,
+    /// The path without the query string
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    /// Header names are lower-cased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, Error> {
+    let mut line = Vec::new();
+
+    reader.take(MAX_LINE as u64).read_until(b'\n', &mut line)?;
+
+    if line.last() != Some(&b'\n') {
+        return Err(Error::INVALID_PARAM(String::from("HTTP line too long or connection closed")));
+    }
+
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+fn hex(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|v| v as u8)
+}
+
+/// Decodes `%xx` escapes, and `+` as a space
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() && hex(bytes[i + 1]).is_some() && hex(bytes[i + 2]).is_some() => {
+                decoded.push(hex(bytes[i + 1]).unwrap() << 4 | hex(bytes[i + 2]).unwrap());
+                i += 2;
+            },
+            other => decoded.push(other)
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+impl Request {
+    /// Reads one request; `None` if the client closed the connection without sending one
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<Option<Request>, Error> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let line = read_line(reader)?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method, target),
+            _ => return Err(Error::INVALID_PARAM(format!("Bad HTTP request line: {}", line)))
+        };
+        let (path, query) = match target.find('?') {
+            Some(index) => (&target[..index], &target[index + 1..]),
+            None => (target, "")
+        };
+        let query = query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.find('=') {
+                Some(index) => (percent_decode(&pair[..index]), percent_decode(&pair[index + 1..])),
+                None => (percent_decode(pair), String::new())
+            })
+            .collect();
+
+        let mut headers = Vec::new();
+
+        loop {
+            let line = read_line(reader)?;
+
+            if line.is_empty() {
+                break;
+            }
+
+            if headers.len() == MAX_HEADERS {
+                return Err(Error::INVALID_PARAM(String::from("Too many HTTP headers")));
+            }
+
+            match line.find(':') {
+                Some(index) => headers.push((line[..index].trim().to_ascii_lowercase(), line[index + 1..].trim().to_string())),
+                None => return Err(Error::INVALID_PARAM(format!("Bad HTTP header: {}", line)))
+            }
+        }
+
+        let mut request = Request {
+            method: method.to_string(),
+            path: percent_decode(path),
+            query,
+            headers,
+            body: Vec::new()
+        };
+
+        if let Some(length) = request.header("content-length") {
+            let length :usize = length.parse()
+                .map_err(|_| Error::INVALID_PARAM(format!("Bad Content-Length: {}", length)))?;
+
+            if length > MAX_BODY {
+                return Err(Error::INVALID_PARAM(format!("Request body of {} bytes is too large", length)));
+            }
+
+            request.body.resize(length, 0);
+            reader.read_exact(&mut request.body)?;
+        }
+
+        Ok(Some(request))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// The body parsed as JSON
+    pub fn json(&self) -> Result<Value, Error> {
+        serde_json::from_slice(&self.body).map_err(|e| Error::INVALID_PARAM(format!("Body is not JSON: {}", e)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Response {
+        Response { status, content_type, headers: Vec::new(), body }
+    }
+
+    pub fn json(status: u16, body: &Value) -> Response {
+        Response::new(status, "application/json", body.to_string().into_bytes())
+    }
+
+    /// A JSON `{"error": ...}` body
+    pub fn error(status: u16, message: &str) -> Response {
+        Response::json(status, &json!({ "error": message }))
+    }
+
+    /// Maps a crate error to 400 for bad parameters and 500 for everything else
+    pub fn from_error(err: &Error) -> Response {
+        match err {
+            Error::INVALID_PARAM(_) => Response::error(400, &err.to_string()),
+            Error::BUSY(_) => Response::error(409, &err.to_string()),
+            _ => Response::error(500, &err.to_string())
+        }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Response {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                               self.status, reason(self.status), self.content_type, self.body.len());
+
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&self.body)?;
+        writer.flush()?;
+
+        Ok( () )
+    }
+}
+
+pub(crate) fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn parses_request() {
+        let raw = b"PUT /settings/freq?token=a%20b&x HTTP/1.1\r\nHost: localhost\r\nContent-Length: 9\r\n\r\n915000000";
+        let request = Request::read(&mut Cursor::new(&raw[..])).unwrap().unwrap();
+
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/settings/freq");
+        assert_eq!(request.query("token"), Some("a b"));
+        assert_eq!(request.query("x"), Some(""));
+        assert_eq!(request.header("HOST"), Some("localhost"));
+        assert_eq!(request.json().unwrap(), json!(915_000_000));
+
+        assert!(Request::read(&mut Cursor::new(&b""[..])).unwrap().is_none());
+        assert!(Request::read(&mut Cursor::new(&b"GET /\r\n\r\n"[..])).is_err());
+        assert!(Request::read(&mut Cursor::new(&b"GET / HTTP/1.1\r\nHost"[..])).is_err());
+    }
+
+    #[test]
+    fn writes_response() {
+        let mut output = Vec::new();
+
+        Response::error(404, "No such endpoint").with_header("X-Test", "1").write_to(&mut output).unwrap();
+
+        let text = String::from_utf8(output).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(text.contains("Content-Length: 28\r\nConnection: close\r\nX-Test: 1\r\n\r\n"));
+        assert!(text.ends_with(r#"{"error":"No such endpoint"}"#));
+    }
+}
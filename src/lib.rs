@@ -17,4 +17,27 @@ extern crate lazy_static;
 pub mod error;
 pub mod hackrf;
 pub mod device;
+pub mod dsp;
+pub mod tuning;
+pub mod stream;
+pub mod formats;
+pub mod sigmf;
+pub mod wav;
+pub mod recorder;
+pub mod trigger;
+pub mod radio;
+pub mod simulator;
+pub mod rtl_tcp;
+pub mod vita49;
+pub mod sweep;
+pub mod registers;
+pub mod flash;
+mod http;
+pub mod control;
+pub mod spectrum;
+mod websocket;
+pub mod waterfall;
+pub mod soapy;
+#[cfg(feature = "zmq")]
+pub mod zeromq;
 
@@ -0,0 +1,271 @@
+//! The operations servers and tools need from a radio, so they can run against either a real
+//! `Device` or a `SimulatedDevice`
+
+use std::convert::TryFrom;
+
+use serde_json::{json, Map, Value};
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::stream::{RxSink, TxSource};
+use crate::tuning::{TuningHandle, TuningState};
+use crate::sweep_style;
+
+/// A HackRF, real or simulated.
+///
+/// Setters record what they applied in the radio's `TuningHandle`, just like `Device`'s do.
+pub trait Radio {
+    fn set_freq(&self, freq_hz: u64) -> Result<(), Error>;
+    fn set_sample_rate(&self, freq_hz: f64) -> Result<(), Error>;
+    fn set_baseband_filter_bandwidth(&self, bandwidth_hz: u32) -> Result<(), Error>;
+    fn set_lna_gain(&self, value: u32) -> Result<(), Error>;
+    fn set_vga_gain(&self, value: u32) -> Result<(), Error>;
+    fn set_txvga_gain(&self, value: u32) -> Result<(), Error>;
+    fn set_amp_enable(&self, value: bool) -> Result<(), Error>;
+    fn set_antenna_enable(&self, value: bool) -> Result<(), Error>;
+
+    /// Puts the next `start_rx_sink` into sweep mode; see `Device::init_sweep` and `SweepPlan`
+    fn init_sweep(&self, frequency_list: &[u16], num_bytes: u32, step_width: u32, offset: u32, style: sweep_style) -> Result<(), Error>;
+
+    fn board_id_read(&self) -> Result<u8, Error>;
+    fn board_id_name(&self) -> Result<String, Error>;
+    fn version_string_read(&self) -> Result<String, Error>;
+
+    /// A handle that tracks the radio's settings
+    fn tuning_handle(&self) -> TuningHandle;
+
+    /// The settings last applied
+    fn tuning(&self) -> TuningState {
+        self.tuning_handle().get()
+    }
+
+    /// Starts receiving, writing every buffer to `sink` until `stop_rx` or the sink returns an error
+    fn start_rx_sink(&mut self, sink: Box<dyn RxSink + Send>) -> Result<(), Error>;
+    fn stop_rx(&mut self) -> Result<(), Error>;
+
+    /// Starts transmitting from `source` until `stop_tx` or it runs out
+    fn start_tx_source(&mut self, source: Box<dyn TxSource + Send>) -> Result<(), Error>;
+    fn stop_tx(&mut self) -> Result<(), Error>;
+
+    fn is_streaming(&self) -> Result<bool, Error>;
+}
+
+impl <'a> Radio for Device<'a> {
+    fn set_freq(&self, freq_hz: u64) -> Result<(), Error> {
+        Device::set_freq(self, freq_hz)
+    }
+
+    fn set_sample_rate(&self, freq_hz: f64) -> Result<(), Error> {
+        Device::set_sample_rate(self, freq_hz)
+    }
+
+    fn set_baseband_filter_bandwidth(&self, bandwidth_hz: u32) -> Result<(), Error> {
+        Device::set_baseband_filter_bandwidth(self, bandwidth_hz)
+    }
+
+    fn set_lna_gain(&self, value: u32) -> Result<(), Error> {
+        Device::set_lna_gain(self, value)
+    }
+
+    fn set_vga_gain(&self, value: u32) -> Result<(), Error> {
+        Device::set_vga_gain(self, value)
+    }
+
+    fn set_txvga_gain(&self, value: u32) -> Result<(), Error> {
+        Device::set_txvga_gain(self, value)
+    }
+
+    fn set_amp_enable(&self, value: bool) -> Result<(), Error> {
+        Device::set_amp_enable(self, value)
+    }
+
+    fn set_antenna_enable(&self, value: bool) -> Result<(), Error> {
+        Device::set_antenna_enable(self, value)
+    }
+
+    fn init_sweep(&self, frequency_list: &[u16], num_bytes: u32, step_width: u32, offset: u32, style: sweep_style) -> Result<(), Error> {
+        Device::init_sweep(self, frequency_list, num_bytes, step_width, offset, style)
+    }
+
+    fn board_id_read(&self) -> Result<u8, Error> {
+        Device::board_id_read(self)
+    }
+
+    fn board_id_name(&self) -> Result<String, Error> {
+        Device::board_id_name(self)
+    }
+
+    fn version_string_read(&self) -> Result<String, Error> {
+        Device::version_string_read(self)
+    }
+
+    fn tuning_handle(&self) -> TuningHandle {
+        Device::tuning_handle(self)
+    }
+
+    fn start_rx_sink(&mut self, sink: Box<dyn RxSink + Send>) -> Result<(), Error> {
+        Device::start_rx_sink(self, sink)
+    }
+
+    fn stop_rx(&mut self) -> Result<(), Error> {
+        Device::stop_rx(self)
+    }
+
+    fn start_tx_source(&mut self, source: Box<dyn TxSource + Send>) -> Result<(), Error> {
+        Device::start_tx_source(self, source)
+    }
+
+    fn stop_tx(&mut self) -> Result<(), Error> {
+        Device::stop_tx(self)
+    }
+
+    fn is_streaming(&self) -> Result<bool, Error> {
+        Device::is_streaming(self)
+    }
+}
+
+/// Lets a radio be chosen at run time, e.g. a `Device` or a `SimulatedDevice` behind `Box<dyn Radio>`
+impl <R: Radio + ?Sized> Radio for Box<R> {
+    fn set_freq(&self, freq_hz: u64) -> Result<(), Error> {
+        (**self).set_freq(freq_hz)
+    }
+
+    fn set_sample_rate(&self, freq_hz: f64) -> Result<(), Error> {
+        (**self).set_sample_rate(freq_hz)
+    }
+
+    fn set_baseband_filter_bandwidth(&self, bandwidth_hz: u32) -> Result<(), Error> {
+        (**self).set_baseband_filter_bandwidth(bandwidth_hz)
+    }
+
+    fn set_lna_gain(&self, value: u32) -> Result<(), Error> {
+        (**self).set_lna_gain(value)
+    }
+
+    fn set_vga_gain(&self, value: u32) -> Result<(), Error> {
+        (**self).set_vga_gain(value)
+    }
+
+    fn set_txvga_gain(&self, value: u32) -> Result<(), Error> {
+        (**self).set_txvga_gain(value)
+    }
+
+    fn set_amp_enable(&self, value: bool) -> Result<(), Error> {
+        (**self).set_amp_enable(value)
+    }
+
+    fn set_antenna_enable(&self, value: bool) -> Result<(), Error> {
+        (**self).set_antenna_enable(value)
+    }
+
+    fn init_sweep(&self, frequency_list: &[u16], num_bytes: u32, step_width: u32, offset: u32, style: sweep_style) -> Result<(), Error> {
+        (**self).init_sweep(frequency_list, num_bytes, step_width, offset, style)
+    }
+
+    fn board_id_read(&self) -> Result<u8, Error> {
+        (**self).board_id_read()
+    }
+
+    fn board_id_name(&self) -> Result<String, Error> {
+        (**self).board_id_name()
+    }
+
+    fn version_string_read(&self) -> Result<String, Error> {
+        (**self).version_string_read()
+    }
+
+    fn tuning_handle(&self) -> TuningHandle {
+        (**self).tuning_handle()
+    }
+
+    fn start_rx_sink(&mut self, sink: Box<dyn RxSink + Send>) -> Result<(), Error> {
+        (**self).start_rx_sink(sink)
+    }
+
+    fn stop_rx(&mut self) -> Result<(), Error> {
+        (**self).stop_rx()
+    }
+
+    fn start_tx_source(&mut self, source: Box<dyn TxSource + Send>) -> Result<(), Error> {
+        (**self).start_tx_source(source)
+    }
+
+    fn stop_tx(&mut self) -> Result<(), Error> {
+        (**self).stop_tx()
+    }
+
+    fn is_streaming(&self) -> Result<bool, Error> {
+        (**self).is_streaming()
+    }
+}
+
+/// A setting `apply_settings` understands, converted from JSON
+enum Setting {
+    Freq(u64),
+    SampleRate(f64),
+    BasebandFilter(u32),
+    LnaGain(u32),
+    VgaGain(u32),
+    TxVgaGain(u32),
+    AmpEnable(bool),
+    AntennaEnable(bool)
+}
+
+/// Applies settings given as JSON. The keys are `freq`, `sample_rate`, `baseband_filter`,
+/// `lna_gain`, `vga_gain`, `txvga_gain`, `amp_enable` and `antenna_enable`. Every key is checked
+/// before any is applied, so an unknown key or a value of the wrong type changes nothing; after
+/// that it stops at the first setting the radio rejects.
+pub fn apply_settings<R: Radio>(radio: &R, settings: &Map<String, Value>) -> Result<(), Error> {
+    let number = |key: &str, value: &Value| value.as_f64()
+        .ok_or_else(|| Error::INVALID_PARAM(format!("{} must be a number", key)));
+    let unsigned = |key: &str, value: &Value| value.as_u64()
+        .or_else(|| value.as_f64().filter(|v| *v >= 0.0 && v.fract() == 0.0).map(|v| v as u64))
+        .ok_or_else(|| Error::INVALID_PARAM(format!("{} must be a whole number of at least 0", key)));
+    let unsigned32 = |key: &str, value: &Value| unsigned(key, value)
+        .and_then(|v| u32::try_from(v).map_err(|_| Error::INVALID_PARAM(format!("{} must be at most {}", key, u32::MAX))));
+    let flag = |key: &str, value: &Value| value.as_bool()
+        .ok_or_else(|| Error::INVALID_PARAM(format!("{} must be true or false", key)));
+
+    let settings = settings.iter().map(|(key, value)| Ok(match key.as_str() {
+        "freq" => Setting::Freq(unsigned(key, value)?),
+        "sample_rate" => Setting::SampleRate(number(key, value)?),
+        "baseband_filter" => Setting::BasebandFilter(unsigned32(key, value)?),
+        "lna_gain" => Setting::LnaGain(unsigned32(key, value)?),
+        "vga_gain" => Setting::VgaGain(unsigned32(key, value)?),
+        "txvga_gain" => Setting::TxVgaGain(unsigned32(key, value)?),
+        "amp_enable" => Setting::AmpEnable(flag(key, value)?),
+        "antenna_enable" => Setting::AntennaEnable(flag(key, value)?),
+        other => return Err(Error::INVALID_PARAM(format!("Unknown setting {}", other)))
+    })).collect::<Result<Vec<_>, Error>>()?;
+
+    for setting in settings {
+        match setting {
+            Setting::Freq(freq_hz) => radio.set_freq(freq_hz)?,
+            Setting::SampleRate(rate) => radio.set_sample_rate(rate)?,
+            Setting::BasebandFilter(bandwidth_hz) => radio.set_baseband_filter_bandwidth(bandwidth_hz)?,
+            Setting::LnaGain(gain) => radio.set_lna_gain(gain)?,
+            Setting::VgaGain(gain) => radio.set_vga_gain(gain)?,
+            Setting::TxVgaGain(gain) => radio.set_txvga_gain(gain)?,
+            Setting::AmpEnable(enable) => radio.set_amp_enable(enable)?,
+            Setting::AntennaEnable(enable) => radio.set_antenna_enable(enable)?
+        }
+    }
+
+    Ok( () )
+}
+
+/// The radio's settings as a JSON object, with the keys `apply_settings` takes; unset ones are null
+pub fn settings_json<R: Radio>(radio: &R) -> Value {
+    let state = radio.tuning();
+
+    json!({
+        "freq": state.freq_hz,
+        "sample_rate": state.sample_rate,
+        "baseband_filter": state.baseband_filter_hz,
+        "lna_gain": state.lna_gain,
+        "vga_gain": state.vga_gain,
+        "txvga_gain": state.txvga_gain,
+        "amp_enable": state.amp_enable,
+        "antenna_enable": state.antenna_enable
+    })
+}
@@ -0,0 +1,507 @@
+//! Unattended recording into a directory of bounded segment files
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::formats::{IqWriter, SampleFormat};
+use crate::stream::RxSink;
+use crate::tuning::{TuningHandle, TuningState};
+
+/// File name prefix used unless `with_prefix` says otherwise
+pub const DEFAULT_PREFIX: &str = "hackrf";
+
+/// A finished segment still on disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub sidecar: Option<PathBuf>,
+    /// Size of the data and sidecar together
+    pub bytes: u64
+}
+
+/// The segment currently being written
+struct Current {
+    writer: IqWriter<BufWriter<File>>,
+    path: PathBuf,
+    start: DateTime<Utc>,
+    tuning: TuningState,
+    samples: u64
+}
+
+/// Records RX samples into a directory, starting a new file whenever the current one reaches a size or
+/// duration limit or the device is retuned.
+///
+/// Files are named `<prefix>_<UTC start time>_<center frequency>Hz.<format>`, so a directory listing
+/// sorts oldest first. With a disk cap set, the oldest segments (including ones left by earlier runs
+/// with the same prefix) are deleted to keep the directory's recordings under it.
+pub struct RotatingRecorder {
+    directory: PathBuf,
+    prefix: String,
+    format: SampleFormat,
+    tuning: TuningHandle,
+    generation: u64,
+    max_file_size: Option<u64>,
+    max_duration: Option<Duration>,
+    disk_cap: Option<u64>,
+    sidecar: bool,
+    current: Option<Current>,
+    segments: VecDeque<Segment>
+}
+
+impl RotatingRecorder {
+    /// Records into `directory`, naming files from `device`'s tuning
+    pub fn create<P: AsRef<Path>>(directory: P, format: SampleFormat, device: &Device) -> Result<RotatingRecorder, Error> {
+        RotatingRecorder::with_tuning(directory, format, device.tuning_handle())
+    }
+
+    /// Records into `directory`, naming files from `tuning`. The directory is created if needed.
+    pub fn with_tuning<P: AsRef<Path>>(directory: P, format: SampleFormat, tuning: TuningHandle) -> Result<RotatingRecorder, Error> {
+        fs::create_dir_all(&directory)?;
+
+        RotatingRecorder {
+            directory: directory.as_ref().to_path_buf(),
+            prefix: String::from(DEFAULT_PREFIX),
+            format,
+            generation: tuning.generation(),
+            tuning,
+            max_file_size: None,
+            max_duration: None,
+            disk_cap: None,
+            sidecar: false,
+            current: None,
+            segments: VecDeque::new()
+        }.scan()
+    }
+
+    /// Sets the file name prefix; earlier segments are only recognised (and capped) by this prefix
+    pub fn with_prefix(mut self, prefix: &str) -> Result<RotatingRecorder, Error> {
+        self.prefix = String::from(prefix);
+        self.scan()
+    }
+
+    /// Starts a new file once the current one reaches `bytes`
+    pub fn with_max_file_size(mut self, bytes: u64) -> RotatingRecorder {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Starts a new file once the current one holds `duration` worth of samples
+    pub fn with_max_duration(mut self, duration: Duration) -> RotatingRecorder {
+        self.max_duration = Some(duration);
+        self
+    }
+
+    /// Deletes the oldest segments to keep the total size of the recordings under `bytes`
+    pub fn with_disk_cap(mut self, bytes: u64) -> RotatingRecorder {
+        self.disk_cap = Some(bytes);
+        self
+    }
+
+    /// Writes a `<segment>.json` file describing each segment when it's closed
+    pub fn with_sidecar(mut self, sidecar: bool) -> RotatingRecorder {
+        self.sidecar = sidecar;
+        self
+    }
+
+    /// Finished segments still on disk, oldest first
+    pub fn segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments.iter()
+    }
+
+    /// The file currently being written, if any
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|c| c.path.as_path())
+    }
+
+    /// Picks up segments left in the directory by earlier runs
+    fn scan(mut self) -> Result<RotatingRecorder, Error> {
+        let start = format!("{}_", self.prefix);
+        let mut found = Vec::new();
+
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => continue
+            };
+
+            if !name.starts_with(&start) || SampleFormat::from_path(&path).is_none() {
+                continue;
+            }
+
+            let sidecar = sidecar_path(&path);
+            let sidecar = if sidecar.exists() { Some(sidecar) } else { None };
+            let bytes = fs::metadata(&path)?.len() + sidecar.as_ref().and_then(|s| fs::metadata(s).ok()).map(|m| m.len()).unwrap_or(0);
+
+            found.push(Segment { path, sidecar, bytes });
+        }
+
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+
+        if !found.is_empty() {
+            debug!("Found {} earlier segments in {:?}", found.len(), self.directory);
+        }
+
+        self.segments = found.into_iter().collect();
+
+        Ok(self)
+    }
+
+    /// Number of samples the current segment may hold, if limited
+    fn segment_limit(&self, state: &TuningState) -> Result<Option<u64>, Error> {
+        let by_size = self.max_file_size.map(|bytes| (bytes / self.format.sample_size() as u64).max(1));
+        let by_time = match self.max_duration {
+            Some(duration) => {
+                let rate = state.sample_rate
+                    .ok_or_else(|| Error::INVALID_PARAM(String::from("sample rate must be set to rotate by duration")))?;
+
+                Some(((duration.as_secs_f64() * rate).round() as u64).max(1))
+            },
+            None => None
+        };
+
+        Ok(match (by_size, by_time) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        })
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        let tuning = self.tuning.get();
+        let (path, start) = timestamped_path(&self.directory, &self.prefix, Utc::now(), tuning.freq_hz, self.format);
+
+        debug!("Starting segment {:?}", path);
+
+        self.generation = tuning.generation;
+        self.current = Some(Current {
+            writer: IqWriter::create_with_format(&path, self.format)?,
+            path,
+            start,
+            tuning,
+            samples: 0
+        });
+
+        Ok( () )
+    }
+
+    /// Finishes the current segment, if any
+    fn close(&mut self) -> Result<(), Error> {
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => return Ok( () )
+        };
+        let path = current.path.clone();
+        let mut bytes = current.samples * self.format.sample_size() as u64;
+
+        current.writer.into_inner()?;
+
+        let sidecar = if self.sidecar {
+            let sidecar = sidecar_path(&path);
+            let file = File::create(&sidecar)?;
+
+            serde_json::to_writer_pretty(file, &sidecar_json(&current.path, self.format, &current.tuning, current.start, current.samples))
+                .map_err(|e| Error::OTHER(format!("Error writing segment metadata: {}", e)))?;
+
+            bytes += fs::metadata(&sidecar)?.len();
+            Some(sidecar)
+        } else {
+            None
+        };
+
+        self.segments.push_back(Segment { path, sidecar, bytes });
+
+        Ok( () )
+    }
+
+    /// Deletes the oldest segments until everything fits under the cap
+    fn enforce_cap(&mut self) -> Result<(), Error> {
+        let cap = match self.disk_cap {
+            Some(cap) => cap,
+            None => return Ok( () )
+        };
+        let current = self.current.as_ref().map(|c| c.samples * self.format.sample_size() as u64).unwrap_or(0);
+        let mut total = current + self.segments.iter().map(|s| s.bytes).sum::<u64>();
+
+        while total > cap {
+            let oldest = match self.segments.pop_front() {
+                Some(oldest) => oldest,
+                None => break
+            };
+
+            debug!("Deleting {:?} to stay under the {} byte cap", oldest.path, cap);
+
+            remove_if_present(&oldest.path)?;
+
+            if let Some(ref sidecar) = oldest.sidecar {
+                remove_if_present(sidecar)?;
+            }
+
+            total -= oldest.bytes;
+        }
+
+        Ok( () )
+    }
+
+    /// Closes the current segment; the next write starts a new one
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.close()?;
+        self.enforce_cap()
+    }
+}
+
+/// Deletes `path`, which something else may already have done
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok( () ),
+        result => result
+    }
+}
+
+impl RxSink for RotatingRecorder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        if self.current.is_some() && self.tuning.generation() != self.generation {
+            let changed = {
+                let state = self.tuning.get();
+                let current = &self.current.as_ref().unwrap().tuning;
+
+                self.generation = state.generation;
+                state.freq_hz != current.freq_hz || state.sample_rate != current.sample_rate
+            };
+
+            if changed {
+                self.close()?;
+            }
+        }
+
+        let mut remaining = samples;
+
+        while remaining.len() >= 2 {
+            if self.current.is_none() {
+                self.open()?;
+            }
+
+            let limit = self.segment_limit(&self.current.as_ref().unwrap().tuning)?;
+            let current = self.current.as_mut().unwrap();
+            let count = match limit {
+                Some(limit) => ((limit - current.samples) as usize).min(remaining.len() / 2),
+                None => remaining.len() / 2
+            };
+
+            current.writer.write(&remaining[..count * 2])?;
+            current.samples += count as u64;
+            remaining = &remaining[count * 2..];
+
+            if limit.map(|limit| current.samples >= limit).unwrap_or(false) {
+                self.close()?;
+            }
+
+            self.enforce_cap()?;
+        }
+
+        Ok( () )
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self.current {
+            Some(ref mut current) => current.writer.flush(),
+            None => Ok( () )
+        }
+    }
+}
+
+impl Drop for RotatingRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            error!("Error finishing segment in {:?}: {:?}", self.directory, e);
+        }
+    }
+}
+
+/// Picks an unused `<prefix>_<UTC time>_<frequency>Hz.<format>` path in `directory`. If files were started
+/// within the same millisecond the time is nudged forward, so names still sort in order; the time used is returned.
+pub(crate) fn timestamped_path(directory: &Path, prefix: &str, mut time: DateTime<Utc>, freq_hz: Option<u64>, format: SampleFormat) -> (PathBuf, DateTime<Utc>) {
+    loop {
+        let path = directory.join(format!("{}_{}_{}Hz.{}", prefix, time.format("%Y%m%dT%H%M%S%.3fZ"), freq_hz.unwrap_or(0), format.extension()));
+
+        if !path.exists() {
+            return (path, time);
+        }
+
+        time += chrono::Duration::milliseconds(1);
+    }
+}
+
+/// `<path>.json`
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+fn sidecar_json(path: &Path, format: SampleFormat, tuning: &TuningState, start: DateTime<Utc>, samples: u64) -> serde_json::Value {
+    json!({
+        "file": path.file_name().and_then(|n| n.to_str()),
+        "format": format.extension(),
+        "samples": samples,
+        "start": start.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "end": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "center_freq": tuning.freq_hz,
+        "sample_rate": tuning.sample_rate,
+        "baseband_filter": tuning.baseband_filter_hz,
+        "lna_gain": tuning.lna_gain,
+        "vga_gain": tuning.vga_gain,
+        "amp_enable": tuning.amp_enable,
+        "antenna_enable": tuning.antenna_enable
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = temp_dir().join(format!("hackrf-recorder-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn tuning() -> TuningHandle {
+        let tuning = TuningHandle::new();
+
+        tuning.update(|t| {
+            t.sample_rate = Some(1000.0);
+            t.freq_hz = Some(433_920_000);
+        });
+
+        tuning
+    }
+
+    fn file_sizes(recorder: &RotatingRecorder) -> Vec<u64> {
+        recorder.segments().map(|s| fs::metadata(&s.path).unwrap().len()).collect()
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let directory = temp_directory("size");
+        let mut recorder = RotatingRecorder::with_tuning(&directory, SampleFormat::Cs8, tuning()).unwrap()
+            .with_max_file_size(100);
+
+        recorder.write(&[0.0; 120]).unwrap();
+        recorder.write(&[0.0; 120]).unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(file_sizes(&recorder), vec![100, 100, 40]);
+
+        let name = recorder.segments().next().unwrap().path.file_name().unwrap().to_str().unwrap().to_string();
+
+        assert!(name.starts_with("hackrf_20"), "{}", name);
+        assert!(name.ends_with("Z_433920000Hz.cs8"), "{}", name);
+
+        drop(recorder);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_duration_and_retune() {
+        let directory = temp_directory("duration");
+        let tuning = tuning();
+        let mut recorder = RotatingRecorder::with_tuning(&directory, SampleFormat::Cf32, tuning.clone()).unwrap()
+            .with_max_duration(Duration::from_millis(50));
+
+        recorder.write(&[0.0; 80]).unwrap();
+        tuning.update(|t| t.lna_gain = Some(16));
+        recorder.write(&[0.0; 20]).unwrap();
+        tuning.update(|t| t.freq_hz = Some(868_000_000));
+        recorder.write(&[0.0; 20]).unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(file_sizes(&recorder), vec![400, 80]);
+        assert!(recorder.segments().last().unwrap().path.to_str().unwrap().ends_with("_868000000Hz.cf32"));
+
+        drop(recorder);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn caps_disk_use() {
+        let directory = temp_directory("cap");
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("hackrf_19700101T000000.000Z_0Hz.cs8"), [0u8; 100]).unwrap();
+        fs::write(directory.join("unrelated.cs8"), [0u8; 100]).unwrap();
+
+        let mut recorder = RotatingRecorder::with_tuning(&directory, SampleFormat::Cs8, tuning()).unwrap()
+            .with_max_file_size(100)
+            .with_disk_cap(250);
+
+        assert_eq!(recorder.segments().count(), 1);
+
+        recorder.write(&[0.0; 200]).unwrap();
+        recorder.finish().unwrap();
+
+        let segments = recorder.segments().cloned().collect::<Vec<_>>();
+
+        assert_eq!(segments.len(), 2);
+        assert!(!directory.join("hackrf_19700101T000000.000Z_0Hz.cs8").exists());
+        assert!(directory.join("unrelated.cs8").exists());
+        assert_eq!(segments.iter().map(|s| s.bytes).sum::<u64>(), 200);
+
+        drop(recorder);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn cap_skips_segments_deleted_elsewhere() {
+        let directory = temp_directory("cap-deleted");
+        let old = directory.join("hackrf_19700101T000000.000Z_0Hz.cs8");
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(&old, [0u8; 100]).unwrap();
+
+        let mut recorder = RotatingRecorder::with_tuning(&directory, SampleFormat::Cs8, tuning()).unwrap()
+            .with_max_file_size(100)
+            .with_disk_cap(250);
+
+        fs::remove_file(&old).unwrap();
+        recorder.write(&[0.0; 200]).unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(recorder.segments().count(), 2);
+
+        drop(recorder);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn writes_sidecars() {
+        let directory = temp_directory("sidecar");
+        let mut recorder = RotatingRecorder::with_tuning(&directory, SampleFormat::Cs8, tuning()).unwrap()
+            .with_sidecar(true);
+
+        recorder.write(&[0.0; 100]).unwrap();
+        recorder.finish().unwrap();
+
+        let segment = recorder.segments().next().unwrap().clone();
+        let sidecar :serde_json::Value = serde_json::from_slice(&fs::read(segment.sidecar.as_ref().unwrap()).unwrap()).unwrap();
+
+        assert!(segment.bytes > 100);
+
+        assert_eq!(sidecar["samples"], 50);
+        assert_eq!(sidecar["center_freq"], 433_920_000);
+        assert_eq!(sidecar["format"], "cs8");
+
+        drop(recorder);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
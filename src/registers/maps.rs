@@ -0,0 +1,233 @@
+//! Register names and bitfield layouts for each chip, from the MAX2837, RFFC5071 and Si5351C
+//! (AN619) register descriptions. Registers without a name of their own are called `R<n>`, and
+//! bits not covered by a field are left undecoded.
+
+use super::{Chip, Field};
+
+/// Names and fields of one chip's registers
+pub struct RegisterMap {
+    pub(super) names: Vec<String>,
+    pub(super) fields: Vec<Field>
+}
+
+impl RegisterMap {
+    fn new(chip: Chip) -> RegisterMap {
+        RegisterMap {
+            names: (0..chip.register_count()).map(|n| format!("R{}", n)).collect(),
+            fields: Vec::new()
+        }
+    }
+
+    fn name(&mut self, register: u16, name: &str) -> &mut RegisterMap {
+        self.names[register as usize] = String::from(name);
+        self
+    }
+
+    fn field(&mut self, register: u16, name: &str, shift: u32, width: u32, description: &'static str) -> &mut RegisterMap {
+        self.fields.push(Field { name: String::from(name), register, shift, width, description });
+        self
+    }
+}
+
+lazy_static! {
+    pub(super) static ref MAX2837: RegisterMap = max2837();
+    pub(super) static ref SI5351C: RegisterMap = si5351c();
+    pub(super) static ref RFFC5071: RegisterMap = rffc5071();
+}
+
+fn max2837() -> RegisterMap {
+    let mut map = RegisterMap::new(Chip::Max2837);
+
+    map.field(1, "LNAgain_SPI_EN", 1, 1, "LNA gain set over SPI rather than by the gain pins")
+        .field(1, "LNAgain", 2, 3, "LNA gain code, 0 for the most gain")
+        .field(5, "VGA", 0, 5, "RX VGA attenuation code, 0 for the most gain")
+        .field(5, "VGAgain_SPI_EN", 5, 1, "RX VGA gain set over SPI rather than by the gain pins")
+        .field(8, "FT", 0, 4, "Low-pass filter bandwidth code")
+        .field(17, "SYN_FRAC_LO", 0, 10, "Synthesizer divider fraction, low 10 bits")
+        .field(18, "SYN_FRAC_HI", 0, 10, "Synthesizer divider fraction, high 10 bits")
+        .field(19, "SYN_INT", 0, 8, "Synthesizer divider integer part")
+        .field(29, "TXVGA_GAIN", 0, 6, "TX VGA attenuation code, 0 for the most gain");
+
+    map.name(17, "SYN_FRAC_LO").name(18, "SYN_FRAC_HI").name(19, "SYN_INT").name(29, "TXVGA");
+    map
+}
+
+fn rffc5071() -> RegisterMap {
+    let mut map = RegisterMap::new(Chip::Rffc5071);
+    let names = ["LF", "XO", "CAL_TIME", "VCO_CTRL", "CT_CAL1", "CT_CAL2", "PLL_CAL1", "PLL_CAL2", "VCO_AUTO",
+                 "PLL_CTRL", "PLL_BIAS", "MIX_CONT", "P1_FREQ1", "P1_FREQ2", "P1_FREQ3", "P2_FREQ1", "P2_FREQ2",
+                 "P2_FREQ3", "FN_CTRL", "EXT_MOD", "FMOD", "SDI_CTRL", "GPO", "T_VCO", "IQMOD1", "IQMOD2",
+                 "IQMOD3", "IQMOD4", "T_CTRL", "DEV_CTRL", "TEST"];
+
+    for (register, name) in names.iter().enumerate() {
+        map.name(register as u16, name);
+    }
+
+    map.field(0, "p2cpdef", 9, 6, "Path 2 charge pump current")
+        .field(0, "p1cpdef", 3, 6, "Path 1 charge pump current")
+        .field(0, "pllcpl", 0, 3, "Charge pump leakage")
+        .field(8, "auto", 15, 1, "Automatic VCO selection")
+        .field(8, "ctmax", 8, 7, "Highest coarse tuning value")
+        .field(8, "ctmin", 1, 7, "Lowest coarse tuning value")
+        .field(9, "divby", 15, 1, "Force the reference divider to divide by one")
+        .field(9, "clkdiv", 11, 3, "Reference divider ratio")
+        .field(11, "fulld", 15, 1, "Full duplex mode")
+        .field(11, "p1mixidd", 12, 3, "Path 1 mixer current")
+        .field(11, "p2mixidd", 9, 3, "Path 2 mixer current");
+
+    for (path, base) in &[(1, 12), (2, 15)] {
+        map.field(*base, &format!("p{}n", path), 7, 9, "PLL N divider integer part")
+            .field(*base, &format!("p{}lodiv", path), 4, 3, "LO divider, as a power of two")
+            .field(*base, &format!("p{}presc", path), 2, 2, "Prescaler select")
+            .field(*base, &format!("p{}vcosel", path), 0, 2, "VCO select")
+            .field(base + 1, &format!("p{}nmsb", path), 0, 16, "PLL N divider fraction, high 16 bits")
+            .field(base + 2, &format!("p{}nlsb", path), 8, 8, "PLL N divider fraction, low 8 bits");
+    }
+
+    map.field(21, "sipin", 15, 1, "Enable and mode set over SPI rather than by the pins")
+        .field(21, "enbl", 14, 1, "Device enabled, when sipin is set")
+        .field(21, "mode", 13, 1, "Path 2 selected, when sipin is set")
+        .field(21, "reset", 1, 1, "Soft reset")
+        .field(22, "p2gpo", 9, 7, "GPO levels while path 2 is selected")
+        .field(22, "p1gpo", 2, 7, "GPO levels while path 1 is selected")
+        .field(22, "gate", 1, 1, "GPOs held low while the device is disabled")
+        .field(22, "lock", 0, 1, "PLL lock detect routed to GPO4")
+        .field(29, "readsel", 12, 4, "What the readback register reports");
+
+    map
+}
+
+fn si5351c() -> RegisterMap {
+    let mut map = RegisterMap::new(Chip::Si5351c);
+
+    map.name(0, "DEVICE_STATUS")
+        .field(0, "SYS_INIT", 7, 1, "System initialising")
+        .field(0, "LOL_B", 6, 1, "PLL B loss of lock")
+        .field(0, "LOL_A", 5, 1, "PLL A loss of lock")
+        .field(0, "LOS_CLKIN", 4, 1, "Loss of the CLKIN signal")
+        .field(0, "LOS_XTAL", 3, 1, "Loss of the crystal signal")
+        .field(0, "REVID", 0, 2, "Device revision");
+
+    map.name(1, "INTERRUPT_STATUS_STICKY").name(2, "INTERRUPT_STATUS_MASK");
+
+    for (register, suffix) in &[(1, "_STKY"), (2, "_MASK")] {
+        for (name, shift) in &[("SYS_INIT", 7), ("LOL_B", 6), ("LOL_A", 5), ("LOS_CLKIN", 4), ("LOS_XTAL", 3)] {
+            let description = if *register == 1 { "Latched status, cleared by writing 0" } else { "Masks the interrupt" };
+
+            map.field(*register, &format!("{}{}", name, suffix), *shift, 1, description);
+        }
+    }
+
+    map.name(3, "OUTPUT_ENABLE_CONTROL").name(9, "OEB_PIN_ENABLE_CONTROL");
+
+    for clock in 0..8 {
+        map.field(3, &format!("CLK{}_OEB", clock), clock, 1, "Output disabled")
+            .field(9, &format!("OEB_CLK{}", clock), clock, 1, "Output ignores the OEB pin");
+    }
+
+    map.name(15, "PLL_INPUT_SOURCE")
+        .field(15, "CLKIN_DIV", 6, 2, "CLKIN divider, as a power of two")
+        .field(15, "PLLB_SRC", 3, 1, "PLL B fed from CLKIN rather than the crystal")
+        .field(15, "PLLA_SRC", 2, 1, "PLL A fed from CLKIN rather than the crystal");
+
+    for clock in 0..8u16 {
+        let register = 16 + clock;
+
+        map.name(register, &format!("CLK{}_CONTROL", clock))
+            .field(register, &format!("CLK{}_PDN", clock), 7, 1, "Output powered down")
+            .field(register, &format!("MS{}_INT", clock), 6, 1, "Multisynth in integer mode")
+            .field(register, &format!("MS{}_SRC", clock), 5, 1, "Multisynth fed from PLL B rather than PLL A")
+            .field(register, &format!("CLK{}_INV", clock), 4, 1, "Output inverted")
+            .field(register, &format!("CLK{}_SRC", clock), 2, 2, "Output source: 0 crystal, 1 CLKIN, 2 multisynth 0/4, 3 own multisynth")
+            .field(register, &format!("CLK{}_IDRV", clock), 0, 2, "Output drive: 2, 4, 6 or 8 mA");
+    }
+
+    map.name(24, "CLK3_0_DISABLE_STATE").name(25, "CLK7_4_DISABLE_STATE");
+
+    for clock in 0..8u32 {
+        map.field(24 + (clock / 4) as u16, &format!("CLK{}_DIS_STATE", clock), (clock % 4) * 2, 2,
+                  "Level while disabled: 0 low, 1 high, 2 high impedance, 3 never disabled");
+    }
+
+    // the PLL and multisynth dividers, eight registers each
+    let blocks = [("MSNA", 26), ("MSNB", 34), ("MS0", 42), ("MS1", 50), ("MS2", 58), ("MS3", 66), ("MS4", 74), ("MS5", 82)];
+
+    for (index, (divider, base)) in blocks.iter().enumerate() {
+        let title = if index < 2 { format!("{}_PARAMETERS", divider) } else { format!("MULTISYNTH{}_PARAMETERS", index - 2) };
+
+        for n in 0..8 {
+            map.name(base + n, &format!("{}_{}", title, n + 1));
+        }
+
+        map.field(*base, &format!("{}_P3_15_8", divider), 0, 8, "Denominator P3, bits 15-8")
+            .field(base + 1, &format!("{}_P3_7_0", divider), 0, 8, "Denominator P3, bits 7-0")
+            .field(base + 2, &format!("{}_P1_17_16", divider), 0, 2, "P1, bits 17-16")
+            .field(base + 3, &format!("{}_P1_15_8", divider), 0, 8, "P1, bits 15-8")
+            .field(base + 4, &format!("{}_P1_7_0", divider), 0, 8, "P1, bits 7-0")
+            .field(base + 5, &format!("{}_P3_19_16", divider), 4, 4, "Denominator P3, bits 19-16")
+            .field(base + 5, &format!("{}_P2_19_16", divider), 0, 4, "Numerator P2, bits 19-16")
+            .field(base + 6, &format!("{}_P2_15_8", divider), 0, 8, "Numerator P2, bits 15-8")
+            .field(base + 7, &format!("{}_P2_7_0", divider), 0, 8, "Numerator P2, bits 7-0");
+
+        if index >= 2 {
+            let output = index - 2;
+
+            map.field(base + 2, &format!("R{}_DIV", output), 4, 3, "Output divider, as a power of two")
+                .field(base + 2, &format!("{}_DIVBY4", divider), 2, 2, "Divide by 4 when both bits are set");
+        }
+    }
+
+    map.name(90, "MULTISYNTH6_PARAMETERS")
+        .field(90, "MS6_P1", 0, 8, "Multisynth 6 integer divide ratio")
+        .name(91, "MULTISYNTH7_PARAMETERS")
+        .field(91, "MS7_P1", 0, 8, "Multisynth 7 integer divide ratio")
+        .name(92, "CLOCK_6_7_OUTPUT_DIVIDER")
+        .field(92, "R7_DIV", 4, 3, "CLK7 output divider, as a power of two")
+        .field(92, "R6_DIV", 0, 3, "CLK6 output divider, as a power of two");
+
+    for clock in 0..6u16 {
+        map.name(165 + clock, &format!("CLK{}_INITIAL_PHASE_OFFSET", clock))
+            .field(165 + clock, &format!("CLK{}_PHOFF", clock), 0, 7, "Phase offset in quarter periods of the VCO");
+    }
+
+    map.name(177, "PLL_RESET")
+        .field(177, "PLLB_RST", 7, 1, "Reset PLL B")
+        .field(177, "PLLA_RST", 5, 1, "Reset PLL A")
+        .name(183, "CRYSTAL_INTERNAL_LOAD_CAPACITANCE")
+        .field(183, "XTAL_CL", 6, 2, "Crystal load capacitance: 1 for 6 pF, 2 for 8 pF, 3 for 10 pF")
+        .name(187, "FANOUT_ENABLE")
+        .field(187, "CLKIN_FANOUT_EN", 7, 1, "CLKIN fanned out to the outputs")
+        .field(187, "XO_FANOUT_EN", 6, 1, "Crystal fanned out to the outputs")
+        .field(187, "MS_FANOUT_EN", 4, 1, "Multisynth 0/4 fanned out to the outputs");
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_fit_and_dont_overlap() {
+        for chip in &Chip::ALL {
+            let mut used = vec![0u32; chip.register_count() as usize];
+
+            for field in chip.fields() {
+                assert!(field.register < chip.register_count(), "{} is outside the map", field.name);
+                assert!(field.shift + field.width <= chip.value_bits(), "{} is too wide", field.name);
+
+                let mask = field.mask() as u32;
+
+                assert_eq!(used[field.register as usize] & mask, 0, "{} overlaps another field", field.name);
+                used[field.register as usize] |= mask;
+            }
+
+            let mut names :Vec<&str> = chip.fields().iter().map(|f| f.name.as_str()).collect();
+            let count = names.len();
+
+            names.sort();
+            names.dedup();
+            assert_eq!(names.len(), count, "{} has duplicate field names", chip.name());
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! Register access to the HackRF's RF chips, for debugging the front end: the MAX2837
+//! transceiver, the Si5351C clock generator and the RFFC5071 mixer.
+//!
+//! Register numbers and values are checked against each chip's map before anything is sent, with
+//! the same limits libhackrf applies. Registers and their bitfields have names, so a `dump()` can
+//! be printed or compared field by field, and single fields can be written without disturbing
+//! the rest of their register. A `RegisterSnapshot` saves all three chips to a file and puts
+//! them back.
+
+use std::fmt;
+
+use crate::device::Device;
+use crate::error::Error;
+
+mod maps;
+mod snapshot;
+
+pub use self::snapshot::{RegisterSnapshot, SNAPSHOT_FORMAT, SNAPSHOT_VERSION};
+
+/// A chip whose registers libhackrf exposes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Chip {
+    Max2837,
+    Si5351c,
+    Rffc5071
+}
+
+impl Chip {
+    pub const ALL: [Chip; 3] = [Chip::Max2837, Chip::Si5351c, Chip::Rffc5071];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Chip::Max2837 => "MAX2837",
+            Chip::Si5351c => "Si5351C",
+            Chip::Rffc5071 => "RFFC5071"
+        }
+    }
+
+    /// Registers are numbered from 0 to one less than this
+    pub fn register_count(self) -> u16 {
+        match self {
+            Chip::Max2837 => 32,
+            Chip::Si5351c => 256,
+            Chip::Rffc5071 => 31
+        }
+    }
+
+    /// Bits in each register
+    pub fn value_bits(self) -> u32 {
+        match self {
+            Chip::Max2837 => 10,
+            Chip::Si5351c => 8,
+            Chip::Rffc5071 => 16
+        }
+    }
+
+    /// The largest value a register holds
+    pub fn max_value(self) -> u16 {
+        (((1u32 << self.value_bits()) - 1) & 0xffff) as u16
+    }
+
+    pub fn check_register(self, register: u16) -> Result<(), Error> {
+        if register >= self.register_count() {
+            return Err(Error::INVALID_PARAM(format!("{} registers are 0 to {}, not {}", self.name(), self.register_count() - 1, register)));
+        }
+
+        Ok( () )
+    }
+
+    pub fn check_value(self, value: u16) -> Result<(), Error> {
+        if value > self.max_value() {
+            return Err(Error::INVALID_PARAM(format!("{} registers are {} bits; 0x{:x} doesn't fit", self.name(), self.value_bits(), value)));
+        }
+
+        Ok( () )
+    }
+
+    fn map(self) -> &'static maps::RegisterMap {
+        match self {
+            Chip::Max2837 => &maps::MAX2837,
+            Chip::Si5351c => &maps::SI5351C,
+            Chip::Rffc5071 => &maps::RFFC5071
+        }
+    }
+
+    /// The register's name from the datasheet, or `R<n>` if it has none. `None` if the chip has
+    /// no such register.
+    pub fn register_name(self, register: u16) -> Option<&'static str> {
+        self.map().names.get(register as usize).map(String::as_str)
+    }
+
+    /// Every named bitfield, in register order
+    pub fn fields(self) -> &'static [Field] {
+        &self.map().fields
+    }
+
+    /// Looks a field up by name, ignoring case
+    pub fn field(self, name: &str) -> Result<&'static Field, Error> {
+        self.fields().iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::INVALID_PARAM(format!("{} has no field {}", self.name(), name)))
+    }
+
+    /// The fields within one register
+    pub fn register_fields(self, register: u16) -> impl Iterator<Item = &'static Field> {
+        self.fields().iter().filter(move |field| field.register == register)
+    }
+}
+
+impl fmt::Display for Chip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A run of bits within a register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub register: u16,
+    /// Position of the lowest bit
+    pub shift: u32,
+    pub width: u32,
+    pub description: &'static str
+}
+
+impl Field {
+    /// The field's bits within its register
+    pub fn mask(&self) -> u16 {
+        ((((1u32 << self.width) - 1) << self.shift) & 0xffff) as u16
+    }
+
+    /// The field's value from a whole register value
+    pub fn extract(&self, register_value: u16) -> u16 {
+        (register_value & self.mask()) >> self.shift
+    }
+
+    /// `register_value` with this field replaced by `value`
+    pub fn insert(&self, register_value: u16, value: u16) -> Result<u16, Error> {
+        if value as u32 >= 1 << self.width {
+            return Err(Error::INVALID_PARAM(format!("{} is {} bits; {} doesn't fit", self.name, self.width, value)));
+        }
+
+        Ok((register_value & !self.mask()) | (value << self.shift))
+    }
+}
+
+/// Every register of one chip, as read at one moment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterDump {
+    pub chip: Chip,
+    /// Indexed by register number
+    pub values: Vec<u16>
+}
+
+impl RegisterDump {
+    pub fn get(&self, register: u16) -> Option<u16> {
+        self.values.get(register as usize).copied()
+    }
+
+    /// The value of the field called `name`
+    pub fn field(&self, name: &str) -> Result<u16, Error> {
+        let field = self.chip.field(name)?;
+
+        self.get(field.register)
+            .map(|value| field.extract(value))
+            .ok_or_else(|| Error::INVALID_PARAM(format!("Dump has no register {}", field.register)))
+    }
+
+    /// Every named field with its value
+    pub fn decode(&self) -> Vec<(&'static Field, u16)> {
+        self.chip.fields().iter()
+            .filter_map(|field| self.get(field.register).map(|value| (field, field.extract(value))))
+            .collect()
+    }
+
+    /// The registers that differ from `earlier`, with the fields that changed in each
+    pub fn diff(&self, earlier: &RegisterDump) -> Result<Vec<RegisterChange>, Error> {
+        if self.chip != earlier.chip || self.values.len() != earlier.values.len() {
+            return Err(Error::INVALID_PARAM(format!("Can't compare a {} dump with a {} dump", self.chip, earlier.chip)));
+        }
+
+        Ok(earlier.values.iter().zip(self.values.iter()).enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(register, (before, after))| {
+                let register = register as u16;
+                let fields = self.chip.register_fields(register)
+                    .map(|field| (field, field.extract(*before), field.extract(*after)))
+                    .filter(|(_, before, after)| before != after)
+                    .collect();
+
+                RegisterChange { chip: self.chip, register, before: *before, after: *after, fields }
+            })
+            .collect())
+    }
+}
+
+/// Lists named registers, and any others that aren't 0, each with its decoded fields
+impl fmt::Display for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} registers", self.chip)?;
+
+        for (register, value) in self.values.iter().enumerate() {
+            let register = register as u16;
+            let name = self.chip.register_name(register).unwrap_or("");
+
+            if *value == 0 && name == format!("R{}", register) {
+                continue;
+            }
+
+            write!(f, "  0x{:02x} {:<34} 0x{:04x}", register, name, value)?;
+
+            for field in self.chip.register_fields(register) {
+                write!(f, " {}={}", field.name, field.extract(*value))?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok( () )
+    }
+}
+
+/// One register that differs between two dumps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub chip: Chip,
+    pub register: u16,
+    pub before: u16,
+    pub after: u16,
+    /// Fields whose value changed, with their values before and after
+    pub fields: Vec<(&'static Field, u16, u16)>
+}
+
+impl fmt::Display for RegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} 0x{:02x} {}: 0x{:04x} -> 0x{:04x}", self.chip, self.register, self.chip.register_name(self.register).unwrap_or(""), self.before, self.after)?;
+
+        for (field, before, after) in &self.fields {
+            write!(f, ", {} {} -> {}", field.name, before, after)?;
+        }
+
+        Ok( () )
+    }
+}
+
+/// Reading and writing chip registers on a radio, real or simulated
+pub trait RegisterAccess {
+    fn read_register(&self, chip: Chip, register: u16) -> Result<u16, Error>;
+    fn write_register(&self, chip: Chip, register: u16, value: u16) -> Result<(), Error>;
+
+    /// Reads every register of `chip` in turn
+    fn dump(&self, chip: Chip) -> Result<RegisterDump, Error> {
+        let values = (0..chip.register_count())
+            .map(|register| self.read_register(chip, register))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(RegisterDump { chip, values })
+    }
+
+    /// Reads the field called `name`
+    fn read_field(&self, chip: Chip, name: &str) -> Result<u16, Error> {
+        let field = chip.field(name)?;
+
+        Ok(field.extract(self.read_register(chip, field.register)?))
+    }
+
+    /// Sets the field called `name` by reading its register, replacing the field's bits and
+    /// writing it back, so the rest of the register is left as it was
+    fn write_field(&self, chip: Chip, name: &str, value: u16) -> Result<(), Error> {
+        let field = chip.field(name)?;
+        let current = self.read_register(chip, field.register)?;
+
+        self.write_register(chip, field.register, field.insert(current, value)?)
+    }
+}
+
+impl <'a> RegisterAccess for Device<'a> {
+    fn read_register(&self, chip: Chip, register: u16) -> Result<u16, Error> {
+        chip.check_register(register)?;
+
+        match chip {
+            Chip::Max2837 => self.max2837_read(register as u8),
+            Chip::Si5351c => self.si5351c_read(register),
+            Chip::Rffc5071 => self.rffc5071_read(register as u8)
+        }
+    }
+
+    fn write_register(&self, chip: Chip, register: u16, value: u16) -> Result<(), Error> {
+        chip.check_register(register)?;
+
+        match chip {
+            Chip::Max2837 => self.max2837_write(register as u8, value),
+            Chip::Si5351c => self.si5351c_write(register, value),
+            Chip::Rffc5071 => self.rffc5071_write(register as u8, value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedDevice;
+
+    #[test]
+    fn limits_match_libhackrf() {
+        assert_eq!(Chip::Max2837.max_value(), 0x3ff);
+        assert_eq!(Chip::Si5351c.max_value(), 0xff);
+        assert_eq!(Chip::Rffc5071.max_value(), 0xffff);
+
+        assert!(Chip::Max2837.check_register(31).is_ok());
+        assert!(Chip::Max2837.check_register(32).is_err());
+        assert!(Chip::Si5351c.check_register(255).is_ok());
+        assert!(Chip::Rffc5071.check_register(31).is_err());
+        assert!(Chip::Max2837.check_value(0x400).is_err());
+        assert!(Chip::Si5351c.check_value(0x100).is_err());
+    }
+
+    #[test]
+    fn simulated_registers() {
+        let sim = SimulatedDevice::new();
+
+        sim.write_register(Chip::Max2837, 5, 0x2a5).unwrap();
+        sim.write_register(Chip::Rffc5071, 30, 0xbeef).unwrap();
+
+        assert_eq!(sim.read_register(Chip::Max2837, 5).unwrap(), 0x2a5);
+        assert!(sim.write_register(Chip::Max2837, 5, 0x400).is_err());
+        assert!(sim.read_register(Chip::Si5351c, 256).is_err());
+
+        let dump = sim.dump(Chip::Rffc5071).unwrap();
+
+        assert_eq!(dump.values.len(), 31);
+        assert_eq!(dump.get(30), Some(0xbeef));
+        assert_eq!(dump.get(31), None);
+    }
+
+    #[test]
+    fn decodes_fields() {
+        let mut dump = RegisterDump { chip: Chip::Max2837, values: vec![0; 32] };
+
+        dump.values[1] = 0b10110;
+        dump.values[19] = 0x153;
+
+        assert_eq!(dump.field("LNAgain").unwrap(), 0b101);
+        assert_eq!(dump.field("lnagain_spi_en").unwrap(), 1);
+        assert_eq!(dump.field("SYN_INT").unwrap(), 0x53);
+        assert!(dump.field("nonsense").is_err());
+        assert!(dump.decode().iter().any(|(field, value)| field.name == "LNAgain" && *value == 5));
+
+        let text = dump.to_string();
+
+        assert!(text.contains("SYN_INT"), "{}", text);
+        assert!(!text.contains(" R2 "), "{}", text);
+        assert_eq!(Chip::Si5351c.register_name(0), Some("DEVICE_STATUS"));
+        assert_eq!(Chip::Si5351c.register_name(4), Some("R4"));
+        assert_eq!(Chip::Rffc5071.register_name(22), Some("GPO"));
+        assert_eq!(Chip::Rffc5071.register_name(31), None);
+    }
+
+    #[test]
+    fn diffs_dumps() {
+        let before = RegisterDump { chip: Chip::Si5351c, values: vec![0; 256] };
+        let mut after = before.clone();
+
+        after.values[0] = 0x20;
+        after.values[16] = 0x4f;
+
+        let changes = after.diff(&before).unwrap();
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].register, 0);
+        assert_eq!(changes[0].fields.iter().map(|(f, b, a)| (f.name.as_str(), *b, *a)).collect::<Vec<_>>(), vec![("LOL_A", 0, 1)]);
+        assert_eq!(changes[1].fields.len(), 3);
+        assert!(changes[1].to_string().contains("CLK0_CONTROL"));
+        assert!(after.diff(&RegisterDump { chip: Chip::Max2837, values: vec![0; 32] }).is_err());
+        assert!(after.diff(&after).unwrap().is_empty());
+    }
+
+    #[test]
+    fn writes_fields_in_place() {
+        let sim = SimulatedDevice::new();
+
+        sim.write_register(Chip::Rffc5071, 12, 0xffff).unwrap();
+        sim.write_field(Chip::Rffc5071, "p1lodiv", 2).unwrap();
+
+        assert_eq!(sim.read_register(Chip::Rffc5071, 12).unwrap(), 0xffaf);
+        assert_eq!(sim.read_field(Chip::Rffc5071, "p1lodiv").unwrap(), 2);
+        assert!(sim.write_field(Chip::Rffc5071, "p1lodiv", 8).is_err());
+        assert!(sim.write_field(Chip::Max2837, "p1lodiv", 1).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Saving every RF chip register to a file and putting them back later, so a front-end
+//! configuration found by tweaking registers can be kept.
+//!
+//! Snapshots are JSON:
+//!
+//! ```json
+//! {
+//!   "format": "hackrf-registers",
+//!   "version": 1,
+//!   "board_id": 2,
+//!   "board_name": "HackRF One",
+//!   "firmware": "2023.01.1",
+//!   "datetime": "2024-05-01T12:00:00.000Z",
+//!   "chips": { "MAX2837": [...], "Si5351C": [...], "RFFC5071": [...] }
+//! }
+//! ```
+//!
+//! with one number per register, in register order.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+
+use super::{Chip, RegisterAccess, RegisterChange, RegisterDump};
+use crate::error::Error;
+use crate::radio::Radio;
+
+pub const SNAPSHOT_FORMAT: &str = "hackrf-registers";
+pub const SNAPSHOT_VERSION: u64 = 1;
+
+/// Chips in the order they're restored: the clocks first, as everything else runs from them
+const RESTORE_ORDER: [Chip; 3] = [Chip::Si5351c, Chip::Rffc5071, Chip::Max2837];
+
+/// Si5351C PLL reset register, and its bits that reset PLL A and PLL B
+const SI5351C_PLL_RESET: u16 = 177;
+const SI5351C_PLLA_RST: u16 = 0x20;
+const SI5351C_PLLB_RST: u16 = 0x80;
+
+/// Registers that report status rather than hold settings, act when written, or are reserved, so
+/// are neither restored nor verified
+fn is_skipped(chip: Chip, register: u16) -> bool {
+    match chip {
+        // device status, sticky interrupt status, the self-clearing PLL reset, and everything AN619
+        // marks reserved or only present on the Si5351B (the VCXO parameters, 162-164)
+        Chip::Si5351c => matches!(register, 0 | 1 | SI5351C_PLL_RESET | 4..=8 | 10..=14 | 93..=148 | 162..=164
+                                            | 171..=176 | 178..=182 | 184..=186 | 188..=255),
+        _ => false
+    }
+}
+
+/// The Si5351C PLL reset bits needed after writing `register`: a new PLL A (MSNA, 26-33) or
+/// PLL B (MSNB, 34-41) divider only takes effect once its PLL is reset
+fn si5351c_pll_reset(register: u16) -> u16 {
+    match register {
+        26..=33 => SI5351C_PLLA_RST,
+        34..=41 => SI5351C_PLLB_RST,
+        _ => 0
+    }
+}
+
+/// Every register of every chip on one board
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterSnapshot {
+    pub board_id: u8,
+    pub board_name: String,
+    pub firmware: String,
+    /// RFC 3339 time the snapshot was taken
+    pub datetime: String,
+    pub dumps: Vec<RegisterDump>
+}
+
+impl RegisterSnapshot {
+    /// Reads every register of every chip on `device`
+    pub fn take<D: Radio + RegisterAccess>(device: &D) -> Result<RegisterSnapshot, Error> {
+        Ok(RegisterSnapshot {
+            board_id: device.board_id_read()?,
+            board_name: device.board_id_name()?,
+            firmware: device.version_string_read()?,
+            datetime: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            dumps: Chip::ALL.iter().map(|chip| device.dump(*chip)).collect::<Result<Vec<_>, Error>>()?
+        })
+    }
+
+    pub fn dump(&self, chip: Chip) -> Option<&RegisterDump> {
+        self.dumps.iter().find(|dump| dump.chip == chip)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let chips :Map<String, Value> = self.dumps.iter()
+            .map(|dump| (String::from(dump.chip.name()), json!(dump.values)))
+            .collect();
+
+        json!({
+            "format": SNAPSHOT_FORMAT,
+            "version": SNAPSHOT_VERSION,
+            "board_id": self.board_id,
+            "board_name": self.board_name,
+            "firmware": self.firmware,
+            "datetime": self.datetime,
+            "chips": chips
+        })
+    }
+
+    /// Parses a snapshot made by `to_json`, which must hold every register of every chip
+    pub fn from_json(snapshot: &Value) -> Result<RegisterSnapshot, Error> {
+        let bad = |what: &str| Error::INVALID_PARAM(format!("Register snapshot {}", what));
+
+        if snapshot["format"].as_str() != Some(SNAPSHOT_FORMAT) {
+            return Err(bad("is not in the hackrf-registers format"));
+        }
+
+        match snapshot["version"].as_u64() {
+            Some(SNAPSHOT_VERSION) => {},
+            Some(version) => return Err(bad(&format!("is version {}; only version {} is understood", version, SNAPSHOT_VERSION))),
+            None => return Err(bad("has no version"))
+        }
+
+        let board_id = snapshot["board_id"].as_u64().filter(|id| *id <= u8::MAX as u64).ok_or_else(|| bad("has no board_id"))? as u8;
+        let text = |key: &str| snapshot[key].as_str().unwrap_or("").to_string();
+        let mut dumps = Vec::new();
+
+        for chip in &Chip::ALL {
+            let values = snapshot["chips"][chip.name()].as_array()
+                .ok_or_else(|| bad(&format!("has no {} registers", chip)))?;
+
+            if values.len() != chip.register_count() as usize {
+                return Err(bad(&format!("has {} {} registers, not {}", values.len(), chip, chip.register_count())));
+            }
+
+            let values = values.iter()
+                .map(|value| value.as_u64()
+                     .filter(|value| *value <= chip.max_value() as u64)
+                     .map(|value| value as u16)
+                     .ok_or_else(|| bad(&format!("has an invalid {} register value: {}", chip, value))))
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            dumps.push(RegisterDump { chip: *chip, values });
+        }
+
+        Ok(RegisterSnapshot { board_id, board_name: text("board_name"), firmware: text("firmware"), datetime: text("datetime"), dumps })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, &self.to_json())
+            .map_err(|e| Error::OTHER(format!("Error writing register snapshot: {}", e)))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<RegisterSnapshot, Error> {
+        let snapshot :Value = serde_json::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|e| Error::INVALID_PARAM(format!("Error parsing register snapshot: {}", e)))?;
+
+        RegisterSnapshot::from_json(&snapshot)
+    }
+
+    /// Writes every register that differs from the snapshot, resetting any Si5351C PLL whose
+    /// divider changed, then reads them all back to check they took. Returns what was changed. Refuses a snapshot taken from a different kind of
+    /// board, as its settings could damage this one.
+    pub fn restore<D: Radio + RegisterAccess>(&self, device: &D) -> Result<Vec<RegisterChange>, Error> {
+        let board_id = device.board_id_read()?;
+
+        if board_id != self.board_id {
+            return Err(Error::INVALID_PARAM(format!("Snapshot is from board ID {} ({}), but this is board ID {} ({})",
+                                                    self.board_id, self.board_name, board_id, device.board_id_name()?)));
+        }
+
+        let mut changes = Vec::new();
+
+        for wanted in RESTORE_ORDER.iter().filter_map(|chip| self.dump(*chip)) {
+            let current = device.dump(wanted.chip)?;
+            let mut pll_reset = 0;
+
+            for change in wanted.diff(&current)? {
+                if is_skipped(change.chip, change.register) {
+                    continue;
+                }
+
+                device.write_register(change.chip, change.register, change.after)?;
+
+                if change.chip == Chip::Si5351c {
+                    pll_reset |= si5351c_pll_reset(change.register);
+                }
+
+                changes.push(change);
+            }
+
+            if pll_reset != 0 {
+                device.write_register(Chip::Si5351c, SI5351C_PLL_RESET, pll_reset)?;
+            }
+        }
+
+        for wanted in RESTORE_ORDER.iter().filter_map(|chip| self.dump(*chip)) {
+            let mismatches :Vec<String> = device.dump(wanted.chip)?.diff(wanted)?.iter()
+                .filter(|change| !is_skipped(change.chip, change.register))
+                .map(|change| format!("{} 0x{:02x} reads 0x{:04x}, not 0x{:04x}", change.chip, change.register, change.after, change.before))
+                .collect();
+
+            if !mismatches.is_empty() {
+                return Err(Error::OTHER(format!("Registers didn't take their restored values: {}", mismatches.join("; "))));
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedDevice;
+    use std::env::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn save_and_restore() {
+        let sim = SimulatedDevice::new();
+        let path = temp_dir().join(format!("hackrf-registers-{}.json", std::process::id()));
+
+        sim.write_register(Chip::Max2837, 1, 0x1e).unwrap();
+        sim.write_register(Chip::Si5351c, 16, 0x4f).unwrap();
+        RegisterSnapshot::take(&sim).unwrap().save(&path).unwrap();
+
+        sim.write_register(Chip::Max2837, 1, 0).unwrap();
+        sim.write_register(Chip::Rffc5071, 12, 0x1234).unwrap();
+
+        let snapshot = RegisterSnapshot::load(&path).unwrap();
+        let changes = snapshot.restore(&sim).unwrap();
+
+        assert_eq!(snapshot.board_name, "HackRF One");
+        assert_eq!(changes.len(), 2);
+        assert_eq!(sim.read_register(Chip::Max2837, 1).unwrap(), 0x1e);
+        assert_eq!(sim.read_register(Chip::Rffc5071, 12).unwrap(), 0);
+        assert_eq!(sim.read_register(Chip::Si5351c, 16).unwrap(), 0x4f);
+
+        // restoring again has nothing left to do
+        assert!(snapshot.restore(&sim).unwrap().is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resets_plls_and_skips_reserved() {
+        let sim = SimulatedDevice::new();
+
+        sim.write_register(Chip::Si5351c, 28, 0x0d).unwrap();
+        sim.write_register(Chip::Si5351c, 100, 0x55).unwrap();
+        let snapshot = RegisterSnapshot::take(&sim).unwrap();
+
+        sim.write_register(Chip::Si5351c, 28, 0).unwrap();
+        sim.write_register(Chip::Si5351c, 100, 0).unwrap();
+        let changes = snapshot.restore(&sim).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(sim.read_register(Chip::Si5351c, 28).unwrap(), 0x0d);
+        assert_eq!(sim.read_register(Chip::Si5351c, 100).unwrap(), 0);
+        assert_eq!(sim.read_register(Chip::Si5351c, SI5351C_PLL_RESET).unwrap(), SI5351C_PLLA_RST);
+
+        // a PLL B divider resets PLL B too
+        sim.write_register(Chip::Si5351c, 28, 0).unwrap();
+        sim.write_register(Chip::Si5351c, 36, 0x01).unwrap();
+        snapshot.restore(&sim).unwrap();
+
+        assert_eq!(sim.read_register(Chip::Si5351c, SI5351C_PLL_RESET).unwrap(), SI5351C_PLLA_RST | SI5351C_PLLB_RST);
+    }
+
+    #[test]
+    fn refuses_other_boards() {
+        let snapshot = RegisterSnapshot::take(&SimulatedDevice::new()).unwrap();
+        let jawbreaker = SimulatedDevice::new().with_board_id(1);
+
+        jawbreaker.write_register(Chip::Max2837, 1, 0x1e).unwrap();
+
+        assert!(snapshot.restore(&jawbreaker).is_err());
+        assert_eq!(jawbreaker.read_register(Chip::Max2837, 1).unwrap(), 0x1e);
+    }
+
+    #[test]
+    fn checks_format_and_version() {
+        let mut json = RegisterSnapshot::take(&SimulatedDevice::new()).unwrap().to_json();
+
+        assert!(RegisterSnapshot::from_json(&json).is_ok());
+
+        json["chips"]["MAX2837"][0] = json!(0x400);
+        assert!(RegisterSnapshot::from_json(&json).is_err());
+
+        json["chips"]["MAX2837"][0] = json!(0);
+        json["chips"].as_object_mut().unwrap().remove("RFFC5071");
+        assert!(RegisterSnapshot::from_json(&json).is_err());
+
+        json["chips"] = json!({});
+        assert!(RegisterSnapshot::from_json(&json).is_err());
+
+        json["version"] = json!(2);
+        assert!(RegisterSnapshot::from_json(&json).is_err());
+
+        json["format"] = json!("something else");
+        assert!(RegisterSnapshot::from_json(&json).is_err());
+    }
+}
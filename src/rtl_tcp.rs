@@ -0,0 +1,551 @@
+//! A server for the [rtl_tcp](https://github.com/osmocom/rtl-sdr/blob/master/src/rtl_tcp.c) protocol,
+//! so clients written for RTL-SDR dongles (SDR++, GQRX, OpenWebRX, ...) can use a HackRF.
+//!
+//! Clients see an R820T tuner. Sample rates below the HackRF's minimum are produced by running the
+//! hardware at a multiple of the requested rate and decimating.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::dsp::{FirDecimator, Stage};
+use crate::error::Error;
+use crate::formats::SampleFormat;
+use crate::radio::Radio;
+use crate::stream::RxSink;
+
+/// Port rtl_tcp listens on by default
+pub const DEFAULT_PORT: u16 = 1234;
+
+/// Tuner type reported in the greeting; clients pick their gain table from it
+const TUNER_R820T: u32 = 5;
+
+/// The R820T gain steps in tenths of a dB, which is what `SetGainByIndex` indexes
+pub const R820T_GAINS: [i32; 29] = [
+    0, 9, 14, 27, 37, 77, 87, 125, 144, 157, 166, 197, 207, 229, 254,
+    280, 297, 328, 338, 364, 372, 386, 402, 421, 434, 439, 445, 480, 496
+];
+
+/// Lowest rate the HackRF is run at; slower requests are decimated down from a multiple of it
+const MIN_HARDWARE_RATE: f64 = 4_000_000.0;
+const MAX_HARDWARE_RATE: f64 = 20_000_000.0;
+
+/// Most decimation done for a client, reaching just below an RTL-SDR's lowest rate of 225 kHz;
+/// slower requests would need huge filters run on the USB thread
+const MAX_DECIMATION: usize = 18;
+
+/// LNA/VGA settings used in "automatic" gain mode, since the HackRF has no AGC
+const AUTO_LNA_GAIN: u32 = 16;
+const AUTO_VGA_GAIN: u32 = 20;
+
+/// Buffers allowed to queue for a slow client before samples are dropped
+const QUEUE_DEPTH: usize = 64;
+
+/// A command sent by an rtl_tcp client: one byte of command and a big-endian 32-bit parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SetFrequency(u32),
+    SetSampleRate(u32),
+    /// True for manual gain, false for automatic
+    SetGainMode(bool),
+    /// Total gain in tenths of a dB
+    SetGain(i32),
+    SetFreqCorrection(i32),
+    SetIfGain(u32),
+    SetTestMode(bool),
+    SetAgcMode(bool),
+    SetDirectSampling(u32),
+    SetOffsetTuning(bool),
+    SetRtlXtal(u32),
+    SetTunerXtal(u32),
+    /// Index into `R820T_GAINS`
+    SetGainByIndex(u32),
+    SetBiasTee(bool),
+    Unknown(u8, u32)
+}
+
+impl Command {
+    pub fn parse(bytes: [u8; 5]) -> Command {
+        let param = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+
+        match bytes[0] {
+            0x01 => Command::SetFrequency(param),
+            0x02 => Command::SetSampleRate(param),
+            0x03 => Command::SetGainMode(param != 0),
+            0x04 => Command::SetGain(param as i32),
+            0x05 => Command::SetFreqCorrection(param as i32),
+            0x06 => Command::SetIfGain(param),
+            0x07 => Command::SetTestMode(param != 0),
+            0x08 => Command::SetAgcMode(param != 0),
+            0x09 => Command::SetDirectSampling(param),
+            0x0a => Command::SetOffsetTuning(param != 0),
+            0x0b => Command::SetRtlXtal(param),
+            0x0c => Command::SetTunerXtal(param),
+            0x0d => Command::SetGainByIndex(param),
+            0x0e => Command::SetBiasTee(param != 0),
+            cmd => Command::Unknown(cmd, param)
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let (cmd, param) = match *self {
+            Command::SetFrequency(v) => (0x01, v),
+            Command::SetSampleRate(v) => (0x02, v),
+            Command::SetGainMode(v) => (0x03, v as u32),
+            Command::SetGain(v) => (0x04, v as u32),
+            Command::SetFreqCorrection(v) => (0x05, v as u32),
+            Command::SetIfGain(v) => (0x06, v),
+            Command::SetTestMode(v) => (0x07, v as u32),
+            Command::SetAgcMode(v) => (0x08, v as u32),
+            Command::SetDirectSampling(v) => (0x09, v),
+            Command::SetOffsetTuning(v) => (0x0a, v as u32),
+            Command::SetRtlXtal(v) => (0x0b, v),
+            Command::SetTunerXtal(v) => (0x0c, v),
+            Command::SetGainByIndex(v) => (0x0d, v),
+            Command::SetBiasTee(v) => (0x0e, v as u32),
+            Command::Unknown(cmd, v) => (cmd, v)
+        };
+        let param = param.to_be_bytes();
+
+        [cmd, param[0], param[1], param[2], param[3]]
+    }
+}
+
+/// Splits a total gain in dB over the LNA (0-40 dB in 8 dB steps) and VGA (0-62 dB in 2 dB steps)
+pub fn split_gain(gain_db: f64) -> (u32, u32) {
+    let gain = gain_db.max(0.0) as u32;
+    let lna = (gain / 8 * 8).min(40);
+    let vga = ((gain - lna) / 2 * 2).min(62);
+
+    (lna, vga)
+}
+
+/// Hardware rate and decimation for a requested sample rate
+pub fn hardware_rate(rate: f64) -> Result<(f64, usize), Error> {
+    if rate <= 0.0 || rate > MAX_HARDWARE_RATE {
+        return Err(Error::INVALID_PARAM(format!("Unsupported sample rate {}", rate)));
+    }
+
+    if rate < MIN_HARDWARE_RATE / MAX_DECIMATION as f64 {
+        return Err(Error::INVALID_PARAM(format!("Unsupported sample rate {}; the lowest is {}", rate, MIN_HARDWARE_RATE / MAX_DECIMATION as f64)));
+    }
+
+    let decimation = (MIN_HARDWARE_RATE / rate).ceil().max(1.0) as usize;
+    let hardware = rate * decimation as f64;
+
+    if hardware > MAX_HARDWARE_RATE {
+        return Err(Error::INVALID_PARAM(format!("Unsupported sample rate {}", rate)));
+    }
+
+    Ok((hardware, decimation))
+}
+
+/// Turns RX buffers into u8 IQ and queues them for the client
+struct ClientSink {
+    queue: SyncSender<Vec<u8>>,
+    decimation: Arc<AtomicUsize>,
+    decimator: Option<FirDecimator>,
+    decimated: Vec<f32>,
+    dropped: u64
+}
+
+impl RxSink for ClientSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let decimation = self.decimation.load(Ordering::SeqCst);
+
+        if self.decimator.as_ref().map(|d| d.decimation()).unwrap_or(1) != decimation {
+            self.decimator = if decimation > 1 { Some(FirDecimator::lowpass(decimation)) } else { None };
+        }
+
+        let samples = match self.decimator {
+            Some(ref mut decimator) => {
+                self.decimated.clear();
+                decimator.process(samples, &mut self.decimated);
+                &self.decimated[..]
+            },
+            None => samples
+        };
+
+        let mut bytes = Vec::with_capacity(samples.len());
+
+        SampleFormat::Cu8.encode(samples, &mut bytes);
+
+        match self.queue.try_send(bytes) {
+            Ok( () ) => Ok( () ),
+            Err(TrySendError::Full(_)) => {
+                // never block the USB thread on a slow client
+                self.dropped += 1;
+
+                if self.dropped.is_power_of_two() {
+                    warn!("Client is not keeping up; {} buffers dropped", self.dropped);
+                }
+
+                Ok( () )
+            },
+            Err(TrySendError::Disconnected(_)) => Err(Error::STREAMING_EXIT_CALLED(String::from("rtl_tcp client disconnected")))
+        }
+    }
+}
+
+/// What the command loop hears from the connection threads
+enum Event {
+    Command(Command),
+    Closed
+}
+
+/// Serves rtl_tcp clients, one at a time, from any `Radio`
+pub struct RtlTcpServer {
+    listener: TcpListener,
+    shutdown: Arc<AtomicBool>,
+    frequency: u64,
+    sample_rate: f64,
+    gain: Option<f64>
+}
+
+impl RtlTcpServer {
+    /// Listens on `addr`; the radio starts at 100 MHz and 2.048 MS/s with automatic gain, as rtl_tcp does
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<RtlTcpServer, Error> {
+        let listener = TcpListener::bind(addr)?;
+
+        listener.set_nonblocking(true)?;
+
+        Ok(RtlTcpServer {
+            listener,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            frequency: 100_000_000,
+            sample_rate: 2_048_000.0,
+            gain: None
+        })
+    }
+
+    pub fn with_frequency(mut self, freq_hz: u64) -> RtlTcpServer {
+        self.frequency = freq_hz;
+        self
+    }
+
+    pub fn with_sample_rate(mut self, rate: f64) -> RtlTcpServer {
+        self.sample_rate = rate;
+        self
+    }
+
+    /// Starts in manual gain mode with `gain_db` split over the LNA and VGA
+    pub fn with_gain(mut self, gain_db: f64) -> RtlTcpServer {
+        self.gain = Some(gain_db);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// A flag that makes `serve` return, and drops the current client, when set
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+
+    /// Serves clients one after another until shut down
+    pub fn serve<R: Radio>(&self, radio: &mut R) -> Result<(), Error> {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            if let Err(e) = self.serve_one(radio) {
+                error!("Error serving rtl_tcp client: {}", e);
+            }
+        }
+
+        Ok( () )
+    }
+
+    /// Waits for a client and serves it until it disconnects; returns immediately once shut down
+    pub fn serve_one<R: Radio>(&self, radio: &mut R) -> Result<(), Error> {
+        let (stream, peer) = loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                return Ok( () );
+            }
+
+            match self.listener.accept() {
+                Ok(client) => break client,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+                Err(e) => return Err(Error::from(e))
+            }
+        };
+
+        info!("rtl_tcp client connected from {}", peer);
+
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+
+        let result = self.session(radio, stream);
+
+        info!("rtl_tcp client {} disconnected", peer);
+
+        result
+    }
+
+    fn session<R: Radio>(&self, radio: &mut R, mut stream: TcpStream) -> Result<(), Error> {
+        let mut session = Session {
+            ppm: 0,
+            frequency: self.frequency,
+            sample_rate: self.sample_rate,
+            decimation: Arc::new(AtomicUsize::new(1)),
+            manual_gain: self.gain.is_some()
+        };
+
+        session.tune(radio, session.frequency, session.sample_rate, session.ppm)?;
+
+        match self.gain {
+            Some(gain) => session.set_gain(radio, gain)?,
+            None => session.set_gain(radio, -1.0)?
+        }
+
+        let mut greeting = Vec::with_capacity(12);
+
+        greeting.extend_from_slice(b"RTL0");
+        greeting.extend_from_slice(&TUNER_R820T.to_be_bytes());
+        greeting.extend_from_slice(&(R820T_GAINS.len() as u32).to_be_bytes());
+        stream.write_all(&greeting)?;
+
+        let (queue, samples) = mpsc::sync_channel(QUEUE_DEPTH);
+        let (events, commands) = mpsc::channel();
+        let writer = spawn_writer(stream.try_clone()?, samples, events.clone());
+        let reader = spawn_reader(stream.try_clone()?, events);
+
+        radio.start_rx_sink(Box::new(ClientSink {
+            queue,
+            decimation: session.decimation.clone(),
+            decimator: None,
+            decimated: Vec::new(),
+            dropped: 0
+        }))?;
+
+        let result = session.run(radio, &commands, &self.shutdown);
+
+        let _ = radio.stop_rx();
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+        let _ = writer.join();
+        let _ = reader.join();
+
+        result
+    }
+}
+
+/// Settings for one client connection
+struct Session {
+    ppm: i32,
+    frequency: u64,
+    sample_rate: f64,
+    decimation: Arc<AtomicUsize>,
+    manual_gain: bool
+}
+
+impl Session {
+    fn run<R: Radio>(&mut self, radio: &mut R, commands: &Receiver<Event>, shutdown: &AtomicBool) -> Result<(), Error> {
+        while !shutdown.load(Ordering::SeqCst) {
+            match commands.recv_timeout(Duration::from_millis(100)) {
+                Ok(Event::Command(command)) => {
+                    debug!("rtl_tcp command: {:?}", command);
+
+                    // a bad value from one client shouldn't end the session
+                    if let Err(e) = self.apply(radio, command) {
+                        warn!("Error applying {:?}: {}", command, e);
+                    }
+                },
+                Ok(Event::Closed) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+
+        Ok( () )
+    }
+
+    fn apply<R: Radio>(&mut self, radio: &mut R, command: Command) -> Result<(), Error> {
+        match command {
+            Command::SetFrequency(freq) => self.retune(radio, freq as u64, self.sample_rate, self.ppm),
+            Command::SetSampleRate(rate) => self.retune(radio, self.frequency, rate as f64, self.ppm),
+            Command::SetFreqCorrection(ppm) => self.retune(radio, self.frequency, self.sample_rate, ppm),
+            Command::SetGainMode(manual) => {
+                self.manual_gain = manual;
+
+                if !manual {
+                    self.set_gain(radio, -1.0)?;
+                }
+
+                Ok( () )
+            },
+            Command::SetGain(tenths) => {
+                if self.manual_gain {
+                    self.set_gain(radio, tenths as f64 / 10.0)?;
+                }
+
+                Ok( () )
+            },
+            Command::SetGainByIndex(index) => {
+                let tenths = R820T_GAINS.get(index as usize)
+                    .ok_or_else(|| Error::INVALID_PARAM(format!("Gain index {} out of range", index)))?;
+
+                self.set_gain(radio, *tenths as f64 / 10.0)
+            },
+            Command::SetBiasTee(enable) => radio.set_antenna_enable(enable),
+            other => {
+                debug!("Ignoring unsupported rtl_tcp command {:?}", other);
+                Ok( () )
+            }
+        }
+    }
+
+    /// Moves to a new frequency, sample rate and ppm offset, only keeping them once the radio has
+    /// taken them. If the radio fails part way, the previous tuning is put back.
+    fn retune<R: Radio>(&mut self, radio: &mut R, frequency: u64, sample_rate: f64, ppm: i32) -> Result<(), Error> {
+        if let Err(e) = self.tune(radio, frequency, sample_rate, ppm) {
+            if let Err(restore) = self.tune(radio, self.frequency, self.sample_rate, self.ppm) {
+                warn!("Couldn't restore the previous tuning: {}", restore);
+            }
+
+            return Err(e);
+        }
+
+        self.frequency = frequency;
+        self.sample_rate = sample_rate;
+        self.ppm = ppm;
+
+        Ok( () )
+    }
+
+    /// Applies the frequency and sample rate, corrected by the ppm offset as an RTL-SDR's crystal would be
+    fn tune<R: Radio>(&self, radio: &mut R, frequency: u64, sample_rate: f64, ppm: i32) -> Result<(), Error> {
+        let correction = 1.0 + ppm as f64 * 1e-6;
+        let (hardware, decimation) = hardware_rate(sample_rate * correction)?;
+
+        radio.set_sample_rate(hardware)?;
+        radio.set_freq((frequency as f64 * correction).round() as u64)?;
+        self.decimation.store(decimation, Ordering::SeqCst);
+
+        Ok( () )
+    }
+
+    /// Sets a total gain in dB, or the automatic mode settings if negative
+    fn set_gain<R: Radio>(&mut self, radio: &mut R, gain_db: f64) -> Result<(), Error> {
+        let (lna, vga) = if gain_db < 0.0 { (AUTO_LNA_GAIN, AUTO_VGA_GAIN) } else { split_gain(gain_db) };
+
+        radio.set_lna_gain(lna)?;
+        radio.set_vga_gain(vga)
+    }
+}
+
+fn spawn_writer(mut stream: TcpStream, samples: Receiver<Vec<u8>>, events: mpsc::Sender<Event>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for buffer in samples.iter() {
+            if let Err(e) = stream.write_all(&buffer) {
+                debug!("rtl_tcp write ended: {}", e);
+                break;
+            }
+        }
+
+        let _ = events.send(Event::Closed);
+    })
+}
+
+fn spawn_reader(mut stream: TcpStream, events: mpsc::Sender<Event>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut bytes = [0u8; 5];
+
+        while stream.read_exact(&mut bytes).is_ok() {
+            if events.send(Event::Command(Command::parse(bytes))).is_err() {
+                return;
+            }
+        }
+
+        let _ = events.send(Event::Closed);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedDevice;
+    use crate::tuning::TuningHandle;
+    use std::time::Instant;
+
+    #[test]
+    fn command_round_trip() {
+        for command in &[Command::SetFrequency(433_920_000), Command::SetFreqCorrection(-12), Command::SetGainMode(true),
+                         Command::SetGainByIndex(28), Command::SetBiasTee(true), Command::Unknown(0x7f, 1)] {
+            assert_eq!(Command::parse(command.to_bytes()), *command);
+        }
+
+        assert_eq!(Command::SetSampleRate(2_048_000).to_bytes(), [0x02, 0x00, 0x1f, 0x40, 0x00]);
+    }
+
+    #[test]
+    fn gains_and_rates() {
+        assert_eq!(split_gain(0.0), (0, 0));
+        assert_eq!(split_gain(49.6), (40, 8));
+        assert_eq!(split_gain(21.0), (16, 4));
+        assert_eq!(split_gain(200.0), (40, 62));
+
+        assert_eq!(hardware_rate(2_048_000.0).unwrap(), (4_096_000.0, 2));
+        assert_eq!(hardware_rate(250_000.0).unwrap(), (4_000_000.0, 16));
+        assert_eq!(hardware_rate(10e6).unwrap(), (10e6, 1));
+        assert!(hardware_rate(25e6).is_err());
+        assert_eq!(hardware_rate(225_001.0).unwrap().1, 18);
+        assert!(hardware_rate(1.0).is_err());
+        assert!(hardware_rate(200_000.0).is_err());
+    }
+
+    fn wait_for<F: Fn() -> bool>(condition: F) {
+        let start = Instant::now();
+
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn serves_simulated_device_over_loopback() {
+        let server = RtlTcpServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut sim = SimulatedDevice::new().with_tone(100_100_000, 0.5).with_buffer_size(16384);
+        let tuning :TuningHandle = sim.tuning_handle();
+
+        let handle = thread::spawn(move || server.serve_one(&mut sim));
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut greeting = [0u8; 12];
+
+        client.read_exact(&mut greeting).unwrap();
+
+        assert_eq!(&greeting[0..4], b"RTL0");
+        assert_eq!(u32::from_be_bytes([greeting[4], greeting[5], greeting[6], greeting[7]]), TUNER_R820T);
+        assert_eq!(tuning.get().freq_hz, Some(100_000_000));
+        assert_eq!(tuning.get().sample_rate, Some(4_096_000.0));
+
+        let mut samples = vec![0u8; 4096];
+
+        client.read_exact(&mut samples).unwrap();
+        assert!(samples.iter().any(|v| (*v as i32 - 128).abs() > 32), "tone missing from stream");
+
+        for command in &[Command::SetFrequency(433_920_000), Command::SetSampleRate(10_000_000), Command::SetGainMode(true),
+                         Command::SetGainByIndex(14), Command::SetBiasTee(true)] {
+            client.write_all(&command.to_bytes()).unwrap();
+        }
+
+        wait_for(|| tuning.get().antenna_enable == Some(true));
+
+        let state = tuning.get();
+
+        assert_eq!(state.freq_hz, Some(433_920_000));
+        assert_eq!(state.sample_rate, Some(10e6));
+        assert_eq!((state.lna_gain, state.vga_gain), (Some(24), Some(0)));
+
+        // a rate the radio can't do is dropped, rather than spoiling the next retune
+        client.write_all(&Command::SetSampleRate(25_000_000).to_bytes()).unwrap();
+        client.write_all(&Command::SetSampleRate(1).to_bytes()).unwrap();
+        client.write_all(&Command::SetFreqCorrection(10).to_bytes()).unwrap();
+        wait_for(|| tuning.get().freq_hz == Some(433_924_339));
+
+        drop(client);
+        handle.join().unwrap().unwrap();
+    }
+}
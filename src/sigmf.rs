@@ -0,0 +1,718 @@
+//! Reading and writing [SigMF](https://github.com/gnuradio/SigMF) recordings
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::formats::SampleFormat;
+use crate::stream::{RxSink, TxSource};
+use crate::tuning::{TuningHandle, TuningState};
+
+/// Version of the SigMF specification written to the metadata
+pub const SIGMF_VERSION: &str = "1.0.0";
+
+/// Namespace for the HackRF specific capture fields
+const EXTENSION: &str = "hackrf";
+
+/// Sample formats a recording can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigmfDatatype {
+    /// Complex signed 8-bit, the HackRF's native format
+    Ci8,
+    /// Complex unsigned 8-bit, as produced by rtl-sdr
+    Cu8,
+    /// Complex signed 16-bit little-endian
+    Ci16Le,
+    /// Complex 32-bit little-endian float
+    Cf32Le
+}
+
+impl SigmfDatatype {
+    /// The `core:datatype` string
+    pub fn name(&self) -> &'static str {
+        match self {
+            SigmfDatatype::Ci8 => "ci8",
+            SigmfDatatype::Cu8 => "cu8",
+            SigmfDatatype::Ci16Le => "ci16_le",
+            SigmfDatatype::Cf32Le => "cf32_le"
+        }
+    }
+
+    /// Parses a `core:datatype` string
+    pub fn from_name(name: &str) -> Option<SigmfDatatype> {
+        match name {
+            "ci8" => Some(SigmfDatatype::Ci8),
+            "cu8" => Some(SigmfDatatype::Cu8),
+            "ci16_le" => Some(SigmfDatatype::Ci16Le),
+            "cf32_le" => Some(SigmfDatatype::Cf32Le),
+            _ => None
+        }
+    }
+
+    /// The raw sample format the data file is stored in
+    pub fn sample_format(&self) -> SampleFormat {
+        match self {
+            SigmfDatatype::Ci8 => SampleFormat::Cs8,
+            SigmfDatatype::Cu8 => SampleFormat::Cu8,
+            SigmfDatatype::Ci16Le => SampleFormat::Cs16,
+            SigmfDatatype::Cf32Le => SampleFormat::Cf32
+        }
+    }
+
+    /// Number of bytes in one complex sample
+    pub fn sample_size(&self) -> usize {
+        self.sample_format().sample_size()
+    }
+}
+
+impl From<SampleFormat> for SigmfDatatype {
+    fn from(format: SampleFormat) -> SigmfDatatype {
+        match format {
+            SampleFormat::Cs8 => SigmfDatatype::Ci8,
+            SampleFormat::Cu8 => SigmfDatatype::Cu8,
+            SampleFormat::Cs16 => SigmfDatatype::Ci16Le,
+            SampleFormat::Cf32 => SigmfDatatype::Cf32Le
+        }
+    }
+}
+
+/// An entry in the `annotations` section
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Annotation {
+    pub sample_start: u64,
+    pub sample_count: Option<u64>,
+    pub label: Option<String>,
+    pub comment: Option<String>,
+    pub freq_lower_edge: Option<f64>,
+    pub freq_upper_edge: Option<f64>
+}
+
+impl Annotation {
+    fn to_json(&self) -> Value {
+        let mut fields = Map::new();
+
+        fields.insert(String::from("core:sample_start"), json!(self.sample_start));
+
+        if let Some(count) = self.sample_count {
+            fields.insert(String::from("core:sample_count"), json!(count));
+        }
+
+        if let Some(ref label) = self.label {
+            fields.insert(String::from("core:label"), json!(label));
+        }
+
+        if let Some(ref comment) = self.comment {
+            fields.insert(String::from("core:comment"), json!(comment));
+        }
+
+        if let Some(edge) = self.freq_lower_edge {
+            fields.insert(String::from("core:freq_lower_edge"), json!(edge));
+        }
+
+        if let Some(edge) = self.freq_upper_edge {
+            fields.insert(String::from("core:freq_upper_edge"), json!(edge));
+        }
+
+        Value::Object(fields)
+    }
+}
+
+/// A segment of the recording made with one set of radio settings
+#[derive(Debug, Clone)]
+struct Capture {
+    sample_start: u64,
+    tuning: TuningState,
+    datetime: DateTime<Utc>
+}
+
+impl Capture {
+    fn to_json(&self) -> Value {
+        let mut fields = Map::new();
+        let mut extension = |name: &str, value: Value| {
+            fields.insert(format!("{}:{}", EXTENSION, name), value);
+        };
+
+        if let Some(gain) = self.tuning.lna_gain {
+            extension("lna_gain", json!(gain));
+        }
+
+        if let Some(gain) = self.tuning.vga_gain {
+            extension("vga_gain", json!(gain));
+        }
+
+        if let Some(amp) = self.tuning.amp_enable {
+            extension("amp_enable", json!(amp));
+        }
+
+        if let Some(antenna) = self.tuning.antenna_enable {
+            extension("antenna_enable", json!(antenna));
+        }
+
+        if let Some(bandwidth) = self.tuning.baseband_filter_hz {
+            extension("baseband_filter_hz", json!(bandwidth));
+        }
+
+        fields.insert(String::from("core:sample_start"), json!(self.sample_start));
+        fields.insert(String::from("core:datetime"), json!(self.datetime.to_rfc3339_opts(SecondsFormat::Millis, true)));
+
+        if let Some(freq) = self.tuning.freq_hz {
+            fields.insert(String::from("core:frequency"), json!(freq as f64));
+        }
+
+        Value::Object(fields)
+    }
+}
+
+/// Returns the `.sigmf-data` and `.sigmf-meta` paths for a recording.
+/// `base` may be given with or without either extension.
+pub fn sigmf_paths<P: AsRef<Path>>(base: P) -> (PathBuf, PathBuf) {
+    let base = base.as_ref().to_string_lossy().into_owned();
+    let stem = [".sigmf-data", ".sigmf-meta", ".sigmf"].iter()
+        .find(|ext| base.ends_with(*ext))
+        .map(|ext| &base[..base.len() - ext.len()])
+        .unwrap_or(&base);
+
+    let with_ext = |ext: &str| {
+        let mut path = OsString::from(stem);
+        path.push(ext);
+        PathBuf::from(path)
+    };
+
+    (with_ext(".sigmf-data"), with_ext(".sigmf-meta"))
+}
+
+/// Records an RX stream to a SigMF data/metadata pair.
+///
+/// Settings are followed through the device's `TuningHandle`: whenever they change a new `captures`
+/// entry starts at the first sample of the next buffer written. The metadata is written by `finish`,
+/// or on drop if `finish` was never called.
+pub struct SigmfWriter {
+    data: BufWriter<File>,
+    meta_path: PathBuf,
+    datatype: SigmfDatatype,
+    hw: Option<String>,
+    description: Option<String>,
+    sample_rate: f64,
+    tuning: TuningHandle,
+    generation: u64,
+    captures: Vec<Capture>,
+    annotations: Vec<Annotation>,
+    samples_written: u64,
+    scratch: Vec<u8>,
+    finished: bool
+}
+
+impl SigmfWriter {
+    /// Creates a recording of `device`'s RX stream; the sample rate must already be set
+    pub fn create<P: AsRef<Path>>(base: P, datatype: SigmfDatatype, device: &Device) -> Result<SigmfWriter, Error> {
+        let hw = format!("{} (board id {}), firmware {}", device.board_id_name()?, device.board_id_read()?, device.version_string_read()?);
+
+        SigmfWriter::with_tuning(base, datatype, Some(hw), device.tuning_handle())
+    }
+
+    /// Creates a recording that follows the settings in `tuning`; the sample rate must already be set
+    pub fn with_tuning<P: AsRef<Path>>(base: P, datatype: SigmfDatatype, hw: Option<String>, tuning: TuningHandle) -> Result<SigmfWriter, Error> {
+        let state = tuning.get();
+        let sample_rate = state.sample_rate
+            .ok_or_else(|| Error::INVALID_PARAM(String::from("sample rate must be set before recording")))?;
+        let (data_path, meta_path) = sigmf_paths(base);
+
+        debug!("Recording {} to {:?}", datatype.name(), data_path);
+
+        Ok(SigmfWriter {
+            data: BufWriter::new(File::create(&data_path)?),
+            meta_path,
+            datatype,
+            hw,
+            description: None,
+            sample_rate,
+            generation: state.generation,
+            captures: vec![Capture { sample_start: 0, tuning: state, datetime: Utc::now() }],
+            tuning,
+            annotations: Vec::new(),
+            samples_written: 0,
+            scratch: Vec::new(),
+            finished: false
+        })
+    }
+
+    /// Sets `core:description`
+    pub fn set_description(&mut self, description: &str) {
+        self.description = Some(String::from(description));
+    }
+
+    /// Adds an entry to the `annotations` section
+    pub fn annotate(&mut self, annotation: Annotation) {
+        self.annotations.push(annotation);
+    }
+
+    /// Number of complex samples written so far
+    pub fn samples_written(&self) -> u64 {
+        self.samples_written
+    }
+
+    pub fn meta_path(&self) -> &Path {
+        &self.meta_path
+    }
+
+    /// Starts a new capture segment if the device was retuned since the last buffer
+    fn check_tuning(&mut self) {
+        let generation = self.tuning.generation();
+
+        if generation == self.generation {
+            return;
+        }
+
+        let state = self.tuning.get();
+
+        self.generation = state.generation;
+
+        if state.sample_rate.map(|rate| rate != self.sample_rate).unwrap_or(false) {
+            warn!("Sample rate changed to {:?} mid-recording; SigMF only records {}", state.sample_rate, self.sample_rate);
+        }
+
+        let capture = Capture { sample_start: self.samples_written, tuning: state, datetime: Utc::now() };
+
+        // several changes between buffers only need one entry
+        match self.captures.last_mut() {
+            Some(last) if last.sample_start == self.samples_written => *last = capture,
+            _ => self.captures.push(capture)
+        }
+    }
+
+    fn metadata(&self) -> Value {
+        let mut global = Map::new();
+
+        global.insert(String::from("core:datatype"), json!(self.datatype.name()));
+        global.insert(String::from("core:sample_rate"), json!(self.sample_rate));
+        global.insert(String::from("core:version"), json!(SIGMF_VERSION));
+        global.insert(String::from("core:recorder"), json!(concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"))));
+        global.insert(String::from("core:extensions"), json!([{ "name": EXTENSION, "version": env!("CARGO_PKG_VERSION"), "optional": true }]));
+
+        if let Some(ref hw) = self.hw {
+            global.insert(String::from("core:hw"), json!(hw));
+        }
+
+        if let Some(ref description) = self.description {
+            global.insert(String::from("core:description"), json!(description));
+        }
+
+        json!({
+            "global": global,
+            "captures": self.captures.iter().map(|c| c.to_json()).collect::<Vec<_>>(),
+            "annotations": self.annotations.iter().map(|a| a.to_json()).collect::<Vec<_>>()
+        })
+    }
+
+    /// Flushes the data file and writes the metadata
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.data.flush()?;
+
+        let meta = File::create(&self.meta_path)?;
+
+        serde_json::to_writer_pretty(meta, &self.metadata())
+            .map_err(|e| Error::OTHER(format!("Error writing SigMF metadata: {}", e)))?;
+
+        self.finished = true;
+
+        Ok( () )
+    }
+}
+
+impl RxSink for SigmfWriter {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        self.check_tuning();
+        self.scratch.clear();
+        self.datatype.sample_format().encode(samples, &mut self.scratch);
+
+        self.data.write_all(&self.scratch)?;
+        self.samples_written += (samples.len() / 2) as u64;
+
+        Ok( () )
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.data.flush()?;
+
+        Ok( () )
+    }
+}
+
+impl Drop for SigmfWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(e) = self.finish() {
+                error!("Error finishing SigMF recording {:?}: {}", self.meta_path, e);
+            }
+        }
+    }
+}
+
+/// A `captures` entry read back from a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigmfCapture {
+    pub sample_start: u64,
+    pub frequency: Option<f64>
+}
+
+/// The parts of a `.sigmf-meta` file needed to play a recording back
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigmfMetadata {
+    pub datatype: SigmfDatatype,
+    pub sample_rate: f64,
+    pub hw: Option<String>,
+    pub description: Option<String>,
+    pub captures: Vec<SigmfCapture>
+}
+
+impl SigmfMetadata {
+    /// Reads and parses a `.sigmf-meta` file
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<SigmfMetadata, Error> {
+        let meta :Value = serde_json::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|e| Error::INVALID_PARAM(format!("Error parsing SigMF metadata: {}", e)))?;
+
+        SigmfMetadata::from_json(&meta)
+    }
+
+    fn from_json(meta: &Value) -> Result<SigmfMetadata, Error> {
+        let global = &meta["global"];
+
+        let datatype = global["core:datatype"].as_str()
+            .ok_or_else(|| Error::INVALID_PARAM(String::from("SigMF metadata has no core:datatype")))?;
+        let datatype = SigmfDatatype::from_name(datatype)
+            .ok_or_else(|| Error::INVALID_PARAM(format!("Unsupported SigMF datatype: {}", datatype)))?;
+        let sample_rate = global["core:sample_rate"].as_f64()
+            .ok_or_else(|| Error::INVALID_PARAM(String::from("SigMF metadata has no core:sample_rate")))?;
+
+        let mut captures = meta["captures"].as_array().map(|captures| {
+            captures.iter().map(|c| SigmfCapture {
+                sample_start: c["core:sample_start"].as_u64().unwrap_or(0),
+                frequency: c["core:frequency"].as_f64()
+            }).collect::<Vec<_>>()
+        }).unwrap_or_default();
+
+        captures.sort_by_key(|c| c.sample_start);
+
+        Ok(SigmfMetadata {
+            datatype,
+            sample_rate,
+            hw: global["core:hw"].as_str().map(String::from),
+            description: global["core:description"].as_str().map(String::from),
+            captures
+        })
+    }
+
+    /// The capture segment that `sample` falls in
+    pub fn capture_at(&self, sample: u64) -> Option<&SigmfCapture> {
+        self.captures.iter().rev().find(|c| c.sample_start <= sample)
+    }
+}
+
+/// Plays a SigMF recording out through `Device::start_tx_source`.
+///
+/// Samples are converted to the HackRF's signed 8-bit format as they are read. Playback covers
+/// `duration` samples from the start offset (by default the whole file), optionally looping.
+pub struct SigmfSource {
+    data: BufReader<File>,
+    meta: SigmfMetadata,
+    start: u64,
+    end: u64,
+    position: Option<u64>,
+    looping: bool,
+    scratch: Vec<u8>
+}
+
+impl SigmfSource {
+    /// Opens a recording; `base` may be given with or without the `.sigmf-data`/`.sigmf-meta` extension
+    pub fn open<P: AsRef<Path>>(base: P) -> Result<SigmfSource, Error> {
+        let (data_path, meta_path) = sigmf_paths(base);
+        let meta = SigmfMetadata::read(&meta_path)?;
+        let total = fs::metadata(&data_path)?.len() / meta.datatype.sample_size() as u64;
+
+        debug!("Opened {:?}: {} {} samples at {} S/s", data_path, total, meta.datatype.name(), meta.sample_rate);
+
+        Ok(SigmfSource {
+            data: BufReader::new(File::open(&data_path)?),
+            meta,
+            start: 0,
+            end: total,
+            position: None,
+            looping: false,
+            scratch: Vec::new()
+        })
+    }
+
+    /// Starts over from the start offset when the end is reached
+    pub fn with_loop(mut self, looping: bool) -> SigmfSource {
+        self.looping = looping;
+        self
+    }
+
+    /// Skips the first `samples` samples of the recording
+    pub fn with_start_offset(mut self, samples: u64) -> SigmfSource {
+        let length = self.end - self.start;
+
+        self.start = samples;
+        self.end = samples + length;
+        self.clamp_end();
+        self
+    }
+
+    /// Plays at most `samples` samples from the start offset
+    pub fn with_duration(mut self, samples: u64) -> SigmfSource {
+        self.end = self.start + samples;
+        self.clamp_end();
+        self
+    }
+
+    fn clamp_end(&mut self) {
+        let total = self.data.get_ref().metadata().map(|m| m.len()).unwrap_or(0) / self.meta.datatype.sample_size() as u64;
+
+        self.end = self.end.min(total);
+        self.start = self.start.min(self.end);
+    }
+
+    pub fn metadata(&self) -> &SigmfMetadata {
+        &self.meta
+    }
+
+    /// Number of samples played per pass
+    pub fn duration(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Center frequency of the capture playback starts in
+    pub fn frequency(&self) -> Option<f64> {
+        self.meta.capture_at(self.start).and_then(|c| c.frequency)
+    }
+
+    /// Tunes `device` and sets its sample rate to match the recording
+    pub fn configure(&self, device: &Device) -> Result<(), Error> {
+        let retuned = self.meta.captures.iter()
+            .filter(|c| c.sample_start > self.start && c.sample_start < self.end)
+            .any(|c| c.frequency != self.frequency());
+
+        if retuned {
+            warn!("Recording was retuned during the played section; transmitting it all at {:?} Hz", self.frequency());
+        }
+
+        device.set_sample_rate(self.meta.sample_rate)?;
+
+        match self.frequency() {
+            Some(freq) => device.set_freq(freq.round() as u64),
+            None => Err(Error::INVALID_PARAM(String::from("SigMF recording has no core:frequency")))
+        }
+    }
+}
+
+impl TxSource for SigmfSource {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        let sample_size = self.meta.datatype.sample_size();
+        let mut filled = 0;
+
+        while filled + 2 <= buffer.len() {
+            let position = match self.position {
+                Some(position) if position < self.end => position,
+                Some(_) if !self.looping || self.end == self.start => break,
+                _ => {
+                    self.data.seek(SeekFrom::Start(self.start * sample_size as u64))?;
+                    self.start
+                }
+            };
+
+            let count = ((buffer.len() - filled) as u64 / 2).min(self.end - position) as usize;
+
+            self.scratch.resize(count * sample_size, 0);
+
+            if let Err(e) = self.data.read_exact(&mut self.scratch) {
+                if e.kind() != ErrorKind::UnexpectedEof {
+                    return Err(Error::from(e));
+                }
+
+                // the file got shorter under us; treat what's there as the end
+                warn!("SigMF data ended early at sample {}", position);
+                self.end = position;
+                self.position = Some(position);
+                continue;
+            }
+
+            self.meta.datatype.sample_format().decode_i8(&self.scratch, &mut buffer[filled..filled + count * 2]);
+
+            filled += count * 2;
+            self.position = Some(position + count as u64);
+        }
+
+        Ok(filled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_base(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rs-libhackrf-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn paths() {
+        let expected = (PathBuf::from("/tmp/a.b.sigmf-data"), PathBuf::from("/tmp/a.b.sigmf-meta"));
+
+        assert_eq!(sigmf_paths("/tmp/a.b"), expected);
+        assert_eq!(sigmf_paths("/tmp/a.b.sigmf-data"), expected);
+        assert_eq!(sigmf_paths("/tmp/a.b.sigmf-meta"), expected);
+    }
+
+    #[test]
+    fn needs_sample_rate() {
+        assert!(SigmfWriter::with_tuning(temp_base("no-rate"), SigmfDatatype::Ci8, None, TuningHandle::new()).is_err());
+    }
+
+    #[test]
+    fn records_retunes_as_captures() {
+        let base = temp_base("retune");
+        let tuning = TuningHandle::new();
+
+        tuning.update(|t| {
+            t.sample_rate = Some(10e6);
+            t.freq_hz = Some(100_000_000);
+            t.lna_gain = Some(16);
+        });
+
+        let mut writer = SigmfWriter::with_tuning(&base, SigmfDatatype::Ci8, Some(String::from("HackRF One")), tuning.clone()).unwrap();
+
+        writer.write(&[0.5, -0.5].repeat(100)).unwrap();
+
+        tuning.update(|t| t.freq_hz = Some(101_000_000));
+        tuning.update(|t| t.vga_gain = Some(20));
+
+        writer.write(&[0.25, 0.0].repeat(50)).unwrap();
+        writer.annotate(Annotation { sample_start: 10, sample_count: Some(20), label: Some(String::from("burst")), ..Annotation::default() });
+        writer.finish().unwrap();
+
+        let (data_path, meta_path) = sigmf_paths(&base);
+        let data = fs::read(&data_path).unwrap();
+        let meta :Value = serde_json::from_slice(&fs::read(&meta_path).unwrap()).unwrap();
+
+        assert_eq!(data.len(), 300);
+        assert_eq!(data[0] as i8, 64);
+        assert_eq!(data[1] as i8, -64);
+
+        assert_eq!(meta["global"]["core:datatype"], "ci8");
+        assert_eq!(meta["global"]["core:sample_rate"], 10e6);
+        assert_eq!(meta["global"]["core:hw"], "HackRF One");
+
+        let captures = meta["captures"].as_array().unwrap();
+
+        assert_eq!(captures.len(), 2);
+        assert_eq!(captures[0]["core:frequency"], 100e6);
+        assert_eq!(captures[0]["hackrf:lna_gain"], 16);
+        assert_eq!(captures[1]["core:sample_start"], 100);
+        assert_eq!(captures[1]["core:frequency"], 101e6);
+        assert_eq!(captures[1]["hackrf:vga_gain"], 20);
+
+        assert_eq!(meta["annotations"][0]["core:label"], "burst");
+
+        fs::remove_file(data_path).unwrap();
+        fs::remove_file(meta_path).unwrap();
+    }
+
+    fn write_recording(name: &str, datatype: SigmfDatatype, samples: &[f32]) -> PathBuf {
+        let base = temp_base(name);
+        let tuning = TuningHandle::new();
+
+        tuning.update(|t| {
+            t.sample_rate = Some(8e6);
+            t.freq_hz = Some(433_920_000);
+        });
+
+        let mut writer = SigmfWriter::with_tuning(&base, datatype, None, tuning.clone()).unwrap();
+
+        writer.write(&samples[..samples.len() / 2]).unwrap();
+        tuning.update(|t| t.freq_hz = Some(434_000_000));
+        writer.write(&samples[samples.len() / 2..]).unwrap();
+        writer.finish().unwrap();
+
+        base
+    }
+
+    fn remove_recording(base: &Path) {
+        let (data_path, meta_path) = sigmf_paths(base);
+
+        fs::remove_file(data_path).unwrap();
+        fs::remove_file(meta_path).unwrap();
+    }
+
+    #[test]
+    fn plays_back_every_datatype() {
+        let samples = (0..200).map(|n| (n as f32 - 100.0) / 128.0).collect::<Vec<_>>();
+        let expected = (0..200).map(|n| (n - 100) as i8).collect::<Vec<_>>();
+
+        for &datatype in &[SigmfDatatype::Ci8, SigmfDatatype::Cu8, SigmfDatatype::Ci16Le, SigmfDatatype::Cf32Le] {
+            let base = write_recording(&format!("play-{}", datatype.name()), datatype, &samples);
+            let mut source = SigmfSource::open(&base).unwrap();
+            let mut buffer = vec![0i8; 256];
+
+            assert_eq!(source.metadata().datatype, datatype);
+            assert_eq!(source.metadata().sample_rate, 8e6);
+            assert_eq!(source.frequency(), Some(433_920_000.0));
+            assert_eq!(source.read(&mut buffer).unwrap(), 200);
+            assert_eq!(&buffer[..200], &expected[..], "{}", datatype.name());
+            assert_eq!(source.read(&mut buffer).unwrap(), 0);
+
+            remove_recording(&base);
+        }
+    }
+
+    #[test]
+    fn offset_duration_and_loop() {
+        let samples = (0..40).map(|n| n as f32 / 128.0).collect::<Vec<_>>();
+        let base = write_recording("play-loop", SigmfDatatype::Ci8, &samples);
+        let mut source = SigmfSource::open(&base).unwrap().with_start_offset(12).with_duration(3).with_loop(true);
+        let mut buffer = vec![0i8; 14];
+
+        assert_eq!(source.duration(), 3);
+        assert_eq!(source.frequency(), Some(434_000_000.0));
+        assert_eq!(source.read(&mut buffer).unwrap(), 14);
+        assert_eq!(buffer, vec![24, 25, 26, 27, 28, 29, 24, 25, 26, 27, 28, 29, 24, 25]);
+        assert_eq!(source.read(&mut buffer[..4]).unwrap(), 4);
+        assert_eq!(&buffer[..4], &[26, 27, 28, 29]);
+
+        let mut source = SigmfSource::open(&base).unwrap().with_start_offset(18).with_duration(100);
+
+        assert_eq!(source.duration(), 2);
+        assert_eq!(source.read(&mut buffer).unwrap(), 4);
+
+        remove_recording(&base);
+    }
+
+    #[test]
+    fn writes_cf32_and_finishes_on_drop() {
+        let base = temp_base("cf32");
+        let tuning = TuningHandle::new();
+
+        tuning.update(|t| t.sample_rate = Some(2e6));
+
+        {
+            let mut writer = SigmfWriter::with_tuning(&base, SigmfDatatype::Cf32Le, None, tuning).unwrap();
+
+            writer.write(&[0.125, -1.0]).unwrap();
+        }
+
+        let (data_path, meta_path) = sigmf_paths(&base);
+        let data = fs::read(&data_path).unwrap();
+
+        assert_eq!(data, [0.125f32.to_le_bytes(), (-1.0f32).to_le_bytes()].concat());
+        assert!(meta_path.exists());
+
+        fs::remove_file(data_path).unwrap();
+        fs::remove_file(meta_path).unwrap();
+    }
+}
@@ -0,0 +1,708 @@
+//! A software stand-in for a HackRF, for developing and testing without hardware
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::radio::Radio;
+use crate::registers::{Chip, RegisterAccess};
+use crate::stream::{self, RxSink, TxSource};
+use crate::sweep::{self, BLOCK_BYTES, MAX_FREQ_MHZ, MAX_RANGES};
+use crate::sweep_style;
+use crate::tuning::{TuningHandle, TuningState};
+
+/// Bytes per RX/TX buffer, the same as libhackrf's transfers
+pub const DEFAULT_BUFFER_SIZE: usize = 262144;
+
+/// A carrier at a fixed RF frequency; it shows up in the received samples whenever it's within
+/// half the sample rate of the center frequency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub freq_hz: u64,
+    /// Peak amplitude relative to full scale
+    pub amplitude: f32
+}
+
+/// What the simulated antenna picks up
+struct Signal {
+    tones: Vec<(Tone, f64)>,
+    noise: f32,
+    source: Option<Box<dyn TxSource + Send>>,
+    rng: u64,
+    scratch: Vec<i8>
+}
+
+impl Signal {
+    /// Uniform in [0, 1) from an xorshift generator; good enough for noise
+    fn uniform(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+
+        (self.rng >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    /// Fills `output` with interleaved IQ as the hardware would deliver it, returning how much was
+    /// filled; less than `output.len()` means a file-backed source ran out
+    fn generate(&mut self, tuning: &TuningState, output: &mut [f32]) -> Result<usize, Error> {
+        let count = match self.source {
+            Some(ref mut source) => {
+                self.scratch.resize(output.len(), 0);
+
+                let count = source.read(&mut self.scratch)?;
+
+                output.iter_mut().zip(&self.scratch[..count]).for_each(|(o, v)| *o = stream::i8_to_f32(*v));
+                count
+            },
+            None => {
+                output.iter_mut().for_each(|v| *v = 0.0);
+                output.len()
+            }
+        };
+
+        let center = tuning.freq_hz.unwrap_or(0) as f64;
+        let rate = tuning.sample_rate.unwrap_or(10e6);
+
+        for (tone, phase) in self.tones.iter_mut() {
+            let offset = tone.freq_hz as f64 - center;
+
+            if offset.abs() >= rate / 2.0 {
+                continue;
+            }
+
+            let step = 2.0 * PI * offset / rate;
+            let amplitude = tone.amplitude as f64;
+
+            for iq in output[..count].chunks_exact_mut(2) {
+                iq[0] += (amplitude * phase.cos()) as f32;
+                iq[1] += (amplitude * phase.sin()) as f32;
+                *phase = (*phase + step) % (2.0 * PI);
+            }
+        }
+
+        if self.noise > 0.0 {
+            let sigma = self.noise as f64;
+
+            for iq in 0..count / 2 {
+                // Box-Muller
+                let r = sigma * (-2.0 * (1.0 - self.uniform()).ln()).sqrt();
+                let theta = 2.0 * PI * self.uniform();
+
+                output[iq * 2] += (r * theta.cos()) as f32;
+                output[iq * 2 + 1] += (r * theta.sin()) as f32;
+            }
+        }
+
+        // quantize like the ADC does
+        output[..count].iter_mut().for_each(|v| *v = stream::i8_to_f32(stream::f32_to_i8(*v)));
+
+        Ok(count)
+    }
+}
+
+/// A streaming thread and the flag that stops it
+struct Streamer {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>
+}
+
+impl Streamer {
+    fn finish(self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if self.thread.join().is_err() {
+            error!("Simulated streaming thread panicked");
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        !self.stop.load(Ordering::SeqCst)
+    }
+}
+
+/// Keeps a streaming thread to the sample rate
+struct Pacer {
+    realtime: bool,
+    started: Instant,
+    samples: u64,
+    rate: f64
+}
+
+impl Pacer {
+    fn wait(&mut self, tuning: &TuningState, samples: usize) {
+        if !self.realtime {
+            return;
+        }
+
+        let rate = tuning.sample_rate.unwrap_or(10e6);
+
+        if rate != self.rate {
+            *self = Pacer { realtime: true, started: Instant::now(), samples: 0, rate };
+        }
+
+        self.samples += samples as u64;
+
+        let due = self.started + Duration::from_secs_f64(self.samples as f64 / rate);
+        let now = Instant::now();
+
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+/// What `init_sweep` asked for
+#[derive(Debug, Clone)]
+struct Sweep {
+    /// Header frequencies in the order they're visited
+    steps: Vec<u64>,
+    offset: u64,
+    blocks_per_step: usize
+}
+
+/// Behaves like a `Device`: settings are validated with the same limits libhackrf applies and are
+/// recorded in a `TuningHandle`, and streaming runs on its own thread at the sample rate.
+///
+/// Received samples are made from any number of `Tone`s plus Gaussian noise, or read from a
+/// `TxSource` such as a recording, so servers and tools can be tested over loopback. Sweep mode
+/// is supported too, with blocks headed just as the firmware heads them.
+pub struct SimulatedDevice {
+    tuning: TuningHandle,
+    signal: Arc<Mutex<Signal>>,
+    sweep: Mutex<Option<Sweep>>,
+    registers: Mutex<HashMap<Chip, Vec<u16>>>,
+    board_id: u8,
+    buffer_size: usize,
+    realtime: bool,
+    rx: Option<Streamer>,
+    tx: Option<Streamer>,
+    tx_samples: Arc<AtomicU64>
+}
+
+impl SimulatedDevice {
+    /// A HackRF One receiving nothing but a little noise
+    pub fn new() -> SimulatedDevice {
+        SimulatedDevice {
+            tuning: TuningHandle::new(),
+            signal: Arc::new(Mutex::new(Signal {
+                tones: Vec::new(),
+                noise: 0.01,
+                source: None,
+                rng: 0x2545_f491_4f6c_dd1d,
+                scratch: Vec::new()
+            })),
+            sweep: Mutex::new(None),
+            registers: Mutex::new(HashMap::new()),
+            board_id: 2,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            realtime: true,
+            rx: None,
+            tx: None,
+            tx_samples: Arc::new(AtomicU64::new(0))
+        }
+    }
+
+    /// Receives the samples in `source`, e.g. an `IqReader` or `SigmfSource`, instead of silence;
+    /// tones and noise are added on top
+    pub fn with_source<S: TxSource + Send + 'static>(self, source: S) -> SimulatedDevice {
+        self.signal.lock().unwrap().source = Some(Box::new(source));
+        self
+    }
+
+    pub fn with_tone(self, freq_hz: u64, amplitude: f32) -> SimulatedDevice {
+        self.signal.lock().unwrap().tones.push((Tone { freq_hz, amplitude }, 0.0));
+        self
+    }
+
+    /// Standard deviation of the noise on each of I and Q, relative to full scale
+    pub fn with_noise(self, noise: f32) -> SimulatedDevice {
+        self.signal.lock().unwrap().noise = noise;
+        self
+    }
+
+    /// Size in bytes of each buffer handed to a sink
+    pub fn with_buffer_size(mut self, bytes: usize) -> SimulatedDevice {
+        self.buffer_size = bytes.max(2) & !1;
+        self
+    }
+
+    /// When false, samples are produced as fast as they're consumed rather than at the sample rate
+    pub fn with_realtime(mut self, realtime: bool) -> SimulatedDevice {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Pretends to be a different board, see `hackrf_board_id`
+    pub fn with_board_id(mut self, board_id: u8) -> SimulatedDevice {
+        self.board_id = board_id;
+        self
+    }
+
+    /// Replaces the tones the antenna picks up
+    pub fn set_tones(&self, tones: &[Tone]) {
+        self.signal.lock().unwrap().tones = tones.iter().map(|t| (*t, 0.0)).collect();
+    }
+
+    /// Number of complex samples taken from TX sources so far
+    pub fn tx_samples(&self) -> u64 {
+        self.tx_samples.load(Ordering::SeqCst) / 2
+    }
+
+    fn pacer(&self) -> Pacer {
+        Pacer { realtime: self.realtime, started: Instant::now(), samples: 0, rate: 0.0 }
+    }
+
+    fn check_idle(&self) -> Result<(), Error> {
+        if self.rx.as_ref().map(Streamer::is_running).unwrap_or(false) || self.tx.as_ref().map(Streamer::is_running).unwrap_or(false) {
+            return Err(Error::BUSY(String::from("Simulated device is already streaming")));
+        }
+
+        Ok( () )
+    }
+
+    /// Streams sweep blocks, hopping through the steps one dwell at a time
+    fn start_sweep(&mut self, mut sink: Box<dyn RxSink + Send>, sweep: Sweep) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = stop.clone();
+        let signal = self.signal.clone();
+        let tuning = self.tuning.clone();
+        let mut pacer = self.pacer();
+        let mut buffer = vec![0f32; (self.buffer_size / BLOCK_BYTES).max(1) * BLOCK_BYTES];
+        let (mut step, mut dwell) = (0, 0);
+
+        let thread = thread::spawn(move || {
+            'streaming: while !running.load(Ordering::SeqCst) {
+                let state = tuning.get();
+
+                for block in buffer.chunks_exact_mut(BLOCK_BYTES) {
+                    let freq = sweep.steps[step];
+                    let tuned = TuningState { freq_hz: Some(freq + sweep.offset), ..state.clone() };
+
+                    if let Err(e) = signal.lock().unwrap().generate(&tuned, block) {
+                        error!("Error reading simulated RX source: {}", e);
+                        break 'streaming;
+                    }
+
+                    sweep::write_header(block, freq);
+                    dwell += 1;
+
+                    if dwell == sweep.blocks_per_step {
+                        dwell = 0;
+                        step = (step + 1) % sweep.steps.len();
+                    }
+                }
+
+                if let Err(e) = sink.write(&buffer) {
+                    debug!("Simulated sweep stopped by sink: {}", e);
+                    break;
+                }
+
+                pacer.wait(&state, buffer.len() / 2);
+            }
+
+            running.store(true, Ordering::SeqCst);
+        });
+
+        self.rx = Some(Streamer { stop, thread });
+    }
+
+    fn check_range(name: &str, value: u32, max: u32) -> Result<(), Error> {
+        if value > max {
+            return Err(Error::INVALID_PARAM(format!("{} must be at most {}", name, max)));
+        }
+
+        Ok( () )
+    }
+}
+
+impl Default for SimulatedDevice {
+    fn default() -> SimulatedDevice {
+        SimulatedDevice::new()
+    }
+}
+
+impl Radio for SimulatedDevice {
+    fn set_freq(&self, freq_hz: u64) -> Result<(), Error> {
+        if freq_hz > 7_250_000_000 {
+            return Err(Error::INVALID_PARAM(String::from("Frequency must be at most 7250MHz")));
+        }
+
+        self.tuning.update(|t| t.freq_hz = Some(freq_hz));
+
+        Ok( () )
+    }
+
+    fn set_sample_rate(&self, freq_hz: f64) -> Result<(), Error> {
+        if !(4_000_000.0..=20_000_000.0).contains(&freq_hz) {
+            return Err(Error::INVALID_PARAM(String::from("Frequency must be between 4MHz and 20MHz")));
+        }
+
+        self.tuning.update(|t| t.sample_rate = Some(freq_hz));
+
+        Ok( () )
+    }
+
+    fn set_baseband_filter_bandwidth(&self, bandwidth_hz: u32) -> Result<(), Error> {
+        self.tuning.update(|t| t.baseband_filter_hz = Some(bandwidth_hz));
+
+        Ok( () )
+    }
+
+    fn set_lna_gain(&self, value: u32) -> Result<(), Error> {
+        SimulatedDevice::check_range("LNA gain", value, 40)?;
+        self.tuning.update(|t| t.lna_gain = Some(value));
+
+        Ok( () )
+    }
+
+    fn set_vga_gain(&self, value: u32) -> Result<(), Error> {
+        SimulatedDevice::check_range("VGA gain", value, 62)?;
+        self.tuning.update(|t| t.vga_gain = Some(value));
+
+        Ok( () )
+    }
+
+    fn set_txvga_gain(&self, value: u32) -> Result<(), Error> {
+        SimulatedDevice::check_range("TX VGA gain", value, 47)?;
+        self.tuning.update(|t| t.txvga_gain = Some(value));
+
+        Ok( () )
+    }
+
+    fn set_amp_enable(&self, value: bool) -> Result<(), Error> {
+        self.tuning.update(|t| t.amp_enable = Some(value));
+
+        Ok( () )
+    }
+
+    fn set_antenna_enable(&self, value: bool) -> Result<(), Error> {
+        self.tuning.update(|t| t.antenna_enable = Some(value));
+
+        Ok( () )
+    }
+
+    fn init_sweep(&self, frequency_list: &[u16], num_bytes: u32, step_width: u32, offset: u32, style: sweep_style) -> Result<(), Error> {
+        let num_ranges = frequency_list.len() / 2;
+
+        if !frequency_list.chunks_exact(2).remainder().is_empty() || !(1..=MAX_RANGES).contains(&num_ranges) {
+            return Err(Error::INVALID_PARAM(format!("Frequency list must hold 1 to {} start/stop pairs", MAX_RANGES)));
+        }
+
+        if num_bytes < BLOCK_BYTES as u32 || num_bytes as usize & (BLOCK_BYTES - 1) != 0 {
+            return Err(Error::INVALID_PARAM(format!("Bytes per step must be a multiple of {}", BLOCK_BYTES)));
+        }
+
+        if step_width == 0 || frequency_list.chunks(2).any(|r| r[0] >= r[1] || r[1] > MAX_FREQ_MHZ) {
+            return Err(Error::INVALID_PARAM(String::from("Invalid sweep ranges or step width")));
+        }
+
+        *self.sweep.lock().unwrap() = Some(Sweep {
+            steps: sweep::sweep_steps(frequency_list, step_width, style),
+            offset: offset as u64,
+            blocks_per_step: num_bytes as usize / BLOCK_BYTES
+        });
+
+        Ok( () )
+    }
+
+    fn board_id_read(&self) -> Result<u8, Error> {
+        Ok(self.board_id)
+    }
+
+    fn board_id_name(&self) -> Result<String, Error> {
+        Ok(String::from(match self.board_id {
+            0 => "Jellybean",
+            1 => "Jawbreaker",
+            2 => "HackRF One",
+            3 => "rad1o",
+            _ => "Invalid Board ID"
+        }))
+    }
+
+    fn version_string_read(&self) -> Result<String, Error> {
+        Ok(format!("simulated ({} {})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")))
+    }
+
+    fn tuning_handle(&self) -> TuningHandle {
+        self.tuning.clone()
+    }
+
+    fn start_rx_sink(&mut self, mut sink: Box<dyn RxSink + Send>) -> Result<(), Error> {
+        self.check_idle()?;
+
+        let sweep = self.sweep.lock().unwrap().clone();
+
+        if let Some(sweep) = sweep {
+            self.start_sweep(sink, sweep);
+            return Ok( () );
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = stop.clone();
+        let signal = self.signal.clone();
+        let tuning = self.tuning.clone();
+        let mut pacer = self.pacer();
+        let mut buffer = vec![0f32; self.buffer_size];
+
+        let thread = thread::spawn(move || {
+            while !running.load(Ordering::SeqCst) {
+                let state = tuning.get();
+                let count = match signal.lock().unwrap().generate(&state, &mut buffer) {
+                    Ok(count) => count,
+                    Err(e) => {
+                        error!("Error reading simulated RX source: {}", e);
+                        break;
+                    }
+                };
+
+                if count > 0 {
+                    if let Err(e) = sink.write(&buffer[..count]) {
+                        debug!("Simulated RX stopped by sink: {}", e);
+                        break;
+                    }
+                }
+
+                if count < buffer.len() {
+                    debug!("Simulated RX source finished");
+                    break;
+                }
+
+                pacer.wait(&state, count / 2);
+            }
+
+            running.store(true, Ordering::SeqCst);
+        });
+
+        self.rx = Some(Streamer { stop, thread });
+
+        Ok( () )
+    }
+
+    fn stop_rx(&mut self) -> Result<(), Error> {
+        if let Some(rx) = self.rx.take() {
+            rx.finish();
+        }
+
+        // like the firmware, leave sweep mode when streaming stops
+        *self.sweep.lock().unwrap() = None;
+
+        Ok( () )
+    }
+
+    fn start_tx_source(&mut self, mut source: Box<dyn TxSource + Send>) -> Result<(), Error> {
+        self.check_idle()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = stop.clone();
+        let tuning = self.tuning.clone();
+        let counter = self.tx_samples.clone();
+        let mut pacer = self.pacer();
+        let mut buffer = vec![0i8; self.buffer_size];
+
+        let thread = thread::spawn(move || {
+            while !running.load(Ordering::SeqCst) {
+                let count = match source.read(&mut buffer) {
+                    Ok(count) => count,
+                    Err(e) => {
+                        error!("Error reading TX samples from source: {}", e);
+                        break;
+                    }
+                };
+
+                counter.fetch_add(count as u64, Ordering::SeqCst);
+
+                if count < buffer.len() {
+                    debug!("Simulated TX source finished");
+                    break;
+                }
+
+                pacer.wait(&tuning.get(), count / 2);
+            }
+
+            running.store(true, Ordering::SeqCst);
+        });
+
+        self.tx = Some(Streamer { stop, thread });
+
+        Ok( () )
+    }
+
+    fn stop_tx(&mut self) -> Result<(), Error> {
+        if let Some(tx) = self.tx.take() {
+            tx.finish();
+        }
+
+        Ok( () )
+    }
+
+    fn is_streaming(&self) -> Result<bool, Error> {
+        Ok(self.rx.as_ref().map(Streamer::is_running).unwrap_or(false) || self.tx.as_ref().map(Streamer::is_running).unwrap_or(false))
+    }
+}
+
+/// Chip registers are plain memory, all 0 until written
+impl RegisterAccess for SimulatedDevice {
+    fn read_register(&self, chip: Chip, register: u16) -> Result<u16, Error> {
+        chip.check_register(register)?;
+
+        Ok(self.registers.lock().unwrap().get(&chip).map_or(0, |values| values[register as usize]))
+    }
+
+    fn write_register(&self, chip: Chip, register: u16, value: u16) -> Result<(), Error> {
+        chip.check_register(register)?;
+        chip.check_value(value)?;
+
+        self.registers.lock().unwrap()
+            .entry(chip)
+            .or_insert_with(|| vec![0; chip.register_count() as usize])[register as usize] = value;
+
+        Ok( () )
+    }
+}
+
+impl Drop for SimulatedDevice {
+    fn drop(&mut self) {
+        let _ = self.stop_rx();
+        let _ = self.stop_tx();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::Fft;
+    use crate::sweep::{SweepPlan, SweepProcessor};
+    use std::sync::mpsc;
+
+    /// Collects whatever the simulator sends into a channel
+    struct ChannelSink(mpsc::Sender<Vec<f32>>);
+
+    impl RxSink for ChannelSink {
+        fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+            self.0.send(samples.to_vec()).map_err(|_| Error::STREAMING_EXIT_CALLED(String::from("receiver gone")))
+        }
+    }
+
+    #[test]
+    fn validates_like_libhackrf() {
+        let sim = SimulatedDevice::new();
+
+        assert!(sim.set_lna_gain(48).is_err());
+        assert!(sim.set_vga_gain(64).is_err());
+        assert!(sim.set_txvga_gain(48).is_err());
+        assert!(sim.set_sample_rate(2e6).is_err());
+        assert!(sim.set_freq(8_000_000_000).is_err());
+
+        sim.set_lna_gain(40).unwrap();
+        sim.set_freq(100_000_000).unwrap();
+
+        assert_eq!(sim.tuning().lna_gain, Some(40));
+        assert_eq!(sim.tuning().freq_hz, Some(100_000_000));
+        assert_eq!(sim.board_id_name().unwrap(), "HackRF One");
+    }
+
+    #[test]
+    fn tone_lands_at_its_offset() {
+        let mut sim = SimulatedDevice::new()
+            .with_tone(100_250_000, 0.5)
+            .with_noise(0.0)
+            .with_buffer_size(2048)
+            .with_realtime(false);
+        let (tx, rx) = mpsc::channel();
+
+        sim.set_freq(100_000_000).unwrap();
+        sim.set_sample_rate(8e6).unwrap();
+        sim.start_rx_sink(Box::new(ChannelSink(tx))).unwrap();
+
+        let mut buffer = rx.recv().unwrap();
+
+        sim.stop_rx().unwrap();
+        assert!(!sim.is_streaming().unwrap());
+        assert_eq!(buffer.len(), 2048);
+
+        Fft::new(1024).process(&mut buffer);
+
+        let peak = (0..1024).max_by(|a, b| {
+            let power = |n: usize| buffer[n * 2].powi(2) + buffer[n * 2 + 1].powi(2);
+            power(*a).partial_cmp(&power(*b)).unwrap()
+        }).unwrap();
+
+        // 250 kHz at 8 MS/s is bin 32 of 1024
+        assert_eq!(peak, 32);
+    }
+
+    #[test]
+    fn sweeps_with_headers() {
+        let mut sim = SimulatedDevice::new().with_tone(2_432_000_000, 0.5).with_realtime(false);
+        let plan = SweepPlan::new(&[(2400, 2440)]).unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        assert!(sim.init_sweep(&[2400], BLOCK_BYTES as u32, 20_000_000, 0, sweep_style::LINEAR).is_err());
+        assert!(sim.init_sweep(&[2400, 2420], 1000, 20_000_000, 0, sweep_style::LINEAR).is_err());
+
+        plan.start(&mut sim, Box::new(ChannelSink(tx))).unwrap();
+
+        let buffer = rx.recv().unwrap();
+        let headers = buffer.chunks(BLOCK_BYTES).map(|block| sweep::parse_header(block).unwrap()).collect::<Vec<_>>();
+
+        sim.stop_rx().unwrap();
+
+        assert_eq!(sim.tuning().sample_rate, Some(20e6));
+        assert_eq!(&headers[..5], &[2_400_000_000, 2_405_000_000, 2_420_000_000, 2_425_000_000, 2_400_000_000]);
+
+        // the third block is tuned to 2427.5 MHz; its second segment covers 2430-2435 MHz
+        let segments = SweepProcessor::new(64).process_block(&buffer[BLOCK_BYTES * 2..BLOCK_BYTES * 3]).unwrap();
+        let loudest = segments[1].power_db.iter().cloned().fold(f32::MIN, f32::max);
+
+        assert!(loudest > -20.0);
+        assert!(segments[0].power_db.iter().all(|p| *p < loudest - 30.0));
+
+        // a plain start afterwards isn't a sweep
+        let (tx, rx) = mpsc::channel();
+
+        sim.start_rx_sink(Box::new(ChannelSink(tx))).unwrap();
+        assert_eq!(sweep::parse_header(&rx.recv().unwrap()), None);
+    }
+
+    #[test]
+    fn plays_a_source_then_stops() {
+        struct Ramp(i8);
+
+        impl TxSource for Ramp {
+            fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+                let count = buffer.len().min((100 - self.0) as usize);
+
+                for v in buffer[..count].iter_mut() {
+                    *v = self.0 / 2;
+                    self.0 += 1;
+                }
+
+                Ok(count)
+            }
+        }
+
+        let mut sim = SimulatedDevice::new().with_source(Ramp(0)).with_noise(0.0).with_buffer_size(64).with_realtime(false);
+        let (tx, rx) = mpsc::channel();
+
+        sim.start_rx_sink(Box::new(ChannelSink(tx))).unwrap();
+
+        let received = rx.iter().flatten().collect::<Vec<_>>();
+
+        assert_eq!(received.len(), 100);
+        assert_eq!(received[99], stream::i8_to_f32(49));
+        assert!(!sim.is_streaming().unwrap());
+
+        sim.start_tx_source(Box::new(Ramp(0))).unwrap();
+
+        while sim.is_streaming().unwrap() {
+            thread::yield_now();
+        }
+
+        sim.stop_tx().unwrap();
+        assert_eq!(sim.tx_samples(), 50);
+    }
+}
@@ -0,0 +1,380 @@
+//! The C ABI declared in `soapy-module/hackrf_rs_soapy.h`.
+//!
+//! Calls that can fail return 0 or a negative `SOAPY_SDR_*` code, or 0 and NULL for getters and
+//! `open`, and leave the reason for `hackrf_rs_soapy_last_error` on the calling thread. Handles
+//! must come from `hackrf_rs_soapy_open` and strings must be NUL-terminated.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_double, c_int, c_long, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::{Direction, Samples, SamplesMut, SoapyDevice, StreamFormat};
+use crate::error::Error;
+use crate::hackrf::HackRF;
+use crate::radio::Radio;
+use crate::simulator::SimulatedDevice;
+
+pub type Handle = SoapyDevice<Box<dyn Radio>>;
+
+/// `SOAPY_SDR_STREAM_ERROR`, used for every failure that isn't a timeout or overflow
+const STREAM_ERROR: c_int = -2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn record<E: ToString>(e: E) {
+    let message = CString::new(e.to_string().replace('\0', " ")).unwrap_or_default();
+
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+fn status(result: Result<(), Error>) -> c_int {
+    match result {
+        Ok( () ) => 0,
+        Err(e) => {
+            record(e);
+            STREAM_ERROR
+        }
+    }
+}
+
+fn value(result: Result<f64, Error>) -> c_double {
+    result.unwrap_or_else(|e| {
+        record(e);
+        0.0
+    })
+}
+
+unsafe fn string<'a>(s: *const c_char) -> Result<&'a str, Error> {
+    if s.is_null() {
+        return Err(Error::INVALID_PARAM(String::from("NULL string")));
+    }
+
+    CStr::from_ptr(s).to_str().map_err(|_| Error::INVALID_PARAM(String::from("String is not UTF-8")))
+}
+
+/// Copies `s` into `out` as a C string, truncating to fit, and returns its full length
+unsafe fn copy_string(s: &str, out: *mut c_char, len: usize) -> c_int {
+    if !out.is_null() && len > 0 {
+        let count = s.len().min(len - 1);
+
+        ptr::copy_nonoverlapping(s.as_ptr() as *const c_char, out, count);
+        *out.add(count) = 0;
+    }
+
+    s.len() as c_int
+}
+
+/// The library, initialised on first use and kept for the rest of the process: devices borrow it,
+/// and dropping it calls `hackrf_exit`, which fails while any device is still open
+struct Library(*mut HackRF);
+
+// only used while LIBRARY is locked
+unsafe impl Send for Library {}
+
+static LIBRARY: Mutex<Option<Library>> = Mutex::new(None);
+
+/// Runs `f` on the process-wide library with a fresh device list
+fn with_library<T, F: FnOnce(&'static mut HackRF) -> Result<T, Error>>(f: F) -> Result<T, Error> {
+    let mut library = LIBRARY.lock().unwrap();
+
+    if library.is_none() {
+        *library = Some(Library(Box::into_raw(Box::new(HackRF::new()?))));
+    }
+
+    // never freed, and the lock keeps callers from using it at the same time; the devices it
+    // opens only borrow it in name, so they don't alias it
+    let hackrf = unsafe { &mut *library.as_ref().unwrap().0 };
+
+    hackrf.refresh_device_list()?;
+    f(hackrf)
+}
+
+fn open_device(index: c_int) -> Result<Box<dyn Radio>, Error> {
+    with_library(|hackrf| Ok(Box::new(hackrf.open_device(index)?) as Box<dyn Radio>))
+}
+
+#[no_mangle]
+pub extern "C" fn hackrf_rs_soapy_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Number of HackRFs attached, or a negative code
+#[no_mangle]
+pub extern "C" fn hackrf_rs_soapy_count() -> c_int {
+    match with_library(|hackrf| hackrf.get_device_list().map(|list| list.len())) {
+        Ok(count) => count as c_int,
+        Err(e) => {
+            record(e);
+            STREAM_ERROR
+        }
+    }
+}
+
+/// Opens the HackRF at `index`, or a simulated one if `simulate` is non-zero
+#[no_mangle]
+pub extern "C" fn hackrf_rs_soapy_open(index: c_int, simulate: c_int) -> *mut Handle {
+    let radio = if simulate != 0 {
+        Ok(Box::new(SimulatedDevice::new()) as Box<dyn Radio>)
+    } else {
+        open_device(index)
+    };
+
+    match radio {
+        Ok(radio) => Box::into_raw(Box::new(SoapyDevice::new(radio))),
+        Err(e) => {
+            record(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_close(handle: *mut Handle) {
+    if handle.is_null() {
+        return;
+    }
+
+    let device = Box::from_raw(handle);
+
+    for direction in &[Direction::Rx, Direction::Tx] {
+        if let Err(e) = device.close_stream(*direction) {
+            warn!("Error stopping {:?} stream: {}", direction, e);
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_hardware_key(handle: *const Handle, out: *mut c_char, len: usize) -> c_int {
+    match (*handle).hardware_key() {
+        Ok(key) => copy_string(&key, out, len),
+        Err(e) => status(Err(e))
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_set_frequency(handle: *const Handle, direction: c_int, freq_hz: c_double) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).set_frequency(d, freq_hz)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_get_frequency(handle: *const Handle, direction: c_int) -> c_double {
+    value(Direction::from_soapy(direction).map(|d| (*handle).frequency(d)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_set_sample_rate(handle: *const Handle, direction: c_int, rate: c_double) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).set_sample_rate(d, rate)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_get_sample_rate(handle: *const Handle, direction: c_int) -> c_double {
+    value(Direction::from_soapy(direction).map(|d| (*handle).sample_rate(d)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_set_bandwidth(handle: *const Handle, direction: c_int, bandwidth: c_double) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).set_bandwidth(d, bandwidth)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_get_bandwidth(handle: *const Handle, direction: c_int) -> c_double {
+    value(Direction::from_soapy(direction).map(|d| (*handle).bandwidth(d)))
+}
+
+/// Writes up to `max` gain element names, which are static, and returns how many there are
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_list_gains(handle: *const Handle, direction: c_int, names: *mut *const c_char, max: usize) -> usize {
+    let gains = match Direction::from_soapy(direction) {
+        Ok(direction) => (*handle).list_gains(direction),
+        Err(e) => {
+            record(e);
+            return 0;
+        }
+    };
+
+    for (i, name) in gains.iter().enumerate().take(if names.is_null() { 0 } else { max }) {
+        let name :&'static [u8] = match *name {
+            "LNA" => b"LNA\0",
+            "AMP" => b"AMP\0",
+            _ => b"VGA\0"
+        };
+
+        *names.add(i) = name.as_ptr() as *const c_char;
+    }
+
+    gains.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_set_gain(handle: *const Handle, direction: c_int, gain: c_double) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).set_gain(d, gain)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_get_gain(handle: *const Handle, direction: c_int) -> c_double {
+    value(Direction::from_soapy(direction).and_then(|d| (*handle).gain(d)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_set_gain_element(handle: *const Handle, direction: c_int, name: *const c_char, gain: c_double) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).set_gain_element(d, string(name)?, gain)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_get_gain_element(handle: *const Handle, direction: c_int, name: *const c_char) -> c_double {
+    value(Direction::from_soapy(direction).and_then(|d| (*handle).gain_element(d, string(name)?)))
+}
+
+/// The range of the gain element `name`, or of the overall gain if it's NULL
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_get_gain_range(handle: *const Handle, direction: c_int, name: *const c_char,
+                                                        minimum: *mut c_double, maximum: *mut c_double, step: *mut c_double) -> c_int {
+    let range = Direction::from_soapy(direction).and_then(|d| {
+        if name.is_null() {
+            Ok((*handle).overall_gain_range(d))
+        } else {
+            (*handle).gain_range(d, string(name)?)
+        }
+    });
+
+    status(range.map(|range| {
+        *minimum = range.minimum;
+        *maximum = range.maximum;
+        *step = range.step;
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_write_setting(handle: *const Handle, key: *const c_char, value: *const c_char) -> c_int {
+    status(string(key).and_then(|key| (*handle).write_setting(key, string(value)?)))
+}
+
+/// Copies the setting into `out` and returns its full length, or a negative code
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_read_setting(handle: *const Handle, key: *const c_char, out: *mut c_char, len: usize) -> c_int {
+    match string(key).and_then(|key| (*handle).read_setting(key)) {
+        Ok(setting) => copy_string(&setting, out, len),
+        Err(e) => status(Err(e))
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_setup_stream(handle: *const Handle, direction: c_int, format: *const c_char) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).setup_stream(d, StreamFormat::from_name(string(format)?)?)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_close_stream(handle: *const Handle, direction: c_int) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).close_stream(d)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_activate_stream(handle: *const Handle, direction: c_int) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).activate_stream(d)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_deactivate_stream(handle: *const Handle, direction: c_int) -> c_int {
+    status(Direction::from_soapy(direction).and_then(|d| (*handle).deactivate_stream(d)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_stream_mtu(handle: *const Handle, direction: c_int) -> usize {
+    Direction::from_soapy(direction).map(|d| (*handle).stream_mtu(d)).unwrap_or(0)
+}
+
+/// Reads up to `num_elems` complex samples into `buffer`, in the stream's format, returning the
+/// number read or a negative code
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_read_stream(handle: *const Handle, buffer: *mut c_void, num_elems: usize, timeout_us: c_long) -> c_int {
+    let device = &*handle;
+    let values = num_elems * 2;
+    let buffer = match device.stream_format(Direction::Rx) {
+        Some(StreamFormat::CF32) => SamplesMut::CF32(slice::from_raw_parts_mut(buffer as *mut f32, values)),
+        Some(StreamFormat::CS16) => SamplesMut::CS16(slice::from_raw_parts_mut(buffer as *mut i16, values)),
+        Some(StreamFormat::CS8) => SamplesMut::CS8(slice::from_raw_parts_mut(buffer as *mut i8, values)),
+        None => return status(Err(Error::INVALID_PARAM(String::from("Rx stream is not set up"))))
+    };
+
+    match device.read_stream(buffer, Duration::from_micros(timeout_us.max(0) as u64)) {
+        Ok(count) => count as c_int,
+        Err(e) => {
+            record(&e);
+            e.code()
+        }
+    }
+}
+
+/// Queues up to `num_elems` complex samples from `buffer`, in the stream's format, returning the
+/// number taken or a negative code
+#[no_mangle]
+pub unsafe extern "C" fn hackrf_rs_soapy_write_stream(handle: *const Handle, buffer: *const c_void, num_elems: usize, timeout_us: c_long) -> c_int {
+    let device = &*handle;
+    let values = num_elems * 2;
+    let samples = match device.stream_format(Direction::Tx) {
+        Some(StreamFormat::CF32) => Samples::CF32(slice::from_raw_parts(buffer as *const f32, values)),
+        Some(StreamFormat::CS16) => Samples::CS16(slice::from_raw_parts(buffer as *const i16, values)),
+        Some(StreamFormat::CS8) => Samples::CS8(slice::from_raw_parts(buffer as *const i8, values)),
+        None => return status(Err(Error::INVALID_PARAM(String::from("Tx stream is not set up"))))
+    };
+
+    match device.write_stream(samples, Duration::from_micros(timeout_us.max(0) as u64)) {
+        Ok(count) => count as c_int,
+        Err(e) => {
+            record(&e);
+            e.code()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drives_simulator_through_c_abi() {
+        unsafe {
+            let handle = hackrf_rs_soapy_open(0, 1);
+            let mut key = [0 as c_char; 32];
+            let mut names = [ptr::null(); 4];
+
+            assert!(!handle.is_null());
+            assert_eq!(hackrf_rs_soapy_hardware_key(handle, key.as_mut_ptr(), key.len()), 10);
+            assert_eq!(CStr::from_ptr(key.as_ptr()).to_str().unwrap(), "HackRF One");
+
+            assert_eq!(hackrf_rs_soapy_set_frequency(handle, 1, 433.92e6), 0);
+            assert_eq!(hackrf_rs_soapy_get_frequency(handle, 1), 433.92e6);
+            assert_eq!(hackrf_rs_soapy_set_sample_rate(handle, 1, 4e6), 0);
+            assert_eq!(hackrf_rs_soapy_get_bandwidth(handle, 1), 2.5e6);
+
+            assert_eq!(hackrf_rs_soapy_list_gains(handle, 1, names.as_mut_ptr(), names.len()), 3);
+            assert_eq!(CStr::from_ptr(names[0]).to_str().unwrap(), "LNA");
+            assert_eq!(hackrf_rs_soapy_set_gain_element(handle, 1, names[0], 20.0), 0);
+            assert_eq!(hackrf_rs_soapy_get_gain_element(handle, 1, names[0]), 16.0);
+
+            assert_eq!(hackrf_rs_soapy_set_gain_element(handle, 1, b"IF\0".as_ptr() as *const c_char, 20.0), STREAM_ERROR);
+            assert!(CStr::from_ptr(hackrf_rs_soapy_last_error()).to_str().unwrap().contains("IF"));
+            assert_eq!(hackrf_rs_soapy_setup_stream(handle, 1, b"CU8\0".as_ptr() as *const c_char), STREAM_ERROR);
+
+            let mut samples = vec![0i16; 2048];
+
+            assert_eq!(hackrf_rs_soapy_setup_stream(handle, 1, b"CS16\0".as_ptr() as *const c_char), 0);
+            assert_eq!(hackrf_rs_soapy_read_stream(handle, samples.as_mut_ptr() as *mut c_void, 1024, 1000), STREAM_ERROR);
+            assert_eq!(hackrf_rs_soapy_activate_stream(handle, 1), 0);
+
+            let count = hackrf_rs_soapy_read_stream(handle, samples.as_mut_ptr() as *mut c_void, 1024, 1_000_000);
+
+            assert!(count > 0 && count <= 1024, "{}", count);
+            assert!(samples[..count as usize * 2].iter().all(|v| v & 0xff == 0));
+            assert_eq!(hackrf_rs_soapy_deactivate_stream(handle, 1), 0);
+
+            hackrf_rs_soapy_close(handle);
+        }
+    }
+}
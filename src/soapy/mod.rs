@@ -0,0 +1,758 @@
+//! A [SoapySDR](https://github.com/pothosware/SoapySDR) device built on `Radio`, so Soapy
+//! applications can use a HackRF, or the simulator, through this crate.
+//!
+//! `SoapyDevice` follows the `SoapySDR::Device` API: settings by direction, named gain elements,
+//! and pull-style `read_stream`/`write_stream` over the crate's push-style sinks and sources.
+//! With the `soapy` feature a C ABI over it is exported as `hackrf_rs_soapy_*` (see
+//! `soapy-module/hackrf_rs_soapy.h`), which the small C++ module in `soapy-module/` registers
+//! with Soapy as the `hackrf_rs` driver, since Soapy's module registry is C++.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::radio::Radio;
+use crate::stream::{self, RxSink, TxSource};
+
+#[cfg(feature = "soapy")]
+mod ffi;
+
+/// Soapy's `SOAPY_SDR_TX` and `SOAPY_SDR_RX`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Tx = 0,
+    Rx = 1
+}
+
+impl Direction {
+    pub fn from_soapy(direction: i32) -> Result<Direction, Error> {
+        match direction {
+            0 => Ok(Direction::Tx),
+            1 => Ok(Direction::Rx),
+            other => Err(Error::INVALID_PARAM(format!("Unknown direction {}", other)))
+        }
+    }
+}
+
+/// The stream formats supported, named as Soapy names them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// Complex f32, scaled to [-1, 1)
+    CF32,
+    /// Complex i16, the 8-bit samples shifted into the top byte
+    CS16,
+    /// Complex i8, the HackRF's native format
+    CS8
+}
+
+impl StreamFormat {
+    pub fn from_name(name: &str) -> Result<StreamFormat, Error> {
+        match name {
+            "CF32" => Ok(StreamFormat::CF32),
+            "CS16" => Ok(StreamFormat::CS16),
+            "CS8" => Ok(StreamFormat::CS8),
+            other => Err(Error::INVALID_PARAM(format!("Unsupported stream format {}", other)))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamFormat::CF32 => "CF32",
+            StreamFormat::CS16 => "CS16",
+            StreamFormat::CS8 => "CS8"
+        }
+    }
+}
+
+/// Buffers for `read_stream`, as interleaved IQ in the stream's format
+pub enum SamplesMut<'a> {
+    CF32(&'a mut [f32]),
+    CS16(&'a mut [i16]),
+    CS8(&'a mut [i8])
+}
+
+impl <'a> SamplesMut<'a> {
+    fn format(&self) -> StreamFormat {
+        match self {
+            SamplesMut::CF32(_) => StreamFormat::CF32,
+            SamplesMut::CS16(_) => StreamFormat::CS16,
+            SamplesMut::CS8(_) => StreamFormat::CS8
+        }
+    }
+
+    /// Number of complex samples that fit
+    fn len(&self) -> usize {
+        match self {
+            SamplesMut::CF32(buffer) => buffer.len() / 2,
+            SamplesMut::CS16(buffer) => buffer.len() / 2,
+            SamplesMut::CS8(buffer) => buffer.len() / 2
+        }
+    }
+}
+
+/// Samples for `write_stream`, as interleaved IQ in the stream's format
+pub enum Samples<'a> {
+    CF32(&'a [f32]),
+    CS16(&'a [i16]),
+    CS8(&'a [i8])
+}
+
+impl <'a> Samples<'a> {
+    fn format(&self) -> StreamFormat {
+        match self {
+            Samples::CF32(_) => StreamFormat::CF32,
+            Samples::CS16(_) => StreamFormat::CS16,
+            Samples::CS8(_) => StreamFormat::CS8
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Samples::CF32(samples) => samples.len() / 2,
+            Samples::CS16(samples) => samples.len() / 2,
+            Samples::CS8(samples) => samples.len() / 2
+        }
+    }
+
+    /// The first `count` complex samples in the HackRF's format
+    fn to_i8(&self, count: usize) -> Vec<i8> {
+        match self {
+            Samples::CF32(samples) => samples[..count * 2].iter().map(|v| stream::f32_to_i8(*v)).collect(),
+            Samples::CS16(samples) => samples[..count * 2].iter().map(|v| (*v >> 8) as i8).collect(),
+            Samples::CS8(samples) => samples[..count * 2].to_vec()
+        }
+    }
+}
+
+/// Why `read_stream` or `write_stream` returned no samples
+#[derive(Debug)]
+pub enum StreamError {
+    /// Nothing could be transferred before the timeout
+    Timeout,
+    /// RX samples were dropped because they weren't read in time; reading can carry on
+    Overflow,
+    Error(Error)
+}
+
+impl StreamError {
+    /// The matching `SOAPY_SDR_*` error code
+    pub fn code(&self) -> i32 {
+        match self {
+            StreamError::Timeout => -1,
+            StreamError::Overflow => -4,
+            StreamError::Error(_) => -2
+        }
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamError::Timeout => write!(f, "Timed out"),
+            StreamError::Overflow => write!(f, "Overflow"),
+            StreamError::Error(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<Error> for StreamError {
+    fn from(e: Error) -> StreamError {
+        StreamError::Error(e)
+    }
+}
+
+/// A range as Soapy describes them; a `step` of 0 means any value in between
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub minimum: f64,
+    pub maximum: f64,
+    pub step: f64
+}
+
+impl Range {
+    fn new(minimum: f64, maximum: f64, step: f64) -> Range {
+        Range { minimum, maximum, step }
+    }
+
+    /// `value` clamped into the range and rounded down to a step
+    fn fit(&self, value: f64) -> f64 {
+        let value = value.max(self.minimum).min(self.maximum);
+
+        if self.step > 0.0 {
+            self.minimum + ((value - self.minimum) / self.step).floor() * self.step
+        } else {
+            value
+        }
+    }
+}
+
+pub const RX_GAINS: [&str; 3] = ["LNA", "AMP", "VGA"];
+pub const TX_GAINS: [&str; 2] = ["AMP", "VGA"];
+
+/// Gain of the RF amplifier when it's on
+const AMP_GAIN: f64 = 14.0;
+
+/// The MAX2837's baseband filter bandwidths
+pub const BANDWIDTHS: [u32; 16] = [
+    1_750_000, 2_500_000, 3_500_000, 5_000_000, 5_500_000, 6_000_000, 7_000_000, 8_000_000,
+    9_000_000, 10_000_000, 12_000_000, 14_000_000, 15_000_000, 20_000_000, 24_000_000, 28_000_000
+];
+
+/// Sample rates `Device::set_sample_rate` accepts
+pub const MIN_SAMPLE_RATE: f64 = 4e6;
+pub const MAX_SAMPLE_RATE: f64 = 20e6;
+
+/// Complex samples per `read_stream`/`write_stream` call that suit the hardware's transfers
+pub const STREAM_MTU: usize = 131_072;
+
+/// Values (I and Q each count) buffered between the radio and the application
+const BUFFER_VALUES: usize = 8 * 2 * STREAM_MTU;
+
+/// The filter the hardware picks for `bandwidth`: the widest no wider than it, as
+/// `hackrf_compute_baseband_filter_bw_round_down_lt` does
+pub fn baseband_filter(bandwidth: f64) -> u32 {
+    BANDWIDTHS.iter().rev().copied().find(|bw| *bw as f64 <= bandwidth).unwrap_or(BANDWIDTHS[0])
+}
+
+struct Ring {
+    values: VecDeque<i8>,
+    overflowed: bool
+}
+
+type SharedRing = Arc<(Mutex<Ring>, Condvar)>;
+
+fn new_ring() -> SharedRing {
+    Arc::new((Mutex::new(Ring { values: VecDeque::with_capacity(BUFFER_VALUES), overflowed: false }), Condvar::new()))
+}
+
+/// Fills the RX ring, dropping the oldest samples when the application falls behind
+struct RingSink(SharedRing);
+
+impl RxSink for RingSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let (ring, ready) = &*self.0;
+        let mut ring = ring.lock().unwrap();
+
+        ring.values.extend(samples.iter().map(|v| stream::f32_to_i8(*v)));
+
+        if ring.values.len() > BUFFER_VALUES {
+            let excess = ring.values.len() - BUFFER_VALUES;
+
+            ring.values.drain(..excess);
+            ring.overflowed = true;
+        }
+
+        ready.notify_all();
+        Ok( () )
+    }
+}
+
+/// Empties the TX ring, sending zeros when the application falls behind
+struct RingSource(SharedRing);
+
+impl TxSource for RingSource {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        let (ring, space) = &*self.0;
+        let mut ring = ring.lock().unwrap();
+        let count = ring.values.len().min(buffer.len());
+
+        for (out, value) in buffer.iter_mut().zip(ring.values.drain(..count)) {
+            *out = value;
+        }
+
+        buffer[count..].iter_mut().for_each(|v| *v = 0);
+        space.notify_all();
+
+        Ok(buffer.len())
+    }
+}
+
+struct Stream {
+    format: StreamFormat,
+    ring: SharedRing,
+    active: bool
+}
+
+struct Inner<R> {
+    radio: R,
+    rx: Option<Stream>,
+    tx: Option<Stream>,
+    /// Whether the filter follows the sample rate, until a bandwidth is set
+    auto_bandwidth: bool
+}
+
+impl <R> Inner<R> {
+    fn stream(&mut self, direction: Direction) -> &mut Option<Stream> {
+        match direction {
+            Direction::Rx => &mut self.rx,
+            Direction::Tx => &mut self.tx
+        }
+    }
+}
+
+/// A radio presented the way `SoapySDR::Device` presents one. Every method takes `&self`, as
+/// Soapy applications call in from several threads.
+pub struct SoapyDevice<R> {
+    inner: Mutex<Inner<R>>
+}
+
+impl <R: Radio> SoapyDevice<R> {
+    pub fn new(radio: R) -> SoapyDevice<R> {
+        SoapyDevice { inner: Mutex::new(Inner { radio, rx: None, tx: None, auto_bandwidth: true }) }
+    }
+
+    /// Runs `f` on the radio underneath, e.g. to reach settings Soapy has no call for
+    pub fn with_radio<T, F: FnOnce(&mut R) -> T>(&self, f: F) -> T {
+        f(&mut self.inner.lock().unwrap().radio)
+    }
+
+    pub fn driver_key(&self) -> &'static str {
+        "hackrf_rs"
+    }
+
+    pub fn hardware_key(&self) -> Result<String, Error> {
+        self.inner.lock().unwrap().radio.board_id_name()
+    }
+
+    /// `version` and `board_id`, for `getHardwareInfo`
+    pub fn hardware_info(&self) -> Result<Vec<(String, String)>, Error> {
+        let inner = self.inner.lock().unwrap();
+
+        Ok(vec![
+            (String::from("version"), inner.radio.version_string_read()?),
+            (String::from("board_id"), inner.radio.board_id_read()?.to_string())
+        ])
+    }
+
+    pub fn list_antennas(&self, _direction: Direction) -> Vec<&'static str> {
+        vec!["TX/RX"]
+    }
+
+    pub fn list_gains(&self, direction: Direction) -> &'static [&'static str] {
+        match direction {
+            Direction::Rx => &RX_GAINS,
+            Direction::Tx => &TX_GAINS
+        }
+    }
+
+    pub fn gain_range(&self, direction: Direction, name: &str) -> Result<Range, Error> {
+        match (direction, name) {
+            (_, "AMP") => Ok(Range::new(0.0, AMP_GAIN, AMP_GAIN)),
+            (Direction::Rx, "LNA") => Ok(Range::new(0.0, 40.0, 8.0)),
+            (Direction::Rx, "VGA") => Ok(Range::new(0.0, 62.0, 2.0)),
+            (Direction::Tx, "VGA") => Ok(Range::new(0.0, 47.0, 1.0)),
+            _ => Err(Error::INVALID_PARAM(format!("No {:?} gain element {}", direction, name)))
+        }
+    }
+
+    /// Range of the overall gain
+    pub fn overall_gain_range(&self, direction: Direction) -> Range {
+        match direction {
+            Direction::Rx => Range::new(0.0, 40.0 + 62.0 + AMP_GAIN, 1.0),
+            Direction::Tx => Range::new(0.0, 47.0 + AMP_GAIN, 1.0)
+        }
+    }
+
+    /// Sets a gain element, clamped to its range and rounded down to a step the hardware has
+    pub fn set_gain_element(&self, direction: Direction, name: &str, value: f64) -> Result<(), Error> {
+        let value = self.gain_range(direction, name)?.fit(value);
+        let inner = self.inner.lock().unwrap();
+
+        match (direction, name) {
+            (_, "AMP") => inner.radio.set_amp_enable(value > 0.0),
+            (Direction::Rx, "LNA") => inner.radio.set_lna_gain(value as u32),
+            (Direction::Rx, _) => inner.radio.set_vga_gain(value as u32),
+            (Direction::Tx, _) => inner.radio.set_txvga_gain(value as u32)
+        }
+    }
+
+    pub fn gain_element(&self, direction: Direction, name: &str) -> Result<f64, Error> {
+        self.gain_range(direction, name)?;
+
+        let state = self.inner.lock().unwrap().radio.tuning();
+
+        Ok(match (direction, name) {
+            (_, "AMP") => if state.amp_enable.unwrap_or(false) { AMP_GAIN } else { 0.0 },
+            (Direction::Rx, "LNA") => state.lna_gain.unwrap_or(0) as f64,
+            (Direction::Rx, _) => state.vga_gain.unwrap_or(0) as f64,
+            (Direction::Tx, _) => state.txvga_gain.unwrap_or(0) as f64
+        })
+    }
+
+    /// Spreads `value` over the gain elements: the LNA first then the VGA when receiving, leaving
+    /// the amplifier as it is, as it's best switched on deliberately
+    pub fn set_gain(&self, direction: Direction, value: f64) -> Result<(), Error> {
+        let amp = self.gain_element(direction, "AMP")?;
+        let mut remaining = (value - amp).max(0.0);
+
+        if direction == Direction::Rx {
+            let lna = self.gain_range(direction, "LNA")?.fit(remaining);
+
+            self.set_gain_element(direction, "LNA", lna)?;
+            remaining -= lna;
+        }
+
+        self.set_gain_element(direction, "VGA", remaining)
+    }
+
+    /// The sum of the gain elements
+    pub fn gain(&self, direction: Direction) -> Result<f64, Error> {
+        self.list_gains(direction).iter().map(|name| self.gain_element(direction, name)).sum()
+    }
+
+    /// Tunes the radio; it has one LO, so this is the frequency in both directions
+    pub fn set_frequency(&self, _direction: Direction, freq_hz: f64) -> Result<(), Error> {
+        if !(0.0..=7.25e9).contains(&freq_hz) {
+            return Err(Error::INVALID_PARAM(format!("Frequency {} Hz is out of range", freq_hz)));
+        }
+
+        self.inner.lock().unwrap().radio.set_freq(freq_hz.round() as u64)
+    }
+
+    pub fn frequency(&self, _direction: Direction) -> f64 {
+        self.inner.lock().unwrap().radio.tuning().freq_hz.unwrap_or(0) as f64
+    }
+
+    pub fn frequency_range(&self, _direction: Direction) -> Range {
+        Range::new(0.0, 7.25e9, 0.0)
+    }
+
+    /// Sets the sample rate and, unless a bandwidth has been set, the filter to 3/4 of it
+    pub fn set_sample_rate(&self, _direction: Direction, rate: f64) -> Result<(), Error> {
+        let inner = self.inner.lock().unwrap();
+
+        inner.radio.set_sample_rate(rate)?;
+
+        if inner.auto_bandwidth {
+            inner.radio.set_baseband_filter_bandwidth(baseband_filter(rate * 0.75))?;
+        }
+
+        Ok( () )
+    }
+
+    pub fn sample_rate(&self, _direction: Direction) -> f64 {
+        self.inner.lock().unwrap().radio.tuning().sample_rate.unwrap_or(0.0)
+    }
+
+    pub fn sample_rate_range(&self, _direction: Direction) -> Range {
+        Range::new(MIN_SAMPLE_RATE, MAX_SAMPLE_RATE, 0.0)
+    }
+
+    /// Picks the filter for `bandwidth`, or goes back to following the sample rate if it's 0
+    pub fn set_bandwidth(&self, _direction: Direction, bandwidth: f64) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.auto_bandwidth = bandwidth <= 0.0;
+
+        let bandwidth = if inner.auto_bandwidth {
+            inner.radio.tuning().sample_rate.unwrap_or(10e6) * 0.75
+        } else {
+            bandwidth
+        };
+
+        inner.radio.set_baseband_filter_bandwidth(baseband_filter(bandwidth))
+    }
+
+    pub fn bandwidth(&self, _direction: Direction) -> f64 {
+        self.inner.lock().unwrap().radio.tuning().baseband_filter_hz.unwrap_or(0) as f64
+    }
+
+    pub fn list_bandwidths(&self, _direction: Direction) -> Vec<f64> {
+        BANDWIDTHS.iter().map(|bw| *bw as f64).collect()
+    }
+
+    /// The one setting is `bias_tx`, the antenna port's bias tee, as in SoapyHackRF
+    pub fn write_setting(&self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "bias_tx" => self.inner.lock().unwrap().radio.set_antenna_enable(value == "true"),
+            other => Err(Error::INVALID_PARAM(format!("Unknown setting {}", other)))
+        }
+    }
+
+    pub fn read_setting(&self, key: &str) -> Result<String, Error> {
+        match key {
+            "bias_tx" => Ok(self.inner.lock().unwrap().radio.tuning().antenna_enable.unwrap_or(false).to_string()),
+            other => Err(Error::INVALID_PARAM(format!("Unknown setting {}", other)))
+        }
+    }
+
+    /// Sets up the stream for `direction`; there is one per direction
+    pub fn setup_stream(&self, direction: Direction, format: StreamFormat) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let stream = inner.stream(direction);
+
+        if stream.is_some() {
+            return Err(Error::BUSY(format!("{:?} stream is already set up", direction)));
+        }
+
+        *stream = Some(Stream { format, ring: new_ring(), active: false });
+        Ok( () )
+    }
+
+    pub fn close_stream(&self, direction: Direction) -> Result<(), Error> {
+        self.deactivate_stream(direction)?;
+        self.inner.lock().unwrap().stream(direction).take();
+
+        Ok( () )
+    }
+
+    /// The format of the stream set up for `direction`, if there is one
+    pub fn stream_format(&self, direction: Direction) -> Option<StreamFormat> {
+        self.inner.lock().unwrap().stream(direction).as_ref().map(|stream| stream.format)
+    }
+
+    pub fn stream_mtu(&self, _direction: Direction) -> usize {
+        STREAM_MTU
+    }
+
+    /// Starts streaming. The HackRF is half duplex, so only one direction can be active at a time.
+    pub fn activate_stream(&self, direction: Direction) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let ring = match inner.stream(direction) {
+            Some(Stream { active: true, .. }) => return Ok( () ),
+            Some(stream) => stream.ring.clone(),
+            None => return Err(Error::INVALID_PARAM(format!("{:?} stream is not set up", direction)))
+        };
+
+        match direction {
+            Direction::Rx => {
+                {
+                    let mut ring = ring.0.lock().unwrap();
+
+                    ring.values.clear();
+                    ring.overflowed = false;
+                }
+
+                inner.radio.start_rx_sink(Box::new(RingSink(ring)))?;
+            },
+            Direction::Tx => inner.radio.start_tx_source(Box::new(RingSource(ring)))?
+        }
+
+        if let Some(stream) = inner.stream(direction) {
+            stream.active = true;
+        }
+
+        Ok( () )
+    }
+
+    pub fn deactivate_stream(&self, direction: Direction) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+
+        match inner.stream(direction) {
+            Some(stream) if stream.active => stream.active = false,
+            _ => return Ok( () )
+        }
+
+        match direction {
+            Direction::Rx => inner.radio.stop_rx(),
+            Direction::Tx => inner.radio.stop_tx()
+        }
+    }
+
+    /// The ring of an active stream, checking the caller's format matches
+    fn active_ring(&self, direction: Direction, format: StreamFormat) -> Result<SharedRing, Error> {
+        let mut inner = self.inner.lock().unwrap();
+
+        match inner.stream(direction) {
+            Some(stream) if stream.format != format => {
+                Err(Error::INVALID_PARAM(format!("{:?} stream is {}, not {}", direction, stream.format.name(), format.name())))
+            },
+            Some(stream) if stream.active => Ok(stream.ring.clone()),
+            _ => Err(Error::INVALID_PARAM(format!("{:?} stream is not active", direction)))
+        }
+    }
+
+    /// Reads up to a buffer of RX samples, waiting at most `timeout` for the first, and returns
+    /// how many complex samples were read
+    pub fn read_stream(&self, mut buffer: SamplesMut, timeout: Duration) -> Result<usize, StreamError> {
+        let shared = self.active_ring(Direction::Rx, buffer.format())?;
+        let (ring, ready) = &*shared;
+        let deadline = Instant::now() + timeout;
+        let mut ring = ring.lock().unwrap();
+
+        while ring.values.len() < 2 && !ring.overflowed {
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Err(StreamError::Timeout);
+            }
+
+            ring = ready.wait_timeout(ring, deadline - now).unwrap().0;
+        }
+
+        if ring.overflowed {
+            ring.overflowed = false;
+            return Err(StreamError::Overflow);
+        }
+
+        let count = buffer.len().min(ring.values.len() / 2);
+        let values = ring.values.drain(..count * 2);
+
+        match buffer {
+            SamplesMut::CF32(ref mut out) => out.iter_mut().zip(values).for_each(|(o, v)| *o = stream::i8_to_f32(v)),
+            SamplesMut::CS16(ref mut out) => out.iter_mut().zip(values).for_each(|(o, v)| *o = (v as i16) << 8),
+            SamplesMut::CS8(ref mut out) => out.iter_mut().zip(values).for_each(|(o, v)| *o = v)
+        }
+
+        Ok(count)
+    }
+
+    /// Queues as many TX samples as there's room for, waiting at most `timeout` for room for any,
+    /// and returns how many complex samples were taken
+    pub fn write_stream(&self, samples: Samples, timeout: Duration) -> Result<usize, StreamError> {
+        let shared = self.active_ring(Direction::Tx, samples.format())?;
+        let (ring, space) = &*shared;
+        let deadline = Instant::now() + timeout;
+        let mut ring = ring.lock().unwrap();
+
+        while BUFFER_VALUES - ring.values.len() < 2 {
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Err(StreamError::Timeout);
+            }
+
+            ring = space.wait_timeout(ring, deadline - now).unwrap().0;
+        }
+
+        let count = samples.len().min((BUFFER_VALUES - ring.values.len()) / 2);
+
+        ring.values.extend(samples.to_i8(count));
+
+        Ok(count)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedDevice;
+
+    #[test]
+    fn settings_follow_soapy_conventions() {
+        let device = SoapyDevice::new(SimulatedDevice::new());
+
+        device.set_frequency(Direction::Rx, 915e6).unwrap();
+        assert_eq!(device.frequency(Direction::Tx), 915e6);
+        assert!(device.set_frequency(Direction::Rx, 8e9).is_err());
+
+        let rates = device.sample_rate_range(Direction::Rx);
+
+        assert!(device.set_sample_rate(Direction::Rx, rates.minimum).is_ok());
+        assert!(device.set_sample_rate(Direction::Rx, rates.maximum).is_ok());
+        assert!(device.set_sample_rate(Direction::Rx, rates.minimum / 2.0).is_err());
+
+        // the filter follows the rate until a bandwidth is set
+        device.set_sample_rate(Direction::Rx, 10e6).unwrap();
+        assert_eq!(device.bandwidth(Direction::Rx), 7e6);
+        device.set_bandwidth(Direction::Rx, 13e6).unwrap();
+        device.set_sample_rate(Direction::Rx, 20e6).unwrap();
+        assert_eq!(device.bandwidth(Direction::Rx), 12e6);
+        device.set_bandwidth(Direction::Rx, 0.0).unwrap();
+        assert_eq!(device.bandwidth(Direction::Rx), 15e6);
+
+        assert_eq!(device.list_gains(Direction::Rx), &["LNA", "AMP", "VGA"]);
+        assert_eq!(device.gain_range(Direction::Tx, "VGA").unwrap(), Range { minimum: 0.0, maximum: 47.0, step: 1.0 });
+        assert!(device.gain_range(Direction::Tx, "LNA").is_err());
+
+        device.set_gain_element(Direction::Rx, "LNA", 30.0).unwrap();
+        device.set_gain_element(Direction::Rx, "VGA", 99.0).unwrap();
+        device.set_gain_element(Direction::Rx, "AMP", 1.0).unwrap();
+        assert_eq!(device.gain_element(Direction::Rx, "LNA").unwrap(), 24.0);
+        assert_eq!(device.gain_element(Direction::Rx, "VGA").unwrap(), 62.0);
+        assert_eq!(device.gain_element(Direction::Tx, "AMP").unwrap(), 0.0);
+        assert_eq!(device.gain(Direction::Rx).unwrap(), 24.0 + 62.0 + 0.0);
+
+        device.set_gain_element(Direction::Rx, "AMP", 14.0).unwrap();
+        device.set_gain(Direction::Rx, 14.0 + 45.0).unwrap();
+        assert_eq!(device.gain_element(Direction::Rx, "LNA").unwrap(), 40.0);
+        assert_eq!(device.gain_element(Direction::Rx, "VGA").unwrap(), 4.0);
+        device.set_gain(Direction::Tx, 30.0).unwrap();
+        assert_eq!(device.gain_element(Direction::Tx, "VGA").unwrap(), 16.0);
+
+        assert_eq!(device.read_setting("bias_tx").unwrap(), "false");
+        device.write_setting("bias_tx", "true").unwrap();
+        assert_eq!(device.read_setting("bias_tx").unwrap(), "true");
+        assert!(device.with_radio(|radio| radio.tuning().antenna_enable.unwrap()));
+        assert!(device.write_setting("clock_source", "external").is_err());
+    }
+
+    #[test]
+    fn streams_in_both_directions() {
+        let device = SoapyDevice::new(SimulatedDevice::new().with_tone(100_500_000, 0.5).with_noise(0.0));
+        let mut buffer = vec![0f32; 2 * 4096];
+
+        device.set_frequency(Direction::Rx, 100e6).unwrap();
+        device.set_sample_rate(Direction::Rx, 4e6).unwrap();
+
+        assert!(device.activate_stream(Direction::Rx).is_err());
+        device.setup_stream(Direction::Rx, StreamFormat::CF32).unwrap();
+        assert!(device.setup_stream(Direction::Rx, StreamFormat::CS8).is_err());
+        device.activate_stream(Direction::Rx).unwrap();
+
+        let mut read = 0;
+
+        while read < 4096 {
+            read += device.read_stream(SamplesMut::CF32(&mut buffer[read * 2..]), Duration::from_secs(1)).unwrap();
+        }
+
+        assert!(buffer.chunks_exact(2).all(|iq| (iq[0].hypot(iq[1]) - 0.5).abs() < 0.02));
+        assert!(device.read_stream(SamplesMut::CS8(&mut [0i8; 16]), Duration::from_secs(1)).is_err());
+
+        // the half-duplex radio can't transmit while receiving
+        device.setup_stream(Direction::Tx, StreamFormat::CS16).unwrap();
+        assert!(device.activate_stream(Direction::Tx).is_err());
+        device.deactivate_stream(Direction::Rx).unwrap();
+        assert!(matches!(device.read_stream(SamplesMut::CF32(&mut buffer), Duration::from_millis(10)), Err(StreamError::Error(_))));
+
+        device.activate_stream(Direction::Tx).unwrap();
+
+        let tone :Vec<i16> = (0..2 * 50_000).map(|i| if i % 2 == 0 { 0x4000 } else { 0 }).collect();
+        let mut written = 0;
+
+        while written < 50_000 {
+            written += device.write_stream(Samples::CS16(&tone[written * 2..]), Duration::from_secs(1)).unwrap();
+        }
+
+        let start = Instant::now();
+
+        while device.with_radio(|radio| radio.tx_samples()) < 50_000 {
+            assert!(start.elapsed() < Duration::from_secs(5), "samples weren't sent");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        device.close_stream(Direction::Tx).unwrap();
+        device.close_stream(Direction::Rx).unwrap();
+        assert!(!device.with_radio(|radio| radio.is_streaming().unwrap()));
+    }
+
+    #[test]
+    fn overflow_is_reported_once() {
+        let device = SoapyDevice::new(SimulatedDevice::new().with_realtime(false));
+        let mut buffer = vec![0i8; 2 * STREAM_MTU];
+
+        device.set_sample_rate(Direction::Rx, 20e6).unwrap();
+        device.setup_stream(Direction::Rx, StreamFormat::CS8).unwrap();
+        device.activate_stream(Direction::Rx).unwrap();
+
+        // nothing is read, so the ring fills up and starts dropping samples
+        let start = Instant::now();
+
+        loop {
+            match device.read_stream(SamplesMut::CS8(&mut []), Duration::from_secs(1)) {
+                Ok(0) => assert!(start.elapsed() < Duration::from_secs(10), "no overflow"),
+                Err(StreamError::Overflow) => break,
+                other => panic!("{:?}", other)
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(device.read_stream(SamplesMut::CS8(&mut buffer), Duration::from_secs(1)).unwrap(), STREAM_MTU);
+
+        device.close_stream(Direction::Rx).unwrap();
+    }
+}
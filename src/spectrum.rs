@@ -0,0 +1,426 @@
+//! Averaged power spectra for live displays, computed from the RX stream or from sweeps, and the
+//! compact binary frames they're sent to viewers in.
+//!
+//! A frame is a 24-byte little-endian header followed by one byte per bin, lowest frequency
+//! first:
+//!
+//! | Offset | Type  | Field                                   |
+//! |--------|-------|-----------------------------------------|
+//! | 0      | `u8`  | version, currently 1                    |
+//! | 1      | `u8`  | source: 0 for RX, 1 for a sweep         |
+//! | 2      | `u16` | reserved, 0                             |
+//! | 4      | `u32` | number of bins                          |
+//! | 8      | `f64` | frequency at the low edge, Hz           |
+//! | 16     | `f64` | frequency at the high edge, Hz          |
+//!
+//! A bin's byte `v` is `v / 2 - 127.5` dBFS; 0 means at or below -127.5 dBFS, or no data.
+
+use crate::dsp::{Fft, hann_window};
+use crate::error::Error;
+use crate::stream::RxSink;
+use crate::sweep::{SweepPlan, SweepProcessor, SweepSegment};
+use crate::tuning::TuningHandle;
+
+pub const FRAME_VERSION: u8 = 1;
+pub const FRAME_HEADER_SIZE: usize = 24;
+
+/// The lowest level a frame can carry, in dBFS
+pub const FLOOR_DB: f32 = -127.5;
+
+/// FFT sizes `RxSpectrum` accepts
+pub const MIN_FFT_SIZE: usize = 16;
+pub const MAX_FFT_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSource {
+    Rx,
+    Sweep
+}
+
+/// One averaged spectrum
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrumFrame {
+    pub source: FrameSource,
+    pub freq_start: f64,
+    pub freq_stop: f64,
+    /// dBFS per bin from `freq_start` up; NaN where a sweep had no data
+    pub power_db: Vec<f32>
+}
+
+impl SpectrumFrame {
+    pub fn bin_width(&self) -> f64 {
+        (self.freq_stop - self.freq_start) / self.power_db.len().max(1) as f64
+    }
+
+    /// Frequency at the center of bin `index`
+    pub fn bin_freq(&self, index: usize) -> f64 {
+        self.freq_start + (index as f64 + 0.5) * self.bin_width()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + self.power_db.len());
+
+        frame.push(FRAME_VERSION);
+        frame.push(match self.source { FrameSource::Rx => 0, FrameSource::Sweep => 1 });
+        frame.extend_from_slice(&0u16.to_le_bytes());
+        frame.extend_from_slice(&(self.power_db.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&self.freq_start.to_le_bytes());
+        frame.extend_from_slice(&self.freq_stop.to_le_bytes());
+        frame.extend(self.power_db.iter().map(|db| {
+            if db.is_nan() { 0 } else { ((db - FLOOR_DB) * 2.0).round().clamp(0.0, 255.0) as u8 }
+        }));
+
+        frame
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<SpectrumFrame, Error> {
+        if frame.len() < FRAME_HEADER_SIZE || frame[0] != FRAME_VERSION {
+            return Err(Error::INVALID_PARAM(String::from("Not a spectrum frame")));
+        }
+
+        let mut word = [0u8; 8];
+        let mut f64_at = |offset: usize| {
+            word.copy_from_slice(&frame[offset..offset + 8]);
+            f64::from_le_bytes(word)
+        };
+        let (freq_start, freq_stop) = (f64_at(8), f64_at(16));
+        let bins = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]) as usize;
+
+        if frame.len() != FRAME_HEADER_SIZE + bins {
+            return Err(Error::INVALID_PARAM(format!("Spectrum frame should have {} bins", bins)));
+        }
+
+        Ok(SpectrumFrame {
+            source: if frame[1] == 1 { FrameSource::Sweep } else { FrameSource::Rx },
+            freq_start,
+            freq_stop,
+            power_db: frame[FRAME_HEADER_SIZE..].iter().map(|v| *v as f32 / 2.0 + FLOOR_DB).collect()
+        })
+    }
+}
+
+/// An RX sink that averages windowed FFTs and hands a frame to `output` at most `frame_rate`
+/// times a second; samples between frames are skipped rather than analysed
+pub struct RxSpectrum<F> {
+    fft: Fft,
+    window: Vec<f32>,
+    scale: f32,
+    averaging: usize,
+    frame_rate: f64,
+    tuning: TuningHandle,
+    input: Vec<f32>,
+    scratch: Vec<f32>,
+    sum: Vec<f32>,
+    averaged: usize,
+    skip: u64,
+    output: F
+}
+
+impl<F: FnMut(SpectrumFrame) -> Result<(), Error>> RxSpectrum<F> {
+    /// Averages `averaging` FFTs of `fft_size` bins into each frame, labelling frames with the
+    /// frequency and rate in `tuning`
+    pub fn new(fft_size: usize, averaging: usize, tuning: TuningHandle, output: F) -> Result<RxSpectrum<F>, Error> {
+        if !fft_size.is_power_of_two() || !(MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&fft_size) {
+            return Err(Error::INVALID_PARAM(format!("FFT size must be a power of two from {} to {}", MIN_FFT_SIZE, MAX_FFT_SIZE)));
+        }
+
+        if averaging == 0 {
+            return Err(Error::INVALID_PARAM(String::from("At least one FFT must be averaged")));
+        }
+
+        let window = hann_window(fft_size);
+        let gain :f32 = window.iter().sum();
+
+        Ok(RxSpectrum {
+            fft: Fft::new(fft_size),
+            window,
+            // a full-scale tone reads 0 dBFS
+            scale: 1.0 / (gain * gain),
+            averaging,
+            frame_rate: 25.0,
+            tuning,
+            input: Vec::with_capacity(fft_size * 2),
+            scratch: vec![0.0; fft_size * 2],
+            sum: vec![0.0; fft_size],
+            averaged: 0,
+            skip: 0,
+            output
+        })
+    }
+
+    /// Most frames per second; 0 for as many as the samples allow
+    pub fn with_frame_rate(mut self, frame_rate: f64) -> RxSpectrum<F> {
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    fn accumulate(&mut self) {
+        for (n, (out, iq)) in self.scratch.chunks_exact_mut(2).zip(self.input.chunks_exact(2)).enumerate() {
+            out[0] = iq[0] * self.window[n];
+            out[1] = iq[1] * self.window[n];
+        }
+
+        self.fft.process(&mut self.scratch);
+
+        for (sum, bin) in self.sum.iter_mut().zip(self.scratch.chunks_exact(2)) {
+            *sum += bin[0] * bin[0] + bin[1] * bin[1];
+        }
+
+        self.averaged += 1;
+    }
+
+    fn emit(&mut self) -> Result<(), Error> {
+        let size = self.sum.len();
+        let state = self.tuning.get();
+        let rate = state.sample_rate.unwrap_or(0.0);
+        let center = state.freq_hz.unwrap_or(0) as f64;
+        let scale = self.scale / self.averaged as f32;
+        // DC is bin 0; rotate so the lowest frequency comes first
+        let power_db = (0..size)
+            .map(|i| 10.0 * (self.sum[(i + size / 2) % size] * scale).max(1e-20).log10())
+            .collect();
+
+        self.sum.iter_mut().for_each(|v| *v = 0.0);
+        self.averaged = 0;
+
+        if self.frame_rate > 0.0 && rate > 0.0 {
+            let interval = (rate / self.frame_rate) as u64;
+
+            self.skip = interval.saturating_sub((self.averaging * size) as u64);
+        }
+
+        (self.output)(SpectrumFrame {
+            source: FrameSource::Rx,
+            freq_start: center - rate / 2.0,
+            freq_stop: center + rate / 2.0,
+            power_db
+        })
+    }
+}
+
+impl<F: FnMut(SpectrumFrame) -> Result<(), Error>> RxSink for RxSpectrum<F> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let mut input = samples;
+        let wanted = self.fft.size() * 2;
+
+        while !input.is_empty() {
+            if self.skip > 0 {
+                let count = (self.skip as usize).min(input.len() / 2).max(1);
+
+                input = &input[(count * 2).min(input.len())..];
+                self.skip -= count as u64;
+                continue;
+            }
+
+            let count = (wanted - self.input.len()).min(input.len());
+
+            self.input.extend_from_slice(&input[..count]);
+            input = &input[count..];
+
+            if self.input.len() == wanted {
+                self.accumulate();
+                self.input.clear();
+
+                if self.averaged == self.averaging {
+                    self.emit()?;
+                }
+            }
+        }
+
+        Ok( () )
+    }
+}
+
+/// An RX sink for sweep mode that stitches the segments of `averaging` whole sweeps into a frame
+/// spanning the plan
+pub struct SweepSpectrum<F> {
+    processor: SweepProcessor,
+    freq_start: u64,
+    freq_stop: u64,
+    bin_width: f64,
+    sum: Vec<f32>,
+    counts: Vec<u32>,
+    averaging: usize,
+    sweeps: usize,
+    first_freq: Option<u64>,
+    segments: Vec<SweepSegment>,
+    output: F
+}
+
+impl<F: FnMut(SpectrumFrame) -> Result<(), Error>> SweepSpectrum<F> {
+    pub fn new(plan: &SweepPlan, averaging: usize, output: F) -> Result<SweepSpectrum<F>, Error> {
+        if averaging == 0 {
+            return Err(Error::INVALID_PARAM(String::from("At least one sweep must be averaged")));
+        }
+
+        let (freq_start, freq_stop) = plan.span();
+        let bins = ((freq_stop - freq_start) as f64 / plan.bin_width()).round() as usize;
+
+        Ok(SweepSpectrum {
+            processor: SweepProcessor::new(plan.fft_size()),
+            freq_start,
+            freq_stop,
+            bin_width: plan.bin_width(),
+            sum: vec![0.0; bins],
+            counts: vec![0; bins],
+            averaging,
+            sweeps: 0,
+            first_freq: None,
+            segments: Vec::new(),
+            output
+        })
+    }
+
+    fn emit(&mut self) -> Result<(), Error> {
+        let power_db = self.sum.iter().zip(self.counts.iter())
+            .map(|(sum, count)| if *count == 0 { f32::NAN } else { 10.0 * (sum / *count as f32).max(1e-20).log10() })
+            .collect();
+
+        self.sum.iter_mut().for_each(|v| *v = 0.0);
+        self.counts.iter_mut().for_each(|v| *v = 0);
+
+        (self.output)(SpectrumFrame {
+            source: FrameSource::Sweep,
+            freq_start: self.freq_start as f64,
+            freq_stop: self.freq_stop as f64,
+            power_db
+        })
+    }
+}
+
+impl<F: FnMut(SpectrumFrame) -> Result<(), Error>> RxSink for SweepSpectrum<F> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let mut segments = std::mem::take(&mut self.segments);
+
+        self.processor.process(samples, &mut segments);
+
+        // segments come two to a block, the first starting at the block's frequency
+        for block in segments.chunks(2) {
+            let freq = block[0].freq_low;
+
+            match self.first_freq {
+                None => self.first_freq = Some(freq),
+                Some(first) if first == freq => {
+                    self.sweeps += 1;
+
+                    if self.sweeps == self.averaging {
+                        self.sweeps = 0;
+                        self.emit()?;
+                    }
+                },
+                _ => {}
+            }
+
+            for segment in block {
+                let first_bin = ((segment.freq_low as f64 - self.freq_start as f64) / self.bin_width).round() as i64;
+
+                for (i, db) in segment.power_db.iter().enumerate() {
+                    let bin = first_bin + i as i64;
+
+                    if bin >= 0 && (bin as usize) < self.sum.len() {
+                        self.sum[bin as usize] += 10f32.powf(db / 10.0);
+                        self.counts[bin as usize] += 1;
+                    }
+                }
+            }
+        }
+
+        segments.clear();
+        self.segments = segments;
+
+        Ok( () )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::Nco;
+    use crate::sweep::{self, BLOCK_BYTES};
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = SpectrumFrame {
+            source: FrameSource::Sweep,
+            freq_start: 2.4e9,
+            freq_stop: 2.5e9,
+            power_db: vec![-200.0, -127.5, -60.25, 0.0, 10.0, f32::NAN]
+        };
+        let encoded = frame.encode();
+
+        assert_eq!(encoded.len(), FRAME_HEADER_SIZE + 6);
+        assert_eq!(&encoded[FRAME_HEADER_SIZE..], &[0, 0, 135, 255, 255, 0]);
+
+        let decoded = SpectrumFrame::decode(&encoded).unwrap();
+
+        assert_eq!((decoded.source, decoded.freq_start, decoded.freq_stop), (FrameSource::Sweep, 2.4e9, 2.5e9));
+        assert_eq!(decoded.power_db[2], -60.0);
+        assert!(SpectrumFrame::decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rx_spectrum_averages_and_paces() {
+        let tuning = TuningHandle::new();
+        let mut frames = Vec::new();
+
+        tuning.update(|t| {
+            t.freq_hz = Some(100_000_000);
+            t.sample_rate = Some(1_024_000.0);
+        });
+
+        {
+            let mut spectrum = RxSpectrum::new(256, 4, tuning, |frame| {
+                frames.push(frame);
+                Ok( () )
+            }).unwrap().with_frame_rate(100.0);
+
+            // a full-scale tone 100 kHz up, for one second
+            let mut samples = [1.0f32, 0.0].repeat(1_024_000);
+
+            Nco::new(100e3, 1_024_000.0).mix_in_place(&mut samples);
+
+            for chunk in samples.chunks(30000) {
+                spectrum.write(chunk).unwrap();
+            }
+        }
+
+        assert_eq!(frames.len(), 100);
+
+        let frame = &frames[0];
+        let peak = (0..256).max_by(|a, b| frame.power_db[*a].partial_cmp(&frame.power_db[*b]).unwrap()).unwrap();
+
+        assert_eq!((frame.freq_start, frame.freq_stop), (99_488_000.0, 100_512_000.0));
+        assert_eq!(peak, 128 + 25);
+        assert!(frame.power_db[peak].abs() < 0.5, "{}", frame.power_db[peak]);
+        assert!(RxSpectrum::new(100, 1, TuningHandle::new(), |_| Ok( () )).is_err());
+    }
+
+    #[test]
+    fn sweep_spectrum_stitches_sweeps() {
+        let plan = SweepPlan::new(&[(2400, 2420)]).unwrap().with_bin_width(312.5e3).unwrap();
+        let mut frames = Vec::new();
+        let mut blocks = Vec::new();
+
+        // two passes over the two interleaved steps, then the start of a third
+        for freq in &[2_400_000_000u64, 2_405_000_000, 2_400_000_000, 2_405_000_000, 2_400_000_000] {
+            let mut block = vec![0.001f32; BLOCK_BYTES];
+
+            sweep::write_header(&mut block, *freq);
+            blocks.extend(block);
+        }
+
+        {
+            let mut spectrum = SweepSpectrum::new(&plan, 2, |frame| {
+                frames.push(frame);
+                Ok( () )
+            }).unwrap();
+
+            spectrum.write(&blocks).unwrap();
+        }
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].power_db.len(), 64);
+        assert_eq!((frames[0].freq_start, frames[0].freq_stop), (2.4e9, 2.42e9));
+        assert!(frames[0].power_db.iter().all(|db| !db.is_nan()));
+    }
+}
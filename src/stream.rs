@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex};
+
+use crate::error::Error;
+
+/// Something RX samples can be written to, see `Device::start_rx_sink`
+pub trait RxSink {
+    /// Writes a buffer of interleaved IQ samples
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error>;
+
+    /// Pushes any buffered samples out
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok( () )
+    }
+}
+
+/// Lets a sink be shared with the RX callback while the caller keeps a handle to finish it afterwards
+impl <S: RxSink> RxSink for Arc<Mutex<S>> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        self.lock().unwrap().write(samples)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.lock().unwrap().flush()
+    }
+}
+
+impl <S: RxSink + ?Sized> RxSink for Box<S> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        (**self).write(samples)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        (**self).flush()
+    }
+}
+
+/// Something TX samples can be read from, see `Device::start_tx_source`
+pub trait TxSource {
+    /// Fills `buffer` with interleaved IQ in the HackRF's native signed 8-bit format, returning the number
+    /// of values written. Anything less than `buffer.len()` means the source has run out.
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error>;
+}
+
+/// Lets a source be shared with the TX callback while the caller keeps a handle to it
+impl <S: TxSource> TxSource for Arc<Mutex<S>> {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        self.lock().unwrap().read(buffer)
+    }
+}
+
+impl <S: TxSource + ?Sized> TxSource for Box<S> {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        (**self).read(buffer)
+    }
+}
+
+/// Converts a sample in [-1, 1) to the HackRF's native signed 8-bit format
+#[inline]
+pub fn f32_to_i8(value: f32) -> i8 {
+    (value * 128.0).round().clamp(-128.0, 127.0) as i8
+}
+
+/// Converts a sample in the HackRF's native signed 8-bit format to [-1, 1)
+#[inline]
+pub fn i8_to_f32(value: i8) -> f32 {
+    value as f32 * (1.0 / 128.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i8_round_trip() {
+        for v in -128..=127i8 {
+            assert_eq!(f32_to_i8(i8_to_f32(v)), v);
+        }
+
+        assert_eq!(f32_to_i8(1.5), 127);
+        assert_eq!(f32_to_i8(-1.5), -128);
+    }
+}
@@ -0,0 +1,362 @@
+//! Sweep mode, where the firmware hops across a list of frequency ranges by itself.
+//!
+//! After `init_sweep` the RX stream arrives in blocks of `BLOCK_BYTES`, each opening with a
+//! 10-byte header (`0x7f 0x7f` then the little-endian u64 frequency in Hz the block was captured
+//! at) in place of its first five samples. `SweepProcessor` turns each block into two spectrum
+//! segments the same way `hackrf_sweep` does.
+
+use crate::dsp::{Fft, hann_window};
+use crate::error::Error;
+use crate::radio::Radio;
+use crate::stream::{self, RxSink};
+use crate::sweep_style;
+
+/// Bytes in a sweep block, and so also the number of `f32`s in one after conversion
+pub const BLOCK_BYTES: usize = 16384;
+
+/// Most ranges `init_sweep` accepts
+pub const MAX_RANGES: usize = 10;
+
+/// Highest frequency the firmware will sweep to, in MHz
+pub const MAX_FREQ_MHZ: u16 = 7250;
+
+/// Settings `hackrf_sweep` uses, which the segment layout below depends on
+pub const SAMPLE_RATE: f64 = 20e6;
+pub const BASEBAND_FILTER: u32 = 15_000_000;
+pub const TUNE_STEP_HZ: u32 = 20_000_000;
+pub const OFFSET_HZ: u32 = 7_500_000;
+
+/// Samples replaced by the block header
+const HEADER_SAMPLES: usize = 5;
+const HEADER_MARKER: u8 = 0x7f;
+
+/// The ranges to sweep and the resolution to analyse them at
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPlan {
+    ranges: Vec<(u16, u16)>,
+    fft_size: usize
+}
+
+impl SweepPlan {
+    /// Sweeps each `(start, stop)` range in MHz. Like `hackrf_sweep`, a range that isn't a whole
+    /// number of 20 MHz steps is widened at the top until it is. Bins are at most 1 MHz wide
+    /// until `with_bin_width` says otherwise.
+    pub fn new(ranges: &[(u16, u16)]) -> Result<SweepPlan, Error> {
+        if ranges.is_empty() || ranges.len() > MAX_RANGES {
+            return Err(Error::INVALID_PARAM(format!("Between 1 and {} sweep ranges are needed", MAX_RANGES)));
+        }
+
+        let step = (TUNE_STEP_HZ / 1_000_000) as u16;
+        let mut widened = Vec::with_capacity(ranges.len());
+
+        for &(start, stop) in ranges {
+            if start >= stop {
+                return Err(Error::INVALID_PARAM(format!("Sweep range {}-{} MHz is empty", start, stop)));
+            }
+
+            let steps = 1 + (stop - start - 1) / step;
+            let stop = start as u32 + steps as u32 * step as u32;
+
+            if stop > MAX_FREQ_MHZ as u32 {
+                return Err(Error::INVALID_PARAM(format!("Sweep ranges must end at or below {} MHz", MAX_FREQ_MHZ)));
+            }
+
+            widened.push((start, stop as u16));
+        }
+
+        SweepPlan { ranges: widened, fft_size: 32 }.with_bin_width(1e6)
+    }
+
+    /// Picks the FFT size giving bins closest to, and no wider than, `bin_width_hz`
+    pub fn with_bin_width(mut self, bin_width_hz: f64) -> Result<SweepPlan, Error> {
+        // capped so tiny widths fail the range check below rather than overflow
+        let fft_size = if bin_width_hz.is_finite() && bin_width_hz > 0.0 {
+            (SAMPLE_RATE / bin_width_hz).ceil().max(1.0).min(BLOCK_BYTES as f64) as usize
+        } else {
+            0
+        };
+        let fft_size = fft_size.next_power_of_two();
+
+        if !(4..=BLOCK_BYTES / 4).contains(&fft_size) {
+            return Err(Error::INVALID_PARAM(format!("Bin width must be between {} and {} Hz",
+                                                    SAMPLE_RATE as usize / (BLOCK_BYTES / 4), SAMPLE_RATE as usize / 4)));
+        }
+
+        self.fft_size = fft_size;
+        Ok(self)
+    }
+
+    pub fn ranges(&self) -> &[(u16, u16)] {
+        &self.ranges
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    pub fn bin_width(&self) -> f64 {
+        SAMPLE_RATE / self.fft_size as f64
+    }
+
+    /// Lowest and highest frequency covered, in Hz
+    pub fn span(&self) -> (u64, u64) {
+        let start = self.ranges.iter().map(|r| r.0).min().unwrap_or(0);
+        let stop = self.ranges.iter().map(|r| r.1).max().unwrap_or(0);
+
+        (start as u64 * 1_000_000, stop as u64 * 1_000_000)
+    }
+
+    /// The ranges flattened into the list `init_sweep` takes
+    pub fn frequency_list(&self) -> Vec<u16> {
+        self.ranges.iter().flat_map(|&(start, stop)| vec![start, stop]).collect()
+    }
+
+    /// Configures `radio` as `hackrf_sweep` does, then starts sweeping into `sink`; feed what
+    /// arrives to a `SweepProcessor`. `stop_rx` ends the sweep.
+    pub fn start<R: Radio>(&self, radio: &mut R, sink: Box<dyn RxSink + Send>) -> Result<(), Error> {
+        radio.set_sample_rate(SAMPLE_RATE)?;
+        radio.set_baseband_filter_bandwidth(BASEBAND_FILTER)?;
+        radio.init_sweep(&self.frequency_list(), BLOCK_BYTES as u32, TUNE_STEP_HZ, OFFSET_HZ, sweep_style::INTERLEAVED)?;
+        radio.start_rx_sink(sink)
+    }
+}
+
+/// The frequencies, in Hz, a sweep visits in order, as the firmware steps through them. These are
+/// the frequencies written in block headers; the radio is tuned `offset` Hz higher.
+pub fn sweep_steps(frequency_list: &[u16], step_width: u32, style: sweep_style) -> Vec<u64> {
+    let mut steps = Vec::new();
+
+    for range in frequency_list.chunks_exact(2) {
+        let stop = range[1] as u64 * 1_000_000;
+        let mut freq = range[0] as u64 * 1_000_000;
+
+        while freq < stop {
+            steps.push(freq);
+
+            if style == sweep_style::INTERLEAVED {
+                steps.push(freq + step_width as u64 / 4);
+            }
+
+            freq += step_width as u64;
+        }
+    }
+
+    steps
+}
+
+/// Writes a sweep block header over the first samples of `block`
+pub fn write_header(block: &mut [f32], freq_hz: u64) {
+    let mut header = [HEADER_MARKER; HEADER_SAMPLES * 2];
+
+    header[2..].copy_from_slice(&freq_hz.to_le_bytes());
+
+    for (sample, byte) in block.iter_mut().zip(header.iter()) {
+        *sample = stream::i8_to_f32(*byte as i8);
+    }
+}
+
+/// The frequency in a sweep block's header, or `None` if `block` doesn't start with one
+pub fn parse_header(block: &[f32]) -> Option<u64> {
+    if block.len() < HEADER_SAMPLES * 2 {
+        return None;
+    }
+
+    let bytes :Vec<u8> = block[..HEADER_SAMPLES * 2].iter().map(|v| stream::f32_to_i8(*v) as u8).collect();
+
+    if bytes[0] != HEADER_MARKER || bytes[1] != HEADER_MARKER {
+        return None;
+    }
+
+    let mut freq = [0u8; 8];
+
+    freq.copy_from_slice(&bytes[2..]);
+
+    Some(u64::from_le_bytes(freq))
+}
+
+/// Power across part of the spectrum, one line of `hackrf_sweep` output
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepSegment {
+    pub freq_low: u64,
+    pub freq_high: u64,
+    pub bin_width: f64,
+    /// dB relative to full scale, one per bin from `freq_low` up
+    pub power_db: Vec<f32>
+}
+
+/// Computes spectra from sweep blocks
+pub struct SweepProcessor {
+    fft: Fft,
+    window: Vec<f32>,
+    scratch: Vec<f32>,
+    pending: Vec<f32>
+}
+
+impl SweepProcessor {
+    pub fn new(fft_size: usize) -> SweepProcessor {
+        SweepProcessor {
+            fft: Fft::new(fft_size),
+            window: hann_window(fft_size),
+            scratch: vec![0.0; fft_size * 2],
+            pending: Vec::new()
+        }
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft.size()
+    }
+
+    /// The windowed FFT of the last block `process_block` accepted, as interleaved complex bins.
+    /// `hackrf_sweep -I` stitches these back together into a time-domain signal.
+    pub fn spectrum(&self) -> &[f32] {
+        &self.scratch
+    }
+
+    /// Turns every whole block in `samples` into segments appended to `segments`; a partial block
+    /// is kept until the rest arrives
+    pub fn process(&mut self, samples: &[f32], segments: &mut Vec<SweepSegment>) {
+        let mut input = samples;
+
+        if !self.pending.is_empty() {
+            let needed = (BLOCK_BYTES - self.pending.len()).min(input.len());
+
+            self.pending.extend_from_slice(&input[..needed]);
+            input = &input[needed..];
+
+            if self.pending.len() < BLOCK_BYTES {
+                return;
+            }
+
+            let block = std::mem::take(&mut self.pending);
+
+            segments.extend(self.process_block(&block).iter().flatten().cloned());
+        }
+
+        let mut blocks = input.chunks_exact(BLOCK_BYTES);
+
+        for block in &mut blocks {
+            segments.extend(self.process_block(block).iter().flatten().cloned());
+        }
+
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    /// The two segments `hackrf_sweep` reports for one block: the quarter of the sample rate
+    /// starting at the header frequency, and the quarter starting half the rate above it. Together
+    /// with the interleaved step 5 MHz up, they tile the sweep. `None` if the header is missing.
+    pub fn process_block(&mut self, block: &[f32]) -> Option<[SweepSegment; 2]> {
+        let freq = parse_header(block)?;
+        let size = self.fft.size();
+
+        if block.len() < size * 2 + HEADER_SAMPLES * 2 {
+            return None;
+        }
+
+        // the end of the block, well clear of the retuning transient at its start
+        let tail = &block[block.len() - size * 2..];
+
+        for (n, (out, iq)) in self.scratch.chunks_exact_mut(2).zip(tail.chunks_exact(2)).enumerate() {
+            out[0] = iq[0] * self.window[n];
+            out[1] = iq[1] * self.window[n];
+        }
+
+        self.fft.process(&mut self.scratch);
+
+        let scale = 1.0 / (size as f32 * size as f32);
+        let power = |bin: usize| {
+            let (re, im) = (self.scratch[bin * 2], self.scratch[bin * 2 + 1]);
+
+            10.0 * ((re * re + im * im) * scale).max(1e-20).log10()
+        };
+        let quarter = size / 4;
+        let bin_width = SAMPLE_RATE / size as f64;
+        let rate = SAMPLE_RATE as u64;
+
+        Some([
+            SweepSegment {
+                freq_low: freq,
+                freq_high: freq + rate / 4,
+                bin_width,
+                power_db: (0..quarter).map(|i| power(i + 1 + size * 5 / 8)).collect()
+            },
+            SweepSegment {
+                freq_low: freq + rate / 2,
+                freq_high: freq + rate * 3 / 4,
+                bin_width,
+                power_db: (0..quarter).map(|i| power(i + 1 + size / 8)).collect()
+            }
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::Nco;
+    use crate::simulator::SimulatedDevice;
+
+    #[test]
+    fn plans_like_hackrf_sweep() {
+        let plan = SweepPlan::new(&[(2400, 2490), (900, 920)]).unwrap();
+
+        assert_eq!(plan.ranges(), &[(2400, 2500), (900, 920)]);
+        assert_eq!(plan.frequency_list(), vec![2400, 2500, 900, 920]);
+        assert_eq!(plan.span(), (900_000_000, 2_500_000_000));
+        assert_eq!(plan.fft_size(), 32);
+
+        let plan = plan.with_bin_width(100e3).unwrap();
+
+        assert_eq!((plan.fft_size(), plan.bin_width()), (256, 78125.0));
+        assert!(plan.clone().with_bin_width(1e3).is_err());
+        assert!(plan.clone().with_bin_width(0.0).is_err());
+        assert!(plan.clone().with_bin_width(-1e6).is_err());
+        assert!(plan.clone().with_bin_width(f64::NAN).is_err());
+        assert!(plan.clone().with_bin_width(1e-300).is_err());
+        assert!(SweepPlan::new(&[(7240, 7250), (1, 1)]).is_err());
+        assert!(SweepPlan::new(&[(7240, 7260)]).is_err());
+
+        let ranges :Vec<(u16, u16)> = (0..MAX_RANGES as u16).map(|i| (100 * i + 100, 100 * i + 120)).collect();
+        let plan = SweepPlan::new(&ranges).unwrap();
+        let sim = SimulatedDevice::new();
+
+        assert_eq!(plan.frequency_list().len(), MAX_RANGES * 2);
+        assert!(sim.init_sweep(&plan.frequency_list(), BLOCK_BYTES as u32, TUNE_STEP_HZ, OFFSET_HZ, sweep_style::INTERLEAVED).is_ok());
+        assert!(sim.init_sweep(&[100, 120, 200], BLOCK_BYTES as u32, TUNE_STEP_HZ, OFFSET_HZ, sweep_style::INTERLEAVED).is_err());
+        assert!(SweepPlan::new(&[(100, 120); MAX_RANGES + 1]).is_err());
+
+        assert_eq!(sweep_steps(&[900, 940], TUNE_STEP_HZ, sweep_style::INTERLEAVED),
+                   vec![900_000_000, 905_000_000, 920_000_000, 925_000_000]);
+    }
+
+    #[test]
+    fn block_becomes_segments() {
+        let size = 64;
+        let mut processor = SweepProcessor::new(size);
+        // a tone 10.625 MHz above the header frequency, so 3.125 MHz above the tuned frequency
+        let mut block = [0.5f32, 0.0].repeat(BLOCK_BYTES / 2);
+
+        Nco::new(3.125e6, SAMPLE_RATE).mix_in_place(&mut block);
+        write_header(&mut block, 2_400_000_000);
+
+        assert_eq!(parse_header(&block), Some(2_400_000_000));
+        assert_eq!(parse_header(&block[2..]), None);
+
+        let mut segments = Vec::new();
+
+        processor.process(&block[..1000], &mut segments);
+        assert!(segments.is_empty());
+        processor.process(&block[1000..], &mut segments);
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[0].freq_low, segments[0].freq_high), (2_400_000_000, 2_405_000_000));
+        assert_eq!((segments[1].freq_low, segments[1].freq_high), (2_410_000_000, 2_415_000_000));
+        assert_eq!(segments[1].power_db.len(), size / 4);
+
+        let peak = (0..size / 4).max_by(|a, b| segments[1].power_db[*a].partial_cmp(&segments[1].power_db[*b]).unwrap()).unwrap();
+
+        // bin 1 + 8 + 1 of 64 is 3.125 MHz up; it sits at 2410 MHz + (peak + 1) * 312.5 kHz
+        assert_eq!(peak, 1);
+        assert!(segments[1].power_db[peak] > -20.0);
+        assert!(segments[0].power_db.iter().all(|p| *p < -40.0));
+    }
+}
@@ -0,0 +1,523 @@
+//! Burst capture: keep recent samples in memory and only write to disk when something happens
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::formats::{IqWriter, SampleFormat};
+use crate::recorder::{self, DEFAULT_PREFIX};
+use crate::stream::RxSink;
+use crate::tuning::{TuningHandle, TuningState};
+
+/// Pre-trigger length used unless `with_pre_trigger` says otherwise
+pub const DEFAULT_PRE_TRIGGER: usize = 65536;
+/// Post-trigger length used unless `with_post_trigger` says otherwise
+pub const DEFAULT_POST_TRIGGER: u64 = 262144;
+
+/// What fires a capture
+pub enum Trigger {
+    /// The mean power of the last `window` samples rises above `threshold_db` dBFS
+    Power { threshold_db: f32, window: usize },
+    /// A caller supplied test, given each sample's I and Q
+    Predicate(Box<dyn FnMut(f32, f32) -> bool + Send>)
+}
+
+impl Trigger {
+    pub fn power(threshold_db: f32, window: usize) -> Trigger {
+        Trigger::Power { threshold_db, window: window.max(1) }
+    }
+
+    pub fn predicate<F>(predicate: F) -> Trigger
+    where F: FnMut(f32, f32) -> bool + Send + 'static
+    {
+        Trigger::Predicate(Box::new(predicate))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Trigger::Power { threshold_db, window } => format!("power above {} dBFS over {} samples", threshold_db, window),
+            Trigger::Predicate(_) => String::from("predicate")
+        }
+    }
+}
+
+/// Running mean of I² + Q² over a fixed number of samples
+struct PowerDetector {
+    threshold: f64,
+    window: usize,
+    powers: VecDeque<f32>,
+    sum: f64
+}
+
+impl PowerDetector {
+    fn new(threshold_db: f32, window: usize) -> PowerDetector {
+        PowerDetector {
+            threshold: 10f64.powf(threshold_db as f64 / 10.0),
+            window,
+            powers: VecDeque::with_capacity(window),
+            sum: 0.0
+        }
+    }
+
+    /// Adds a sample, returning true once a full window is over the threshold
+    fn push(&mut self, i: f32, q: f32) -> bool {
+        let power = i * i + q * q;
+
+        self.powers.push_back(power);
+        self.sum += power as f64;
+
+        if self.powers.len() > self.window {
+            self.sum -= self.powers.pop_front().unwrap() as f64;
+        }
+
+        self.powers.len() == self.window && self.sum / self.window as f64 > self.threshold
+    }
+
+    fn reset(&mut self) {
+        self.powers.clear();
+        self.sum = 0.0;
+    }
+}
+
+/// A capture written by `TriggeredCapture`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggeredRecording {
+    pub path: PathBuf,
+    pub sidecar: PathBuf,
+    /// Time of the first sample in the file
+    pub start_time: DateTime<Utc>,
+    /// Time of the sample that fired the trigger
+    pub trigger_time: DateTime<Utc>,
+    /// Index in the file of the sample that fired the trigger, i.e. the number of pre-trigger samples
+    pub trigger_offset: u64,
+    pub samples: u64
+}
+
+/// A capture in progress
+struct Capture {
+    writer: IqWriter<BufWriter<File>>,
+    recording: TriggeredRecording,
+    tuning: TuningState,
+    remaining: u64
+}
+
+enum State {
+    Armed,
+    Capturing(Box<Capture>),
+    Holdoff { remaining: u64 }
+}
+
+/// An RX sink that holds the last few samples in a ring buffer and, when the trigger fires, writes them
+/// and the samples that follow to a new file with a JSON sidecar giving the timestamps.
+///
+/// Once a capture is complete nothing more is written until `holdoff` samples have gone by, after which
+/// the trigger is re-armed. Sample times are worked out from the sample rate and the time streaming
+/// started, so they don't drift with callback latency.
+pub struct TriggeredCapture {
+    directory: PathBuf,
+    prefix: String,
+    format: SampleFormat,
+    tuning: TuningHandle,
+    trigger: Trigger,
+    power: Option<PowerDetector>,
+    pre_trigger: usize,
+    post_trigger: u64,
+    holdoff: u64,
+    ring: VecDeque<f32>,
+    state: State,
+    samples_seen: u64,
+    started: Option<DateTime<Utc>>,
+    recordings: Vec<TriggeredRecording>
+}
+
+impl TriggeredCapture {
+    /// Captures into `directory`, naming files from `device`'s tuning
+    pub fn create<P: AsRef<Path>>(directory: P, format: SampleFormat, device: &Device, trigger: Trigger) -> Result<TriggeredCapture, Error> {
+        TriggeredCapture::with_tuning(directory, format, device.tuning_handle(), trigger)
+    }
+
+    /// Captures into `directory`, naming files from `tuning`. The directory is created if needed.
+    pub fn with_tuning<P: AsRef<Path>>(directory: P, format: SampleFormat, tuning: TuningHandle, trigger: Trigger) -> Result<TriggeredCapture, Error> {
+        std::fs::create_dir_all(&directory)?;
+
+        let power = match trigger {
+            Trigger::Power { threshold_db, window } => Some(PowerDetector::new(threshold_db, window.max(1))),
+            Trigger::Predicate(_) => None
+        };
+
+        Ok(TriggeredCapture {
+            directory: directory.as_ref().to_path_buf(),
+            prefix: String::from(DEFAULT_PREFIX),
+            format,
+            tuning,
+            trigger,
+            power,
+            pre_trigger: DEFAULT_PRE_TRIGGER,
+            post_trigger: DEFAULT_POST_TRIGGER,
+            holdoff: 0,
+            ring: VecDeque::new(),
+            state: State::Armed,
+            samples_seen: 0,
+            started: None,
+            recordings: Vec::new()
+        })
+    }
+
+    pub fn with_prefix(mut self, prefix: &str) -> TriggeredCapture {
+        self.prefix = String::from(prefix);
+        self
+    }
+
+    /// Number of samples from before the trigger to include
+    pub fn with_pre_trigger(mut self, samples: usize) -> TriggeredCapture {
+        self.pre_trigger = samples;
+        self
+    }
+
+    /// Number of samples from the trigger on to include
+    pub fn with_post_trigger(mut self, samples: u64) -> TriggeredCapture {
+        self.post_trigger = samples.max(1);
+        self
+    }
+
+    /// Number of samples to ignore after a capture before re-arming
+    pub fn with_holdoff(mut self, samples: u64) -> TriggeredCapture {
+        self.holdoff = samples;
+        self
+    }
+
+    /// Captures completed so far
+    pub fn recordings(&self) -> &[TriggeredRecording] {
+        &self.recordings
+    }
+
+    /// True while waiting for the trigger
+    pub fn is_armed(&self) -> bool {
+        matches!(self.state, State::Armed)
+    }
+
+    /// Wall clock time of sample number `sample` since streaming started
+    fn sample_time(&self, sample: u64, rate: Option<f64>) -> DateTime<Utc> {
+        match (self.started, rate) {
+            (Some(started), Some(rate)) => started + chrono::Duration::nanoseconds((sample as f64 * 1e9 / rate) as i64),
+            _ => Utc::now()
+        }
+    }
+
+    /// Opens a file for a trigger at sample `sample`, writing out the ring buffer
+    fn fire(&mut self, sample: u64) -> Result<(), Error> {
+        let tuning = self.tuning.get();
+        let pre = (self.ring.len() / 2) as u64;
+        let trigger_time = self.sample_time(sample, tuning.sample_rate);
+        let start_time = self.sample_time(sample - pre, tuning.sample_rate);
+        let (path, _) = recorder::timestamped_path(&self.directory, &self.prefix, start_time, tuning.freq_hz, self.format);
+        let mut writer = IqWriter::create_with_format(&path, self.format)?;
+        let (front, back) = self.ring.as_slices();
+
+        writer.write(front)?;
+        writer.write(back)?;
+
+        debug!("Triggered at sample {}, capturing to {:?}", sample, path);
+
+        self.state = State::Capturing(Box::new(Capture {
+            writer,
+            recording: TriggeredRecording {
+                sidecar: recorder::sidecar_path(&path),
+                path,
+                start_time,
+                trigger_time,
+                trigger_offset: pre,
+                samples: pre
+            },
+            tuning,
+            remaining: self.post_trigger
+        }));
+
+        Ok( () )
+    }
+
+    /// Closes the file being captured, if any, and writes its sidecar
+    fn complete(&mut self) -> Result<(), Error> {
+        let state = std::mem::replace(&mut self.state, State::Holdoff { remaining: self.holdoff });
+
+        let Capture { writer, recording, tuning, .. } = match state {
+            State::Capturing(capture) => *capture,
+            other => {
+                self.state = other;
+                return Ok( () );
+            }
+        };
+
+        writer.into_inner()?;
+
+        let sidecar = json!({
+            "file": recording.path.file_name().and_then(|n| n.to_str()),
+            "format": self.format.extension(),
+            "samples": recording.samples,
+            "start": recording.start_time.to_rfc3339_opts(SecondsFormat::Nanos, true),
+            "trigger": recording.trigger_time.to_rfc3339_opts(SecondsFormat::Nanos, true),
+            "trigger_offset": recording.trigger_offset,
+            "trigger_condition": self.trigger.describe(),
+            "center_freq": tuning.freq_hz,
+            "sample_rate": tuning.sample_rate,
+            "lna_gain": tuning.lna_gain,
+            "vga_gain": tuning.vga_gain,
+            "amp_enable": tuning.amp_enable
+        });
+
+        serde_json::to_writer_pretty(File::create(&recording.sidecar)?, &sidecar)
+            .map_err(|e| Error::OTHER(format!("Error writing capture metadata: {}", e)))?;
+
+        self.recordings.push(recording);
+
+        if self.holdoff == 0 {
+            self.rearm();
+        }
+
+        Ok( () )
+    }
+
+    fn rearm(&mut self) {
+        if let Some(ref mut power) = self.power {
+            power.reset();
+        }
+
+        self.state = State::Armed;
+    }
+
+    /// Keeps the last `pre_trigger` samples of `samples` in the ring
+    fn remember(&mut self, samples: &[f32]) {
+        let capacity = self.pre_trigger * 2;
+
+        if samples.len() >= capacity {
+            self.ring.clear();
+            self.ring.extend(&samples[samples.len() - capacity..]);
+        } else {
+            self.ring.extend(samples);
+
+            let excess = self.ring.len().saturating_sub(capacity);
+
+            self.ring.drain(..excess);
+        }
+    }
+
+    /// Tests one sample against the trigger
+    fn test(&mut self, i: f32, q: f32) -> bool {
+        match (&mut self.trigger, &mut self.power) {
+            (Trigger::Predicate(predicate), _) => predicate(i, q),
+            (Trigger::Power { .. }, Some(power)) => power.push(i, q),
+            (Trigger::Power { .. }, None) => false
+        }
+    }
+
+    /// Finishes any capture in progress, truncating it
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.complete()
+    }
+}
+
+impl RxSink for TriggeredCapture {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        if self.started.is_none() {
+            self.started = Some(Utc::now());
+        }
+
+        let pairs = samples.len() / 2;
+        let mut n = 0;
+
+        while n < pairs {
+            let count = match self.state {
+                State::Armed => {
+                    if self.test(samples[n * 2], samples[n * 2 + 1]) {
+                        // the triggering sample is written as the first post-trigger sample
+                        self.fire(self.samples_seen + n as u64)?;
+                        continue;
+                    }
+
+                    1
+                },
+                State::Capturing(ref mut capture) => {
+                    let count = capture.remaining.min((pairs - n) as u64) as usize;
+
+                    capture.writer.write(&samples[n * 2..(n + count) * 2])?;
+                    capture.recording.samples += count as u64;
+                    capture.remaining -= count as u64;
+
+                    if capture.remaining == 0 {
+                        self.remember(&samples[n * 2..(n + count) * 2]);
+                        self.complete()?;
+                        n += count;
+                        continue;
+                    }
+
+                    count
+                },
+                State::Holdoff { ref mut remaining } => {
+                    let count = (*remaining).min((pairs - n) as u64) as usize;
+
+                    *remaining -= count as u64;
+
+                    if *remaining == 0 {
+                        self.remember(&samples[n * 2..(n + count) * 2]);
+                        self.rearm();
+                        n += count;
+                        continue;
+                    }
+
+                    count
+                }
+            };
+
+            self.remember(&samples[n * 2..(n + count) * 2]);
+            n += count;
+        }
+
+        self.samples_seen += pairs as u64;
+
+        Ok( () )
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self.state {
+            State::Capturing(ref mut capture) => capture.writer.flush(),
+            _ => Ok( () )
+        }
+    }
+}
+
+impl Drop for TriggeredCapture {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            error!("Error finishing triggered capture in {:?}: {:?}", self.directory, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs;
+
+    use crate::formats;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = temp_dir().join(format!("hackrf-trigger-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn tuning() -> TuningHandle {
+        let tuning = TuningHandle::new();
+
+        tuning.update(|t| {
+            t.sample_rate = Some(1000.0);
+            t.freq_hz = Some(315_000_000);
+        });
+
+        tuning
+    }
+
+    /// `quiet` samples of silence followed by `loud` samples at -6 dBFS, numbered in I
+    fn burst(quiet: usize, loud: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; quiet * 2];
+
+        for n in 0..loud {
+            samples.push(0.5);
+            samples.push(n as f32 / 128.0);
+        }
+
+        samples
+    }
+
+    fn read(path: &Path) -> Vec<f32> {
+        formats::read_all(File::open(path).unwrap(), SampleFormat::Cf32).unwrap()
+    }
+
+    #[test]
+    fn power_trigger_with_pre_and_post() {
+        let directory = temp_directory("power");
+        let mut capture = TriggeredCapture::with_tuning(&directory, SampleFormat::Cf32, tuning(), Trigger::power(-20.0, 4)).unwrap()
+            .with_pre_trigger(10)
+            .with_post_trigger(20);
+        let samples = burst(50, 20);
+
+        // split awkwardly to cross buffer boundaries
+        capture.write(&samples[..90]).unwrap();
+        capture.write(&samples[90..110]).unwrap();
+        capture.write(&samples[110..]).unwrap();
+
+        assert_eq!(capture.recordings().len(), 1);
+        assert!(capture.is_armed());
+
+        let recording = capture.recordings()[0].clone();
+        let data = read(&recording.path);
+
+        assert_eq!(recording.trigger_offset, 10);
+        assert_eq!(recording.samples, 30);
+        assert_eq!(data, &samples[80..140]);
+        assert_eq!(recording.trigger_time - recording.start_time, chrono::Duration::milliseconds(10));
+
+        let sidecar :serde_json::Value = serde_json::from_slice(&fs::read(&recording.sidecar).unwrap()).unwrap();
+
+        assert_eq!(sidecar["trigger_offset"], 10);
+        assert_eq!(sidecar["center_freq"], 315_000_000);
+
+        drop(capture);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn holdoff_and_rearm() {
+        let directory = temp_directory("holdoff");
+        let mut capture = TriggeredCapture::with_tuning(&directory, SampleFormat::Cf32, tuning(), Trigger::predicate(|i, _| i > 0.9)).unwrap()
+            .with_pre_trigger(2)
+            .with_post_trigger(3)
+            .with_holdoff(10);
+        let mut samples = vec![0.0; 60];
+
+        for &n in &[5, 9, 20] {
+            samples[n * 2] = 1.0;
+            samples[n * 2 + 1] = n as f32 / 128.0;
+        }
+
+        capture.write(&samples).unwrap();
+
+        let recordings = capture.recordings();
+
+        // the second pulse lands in the holdoff after the first capture
+        assert_eq!(recordings.len(), 2);
+        assert_eq!(read(&recordings[0].path), &samples[6..16]);
+        assert_eq!(read(&recordings[1].path), &samples[36..46]);
+        assert!(!capture.is_armed());
+
+        drop(capture);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn short_pre_trigger_and_truncated_capture() {
+        let directory = temp_directory("truncated");
+        let mut capture = TriggeredCapture::with_tuning(&directory, SampleFormat::Cs8, tuning(), Trigger::power(-10.0, 1)).unwrap()
+            .with_pre_trigger(100);
+
+        capture.write(&burst(3, 5)).unwrap();
+        capture.finish().unwrap();
+
+        let recording = &capture.recordings()[0];
+
+        assert_eq!(recording.trigger_offset, 3);
+        assert_eq!(recording.samples, 8);
+        assert_eq!(fs::metadata(&recording.path).unwrap().len(), 16);
+
+        drop(capture);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
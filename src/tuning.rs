@@ -0,0 +1,64 @@
+use std::sync::{Arc, Mutex};
+
+/// The radio settings last successfully applied to a `Device`; `None` until first set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TuningState {
+    pub freq_hz: Option<u64>,
+    pub sample_rate: Option<f64>,
+    pub baseband_filter_hz: Option<u32>,
+    pub lna_gain: Option<u32>,
+    pub vga_gain: Option<u32>,
+    pub txvga_gain: Option<u32>,
+    pub amp_enable: Option<bool>,
+    pub antenna_enable: Option<bool>,
+    /// Incremented on every change, so watchers can cheaply tell something moved
+    pub generation: u64
+}
+
+/// Shared view of a device's `TuningState` that can be handed to streaming callbacks on other threads
+#[derive(Debug, Clone, Default)]
+pub struct TuningHandle {
+    state: Arc<Mutex<TuningState>>
+}
+
+impl TuningHandle {
+    pub fn new() -> TuningHandle {
+        TuningHandle::default()
+    }
+
+    /// A copy of the current state
+    pub fn get(&self) -> TuningState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// The current generation; compare against a saved value to detect changes
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    /// Applies `change` and bumps the generation
+    pub fn update<F: FnOnce(&mut TuningState)>(&self, change: F) {
+        let mut state = self.state.lock().unwrap();
+
+        change(&mut state);
+        state.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_bumps_generation() {
+        let handle = TuningHandle::new();
+        let watcher = handle.clone();
+
+        assert_eq!(watcher.generation(), 0);
+
+        handle.update(|s| s.freq_hz = Some(100_000_000));
+
+        assert_eq!(watcher.generation(), 1);
+        assert_eq!(watcher.get().freq_hz, Some(100_000_000));
+    }
+}
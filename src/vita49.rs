@@ -0,0 +1,657 @@
+//! Streaming RX samples over UDP as [VITA-49](https://www.vita.com/) (VRT) packets.
+//!
+//! Signal data packets carry 16-bit complex cartesian samples (I in the upper half of each word)
+//! with UTC integer and picosecond fractional timestamps. Context packets carry the RF reference
+//! frequency, bandwidth, gain and sample rate, and go out at the start of a stream, whenever the
+//! radio's settings change and periodically so late joiners can pick up the stream.
+
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::stream::RxSink;
+use crate::tuning::{TuningHandle, TuningState};
+
+/// Payload bytes per data packet by default; with the header this fits a 1500 byte Ethernet MTU
+pub const DEFAULT_PAYLOAD_SIZE: usize = 1440;
+
+const TYPE_DATA: u32 = 0x1;
+const TYPE_CONTEXT: u32 = 0x4;
+/// TSI = UTC, TSF = real time (picoseconds)
+const TIMESTAMP_MODE: u32 = (0b01 << 22) | (0b10 << 20);
+/// Words of header, stream ID and timestamps before the payload
+const HEADER_WORDS: usize = 5;
+
+const CIF_CHANGED: u32 = 1 << 31;
+const CIF_BANDWIDTH: u32 = 1 << 29;
+const CIF_RF_FREQ: u32 = 1 << 27;
+const CIF_GAIN: u32 = 1 << 23;
+const CIF_SAMPLE_RATE: u32 = 1 << 21;
+
+/// Gain the HackRF's RF amp adds, as assumed by hackrf_transfer
+const AMP_GAIN_DB: u32 = 14;
+
+/// A VRT timestamp: whole UTC seconds and picoseconds into the second
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub seconds: u32,
+    pub picoseconds: u64
+}
+
+impl Timestamp {
+    pub fn from_datetime(time: &DateTime<Utc>) -> Timestamp {
+        Timestamp {
+            seconds: time.timestamp() as u32,
+            picoseconds: time.timestamp_subsec_nanos() as u64 * 1000
+        }
+    }
+
+    pub fn to_datetime(&self) -> Result<DateTime<Utc>, Error> {
+        Utc.timestamp_opt(self.seconds as i64, (self.picoseconds / 1000) as u32).single()
+            .ok_or_else(|| Error::INVALID_PARAM(format!("Invalid VRT timestamp: {}s {}ps", self.seconds, self.picoseconds)))
+    }
+
+    /// This timestamp plus `samples` at `rate`
+    fn advance(&self, samples: u64, rate: f64) -> Timestamp {
+        let picoseconds = self.picoseconds as u128 + (samples as f64 * 1e12 / rate).round() as u128;
+
+        Timestamp {
+            seconds: self.seconds.wrapping_add((picoseconds / 1_000_000_000_000) as u32),
+            picoseconds: (picoseconds % 1_000_000_000_000) as u64
+        }
+    }
+}
+
+/// The fields of a context packet this crate knows about
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context {
+    pub bandwidth: Option<f64>,
+    pub rf_freq: Option<f64>,
+    /// Stage 1 (LNA, plus the amp when on) and stage 2 (VGA) gain in dB
+    pub gain: Option<(f32, f32)>,
+    pub sample_rate: Option<f64>
+}
+
+impl Context {
+    fn from_tuning(tuning: &TuningState) -> Context {
+        let gain = match (tuning.lna_gain, tuning.vga_gain) {
+            (None, None) => None,
+            (lna, vga) => {
+                let amp = if tuning.amp_enable.unwrap_or(false) { AMP_GAIN_DB } else { 0 };
+
+                Some(((lna.unwrap_or(0) + amp) as f32, vga.unwrap_or(0) as f32))
+            }
+        };
+
+        Context {
+            bandwidth: tuning.baseband_filter_hz.map(|bw| bw as f64),
+            rf_freq: tuning.freq_hz.map(|freq| freq as f64),
+            gain,
+            sample_rate: tuning.sample_rate
+        }
+    }
+}
+
+/// A parsed VRT packet
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Data {
+        stream_id: u32,
+        count: u8,
+        timestamp: Timestamp,
+        /// Interleaved IQ in [-1, 1)
+        samples: Vec<f32>
+    },
+    Context {
+        stream_id: u32,
+        count: u8,
+        timestamp: Timestamp,
+        /// True when something changed since the last context packet
+        changed: bool,
+        context: Context
+    }
+}
+
+fn header(packet_type: u32, count: u8, words: usize) -> u32 {
+    (packet_type << 28) | TIMESTAMP_MODE | (((count & 0xf) as u32) << 16) | words as u32
+}
+
+fn push_prologue(packet: &mut Vec<u8>, word: u32, stream_id: u32, timestamp: &Timestamp) {
+    packet.extend_from_slice(&word.to_be_bytes());
+    packet.extend_from_slice(&stream_id.to_be_bytes());
+    packet.extend_from_slice(&timestamp.seconds.to_be_bytes());
+    packet.extend_from_slice(&timestamp.picoseconds.to_be_bytes());
+}
+
+/// 64-bit fixed point with a 20 bit radix, used for frequencies
+fn to_fixed20(value: f64) -> [u8; 8] {
+    ((value * (1u64 << 20) as f64).round() as i64).to_be_bytes()
+}
+
+fn from_fixed20(bytes: &[u8]) -> f64 {
+    let mut word = [0u8; 8];
+
+    word.copy_from_slice(&bytes[..8]);
+    i64::from_be_bytes(word) as f64 / (1u64 << 20) as f64
+}
+
+/// Builds a signal data packet from interleaved samples
+pub fn encode_data(stream_id: u32, count: u8, timestamp: &Timestamp, samples: &[f32]) -> Vec<u8> {
+    let pairs = samples.len() / 2;
+    let mut packet = Vec::with_capacity((HEADER_WORDS + pairs) * 4);
+
+    push_prologue(&mut packet, header(TYPE_DATA, count, HEADER_WORDS + pairs), stream_id, timestamp);
+
+    for value in &samples[..pairs * 2] {
+        packet.extend_from_slice(&((value * 32768.0).round().clamp(-32768.0, 32767.0) as i16).to_be_bytes());
+    }
+
+    packet
+}
+
+/// Builds a context packet
+pub fn encode_context(stream_id: u32, count: u8, timestamp: &Timestamp, changed: bool, context: &Context) -> Vec<u8> {
+    let mut cif = if changed { CIF_CHANGED } else { 0 };
+    let mut fields = Vec::new();
+
+    if let Some(bandwidth) = context.bandwidth {
+        cif |= CIF_BANDWIDTH;
+        fields.extend_from_slice(&to_fixed20(bandwidth));
+    }
+
+    if let Some(freq) = context.rf_freq {
+        cif |= CIF_RF_FREQ;
+        fields.extend_from_slice(&to_fixed20(freq));
+    }
+
+    if let Some((stage1, stage2)) = context.gain {
+        // two 16-bit fields of dB with a 7 bit radix; stage 2 is the upper half
+        cif |= CIF_GAIN;
+        fields.extend_from_slice(&((stage2 * 128.0).round() as i16).to_be_bytes());
+        fields.extend_from_slice(&((stage1 * 128.0).round() as i16).to_be_bytes());
+    }
+
+    if let Some(rate) = context.sample_rate {
+        cif |= CIF_SAMPLE_RATE;
+        fields.extend_from_slice(&to_fixed20(rate));
+    }
+
+    let mut packet = Vec::with_capacity(HEADER_WORDS * 4 + 4 + fields.len());
+
+    push_prologue(&mut packet, header(TYPE_CONTEXT, count, HEADER_WORDS + 1 + fields.len() / 4), stream_id, timestamp);
+    packet.extend_from_slice(&cif.to_be_bytes());
+    packet.extend_from_slice(&fields);
+
+    packet
+}
+
+/// Parses a packet made by `encode_data` or `encode_context`, or another sender using the same layout
+pub fn parse(packet: &[u8]) -> Result<Packet, Error> {
+    let word = |n: usize| u32::from_be_bytes([packet[n * 4], packet[n * 4 + 1], packet[n * 4 + 2], packet[n * 4 + 3]]);
+
+    if packet.len() < HEADER_WORDS * 4 {
+        return Err(Error::INVALID_PARAM(format!("VRT packet too short: {} bytes", packet.len())));
+    }
+
+    let header = word(0);
+    let words = (header & 0xffff) as usize;
+
+    if words < HEADER_WORDS {
+        return Err(Error::INVALID_PARAM(format!("VRT packet size of {} words is smaller than its header", words)));
+    }
+
+    if words * 4 > packet.len() {
+        return Err(Error::INVALID_PARAM(format!("VRT packet truncated: {} of {} words", packet.len() / 4, words)));
+    }
+
+    if header & (1 << 27) != 0 || (header >> 20) & 0xf != TIMESTAMP_MODE >> 20 {
+        return Err(Error::INVALID_PARAM(format!("Unsupported VRT header {:#010x}", header)));
+    }
+
+    let packet = &packet[..words * 4];
+    let stream_id = word(1);
+    let count = ((header >> 16) & 0xf) as u8;
+    let timestamp = Timestamp { seconds: word(2), picoseconds: ((word(3) as u64) << 32) | word(4) as u64 };
+    let body = &packet[HEADER_WORDS * 4..];
+
+    if timestamp.picoseconds >= 1_000_000_000_000 {
+        return Err(Error::INVALID_PARAM(format!("VRT timestamp has {} picoseconds, a second or more", timestamp.picoseconds)));
+    }
+
+    match header >> 28 {
+        TYPE_DATA => {
+            // a trailer word isn't samples
+            let body = if header & (1 << 26) != 0 && !body.is_empty() { &body[..body.len() - 4] } else { body };
+
+            Ok(Packet::Data {
+                stream_id,
+                count,
+                timestamp,
+                samples: body.chunks_exact(2).map(|v| i16::from_be_bytes([v[0], v[1]]) as f32 * (1.0 / 32768.0)).collect()
+            })
+        },
+        TYPE_CONTEXT => {
+            if body.len() < 4 {
+                return Err(Error::INVALID_PARAM(String::from("VRT context packet has no indicator field")));
+            }
+
+            let cif = word(HEADER_WORDS);
+            let mut fields = &body[4..];
+            let mut context = Context::default();
+            let mut take = |size: usize| -> Result<&[u8], Error> {
+                if fields.len() < size {
+                    return Err(Error::INVALID_PARAM(String::from("VRT context packet truncated")));
+                }
+
+                let (field, rest) = fields.split_at(size);
+
+                fields = rest;
+                Ok(field)
+            };
+
+            // anything outside the fields handled here changes the layout, so can't be skipped
+            if cif & !(CIF_CHANGED | CIF_BANDWIDTH | CIF_RF_FREQ | CIF_GAIN | CIF_SAMPLE_RATE) != 0 {
+                return Err(Error::INVALID_PARAM(format!("Unsupported VRT context fields {:#010x}", cif)));
+            }
+
+            if cif & CIF_BANDWIDTH != 0 {
+                context.bandwidth = Some(from_fixed20(take(8)?));
+            }
+
+            if cif & CIF_RF_FREQ != 0 {
+                context.rf_freq = Some(from_fixed20(take(8)?));
+            }
+
+            if cif & CIF_GAIN != 0 {
+                let gain = take(4)?;
+
+                context.gain = Some((
+                    i16::from_be_bytes([gain[2], gain[3]]) as f32 / 128.0,
+                    i16::from_be_bytes([gain[0], gain[1]]) as f32 / 128.0
+                ));
+            }
+
+            if cif & CIF_SAMPLE_RATE != 0 {
+                context.sample_rate = Some(from_fixed20(take(8)?));
+            }
+
+            Ok(Packet::Context { stream_id, count, timestamp, changed: cif & CIF_CHANGED != 0, context })
+        },
+        other => Err(Error::INVALID_PARAM(format!("Unsupported VRT packet type {}", other)))
+    }
+}
+
+/// An RX sink that sends samples to a UDP address, unicast or multicast, as VRT packets
+pub struct Vita49Sender {
+    socket: UdpSocket,
+    target: SocketAddr,
+    stream_id: u32,
+    payload_size: usize,
+    context_interval: Option<Duration>,
+    tuning: TuningHandle,
+    generation: Option<u64>,
+    data_count: u8,
+    context_count: u8,
+    /// Time of the next sample to be sent
+    timestamp: Option<Timestamp>,
+    rate: f64,
+    since_context: u64,
+    pending: Vec<f32>
+}
+
+impl Vita49Sender {
+    /// Sends to `target`, describing the stream from `device`'s tuning
+    pub fn create<A: ToSocketAddrs>(target: A, device: &Device) -> Result<Vita49Sender, Error> {
+        Vita49Sender::with_tuning(target, device.tuning_handle())
+    }
+
+    /// Sends to `target`, describing the stream from `tuning`
+    pub fn with_tuning<A: ToSocketAddrs>(target: A, tuning: TuningHandle) -> Result<Vita49Sender, Error> {
+        let target = target.to_socket_addrs()?.next()
+            .ok_or_else(|| Error::INVALID_PARAM(String::from("No address to send VITA-49 packets to")))?;
+        let socket = if target.is_ipv4() { UdpSocket::bind("0.0.0.0:0")? } else { UdpSocket::bind("[::]:0")? };
+
+        Ok(Vita49Sender {
+            socket,
+            target,
+            stream_id: 1,
+            payload_size: DEFAULT_PAYLOAD_SIZE,
+            context_interval: Some(Duration::from_secs(1)),
+            tuning,
+            generation: None,
+            data_count: 0,
+            context_count: 0,
+            timestamp: None,
+            rate: 0.0,
+            since_context: 0,
+            pending: Vec::new()
+        })
+    }
+
+    pub fn with_stream_id(mut self, stream_id: u32) -> Vita49Sender {
+        self.stream_id = stream_id;
+        self
+    }
+
+    /// Bytes of samples per data packet, rounded down to whole samples (4 bytes each)
+    pub fn with_payload_size(mut self, bytes: usize) -> Vita49Sender {
+        self.payload_size = (bytes / 4).max(1) * 4;
+        self
+    }
+
+    /// How often to repeat the context packet when nothing has changed; `None` only sends it on changes
+    pub fn with_context_interval(mut self, interval: Option<Duration>) -> Vita49Sender {
+        self.context_interval = interval;
+        self
+    }
+
+    /// Hop limit for multicast packets
+    pub fn with_multicast_ttl(self, ttl: u32) -> Result<Vita49Sender, Error> {
+        self.socket.set_multicast_ttl_v4(ttl)?;
+        Ok(self)
+    }
+
+    /// Whether multicast packets are looped back to receivers on this host
+    pub fn with_multicast_loop(self, enable: bool) -> Result<Vita49Sender, Error> {
+        self.socket.set_multicast_loop_v4(enable)?;
+        Ok(self)
+    }
+
+    fn send_context(&mut self, state: &TuningState, changed: bool) -> Result<(), Error> {
+        let timestamp = self.timestamp.unwrap_or_else(|| Timestamp::from_datetime(&Utc::now()));
+        let packet = encode_context(self.stream_id, self.context_count, &timestamp, changed, &Context::from_tuning(state));
+
+        self.socket.send_to(&packet, self.target)?;
+        self.context_count = self.context_count.wrapping_add(1) & 0xf;
+        self.since_context = 0;
+
+        Ok( () )
+    }
+
+    /// Sends a context packet if the settings changed or it's been a while
+    fn check_context(&mut self) -> Result<(), Error> {
+        let generation = self.tuning.generation();
+
+        if self.generation != Some(generation) {
+            let state = self.tuning.get();
+
+            if state.sample_rate.map(|rate| rate != self.rate).unwrap_or(false) {
+                // start timing afresh at the new rate
+                self.rate = state.sample_rate.unwrap();
+                self.timestamp = None;
+            }
+
+            self.generation = Some(state.generation);
+            return self.send_context(&state, true);
+        }
+
+        let due = match self.context_interval {
+            Some(interval) if self.rate > 0.0 => self.since_context as f64 >= interval.as_secs_f64() * self.rate,
+            _ => false
+        };
+
+        if due {
+            self.send_context(&self.tuning.get(), false)?;
+        }
+
+        Ok( () )
+    }
+}
+
+impl RxSink for Vita49Sender {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        self.check_context()?;
+
+        if self.timestamp.is_none() {
+            self.timestamp = Some(Timestamp::from_datetime(&Utc::now()));
+        }
+
+        let per_packet = self.payload_size / 2;
+        let mut remaining = samples;
+
+        // top up a partial packet left over from the last buffer first
+        if !self.pending.is_empty() {
+            let take = (per_packet - self.pending.len()).min(remaining.len());
+
+            self.pending.extend_from_slice(&remaining[..take]);
+            remaining = &remaining[take..];
+
+            if self.pending.len() < per_packet {
+                return Ok( () );
+            }
+
+            let pending = std::mem::take(&mut self.pending);
+
+            self.send_data(&pending)?;
+        }
+
+        while remaining.len() >= per_packet {
+            self.send_data(&remaining[..per_packet])?;
+            remaining = &remaining[per_packet..];
+        }
+
+        self.pending.extend_from_slice(remaining);
+
+        Ok( () )
+    }
+
+    /// Sends any partial packet
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+
+            self.send_data(&pending)?;
+        }
+
+        Ok( () )
+    }
+}
+
+impl Vita49Sender {
+    fn send_data(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let timestamp = self.timestamp.unwrap_or_else(|| Timestamp::from_datetime(&Utc::now()));
+        let packet = encode_data(self.stream_id, self.data_count, &timestamp, samples);
+
+        self.socket.send_to(&packet, self.target)?;
+        self.data_count = self.data_count.wrapping_add(1) & 0xf;
+
+        let pairs = (samples.len() / 2) as u64;
+
+        self.since_context += pairs;
+
+        if self.rate > 0.0 {
+            self.timestamp = Some(timestamp.advance(pairs, self.rate));
+        }
+
+        Ok( () )
+    }
+}
+
+/// Receives a VITA-49 stream sent by `Vita49Sender`
+pub struct Vita49Receiver {
+    socket: UdpSocket,
+    context: Option<Context>,
+    last_count: Option<u8>,
+    lost: u64,
+    buffer: Vec<u8>
+}
+
+impl Vita49Receiver {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Vita49Receiver, Error> {
+        Ok(Vita49Receiver {
+            socket: UdpSocket::bind(addr)?,
+            context: None,
+            last_count: None,
+            lost: 0,
+            buffer: vec![0; 65536]
+        })
+    }
+
+    /// Joins `group` on the interface with address `interface` (`Ipv4Addr::UNSPECIFIED` for the default)
+    pub fn join_multicast(&self, group: Ipv4Addr, interface: Ipv4Addr) -> Result<(), Error> {
+        self.socket.join_multicast_v4(&group, &interface)?;
+
+        Ok( () )
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// How long `recv` waits before giving up; `None` waits forever
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.socket.set_read_timeout(timeout)?;
+
+        Ok( () )
+    }
+
+    /// The most recent context received
+    pub fn context(&self) -> Option<&Context> {
+        self.context.as_ref()
+    }
+
+    /// Number of data packets missed, judging by gaps in the packet count
+    pub fn lost_packets(&self) -> u64 {
+        self.lost
+    }
+
+    /// Waits for the next packet
+    pub fn recv(&mut self) -> Result<Packet, Error> {
+        let size = self.socket.recv(&mut self.buffer)?;
+        let packet = parse(&self.buffer[..size])?;
+
+        match packet {
+            Packet::Data { count, .. } => {
+                if let Some(last) = self.last_count {
+                    self.lost += ((count.wrapping_sub(last) & 0xf) as u64 + 15) % 16;
+                }
+
+                self.last_count = Some(count);
+            },
+            Packet::Context { ref context, .. } => self.context = Some(context.clone())
+        }
+
+        Ok(packet)
+    }
+
+    /// Waits for the next data packet, keeping track of any context packets on the way,
+    /// and appends its samples to `output`
+    pub fn recv_samples(&mut self, output: &mut Vec<f32>) -> Result<Timestamp, Error> {
+        loop {
+            if let Packet::Data { timestamp, samples, .. } = self.recv()? {
+                output.extend_from_slice(&samples);
+                return Ok(timestamp);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let time = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap() + chrono::Duration::microseconds(999_990);
+        let timestamp = Timestamp::from_datetime(&time);
+
+        assert_eq!(timestamp.picoseconds, 999_990_000_000);
+        assert_eq!(timestamp.to_datetime().unwrap(), time);
+
+        let later = timestamp.advance(100, 10e6);
+
+        assert_eq!(later.seconds, timestamp.seconds + 1);
+        assert_eq!(later.picoseconds, 0);
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let timestamp = Timestamp { seconds: 1_700_000_000, picoseconds: 123_456_789_012 };
+        let samples = [0.5, -0.5, 0.25, -1.0];
+        let packet = encode_data(7, 15, &timestamp, &samples);
+
+        assert_eq!(packet.len(), 28);
+        assert_eq!(&packet[0..4], &[0x10, 0x6f, 0x00, 0x07]);
+        assert_eq!(parse(&packet).unwrap(), Packet::Data { stream_id: 7, count: 15, timestamp, samples: samples.to_vec() });
+
+        let context = Context {
+            bandwidth: Some(1_750_000.0),
+            rf_freq: Some(2_437_000_000.5),
+            gain: Some((30.0, 22.5)),
+            sample_rate: Some(10e6)
+        };
+        let packet = encode_context(7, 3, &timestamp, true, &context);
+
+        assert_eq!(packet.len(), 4 * (5 + 1 + 2 + 2 + 1 + 2));
+        assert_eq!(parse(&packet).unwrap(), Packet::Context { stream_id: 7, count: 3, timestamp, changed: true, context });
+
+        assert!(parse(&packet[..40]).is_err());
+
+        // a size field smaller than the header
+        let mut short = encode_data(7, 0, &timestamp, &[]);
+        short[3] = 1;
+        assert!(parse(&short).is_err());
+
+        let late = Timestamp { seconds: 0, picoseconds: 1_000_000_000_000 };
+        assert!(parse(&encode_data(7, 0, &late, &samples)).is_err());
+    }
+
+    #[test]
+    fn streams_over_loopback() {
+        let mut receiver = Vita49Receiver::bind("127.0.0.1:0").unwrap();
+        let tuning = TuningHandle::new();
+
+        receiver.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        tuning.update(|t| {
+            t.freq_hz = Some(915_000_000);
+            t.sample_rate = Some(8e6);
+            t.lna_gain = Some(16);
+            t.vga_gain = Some(20);
+            t.amp_enable = Some(true);
+        });
+
+        let mut sender = Vita49Sender::with_tuning(receiver.local_addr().unwrap(), tuning.clone()).unwrap()
+            .with_stream_id(42)
+            .with_payload_size(400)
+            .with_context_interval(None);
+        let samples = (0..500).map(|n| (n % 256) as f32 / 256.0 - 0.5).collect::<Vec<_>>();
+
+        sender.write(&samples[..300]).unwrap();
+        sender.write(&samples[300..]).unwrap();
+        sender.flush().unwrap();
+
+        match receiver.recv().unwrap() {
+            Packet::Context { stream_id, changed, context, .. } => {
+                assert_eq!(stream_id, 42);
+                assert!(changed);
+                assert_eq!(context.rf_freq, Some(915e6));
+                assert_eq!(context.gain, Some((30.0, 20.0)));
+                assert_eq!(context.bandwidth, None);
+            },
+            other => panic!("expected context first, got {:?}", other)
+        }
+
+        let mut received = Vec::new();
+        let first = receiver.recv_samples(&mut received).unwrap();
+
+        assert_eq!(received.len(), 200);
+
+        let second = receiver.recv_samples(&mut received).unwrap();
+
+        // 100 samples at 8 MS/s
+        assert_eq!(second, first.advance(100, 8e6));
+
+        receiver.recv_samples(&mut received).unwrap();
+        assert_eq!(received, samples);
+
+        tuning.update(|t| t.freq_hz = Some(433_920_000));
+        sender.write(&samples[..200]).unwrap();
+
+        assert_eq!(receiver.recv_samples(&mut Vec::new()).unwrap(), second.advance(150, 8e6));
+        assert_eq!(receiver.context().unwrap().rf_freq, Some(433_920_000.0));
+        assert_eq!(receiver.lost_packets(), 0);
+    }
+}
@@ -0,0 +1,557 @@
+//! A live spectrum and waterfall for web browsers.
+//!
+//! `GET /` serves a self-contained page (`static/waterfall.html`) that opens a WebSocket to
+//! `/ws`. Over it the server pushes averaged spectra as binary `SpectrumFrame`s and JSON `status`
+//! messages, and takes JSON objects that change what's shown:
+//!
+//! * `fft_size`, `averaging`, `frame_rate` for the analysis;
+//! * `mode`, `"rx"` or `"sweep"`, and `sweep`, `{"ranges": [[start, stop], ...], "bin_width": hz}`
+//!   with ranges in MHz, which also switches to sweep mode;
+//! * anything `radio::apply_settings` takes, e.g. `freq` or `lna_gain`.
+//!
+//! Everyone watching shares the one radio, so a change made by one viewer is seen by all.
+//! Streaming runs only while someone is connected.
+
+use std::collections::HashMap;
+use std::io::{self, BufReader};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+use crate::error::Error;
+use crate::http::{Request, Response};
+use crate::radio::{self, Radio};
+use crate::spectrum::{RxSpectrum, SpectrumFrame, SweepSpectrum, MAX_FFT_SIZE, MIN_FFT_SIZE};
+use crate::stream::RxSink;
+use crate::sweep::SweepPlan;
+use crate::websocket::{self, Message, MessageReader};
+
+const PAGE: &str = include_str!("../static/waterfall.html");
+
+/// Messages queued for a viewer before frames are dropped for them
+const CLIENT_QUEUE: usize = 16;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Work for the thread that owns the radio
+enum Job {
+    Join(u64, SyncSender<Message>, TcpStream),
+    Command(u64, String),
+    Leave(u64),
+    Frame(SpectrumFrame)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Rx,
+    Sweep
+}
+
+/// What's being computed, shared by every viewer
+#[derive(Clone)]
+struct View {
+    mode: Mode,
+    fft_size: usize,
+    averaging: usize,
+    frame_rate: f64,
+    sweep: SweepPlan
+}
+
+impl View {
+    fn status<R: Radio>(&self, radio: &R) -> Message {
+        let (start, stop) = self.sweep.span();
+
+        Message::Text(json!({
+            "type": "status",
+            "mode": if self.mode == Mode::Rx { "rx" } else { "sweep" },
+            "fft_size": self.fft_size,
+            "averaging": self.averaging,
+            "frame_rate": self.frame_rate,
+            "sweep": {
+                "ranges": self.sweep.ranges(),
+                "bin_width": self.sweep.bin_width(),
+                "start": start,
+                "stop": stop
+            },
+            "settings": radio::settings_json(radio)
+        }).to_string())
+    }
+
+    /// Applies a viewer's request, returning whether streaming needs restarting. Nothing in the
+    /// view changes unless the whole request is accepted.
+    fn apply<R: Radio>(&mut self, radio: &R, request: &str) -> Result<bool, Error> {
+        let request :Value = serde_json::from_str(request)
+            .map_err(|e| Error::INVALID_PARAM(format!("Request is not JSON: {}", e)))?;
+        let mut settings :Map<String, Value> = match request {
+            Value::Object(settings) => settings,
+            _ => return Err(Error::INVALID_PARAM(String::from("Request must be a JSON object")))
+        };
+        let mut next = self.clone();
+        let mut restart = false;
+
+        if let Some(value) = settings.remove("fft_size") {
+            let size = value.as_u64().unwrap_or(0) as usize;
+
+            if !size.is_power_of_two() || !(MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&size) {
+                return Err(Error::INVALID_PARAM(format!("fft_size must be a power of two from {} to {}", MIN_FFT_SIZE, MAX_FFT_SIZE)));
+            }
+
+            next.fft_size = size;
+            restart = true;
+        }
+
+        if let Some(value) = settings.remove("averaging") {
+            next.averaging = value.as_u64().filter(|v| *v >= 1)
+                .ok_or_else(|| Error::INVALID_PARAM(String::from("averaging must be at least 1")))? as usize;
+            restart = true;
+        }
+
+        if let Some(value) = settings.remove("frame_rate") {
+            next.frame_rate = value.as_f64().filter(|v| *v >= 0.0)
+                .ok_or_else(|| Error::INVALID_PARAM(String::from("frame_rate must be a number of at least 0")))?;
+            restart = true;
+        }
+
+        if let Some(value) = settings.remove("sweep") {
+            next.sweep = parse_sweep(&value, &self.sweep)?;
+            next.mode = Mode::Sweep;
+            restart = true;
+        }
+
+        if let Some(value) = settings.remove("mode") {
+            next.mode = match value.as_str() {
+                Some("rx") => Mode::Rx,
+                Some("sweep") => Mode::Sweep,
+                _ => return Err(Error::INVALID_PARAM(String::from("mode must be \"rx\" or \"sweep\"")))
+            };
+            restart = true;
+        }
+
+        radio::apply_settings(radio, &settings)?;
+        *self = next;
+
+        Ok(restart)
+    }
+
+    fn start<R: Radio>(&self, radio: &mut R, queue: &Sender<Job>) -> Result<(), Error> {
+        let queue = queue.clone();
+        let output = move |frame| queue.send(Job::Frame(frame))
+            .map_err(|_| Error::STREAMING_EXIT_CALLED(String::from("Waterfall server has shut down")));
+
+        match self.mode {
+            Mode::Rx => {
+                let sink = RxSpectrum::new(self.fft_size, self.averaging, radio.tuning_handle(), output)?
+                    .with_frame_rate(self.frame_rate);
+
+                radio.start_rx_sink(Box::new(sink))
+            },
+            Mode::Sweep => {
+                let sink :Box<dyn RxSink + Send> = Box::new(SweepSpectrum::new(&self.sweep, self.averaging, output)?);
+
+                self.sweep.start(radio, sink)
+            }
+        }
+    }
+}
+
+fn parse_sweep(value: &Value, current: &SweepPlan) -> Result<SweepPlan, Error> {
+    let bad = || Error::INVALID_PARAM(String::from("sweep must be {\"ranges\": [[start, stop], ...], \"bin_width\": hz} with ranges in MHz"));
+    let ranges = match value.get("ranges") {
+        Some(Value::Array(ranges)) => ranges.iter()
+            .map(|range| match (range.get(0).and_then(Value::as_u64), range.get(1).and_then(Value::as_u64)) {
+                (Some(start), Some(stop)) if stop <= u16::MAX as u64 => Ok((start as u16, stop as u16)),
+                _ => Err(bad())
+            })
+            .collect::<Result<Vec<_>, Error>>()?,
+        Some(_) => return Err(bad()),
+        None => current.ranges().to_vec()
+    };
+    let bin_width = match value.get("bin_width") {
+        Some(width) => width.as_f64().ok_or_else(bad)?,
+        None => current.bin_width()
+    };
+
+    SweepPlan::new(&ranges)?.with_bin_width(bin_width)
+}
+
+pub struct WaterfallServer {
+    listener: TcpListener,
+    shutdown: Arc<AtomicBool>,
+    fft_size: usize,
+    averaging: usize,
+    frame_rate: f64,
+    sweep: Option<SweepPlan>
+}
+
+impl WaterfallServer {
+    /// Listens on `addr`; viewers start with 2048-bin RX spectra averaged over 4 FFTs, 25 a second
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<WaterfallServer, Error> {
+        let listener = TcpListener::bind(addr)?;
+
+        listener.set_nonblocking(true)?;
+
+        Ok(WaterfallServer {
+            listener,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            fft_size: 2048,
+            averaging: 4,
+            frame_rate: 25.0,
+            sweep: None
+        })
+    }
+
+    pub fn with_fft_size(mut self, fft_size: usize) -> Result<WaterfallServer, Error> {
+        if !fft_size.is_power_of_two() || !(MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&fft_size) {
+            return Err(Error::INVALID_PARAM(format!("FFT size must be a power of two from {} to {}", MIN_FFT_SIZE, MAX_FFT_SIZE)));
+        }
+
+        self.fft_size = fft_size;
+        Ok(self)
+    }
+
+    /// FFTs averaged per frame in RX mode, or whole sweeps in sweep mode
+    pub fn with_averaging(mut self, averaging: usize) -> WaterfallServer {
+        self.averaging = averaging.max(1);
+        self
+    }
+
+    /// Most RX frames per second; 0 for no limit
+    pub fn with_frame_rate(mut self, frame_rate: f64) -> WaterfallServer {
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    /// Starts in sweep mode with `plan`
+    pub fn with_sweep(mut self, plan: SweepPlan) -> WaterfallServer {
+        self.sweep = Some(plan);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// A flag that makes `serve` return, disconnecting everyone, when set
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+
+    /// Serves viewers until shut down. Set the radio's frequency, rate and gains first; viewers
+    /// can change them afterwards. Every call on `radio` happens on this thread.
+    pub fn serve<R: Radio>(&self, radio: &mut R) -> Result<(), Error> {
+        let mut view = View {
+            mode: if self.sweep.is_some() { Mode::Sweep } else { Mode::Rx },
+            fft_size: self.fft_size,
+            averaging: self.averaging,
+            frame_rate: self.frame_rate,
+            sweep: match self.sweep {
+                Some(ref plan) => plan.clone(),
+                None => SweepPlan::new(&[(2400, 2500)])?
+            }
+        };
+        let (queue, jobs) = mpsc::channel();
+        let mut clients :HashMap<u64, (SyncSender<Message>, TcpStream)> = HashMap::new();
+        let mut next_id = 0;
+        let mut streaming = false;
+
+        while !self.shutdown.load(Ordering::SeqCst) {
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, peer)) => {
+                        debug!("Waterfall client connected from {}", peer);
+                        next_id += 1;
+                        spawn_connection(next_id, stream, queue.clone());
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        warn!("Error accepting waterfall client: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            let restart = match jobs.recv_timeout(Duration::from_millis(50)) {
+                Ok(Job::Join(id, client, stream)) => {
+                    let _ = client.try_send(view.status(radio));
+                    clients.insert(id, (client, stream));
+                    false
+                },
+                Ok(Job::Leave(id)) => {
+                    clients.remove(&id);
+                    false
+                },
+                Ok(Job::Command(id, request)) => match view.apply(radio, &request) {
+                    Ok(restart) => {
+                        let status = view.status(radio);
+
+                        clients.values().for_each(|(client, _)| { let _ = client.try_send(status.clone()); });
+                        restart
+                    },
+                    Err(e) => {
+                        if let Some((client, _)) = clients.get(&id) {
+                            let _ = client.try_send(Message::Text(json!({ "type": "error", "message": e.to_string() }).to_string()));
+                        }
+
+                        false
+                    }
+                },
+                Ok(Job::Frame(frame)) => {
+                    let message = Message::Binary(frame.encode());
+
+                    clients.retain(|_, (client, _)| match client.try_send(message.clone()) {
+                        Ok( () ) | Err(TrySendError::Full(_)) => true,
+                        Err(TrySendError::Disconnected(_)) => false
+                    });
+                    false
+                },
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => false
+            };
+
+            if streaming && (restart || clients.is_empty()) {
+                radio.stop_rx()?;
+                streaming = false;
+            }
+
+            if !streaming && !clients.is_empty() {
+                if let Err(e) = view.start(radio, &queue) {
+                    error!("Error starting waterfall stream: {}", e);
+
+                    let message = Message::Text(json!({ "type": "error", "message": e.to_string() }).to_string());
+
+                    clients.values().for_each(|(client, _)| { let _ = client.try_send(message.clone()); });
+                    // fall back to something that should work rather than retrying forever
+                    view.mode = Mode::Rx;
+                    view.start(radio, &queue)?;
+                }
+
+                streaming = true;
+            }
+        }
+
+        if streaming {
+            radio.stop_rx()?;
+        }
+
+        for (_, stream) in clients.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+
+        Ok( () )
+    }
+}
+
+fn spawn_connection(id: u64, stream: TcpStream, queue: Sender<Job>) {
+    thread::spawn(move || {
+        if let Err(e) = connection(id, stream, &queue) {
+            debug!("Waterfall connection ended: {}", e);
+        }
+
+        let _ = queue.send(Job::Leave(id));
+    });
+}
+
+fn connection(id: u64, mut stream: TcpStream, queue: &Sender<Job>) -> Result<(), Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = match Request::read(&mut reader) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok( () ),
+        Err(e) => return Response::from_error(&e).write_to(&mut stream)
+    };
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/ws") if websocket::is_upgrade(&request) => {},
+        ("GET", "/") | ("GET", "/index.html") => {
+            return Response::new(200, "text/html; charset=utf-8", PAGE.as_bytes().to_vec()).write_to(&mut stream);
+        },
+        ("GET", _) => return Response::error(404, &format!("No such page {}", request.path)).write_to(&mut stream),
+        _ => return Response::error(405, "Only GET is supported").write_to(&mut stream)
+    }
+
+    websocket::accept(&request, &mut stream)?;
+    stream.set_read_timeout(None)?;
+
+    let (client, outgoing) = mpsc::sync_channel(CLIENT_QUEUE);
+    let mut writer = stream.try_clone()?;
+
+    thread::spawn(move || {
+        for message in outgoing.iter() {
+            if websocket::write_message(&mut writer, &message, None).is_err() || message == Message::Close {
+                break;
+            }
+        }
+    });
+
+    queue.send(Job::Join(id, client.clone(), stream.try_clone()?))
+        .map_err(|_| Error::OTHER(String::from("Waterfall server has shut down")))?;
+
+    // anything buffered past the handshake belongs to the first frame
+    let mut messages = MessageReader::new(reader);
+
+    loop {
+        match messages.read()? {
+            Message::Text(request) => {
+                if queue.send(Job::Command(id, request)).is_err() {
+                    return Ok( () );
+                }
+            },
+            Message::Ping(data) => {
+                let _ = client.send(Message::Pong(data));
+            },
+            Message::Close => {
+                let _ = client.send(Message::Close);
+                return Ok( () );
+            },
+            Message::Binary(_) | Message::Pong(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedDevice;
+    use crate::spectrum::FrameSource;
+    use std::io::{BufRead, Read, Write};
+    use std::time::Instant;
+
+    struct Viewer {
+        stream: TcpStream,
+        messages: MessageReader<BufReader<TcpStream>>
+    }
+
+    impl Viewer {
+        fn connect(addr: SocketAddr) -> Viewer {
+            let mut stream = TcpStream::connect(addr).unwrap();
+
+            stream.write_all(b"GET /ws HTTP/1.1\r\nHost: test\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                               Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+
+            reader.read_line(&mut line).unwrap();
+            assert!(line.starts_with("HTTP/1.1 101"), "{}", line);
+
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                assert!(!line.starts_with("Sec-WebSocket-Accept") || line.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+            }
+
+            Viewer { stream, messages: MessageReader::new(reader) }
+        }
+
+        fn send(&mut self, request: Value) {
+            websocket::write_message(&mut self.stream, &Message::Text(request.to_string()), Some([1, 2, 3, 4])).unwrap();
+        }
+
+        /// Reads until `wanted` picks something out of a message
+        fn wait_for<T, F: FnMut(&Message) -> Option<T>>(&mut self, mut wanted: F) -> T {
+            let start = Instant::now();
+
+            loop {
+                assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+
+                if let Some(found) = wanted(&self.messages.read().unwrap()) {
+                    return found;
+                }
+            }
+        }
+
+        fn frame(&mut self, bins: usize) -> SpectrumFrame {
+            self.wait_for(|message| match message {
+                Message::Binary(data) => SpectrumFrame::decode(data).ok().filter(|frame| frame.power_db.len() == bins),
+                _ => None
+            })
+        }
+
+        fn text(&mut self, kind: &str) -> Value {
+            self.wait_for(|message| match message {
+                Message::Text(text) => serde_json::from_str::<Value>(text).ok().filter(|value| value["type"] == kind),
+                _ => None
+            })
+        }
+    }
+
+    fn peak(frame: &SpectrumFrame) -> f64 {
+        let index = (0..frame.power_db.len())
+            .max_by(|a, b| frame.power_db[*a].partial_cmp(&frame.power_db[*b]).unwrap())
+            .unwrap();
+
+        frame.bin_freq(index)
+    }
+
+    #[test]
+    fn streams_spectra_over_websocket() {
+        let server = WaterfallServer::bind("127.0.0.1:0").unwrap().with_fft_size(1024).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let mut sim = SimulatedDevice::new().with_tone(101_000_000, 0.5).with_tone(2_432_000_000, 0.5);
+
+        sim.set_freq(100_000_000).unwrap();
+        sim.set_sample_rate(10e6).unwrap();
+
+        let client = thread::spawn(move || {
+            let mut page = String::new();
+            let mut stream = TcpStream::connect(addr).unwrap();
+
+            stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+            stream.read_to_string(&mut page).unwrap();
+            assert!(page.starts_with("HTTP/1.1 200") && page.contains("new WebSocket"));
+
+            let mut viewer = Viewer::connect(addr);
+
+            assert_eq!(viewer.text("status")["fft_size"], 1024);
+
+            let frame = viewer.frame(1024);
+
+            assert_eq!(frame.source, FrameSource::Rx);
+            assert_eq!((frame.freq_start, frame.freq_stop), (95e6, 105e6));
+            assert!((peak(&frame) - 101e6).abs() < 20e3, "{}", peak(&frame));
+
+            viewer.send(json!({ "fft_size": 256, "averaging": 2, "freq": 100_500_000 }));
+
+            let status = viewer.text("status");
+
+            assert_eq!(status["settings"]["freq"], 100_500_000);
+            assert!((peak(&viewer.frame(256)) - 101e6).abs() < 50e3);
+
+            viewer.send(json!({ "fft_size": 100 }));
+            assert!(viewer.text("error")["message"].as_str().unwrap().contains("fft_size"));
+
+            // a request with a bad key changes nothing
+            viewer.send(json!({ "fft_size": 512, "volume": 11 }));
+            assert!(viewer.text("error")["message"].as_str().unwrap().contains("volume"));
+            viewer.send(json!({ "averaging": 2 }));
+            assert_eq!(viewer.text("status")["fft_size"], 256);
+
+            viewer.send(json!({ "sweep": { "ranges": [[2400, 2440]], "bin_width": 312_500 } }));
+
+            let frame = viewer.wait_for(|message| match message {
+                Message::Binary(data) => SpectrumFrame::decode(data).ok().filter(|frame| frame.source == FrameSource::Sweep),
+                _ => None
+            });
+
+            assert_eq!((frame.freq_start, frame.freq_stop, frame.power_db.len()), (2.4e9, 2.44e9, 128));
+            assert!((peak(&frame) - 2.432e9).abs() < 1e6, "{}", peak(&frame));
+
+            websocket::write_message(&mut viewer.stream, &Message::Close, Some([0; 4])).unwrap();
+            viewer.wait_for(|message| if *message == Message::Close { Some(()) } else { None });
+
+            shutdown.store(true, Ordering::SeqCst);
+        });
+
+        server.serve(&mut sim).unwrap();
+        client.join().unwrap();
+
+        assert!(!sim.is_streaming().unwrap());
+    }
+}
@@ -0,0 +1,621 @@
+//! Stereo WAV IQ recordings in the style of SDR# and HDSDR, including the `auxi` chunk those
+//! programs use for the center frequency and recording times. Files that outgrow the 4 GB RIFF
+//! limit are turned into [RF64](https://tech.ebu.ch/docs/tech/tech3306v1_1.pdf) when finished.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc};
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::formats::SampleFormat;
+use crate::stream::{RxSink, TxSource};
+use crate::tuning::TuningHandle;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Size of the `ds64` chunk body, reserved up front as `JUNK` so a file can become RF64 in place
+const DS64_SIZE: u32 = 28;
+const FMT_SIZE: u32 = 16;
+/// Size of the `auxi` chunk body as written by SDR# and HDSDR
+const AUXI_SIZE: u32 = 68;
+
+/// Byte offset of the sample data: RIFF header, JUNK/ds64, fmt, auxi and the data chunk header
+const DATA_OFFSET: u64 = 12 + (8 + DS64_SIZE as u64) + (8 + FMT_SIZE as u64) + (8 + AUXI_SIZE as u64) + 8;
+
+/// Largest data chunk that can still be described by plain RIFF's 32-bit sizes
+const RIFF_LIMIT: u64 = u32::MAX as u64 - DATA_OFFSET;
+
+/// Largest `ds64`, `fmt ` or `auxi` chunk read; bigger ones are corrupt and skipped like unknown chunks
+const MAX_METADATA_CHUNK: u64 = 64 * 1024;
+
+/// The contents of an `auxi` chunk.
+///
+/// The chunk's `CenterFreq` is only 32 bits, which doesn't cover the HackRF's range, so the full
+/// frequency is also stored in the otherwise unused fields that follow it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Auxi {
+    pub start_time: DateTime<Utc>,
+    pub stop_time: DateTime<Utc>,
+    pub center_freq: u64,
+    pub sample_rate: u32,
+    pub bandwidth: u32
+}
+
+impl Auxi {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(AUXI_SIZE as usize);
+
+        write_systemtime(&mut bytes, &self.start_time);
+        write_systemtime(&mut bytes, &self.stop_time);
+
+        bytes.extend_from_slice(&(self.center_freq.min(u32::MAX as u64) as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes()); // IFFrequency
+        bytes.extend_from_slice(&self.bandwidth.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes()); // IQOffset
+        bytes.extend_from_slice(&(self.center_freq as u32).to_le_bytes());
+        bytes.extend_from_slice(&((self.center_freq >> 32) as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Auxi, Error> {
+        if bytes.len() < 60 {
+            return Err(Error::INVALID_PARAM(format!("auxi chunk is too short: {} bytes", bytes.len())));
+        }
+
+        let field = |n: usize| u32::from_le_bytes([bytes[32 + n * 4], bytes[33 + n * 4], bytes[34 + n * 4], bytes[35 + n * 4]]);
+
+        // prefer the full 64-bit frequency when a HackRF recording left one behind
+        let center_freq = match (field(5) as u64) | ((field(6) as u64) << 32) {
+            0 => field(0) as u64,
+            freq => freq
+        };
+
+        Ok(Auxi {
+            start_time: read_systemtime(&bytes[0..16]),
+            stop_time: read_systemtime(&bytes[16..32]),
+            center_freq,
+            sample_rate: field(1),
+            bandwidth: field(3)
+        })
+    }
+}
+
+/// Appends a Win32 `SYSTEMTIME`
+fn write_systemtime(bytes: &mut Vec<u8>, time: &DateTime<Utc>) {
+    let fields = [
+        time.year() as u16,
+        time.month() as u16,
+        time.weekday().num_days_from_sunday() as u16,
+        time.day() as u16,
+        time.hour() as u16,
+        time.minute() as u16,
+        time.second() as u16,
+        (time.nanosecond() / 1_000_000).min(999) as u16
+    ];
+
+    for field in &fields {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+/// Parses a Win32 `SYSTEMTIME`, falling back to the epoch for zeroed or invalid values
+fn read_systemtime(bytes: &[u8]) -> DateTime<Utc> {
+    let field = |n: usize| u16::from_le_bytes([bytes[n * 2], bytes[n * 2 + 1]]) as u32;
+
+    Utc.with_ymd_and_hms(field(0) as i32, field(1), field(3), field(4), field(5), field(6))
+        .single()
+        .map(|t| t + chrono::Duration::milliseconds(field(7) as i64))
+        .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap())
+}
+
+/// The `fmt ` chunk for stereo IQ in `format`
+fn format_tag(format: SampleFormat) -> Result<(u16, u16), Error> {
+    match format {
+        SampleFormat::Cu8 => Ok((WAVE_FORMAT_PCM, 8)),
+        SampleFormat::Cs16 => Ok((WAVE_FORMAT_PCM, 16)),
+        SampleFormat::Cf32 => Ok((WAVE_FORMAT_IEEE_FLOAT, 32)),
+        SampleFormat::Cs8 => Err(Error::INVALID_PARAM(String::from("WAV has no signed 8-bit format; use cu8")))
+    }
+}
+
+/// Records RX samples to a stereo WAV file with I on the left channel and Q on the right
+pub struct WavWriter {
+    data: BufWriter<File>,
+    path: PathBuf,
+    format: SampleFormat,
+    tuning: TuningHandle,
+    generation: u64,
+    auxi: Auxi,
+    samples_written: u64,
+    riff_limit: u64,
+    scratch: Vec<u8>,
+    finished: bool
+}
+
+impl WavWriter {
+    /// Creates a recording that takes its `auxi` fields from `device`'s tuning
+    pub fn create<P: AsRef<Path>>(path: P, format: SampleFormat, device: &Device) -> Result<WavWriter, Error> {
+        WavWriter::with_tuning(path, format, device.tuning_handle())
+    }
+
+    /// Creates a recording that takes its `auxi` fields from `tuning`; the sample rate must already be set
+    pub fn with_tuning<P: AsRef<Path>>(path: P, format: SampleFormat, tuning: TuningHandle) -> Result<WavWriter, Error> {
+        format_tag(format)?;
+
+        let state = tuning.get();
+        let sample_rate = state.sample_rate
+            .ok_or_else(|| Error::INVALID_PARAM(String::from("sample rate must be set before recording")))?;
+        let now = Utc::now();
+
+        debug!("Recording {:?} WAV to {:?}", format, path.as_ref());
+
+        let mut writer = WavWriter {
+            data: BufWriter::new(File::create(&path)?),
+            path: path.as_ref().to_path_buf(),
+            format,
+            generation: state.generation,
+            auxi: Auxi {
+                start_time: now,
+                stop_time: now,
+                center_freq: state.freq_hz.unwrap_or(0),
+                sample_rate: sample_rate.round() as u32,
+                bandwidth: state.baseband_filter_hz.unwrap_or(0)
+            },
+            tuning,
+            samples_written: 0,
+            riff_limit: RIFF_LIMIT,
+            scratch: Vec::new(),
+            finished: false
+        };
+
+        writer.write_header()?;
+
+        Ok(writer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of complex samples written so far
+    pub fn samples_written(&self) -> u64 {
+        self.samples_written
+    }
+
+    fn data_size(&self) -> u64 {
+        self.samples_written * self.format.sample_size() as u64
+    }
+
+    /// Writes the headers for the current state at the start of the file
+    fn write_header(&mut self) -> Result<(), Error> {
+        let (tag, bits) = format_tag(self.format)?;
+        let data_size = self.data_size();
+        let rf64 = data_size > self.riff_limit;
+        let block_align = self.format.sample_size() as u16;
+        let mut header = Vec::with_capacity(DATA_OFFSET as usize);
+
+        if rf64 {
+            header.extend_from_slice(b"RF64");
+            header.extend_from_slice(&u32::MAX.to_le_bytes());
+            header.extend_from_slice(b"WAVE");
+            header.extend_from_slice(b"ds64");
+            header.extend_from_slice(&DS64_SIZE.to_le_bytes());
+            header.extend_from_slice(&(DATA_OFFSET - 8 + data_size).to_le_bytes());
+            header.extend_from_slice(&data_size.to_le_bytes());
+            header.extend_from_slice(&self.samples_written.to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes());
+        } else {
+            header.extend_from_slice(b"RIFF");
+            header.extend_from_slice(&((DATA_OFFSET - 8 + data_size) as u32).to_le_bytes());
+            header.extend_from_slice(b"WAVE");
+            header.extend_from_slice(b"JUNK");
+            header.extend_from_slice(&DS64_SIZE.to_le_bytes());
+            header.extend_from_slice(&[0; DS64_SIZE as usize]);
+        }
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&FMT_SIZE.to_le_bytes());
+        header.extend_from_slice(&tag.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&self.auxi.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(self.auxi.sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bits.to_le_bytes());
+
+        header.extend_from_slice(b"auxi");
+        header.extend_from_slice(&AUXI_SIZE.to_le_bytes());
+        header.extend_from_slice(&self.auxi.to_bytes());
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&(if rf64 { u32::MAX } else { data_size as u32 }).to_le_bytes());
+
+        self.data.seek(SeekFrom::Start(0))?;
+        self.data.write_all(&header)?;
+        self.data.seek(SeekFrom::Start(DATA_OFFSET + data_size))?;
+
+        Ok( () )
+    }
+
+    fn check_tuning(&mut self) {
+        let generation = self.tuning.generation();
+
+        if generation == self.generation {
+            return;
+        }
+
+        let state = self.tuning.get();
+
+        self.generation = state.generation;
+
+        if state.freq_hz.map(|freq| freq != self.auxi.center_freq).unwrap_or(false) {
+            warn!("Retuned to {:?} Hz mid-recording; WAV only records {} Hz", state.freq_hz, self.auxi.center_freq);
+        }
+    }
+
+    /// Writes interleaved samples in the HackRF's native signed 8-bit format
+    pub fn write_i8(&mut self, samples: &[i8]) -> Result<(), Error> {
+        self.check_tuning();
+        self.scratch.clear();
+        self.format.encode_i8(samples, &mut self.scratch);
+        self.write_scratch(samples.len())
+    }
+
+    fn write_scratch(&mut self, values: usize) -> Result<(), Error> {
+        self.data.write_all(&self.scratch)?;
+        self.samples_written += values as u64 / 2;
+
+        Ok( () )
+    }
+
+    /// Fills in the chunk sizes and stop time; the file is not valid until this is called
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.auxi.stop_time = Utc::now();
+        self.write_header()?;
+        self.data.flush()?;
+        self.finished = true;
+
+        Ok( () )
+    }
+}
+
+impl RxSink for WavWriter {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        self.check_tuning();
+        self.scratch.clear();
+        self.format.encode(samples, &mut self.scratch);
+        self.write_scratch(samples.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.data.flush()?;
+
+        Ok( () )
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(e) = self.finish() {
+                error!("Error finishing WAV recording {:?}: {:?}", self.path, e);
+            }
+        }
+    }
+}
+
+/// Plays a stereo WAV IQ recording out through `Device::start_tx_source`
+pub struct WavReader {
+    data: BufReader<File>,
+    format: SampleFormat,
+    sample_rate: u32,
+    auxi: Option<Auxi>,
+    data_offset: u64,
+    total: u64,
+    position: u64,
+    looping: bool,
+    scratch: Vec<u8>
+}
+
+impl WavReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<WavReader, Error> {
+        let mut data = BufReader::new(File::open(&path)?);
+        let mut header = [0u8; 12];
+
+        data.read_exact(&mut header)?;
+
+        let rf64 = match &header[0..4] {
+            b"RIFF" => false,
+            b"RF64" | b"BW64" => true,
+            _ => return Err(Error::INVALID_PARAM(format!("{:?} is not a WAV file", path.as_ref())))
+        };
+
+        if &header[8..12] != b"WAVE" {
+            return Err(Error::INVALID_PARAM(format!("{:?} is not a WAV file", path.as_ref())));
+        }
+
+        let mut format = None;
+        let mut sample_rate = 0;
+        let mut auxi = None;
+        let mut ds64_data_size = None;
+
+        loop {
+            let mut chunk = [0u8; 8];
+
+            match data.read_exact(&mut chunk) {
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    return Err(Error::INVALID_PARAM(format!("{:?} has no data chunk", path.as_ref())));
+                },
+                result => result?
+            }
+
+            let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+
+            if &chunk[0..4] == b"data" {
+                let size = match ds64_data_size {
+                    Some(size) if rf64 => size,
+                    _ => size
+                };
+                let format :SampleFormat = format
+                    .ok_or_else(|| Error::INVALID_PARAM(String::from("WAV data chunk comes before fmt")))?;
+                let data_offset = data.stream_position()?;
+                let available = data.get_ref().metadata()?.len().saturating_sub(data_offset);
+
+                debug!("Opened {:?}: {:?} at {} S/s, auxi {:?}", path.as_ref(), format, sample_rate, auxi);
+
+                return Ok(WavReader {
+                    data,
+                    format,
+                    sample_rate,
+                    auxi,
+                    data_offset,
+                    // recordings that were never finished have a zero size; play what's there
+                    total: (if size == 0 { available } else { size.min(available) }) / format.sample_size() as u64,
+                    position: 0,
+                    looping: false,
+                    scratch: Vec::new()
+                });
+            }
+
+            // chunks are word aligned
+            let padded = size + size % 2;
+
+            if !matches!(&chunk[0..4], b"ds64" | b"fmt " | b"auxi") || size > MAX_METADATA_CHUNK {
+                data.seek(SeekFrom::Current(padded as i64))?;
+                continue;
+            }
+
+            let mut body = vec![0u8; size as usize];
+
+            data.read_exact(&mut body)?;
+            data.seek(SeekFrom::Current((padded - size) as i64))?;
+
+            match &chunk[0..4] {
+                b"ds64" if body.len() >= 16 => {
+                    ds64_data_size = Some(u64::from_le_bytes([body[8], body[9], body[10], body[11], body[12], body[13], body[14], body[15]]));
+                },
+                b"fmt " if body.len() >= 16 => {
+                    let tag = u16::from_le_bytes([body[0], body[1]]);
+                    let channels = u16::from_le_bytes([body[2], body[3]]);
+                    let bits = u16::from_le_bytes([body[14], body[15]]);
+
+                    if channels != 2 {
+                        return Err(Error::INVALID_PARAM(format!("WAV IQ needs 2 channels, found {}", channels)));
+                    }
+
+                    sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                    format = Some(match (tag, bits) {
+                        (WAVE_FORMAT_PCM, 8) => SampleFormat::Cu8,
+                        (WAVE_FORMAT_PCM, 16) => SampleFormat::Cs16,
+                        (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Cf32,
+                        _ => return Err(Error::INVALID_PARAM(format!("Unsupported WAV format {} with {} bits", tag, bits)))
+                    });
+                },
+                b"auxi" => auxi = Some(Auxi::from_bytes(&body)?),
+                _ => {}
+            }
+        }
+    }
+
+    /// Starts over from the beginning when the end is reached
+    pub fn with_loop(mut self, looping: bool) -> WavReader {
+        self.looping = looping;
+        self
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The `auxi` chunk, if the recording has one
+    pub fn auxi(&self) -> Option<&Auxi> {
+        self.auxi.as_ref()
+    }
+
+    /// Number of complex samples in the recording
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Sets `device`'s sample rate and, when the recording has an `auxi` chunk, its frequency
+    pub fn configure(&self, device: &Device) -> Result<(), Error> {
+        device.set_sample_rate(self.sample_rate as f64)?;
+
+        match self.auxi {
+            Some(ref auxi) if auxi.center_freq != 0 => device.set_freq(auxi.center_freq),
+            _ => {
+                warn!("WAV recording has no center frequency; leaving the device tuned where it is");
+                Ok( () )
+            }
+        }
+    }
+}
+
+impl TxSource for WavReader {
+    fn read(&mut self, buffer: &mut [i8]) -> Result<usize, Error> {
+        let sample_size = self.format.sample_size();
+        let mut filled = 0;
+
+        while filled + 2 <= buffer.len() {
+            if self.position >= self.total {
+                if !self.looping || self.total == 0 {
+                    break;
+                }
+
+                self.data.seek(SeekFrom::Start(self.data_offset))?;
+                self.position = 0;
+            }
+
+            let count = ((buffer.len() - filled) as u64 / 2).min(self.total - self.position) as usize;
+
+            self.scratch.resize(count * sample_size, 0);
+            self.data.read_exact(&mut self.scratch)?;
+            self.format.decode_i8(&self.scratch, &mut buffer[filled..filled + count * 2]);
+
+            filled += count * 2;
+            self.position += count as u64;
+        }
+
+        Ok(filled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs;
+
+    fn temp_path(name: &str) -> PathBuf {
+        temp_dir().join(format!("hackrf-wav-{}-{}.wav", name, std::process::id()))
+    }
+
+    fn tuning() -> TuningHandle {
+        let tuning = TuningHandle::new();
+
+        tuning.update(|t| {
+            t.sample_rate = Some(2e6);
+            t.freq_hz = Some(5_800_000_000);
+            t.baseband_filter_hz = Some(1_750_000);
+        });
+
+        tuning
+    }
+
+    #[test]
+    fn auxi_round_trip() {
+        let auxi = Auxi {
+            start_time: Utc.with_ymd_and_hms(2024, 2, 29, 23, 59, 58).unwrap() + chrono::Duration::milliseconds(250),
+            stop_time: Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 1).unwrap(),
+            center_freq: 5_800_000_000,
+            sample_rate: 10_000_000,
+            bandwidth: 8_750_000
+        };
+        let bytes = auxi.to_bytes();
+
+        assert_eq!(bytes.len(), AUXI_SIZE as usize);
+        // Thursday
+        assert_eq!(&bytes[4..6], &[4, 0]);
+        // 32-bit readers see a saturated frequency rather than a wrapped one
+        assert_eq!(&bytes[32..36], &u32::MAX.to_le_bytes());
+        assert_eq!(Auxi::from_bytes(&bytes).unwrap(), auxi);
+    }
+
+    #[test]
+    fn record_and_play_back() {
+        let path = temp_path("round-trip");
+        let samples = (-100..100).map(|n| n as f32 / 128.0).collect::<Vec<_>>();
+
+        for &format in &[SampleFormat::Cu8, SampleFormat::Cs16, SampleFormat::Cf32] {
+            let mut writer = WavWriter::with_tuning(&path, format, tuning()).unwrap();
+
+            writer.write(&samples).unwrap();
+            writer.finish().unwrap();
+
+            assert_eq!(fs::metadata(&path).unwrap().len(), DATA_OFFSET + 100 * format.sample_size() as u64);
+
+            let mut reader = WavReader::open(&path).unwrap().with_loop(true);
+            let auxi = reader.auxi().unwrap().clone();
+            let mut buffer = vec![0i8; 250];
+
+            assert_eq!(reader.format(), format);
+            assert_eq!(reader.sample_rate(), 2_000_000);
+            assert_eq!(reader.len(), 100);
+            assert_eq!(auxi.center_freq, 5_800_000_000);
+            assert_eq!(auxi.bandwidth, 1_750_000);
+            assert!(auxi.stop_time >= auxi.start_time);
+
+            assert_eq!(reader.read(&mut buffer).unwrap(), 250);
+            assert_eq!(buffer[0], -100);
+            assert_eq!(buffer[199], 99);
+            assert_eq!(buffer[200], -100);
+        }
+
+        assert!(WavWriter::with_tuning(&path, SampleFormat::Cs8, tuning()).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_unknown_chunks() {
+        let path = temp_path("skip");
+        let chunk = |id: &[u8], body: &[u8]| [id, &(body.len() as u32).to_le_bytes()[..], body].concat();
+        let fmt = [&1u16.to_le_bytes()[..], &2u16.to_le_bytes(), &1000u32.to_le_bytes(), &4000u32.to_le_bytes(),
+                   &4u16.to_le_bytes(), &16u16.to_le_bytes()].concat();
+        // an odd sized chunk is followed by a pad byte
+        let body = [&b"WAVE"[..], &chunk(b"LIST", b"abc"), &[0], &chunk(b"fmt ", &fmt), &chunk(b"data", &[0; 8])].concat();
+
+        fs::write(&path, [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat()).unwrap();
+
+        let reader = WavReader::open(&path).unwrap();
+
+        assert_eq!((reader.format(), reader.sample_rate(), reader.len()), (SampleFormat::Cs16, 1000, 2));
+
+        // a chunk claiming 4 GiB is skipped rather than read into memory
+        let body = [&b"WAVE"[..], b"JUNK", &u32::MAX.to_le_bytes()].concat();
+
+        fs::write(&path, [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat()).unwrap();
+        assert!(WavReader::open(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn becomes_rf64_past_the_riff_limit() {
+        let path = temp_path("rf64");
+        let mut writer = WavWriter::with_tuning(&path, SampleFormat::Cs16, tuning()).unwrap();
+
+        writer.riff_limit = 16;
+        writer.write_i8(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]).unwrap();
+        drop(writer);
+
+        let bytes = fs::read(&path).unwrap();
+
+        assert_eq!(&bytes[0..4], b"RF64");
+        assert_eq!(&bytes[12..16], b"ds64");
+        assert_eq!(&bytes[DATA_OFFSET as usize - 4..DATA_OFFSET as usize], &u32::MAX.to_le_bytes());
+
+        let mut reader = WavReader::open(&path).unwrap();
+        let mut buffer = [0i8; 16];
+
+        assert_eq!(reader.len(), 5);
+        assert_eq!(reader.read(&mut buffer).unwrap(), 10);
+        assert_eq!(&buffer[..10], &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+//! The parts of RFC 6455 the spectrum server needs: the opening handshake and unfragmented or
+//! fragmented data frames, without extensions
+
+use std::io::{Read, Write};
+
+use crate::error::Error;
+use crate::http::Request;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest message accepted from a client
+const MAX_MESSAGE: usize = 1 << 16;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close
+}
+
+/// The `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`
+pub(crate) fn accept_key(key: &str) -> String {
+    let mut sha = sha1_smol::Sha1::new();
+
+    sha.update(key.trim().as_bytes());
+    sha.update(GUID.as_bytes());
+
+    base64::encode(sha.digest().bytes())
+}
+
+/// Whether `request` asks to upgrade to a WebSocket
+pub(crate) fn is_upgrade(request: &Request) -> bool {
+    request.header("upgrade").map(|v| v.eq_ignore_ascii_case("websocket")).unwrap_or(false)
+}
+
+/// Completes the opening handshake for `request`
+pub(crate) fn accept<W: Write>(request: &Request, writer: &mut W) -> Result<(), Error> {
+    let key = request.header("sec-websocket-key")
+        .ok_or_else(|| Error::INVALID_PARAM(String::from("WebSocket upgrade without Sec-WebSocket-Key")))?;
+
+    write!(writer, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key))?;
+    writer.flush()?;
+
+    Ok( () )
+}
+
+/// Writes one frame; clients must `mask` what they send, servers must not
+pub(crate) fn write_message<W: Write>(writer: &mut W, message: &Message, mask: Option<[u8; 4]>) -> Result<(), Error> {
+    let (opcode, payload) :(u8, &[u8]) = match message {
+        Message::Text(text) => (OP_TEXT, text.as_bytes()),
+        Message::Binary(data) => (OP_BINARY, data),
+        Message::Ping(data) => (OP_PING, data),
+        Message::Pong(data) => (OP_PONG, data),
+        Message::Close => (OP_CLOSE, &[])
+    };
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+
+    frame.push(0x80 | opcode);
+
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= 0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        },
+        None => frame.extend_from_slice(payload)
+    }
+
+    writer.write_all(&frame)?;
+    writer.flush()?;
+
+    Ok( () )
+}
+
+/// Reads messages from a client, joining fragments and unmasking as needed
+pub(crate) struct MessageReader<R> {
+    reader: R,
+    partial: Vec<u8>,
+    opcode: Option<u8>
+}
+
+impl<R: Read> MessageReader<R> {
+    pub fn new(reader: R) -> MessageReader<R> {
+        MessageReader { reader, partial: Vec::new(), opcode: None }
+    }
+
+    /// The next message; control messages arriving between the fragments of a data message are
+    /// returned as they come
+    pub fn read(&mut self) -> Result<Message, Error> {
+        loop {
+            let mut head = [0u8; 2];
+
+            self.reader.read_exact(&mut head)?;
+
+            let fin = head[0] & 0x80 != 0;
+            let opcode = head[0] & 0x0f;
+            let masked = head[1] & 0x80 != 0;
+            let length = match head[1] & 0x7f {
+                126 => {
+                    let mut length = [0u8; 2];
+
+                    self.reader.read_exact(&mut length)?;
+                    u16::from_be_bytes(length) as u64
+                },
+                127 => {
+                    let mut length = [0u8; 8];
+
+                    self.reader.read_exact(&mut length)?;
+                    u64::from_be_bytes(length)
+                },
+                length => length as u64
+            };
+
+            if length > MAX_MESSAGE as u64 || self.partial.len() + length as usize > MAX_MESSAGE {
+                return Err(Error::INVALID_PARAM(format!("WebSocket message over {} bytes", MAX_MESSAGE)));
+            }
+
+            let mut mask = [0u8; 4];
+
+            if masked {
+                self.reader.read_exact(&mut mask)?;
+            }
+
+            let mut payload = vec![0u8; length as usize];
+
+            self.reader.read_exact(&mut payload)?;
+
+            if masked {
+                payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= mask[i % 4]);
+            }
+
+            match opcode {
+                OP_CLOSE => return Ok(Message::Close),
+                OP_PING => return Ok(Message::Ping(payload)),
+                OP_PONG => return Ok(Message::Pong(payload)),
+                OP_CONTINUATION if self.opcode.is_none() => {
+                    return Err(Error::INVALID_PARAM(String::from("WebSocket continuation without a message")));
+                },
+                OP_CONTINUATION => {},
+                OP_TEXT | OP_BINARY if self.opcode.is_none() => self.opcode = Some(opcode),
+                OP_TEXT | OP_BINARY => return Err(Error::INVALID_PARAM(String::from("WebSocket message interrupted by another"))),
+                other => return Err(Error::INVALID_PARAM(format!("Unknown WebSocket opcode {:#x}", other)))
+            }
+
+            self.partial.extend(payload);
+
+            if fin {
+                let message = std::mem::take(&mut self.partial);
+
+                return match self.opcode.take() {
+                    Some(OP_TEXT) => String::from_utf8(message)
+                        .map(Message::Text)
+                        .map_err(|_| Error::INVALID_PARAM(String::from("WebSocket text message is not UTF-8"))),
+                    _ => Ok(Message::Binary(message))
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn accept_key_matches_rfc() {
+        // the example from RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut buffer = Vec::new();
+        let long = Message::Binary(vec![7u8; 300]);
+
+        write_message(&mut buffer, &Message::Text(String::from("Hello")), Some([0x37, 0xfa, 0x21, 0x3d])).unwrap();
+        write_message(&mut buffer, &long, None).unwrap();
+        write_message(&mut buffer, &Message::Close, None).unwrap();
+
+        // the masked "Hello" from RFC 6455 section 5.7
+        assert_eq!(&buffer[..11], &[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+        assert_eq!(&buffer[11..15], &[0x82, 126, 0x01, 0x2c]);
+
+        let mut reader = MessageReader::new(Cursor::new(buffer));
+
+        assert_eq!(reader.read().unwrap(), Message::Text(String::from("Hello")));
+        assert_eq!(reader.read().unwrap(), long);
+        assert_eq!(reader.read().unwrap(), Message::Close);
+
+        // a fragmented message with a ping in the middle
+        let fragmented = [0x01, 0x03, b'H', b'e', b'l', 0x89, 0x00, 0x80, 0x02, b'l', b'o'];
+        let mut reader = MessageReader::new(Cursor::new(&fragmented[..]));
+
+        assert_eq!(reader.read().unwrap(), Message::Ping(Vec::new()));
+        assert_eq!(reader.read().unwrap(), Message::Text(String::from("Hello")));
+    }
+}
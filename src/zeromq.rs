@@ -0,0 +1,394 @@
+//! Fanning RX samples out over ZeroMQ to GNU Radio's ZMQ SUB Source blocks (or anything else
+//! subscribing), plus a REQ/REP channel for changing the radio's settings remotely.
+//!
+//! Enabled with the `zmq` cargo feature.
+
+use serde_json::{json, Value};
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::radio::{self, Radio};
+use crate::stream::{self, RxSink};
+use crate::tuning::TuningHandle;
+
+/// Magic number opening a GNU Radio tag header
+const GR_HEADER_MAGIC: u16 = 0x5ff0;
+const GR_HEADER_VERSION: u8 = 0x01;
+
+/// PMT serialization type codes
+const PST_FALSE: u8 = 0x01;
+const PST_SYMBOL: u8 = 0x02;
+const PST_DOUBLE: u8 = 0x04;
+
+/// Tag keys used by gr-uhd and gr-osmosdr sources, which GNU Radio blocks already understand
+pub const TAG_FREQ: &str = "rx_freq";
+pub const TAG_RATE: &str = "rx_rate";
+
+impl From<zmq::Error> for Error {
+    fn from(err: zmq::Error) -> Error {
+        Error::OTHER(format!("ZeroMQ error: {}", err))
+    }
+}
+
+/// How samples are packed into messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZmqItem {
+    /// `gr_complex`: little-endian f32 I then Q; use a ZMQ SUB Source with type complex
+    ComplexFloat,
+    /// Interleaved signed bytes, the HackRF's native format; use type byte with vector length 2
+    Int8
+}
+
+impl ZmqItem {
+    /// Bytes per complex sample
+    pub fn size(&self) -> usize {
+        match self {
+            ZmqItem::ComplexFloat => 8,
+            ZmqItem::Int8 => 2
+        }
+    }
+}
+
+/// A stream tag to put in a message header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    /// Absolute index of the sample the tag applies to
+    pub offset: u64,
+    pub key: String,
+    pub value: f64
+}
+
+fn serialize_symbol(output: &mut Vec<u8>, symbol: &str) {
+    output.push(PST_SYMBOL);
+    output.extend_from_slice(&(symbol.len() as u16).to_be_bytes());
+    output.extend_from_slice(symbol.as_bytes());
+}
+
+/// Builds the header GNU Radio's `gen_tag_header` writes when `pass_tags` is on. The fixed fields
+/// are in host (little-endian) order; the PMTs use their own big-endian serialization.
+pub fn tag_header(offset: u64, tags: &[Tag]) -> Vec<u8> {
+    let mut header = Vec::new();
+
+    header.extend_from_slice(&GR_HEADER_MAGIC.to_le_bytes());
+    header.push(GR_HEADER_VERSION);
+    header.extend_from_slice(&offset.to_le_bytes());
+    header.extend_from_slice(&(tags.len() as u64).to_le_bytes());
+
+    for tag in tags {
+        header.extend_from_slice(&tag.offset.to_le_bytes());
+        serialize_symbol(&mut header, &tag.key);
+        header.push(PST_DOUBLE);
+        header.extend_from_slice(&tag.value.to_be_bytes());
+        // srcid
+        header.push(PST_FALSE);
+    }
+
+    header
+}
+
+/// Splits a message published with tags into its offset, tags and sample bytes
+pub fn parse_tag_header(message: &[u8]) -> Result<(u64, Vec<Tag>, &[u8]), Error> {
+    let bad = || Error::INVALID_PARAM(String::from("Malformed GNU Radio tag header"));
+    let mut rest = message;
+    let mut take = |size: usize| -> Result<&[u8], Error> {
+        if rest.len() < size {
+            return Err(bad());
+        }
+
+        let (field, remainder) = rest.split_at(size);
+
+        rest = remainder;
+        Ok(field)
+    };
+    let u64_le = |bytes: &[u8]| {
+        let mut word = [0u8; 8];
+
+        word.copy_from_slice(bytes);
+        u64::from_le_bytes(word)
+    };
+
+    let magic = take(2)?;
+
+    if u16::from_le_bytes([magic[0], magic[1]]) != GR_HEADER_MAGIC || take(1)?[0] != GR_HEADER_VERSION {
+        return Err(bad());
+    }
+
+    let offset = u64_le(take(8)?);
+    let count = u64_le(take(8)?);
+    let mut tags = Vec::new();
+
+    for _ in 0..count {
+        let tag_offset = u64_le(take(8)?);
+
+        if take(1)?[0] != PST_SYMBOL {
+            return Err(bad());
+        }
+
+        let length = take(2)?;
+        let key = String::from_utf8_lossy(take(u16::from_be_bytes([length[0], length[1]]) as usize)?).into_owned();
+
+        if take(1)?[0] != PST_DOUBLE {
+            return Err(Error::INVALID_PARAM(format!("Tag {} is not a double", key)));
+        }
+
+        let mut value = [0u8; 8];
+
+        value.copy_from_slice(take(8)?);
+
+        if take(1)?[0] != PST_FALSE {
+            return Err(bad());
+        }
+
+        tags.push(Tag { offset: tag_offset, key, value: f64::from_be_bytes(value) });
+    }
+
+    Ok((offset, tags, rest))
+}
+
+/// An RX sink that publishes each buffer as one ZeroMQ message
+pub struct ZmqPublisher {
+    socket: zmq::Socket,
+    item: ZmqItem,
+    tags: bool,
+    tuning: TuningHandle,
+    generation: Option<u64>,
+    offset: u64,
+    message: Vec<u8>
+}
+
+impl ZmqPublisher {
+    /// Binds a PUB socket to `endpoint`, e.g. `tcp://*:5555`, tagging from `device`'s tuning
+    pub fn create(context: &zmq::Context, endpoint: &str, item: ZmqItem, device: &Device) -> Result<ZmqPublisher, Error> {
+        ZmqPublisher::with_tuning(context, endpoint, item, device.tuning_handle())
+    }
+
+    pub fn with_tuning(context: &zmq::Context, endpoint: &str, item: ZmqItem, tuning: TuningHandle) -> Result<ZmqPublisher, Error> {
+        let socket = context.socket(zmq::PUB)?;
+
+        socket.bind(endpoint)?;
+
+        Ok(ZmqPublisher {
+            socket,
+            item,
+            tags: false,
+            tuning,
+            generation: None,
+            offset: 0,
+            message: Vec::new()
+        })
+    }
+
+    /// Prefixes every message with a tag header, as a ZMQ PUB Sink with "pass tags" on does;
+    /// `rx_freq` and `rx_rate` tags are sent at the start and whenever they change
+    pub fn with_tags(mut self, tags: bool) -> ZmqPublisher {
+        self.tags = tags;
+        self
+    }
+
+    /// How many messages may queue for a slow subscriber before ZeroMQ drops them
+    pub fn with_high_water_mark(self, messages: i32) -> Result<ZmqPublisher, Error> {
+        self.socket.set_sndhwm(messages)?;
+        Ok(self)
+    }
+
+    /// Number of samples published so far
+    pub fn samples_published(&self) -> u64 {
+        self.offset
+    }
+
+    fn pending_tags(&mut self) -> Vec<Tag> {
+        let generation = self.tuning.generation();
+
+        if self.generation == Some(generation) {
+            return Vec::new();
+        }
+
+        let state = self.tuning.get();
+        let mut tags = Vec::new();
+
+        self.generation = Some(state.generation);
+
+        if let Some(freq) = state.freq_hz {
+            tags.push(Tag { offset: self.offset, key: String::from(TAG_FREQ), value: freq as f64 });
+        }
+
+        if let Some(rate) = state.sample_rate {
+            tags.push(Tag { offset: self.offset, key: String::from(TAG_RATE), value: rate });
+        }
+
+        tags
+    }
+}
+
+impl RxSink for ZmqPublisher {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        self.message.clear();
+
+        if self.tags {
+            let tags = self.pending_tags();
+
+            self.message.extend_from_slice(&tag_header(self.offset, &tags));
+        }
+
+        match self.item {
+            ZmqItem::ComplexFloat => samples.iter().for_each(|v| self.message.extend_from_slice(&v.to_le_bytes())),
+            ZmqItem::Int8 => self.message.extend(samples.iter().map(|v| stream::f32_to_i8(*v) as u8))
+        }
+
+        self.socket.send(&self.message[..], 0)?;
+        self.offset += samples.len() as u64 / 2;
+
+        Ok( () )
+    }
+}
+
+/// Answers JSON requests on a REP socket by calling the radio's setters.
+///
+/// A request is an object of settings to apply, as taken by `radio::apply_settings`, e.g.
+/// `{"freq": 433920000, "lna_gain": 16}`. An empty object just reads the settings back. The reply is
+/// `{"ok": true, "state": {...}}` or `{"ok": false, "error": "..."}`.
+pub struct ZmqControl {
+    socket: zmq::Socket
+}
+
+impl ZmqControl {
+    /// Binds a REP socket to `endpoint`, e.g. `tcp://*:5556`
+    pub fn bind(context: &zmq::Context, endpoint: &str) -> Result<ZmqControl, Error> {
+        let socket = context.socket(zmq::REP)?;
+
+        socket.bind(endpoint)?;
+
+        Ok(ZmqControl { socket })
+    }
+
+    /// Handles at most one request, waiting up to `timeout_ms` (-1 waits forever) for it to arrive.
+    /// Call this from the thread that owns the radio. Returns whether a request was handled.
+    pub fn poll<R: Radio>(&self, radio: &R, timeout_ms: i64) -> Result<bool, Error> {
+        if self.socket.poll(zmq::POLLIN, timeout_ms)? == 0 {
+            return Ok(false);
+        }
+
+        let request = self.socket.recv_bytes(0)?;
+        let reply = match handle_request(radio, &request) {
+            Ok(state) => json!({ "ok": true, "state": state }),
+            Err(e) => json!({ "ok": false, "error": e.to_string() })
+        };
+
+        self.socket.send(reply.to_string().as_bytes(), 0)?;
+
+        Ok(true)
+    }
+}
+
+fn handle_request<R: Radio>(radio: &R, request: &[u8]) -> Result<Value, Error> {
+    let request :Value = serde_json::from_slice(request)
+        .map_err(|e| Error::INVALID_PARAM(format!("Request is not JSON: {}", e)))?;
+    let settings = request.as_object()
+        .ok_or_else(|| Error::INVALID_PARAM(String::from("Request must be a JSON object")))?;
+
+    radio::apply_settings(radio, settings)?;
+
+    Ok(radio::settings_json(radio))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedDevice;
+    use std::thread;
+
+    #[test]
+    fn tag_header_layout() {
+        let tags = [Tag { offset: 5, key: String::from(TAG_FREQ), value: 100e6 }];
+        let header = tag_header(5, &tags);
+
+        assert_eq!(&header[0..3], &[0xf0, 0x5f, 0x01]);
+        assert_eq!(header.len(), 3 + 8 + 8 + 8 + (1 + 2 + 7) + (1 + 8) + 1);
+        assert_eq!(&header[27..30], &[PST_SYMBOL, 0x00, 0x07]);
+
+        let mut message = header.clone();
+
+        message.extend_from_slice(&[1, 2, 3, 4]);
+
+        let (offset, parsed, samples) = parse_tag_header(&message).unwrap();
+
+        assert_eq!(offset, 5);
+        assert_eq!(parsed, tags);
+        assert_eq!(samples, &[1, 2, 3, 4]);
+        assert!(parse_tag_header(&message[..20]).is_err());
+    }
+
+    #[test]
+    fn publishes_with_tags() {
+        let context = zmq::Context::new();
+        let tuning = TuningHandle::new();
+
+        tuning.update(|t| {
+            t.freq_hz = Some(433_920_000);
+            t.sample_rate = Some(10e6);
+        });
+
+        let mut publisher = ZmqPublisher::with_tuning(&context, "inproc://samples", ZmqItem::Int8, tuning.clone()).unwrap().with_tags(true);
+        let subscriber = context.socket(zmq::SUB).unwrap();
+
+        subscriber.connect("inproc://samples").unwrap();
+        subscriber.set_subscribe(b"").unwrap();
+        // give the subscription time to reach the publisher
+        thread::sleep(std::time::Duration::from_millis(100));
+
+        publisher.write(&[0.5, -0.5, 0.25, 0.0]).unwrap();
+        tuning.update(|t| t.freq_hz = Some(868_000_000));
+        publisher.write(&[0.0, 0.0]).unwrap();
+        publisher.write(&[0.0, 0.0]).unwrap();
+
+        let first = subscriber.recv_bytes(0).unwrap();
+        let (offset, tags, samples) = parse_tag_header(&first).unwrap();
+
+        assert_eq!(offset, 0);
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[1], Tag { offset: 0, key: String::from(TAG_RATE), value: 10e6 });
+        assert_eq!(samples, &[64, 192, 32, 0]);
+
+        let (offset, tags, _) = parse_tag_header(&subscriber.recv_bytes(0).unwrap()).unwrap();
+
+        assert_eq!(offset, 2);
+        assert_eq!(tags[0], Tag { offset: 2, key: String::from(TAG_FREQ), value: 868e6 });
+
+        let (_, tags, _) = parse_tag_header(&subscriber.recv_bytes(0).unwrap()).unwrap();
+
+        assert!(tags.is_empty());
+        assert_eq!(publisher.samples_published(), 4);
+    }
+
+    #[test]
+    fn control_channel() {
+        let context = zmq::Context::new();
+        let control = ZmqControl::bind(&context, "inproc://control").unwrap();
+        let sim = SimulatedDevice::new();
+        let client = context.socket(zmq::REQ).unwrap();
+
+        client.connect("inproc://control").unwrap();
+
+        let request = |body: &str| {
+            client.send(body.as_bytes(), 0).unwrap();
+            assert!(control.poll(&sim, 1000).unwrap());
+            serde_json::from_slice::<Value>(&client.recv_bytes(0).unwrap()).unwrap()
+        };
+
+        let reply = request(r#"{"freq": 915000000, "lna_gain": 24, "amp_enable": true}"#);
+
+        assert_eq!(reply["ok"], true);
+        assert_eq!(reply["state"]["freq"], 915_000_000);
+        assert_eq!(sim.tuning().lna_gain, Some(24));
+        assert_eq!(sim.tuning().amp_enable, Some(true));
+
+        let reply = request(r#"{"vga_gain": 99}"#);
+
+        assert_eq!(reply["ok"], false);
+
+        let reply = request("{}");
+
+        assert_eq!(reply["state"]["vga_gain"], Value::Null);
+        assert!(!control.poll(&sim, 0).unwrap());
+    }
+}